@@ -1,75 +1,268 @@
+//! Emulates the 6551 ACIA found in the Deluxe RS-232 Program Pak.
+//!
+//! The chip has four registers starting at its base address ($FF68 for the Pak):
+//! - +0 data: writes transmit a byte and reads return the last byte received
+//! - +1 status on read; a write does a programmed reset
+//! - +2 command: DTR, receive/transmit interrupt control, echo mode and parity
+//! - +3 control: baud rate, word length and stop bits
+//!
+//! Bytes are sent and received one character time apart at the programmed baud rate,
+//! measured against the CPU's clock cycles, and go to and from a [SerialBackend].
+//! Without a backend the port behaves as if nothing were plugged in.
 use super::*;
+use crate::runtime::CPU_HZ;
+use crate::serial::SerialBackend;
+
+/// Where the Deluxe RS-232 Program Pak puts the ACIA
+pub const RS232_PAK_ADDR: u16 = 0xff68;
 
 // status register bits
-const RDRF: u8 = 0b00000001; // receive data register full
-const TDRE: u8 = 0b00000010; // transmit data register empty
+const PARITY_ERROR: u8 = 0x01;
+const FRAMING_ERROR: u8 = 0x02;
+const OVERRUN: u8 = 0x04;
+const RDRF: u8 = 0x08; // receive data register full
+const TDRE: u8 = 0x10; // transmit data register empty
+const NOT_DCD: u8 = 0x20; // data carrier detect (active low)
+const NOT_DSR: u8 = 0x40; // data set ready (active low)
+const IRQ: u8 = 0x80; // an interrupt has occurred
+
+// command register bits
+const DTR: u8 = 0x01; // enables the receiver and transmitter
+const RX_IRQ_DISABLE: u8 = 0x02;
+const TX_CONTROL: u8 = 0x0c;
+const TX_IRQ_ENABLE: u8 = 0x04; // (value of the TX_CONTROL field)
+const ECHO: u8 = 0x10;
+const PARITY_ENABLE: u8 = 0x20;
+
+// control register bits
+const BAUD_RATE: u8 = 0x0f;
+const WORD_LENGTH: u8 = 0x60;
+const TWO_STOP_BITS: u8 = 0x80;
+
+/// Baud rates selected by the low nybble of the control register.
+/// Rate 0 divides the Pak's 1.8432MHz crystal by 16.
+const BAUD_RATES: [u64; 16] = [
+    115200, 50, 75, 110, 135, 150, 300, 600, 1200, 1800, 2400, 3600, 4800, 7200, 9600, 19200,
+];
 
 pub struct Acia {
     pub addr: u16,
-    recv_cache: Option<u8>,
-    tty_count: Arc<Mutex<i32>>,
+    command: u8,
+    control: u8,
+    // status bits other than DCD and DSR (which come from the backend)
+    status: u8,
+    rx_data: u8,
+    // byte written to the data register that hasn't reached the shift register yet
+    tx_data: Option<u8>,
+    // byte being shifted out and the cycle at which its stop bit is done
+    tx_shift: Option<(u8, u64)>,
+    // the earliest cycle at which the next byte can arrive
+    rx_ready_at: u64,
+    backend: Option<Box<dyn SerialBackend>>,
 }
 
 impl Acia {
-    pub fn control_register_address(&self) -> u16 {
+    pub fn data_register_address(&self) -> u16 {
         self.addr
     }
     pub fn status_register_address(&self) -> u16 {
-        self.addr
-    }
-    pub fn data_register_address(&self) -> u16 {
         self.addr + 1
     }
+    pub fn command_register_address(&self) -> u16 {
+        self.addr + 2
+    }
+    pub fn control_register_address(&self) -> u16 {
+        self.addr + 3
+    }
     pub fn owns_address(&self, addr: u16) -> bool {
-        addr == self.addr || addr == (self.addr + 1)
-    }
-    pub fn write(&mut self, addr: u16, _byte: u8) -> Result<(), Error> {
-        if addr == self.control_register_address() {
-            // ignore control register writes
-            return Ok(());
-        } else if addr == self.data_register_address() {
-            // ignore transmit data for now
+        addr >= self.addr && addr - self.addr < 4
+    }
+    /// Connects the port to a backend, replacing any previous one.
+    pub fn set_backend(&mut self, backend: Box<dyn SerialBackend>) {
+        self.backend = Some(backend);
+    }
+    /// Disconnects the port from its backend.
+    pub fn take_backend(&mut self) -> Option<Box<dyn SerialBackend>> {
+        self.backend.take()
+    }
+    /// The current baud rate
+    pub fn baud_rate(&self) -> u64 {
+        BAUD_RATES[(self.control & BAUD_RATE) as usize]
+    }
+    /// The number of CPU cycles it takes to send or receive one character
+    /// (start bit, data bits, parity and stop bits) at the current settings.
+    pub fn character_cycles(&self) -> u64 {
+        let data_bits = 8 - ((self.control & WORD_LENGTH) >> 5) as u64;
+        let parity = (self.command & PARITY_ENABLE != 0) as u64;
+        // counted in half bits because 5-bit words get 1.5 stop bits
+        let stop_half_bits = match self.control & TWO_STOP_BITS != 0 {
+            false => 2,
+            true if data_bits == 5 && parity == 0 => 3,
+            true if data_bits == 8 && parity == 1 => 2,
+            true => 4,
+        };
+        let half_bits = 2 * (1 + data_bits + parity) + stop_half_bits;
+        half_bits * CPU_HZ / (2 * self.baud_rate())
+    }
+    /// True if the chip is asserting its IRQ output
+    pub fn irq(&self) -> bool {
+        self.status & IRQ != 0
+    }
+    fn enabled(&self) -> bool {
+        self.command & DTR != 0
+    }
+    fn tx_irq_enabled(&self) -> bool {
+        self.command & TX_CONTROL == TX_IRQ_ENABLE
+    }
+    fn rx_irq_enabled(&self) -> bool {
+        self.command & RX_IRQ_DISABLE == 0
+    }
+    fn echo(&self) -> bool {
+        // echo only applies when the transmitter control bits are 00
+        self.command & (ECHO | TX_CONTROL) == ECHO
+    }
+    fn connected(&mut self) -> bool {
+        self.backend.as_mut().is_some_and(|b| b.connected())
+    }
+    fn send(&mut self, byte: u8) {
+        acia_dbg!("ACIA sent {:02X}", byte);
+        if let Some(backend) = self.backend.as_mut() {
+            backend.write_byte(byte);
+        }
+    }
+    /// Moves the transmitter and receiver along to `clock_cycles` and
+    /// returns true if the chip is asserting IRQ.
+    pub fn update(&mut self, clock_cycles: u64) -> bool {
+        if !self.enabled() {
+            return self.irq();
+        }
+        // transmitter
+        if let Some((byte, done_at)) = self.tx_shift {
+            if clock_cycles >= done_at {
+                self.tx_shift = None;
+                self.send(byte);
+            }
+        }
+        // transmission is disabled while RTS is high (transmitter control = 00)
+        if self.tx_shift.is_none() && self.command & TX_CONTROL != 0 {
+            if let Some(byte) = self.tx_data.take() {
+                self.tx_shift = Some((byte, clock_cycles + self.character_cycles()));
+                self.status |= TDRE;
+                if self.tx_irq_enabled() {
+                    self.status |= IRQ;
+                }
+            }
+        }
+        // receiver
+        if clock_cycles >= self.rx_ready_at {
+            if let Some(byte) = self.backend.as_mut().and_then(|b| b.read_byte()) {
+                acia_dbg!("ACIA received {:02X}", byte);
+                self.rx_ready_at = clock_cycles + self.character_cycles();
+                if self.status & RDRF != 0 {
+                    // the previous byte wasn't read in time; the new one is lost
+                    self.status |= OVERRUN;
+                } else {
+                    self.rx_data = byte;
+                    self.status = (self.status & !(PARITY_ERROR | FRAMING_ERROR | OVERRUN)) | RDRF;
+                }
+                if self.rx_irq_enabled() {
+                    self.status |= IRQ;
+                }
+                if self.echo() {
+                    self.send(byte);
+                }
+            }
+        }
+        self.irq()
+    }
+    pub fn write(&mut self, addr: u16, byte: u8) -> Result<(), Error> {
+        match addr - self.addr {
+            0 => {
+                acia_dbg!("ACIA transmit {:02X}", byte);
+                // writing while TDRE is clear replaces the byte that was waiting
+                self.tx_data = Some(byte);
+                self.status &= !TDRE;
+            }
+            1 => {
+                // programmed reset clears the low 5 bits of the command register and overrun
+                acia_dbg!("ACIA programmed reset");
+                self.command &= !(ECHO | TX_CONTROL | RX_IRQ_DISABLE | DTR);
+                self.status &= !OVERRUN;
+            }
+            2 => {
+                acia_dbg!("ACIA command {:02X}", byte);
+                self.command = byte;
+                if self.tx_irq_enabled() && self.status & TDRE != 0 && self.enabled() {
+                    self.status |= IRQ;
+                }
+            }
+            3 => {
+                acia_dbg!(
+                    "ACIA control {:02X} ({} baud)",
+                    byte,
+                    BAUD_RATES[(byte & BAUD_RATE) as usize]
+                );
+                self.control = byte;
+            }
+            _ => {
+                return Err(runtime_err!(
+                    None,
+                    "invalid ACIA write address {:04X}",
+                    addr
+                ))
+            }
         }
         Ok(())
     }
     pub fn read(&mut self, addr: u16) -> Result<u8, Error> {
-        let mut flags = 0u8;
-        if addr == self.status_register_address() {
-            // if there is some data ready to read then set the RDRF bit
-            if let Some(_pending_data) = self.recv_cache {
-                acia_dbg!("ACIA status - pending data {:02X}", pending_data);
-                flags |= RDRF;
-            }
-            // if we have a TTY connected then set the TDRE flag
-            let ttyc = self.tty_count.lock();
-            if *ttyc > 0 {
-                flags |= TDRE;
+        match addr - self.addr {
+            0 => {
+                self.status &= !RDRF;
+                Ok(self.rx_data)
             }
-            Ok(flags)
-        } else if addr == self.data_register_address() {
-            // try to get a byte from our cache
-            if let Some(byte) = self.recv_cache.take() {
-                acia_dbg!("ACIA read {:02X}", byte);
-                Ok(byte)
-            } else {
-                // user read the data register when there was no data available.
-                // result is undefined? just return a 0?
-                Ok(0)
+            1 => {
+                let mut status = self.status;
+                if !self.connected() {
+                    status |= NOT_DCD | NOT_DSR;
+                }
+                // reading the status register clears the interrupt
+                self.status &= !IRQ;
+                Ok(status)
             }
-        } else {
-            panic!("invalid ACIA read address")
+            2 => Ok(self.command),
+            3 => Ok(self.control),
+            _ => Err(runtime_err!(None, "invalid ACIA read address {:04X}", addr)),
         }
     }
 }
 
 impl Acia {
+    /// Creates an ACIA in its hardware reset state with no backend.
     pub fn new(addr: u16) -> Result<Acia, Error> {
-        let tty_count = Arc::new(Mutex::new(0));
-
         Ok(Acia {
             addr,
-            recv_cache: None,
-            tty_count,
+            command: RX_IRQ_DISABLE,
+            control: 0,
+            status: TDRE,
+            rx_data: 0,
+            tx_data: None,
+            tx_shift: None,
+            rx_ready_at: 0,
+            backend: None,
         })
     }
 }
+
+impl Core {
+    /// Connects the ACIA to `backend`.
+    /// Fails if the core was created without an ACIA.
+    pub fn attach_serial(&mut self, backend: Box<dyn SerialBackend>) -> Result<(), Error> {
+        match self.acia.as_ref() {
+            Some(acia) => {
+                acia.borrow_mut().set_backend(backend);
+                Ok(())
+            }
+            None => Err(general_err!("no ACIA is configured")),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use crate::acia::RS232_PAK_ADDR;
+use crate::cpu_test::create_core_with_acia;
+use crate::memory::AccessType;
+use crate::serial::Pipe;
+use crate::*;
+use alloc::vec;
+use spin::Mutex;
+
+const DATA: u16 = RS232_PAK_ADDR;
+const STATUS: u16 = RS232_PAK_ADDR + 1;
+const COMMAND: u16 = RS232_PAK_ADDR + 2;
+const CONTROL: u16 = RS232_PAK_ADDR + 3;
+
+/// An ACIA connected to a pipe, set to 9600 baud, 8N1 with DTR on and interrupts off
+fn create_acia() -> (Acia, Pipe) {
+    let mut acia = Acia::new(RS232_PAK_ADDR).unwrap();
+    let pipe = Pipe::new();
+    acia.set_backend(Box::new(pipe.clone()));
+    acia.write(CONTROL, 0x1e).unwrap();
+    acia.write(COMMAND, 0x0b).unwrap();
+    (acia, pipe)
+}
+
+#[test]
+fn test_registers() {
+    let mut acia = Acia::new(RS232_PAK_ADDR).unwrap();
+    assert!(acia.owns_address(0xff68) && acia.owns_address(0xff6b));
+    assert!(!acia.owns_address(0xff67) && !acia.owns_address(0xff6c));
+    // hardware reset state; nothing is connected so DCD and DSR are high
+    assert_eq!(acia.read(COMMAND).unwrap(), 0x02);
+    assert_eq!(acia.read(CONTROL).unwrap(), 0x00);
+    assert_eq!(acia.read(STATUS).unwrap(), 0x70);
+    acia.write(CONTROL, 0x1e).unwrap();
+    acia.write(COMMAND, 0x1b).unwrap();
+    assert_eq!(acia.read(CONTROL).unwrap(), 0x1e);
+    assert_eq!(acia.read(COMMAND).unwrap(), 0x1b);
+    assert_eq!(acia.baud_rate(), 9600);
+    // a programmed reset clears the low 5 bits of the command register only
+    acia.write(COMMAND, 0xeb).unwrap();
+    acia.write(STATUS, 0).unwrap();
+    assert_eq!(acia.read(COMMAND).unwrap(), 0xe0);
+    assert_eq!(acia.read(CONTROL).unwrap(), 0x1e);
+
+    acia.set_backend(Box::new(Pipe::new()));
+    assert_eq!(acia.read(STATUS).unwrap(), 0x10);
+}
+
+#[test]
+fn test_character_time() {
+    let (mut acia, _) = create_acia();
+    // 10 bits at 9600 baud
+    assert_eq!(acia.character_cycles(), 10 * 894_886 / 9600);
+    // 7 data bits, even parity, 2 stop bits at 300 baud
+    acia.write(CONTROL, 0xa6).unwrap();
+    acia.write(COMMAND, 0x6b).unwrap();
+    assert_eq!(acia.character_cycles(), 11 * 894_886 / 300);
+    // 5 data bits and no parity get 1.5 stop bits
+    acia.write(CONTROL, 0xe6).unwrap();
+    acia.write(COMMAND, 0x0b).unwrap();
+    assert_eq!(acia.character_cycles(), 15 * 894_886 / 600);
+    // 8 data bits with parity only get 1
+    acia.write(CONTROL, 0x86).unwrap();
+    acia.write(COMMAND, 0x2b).unwrap();
+    assert_eq!(acia.character_cycles(), 11 * 894_886 / 300);
+}
+
+#[test]
+fn test_transmit() {
+    let (mut acia, pipe) = create_acia();
+    let char_time = acia.character_cycles();
+    acia.write(DATA, b'A').unwrap();
+    assert_eq!(acia.read(STATUS).unwrap() & 0x10, 0);
+    // the byte moves to the shift register and the data register is free again
+    acia.update(100);
+    assert_eq!(acia.read(STATUS).unwrap() & 0x10, 0x10);
+    acia.write(DATA, b'B').unwrap();
+    // but it takes a character time to go out
+    acia.update(100 + char_time - 1);
+    assert!(pipe.take_output().is_empty());
+    assert_eq!(acia.read(STATUS).unwrap() & 0x10, 0);
+    acia.update(100 + char_time);
+    assert_eq!(pipe.take_output(), b"A");
+    acia.update(100 + 2 * char_time);
+    assert_eq!(pipe.take_output(), b"B");
+    assert_eq!(acia.read(STATUS).unwrap() & 0x10, 0x10);
+
+    // nothing is sent while RTS is high or DTR is off
+    acia.write(COMMAND, 0x03).unwrap();
+    acia.write(DATA, b'C').unwrap();
+    acia.update(1_000_000);
+    acia.write(COMMAND, 0x0a).unwrap();
+    acia.update(2_000_000);
+    assert!(pipe.take_output().is_empty());
+    acia.write(COMMAND, 0x0b).unwrap();
+    acia.update(3_000_000);
+    acia.update(4_000_000);
+    assert_eq!(pipe.take_output(), b"C");
+}
+
+#[test]
+fn test_receive() {
+    let (mut acia, pipe) = create_acia();
+    let char_time = acia.character_cycles();
+    pipe.send(b"xyzw");
+    acia.update(0);
+    assert_eq!(acia.read(STATUS).unwrap() & 0x0f, 0x08);
+    assert_eq!(acia.read(DATA).unwrap(), b'x');
+    assert_eq!(acia.read(STATUS).unwrap() & 0x08, 0);
+    // the next byte arrives a character time later
+    acia.update(char_time - 1);
+    assert_eq!(acia.read(STATUS).unwrap() & 0x08, 0);
+    acia.update(char_time);
+    assert_eq!(acia.read(DATA).unwrap(), b'y');
+    // if a byte isn't read in time the next one is lost
+    acia.update(2 * char_time);
+    acia.update(3 * char_time);
+    assert_eq!(pipe.pending(), 0);
+    assert_eq!(acia.read(STATUS).unwrap() & 0x0c, 0x0c);
+    assert_eq!(acia.read(DATA).unwrap(), b'z');
+    // overrun is cleared by a programmed reset
+    acia.write(STATUS, 0).unwrap();
+    assert_eq!(acia.read(STATUS).unwrap() & 0x04, 0);
+    // nothing is received with DTR off
+    pipe.send(b"!");
+    acia.update(10 * char_time);
+    assert_eq!(pipe.pending(), 1);
+}
+
+#[test]
+fn test_echo() {
+    let (mut acia, pipe) = create_acia();
+    acia.write(COMMAND, 0x13).unwrap();
+    pipe.send(b"e");
+    acia.update(0);
+    assert_eq!(acia.read(DATA).unwrap(), b'e');
+    assert_eq!(pipe.take_output(), b"e");
+}
+
+#[test]
+fn test_interrupts() {
+    let (mut acia, pipe) = create_acia();
+    // receive interrupt
+    acia.write(COMMAND, 0x09).unwrap();
+    assert!(!acia.update(0));
+    pipe.send(b"r");
+    assert!(acia.update(1));
+    assert_eq!(acia.read(STATUS).unwrap() & 0x88, 0x88);
+    // reading status clears the interrupt
+    assert!(!acia.irq());
+    assert_eq!(acia.read(DATA).unwrap(), b'r');
+
+    // transmit interrupts happen when the data register is empty
+    acia.write(COMMAND, 0x07).unwrap();
+    assert!(acia.irq());
+    acia.read(STATUS).unwrap();
+    let char_time = acia.character_cycles();
+    acia.write(DATA, b't').unwrap();
+    assert!(acia.update(10_000));
+    acia.read(STATUS).unwrap();
+    // the second byte waits for the first to be shifted out
+    acia.write(DATA, b'u').unwrap();
+    assert!(!acia.update(10_001));
+    assert!(acia.update(10_000 + char_time));
+    assert_eq!(pipe.take_output(), b"t");
+}
+
+#[test]
+fn test_firq_to_cpu() {
+    let mut core = create_core_with_acia(Some(RS232_PAK_ADDR));
+    let pipe = Pipe::new();
+    core.attach_serial(Box::new(pipe.clone())).unwrap();
+    // the FIRQ vector (remapped to $BFF6) points at an RTI at $2000 and the IRQ vector
+    // ($BFF8) at one at $3000; the program is a loop at $1000
+    core.raw_ram[0xbff6] = 0x20;
+    core.raw_ram[0xbff7] = 0x00;
+    core.raw_ram[0xbff8] = 0x30;
+    core.raw_ram[0xbff9] = 0x00;
+    core.raw_ram[0x2000] = 0x3b;
+    core.raw_ram[0x3000] = 0x3b;
+    core.raw_ram[0x1000] = 0x20;
+    core.raw_ram[0x1001] = 0xfe;
+    core.reg.pc = 0x1000;
+    core.reg.s = 0x8000;
+    core.reg.cc.set(registers::CCBit::I, false);
+    core.reg.cc.set(registers::CCBit::F, false);
+    // 9600 baud, receive interrupts on
+    core._write_u8(AccessType::System, CONTROL, 0x1e).unwrap();
+    core._write_u8(AccessType::System, COMMAND, 0x09).unwrap();
+    // the ACIA's IRQ output reaches the CPU through PIA1's CB1 (the cartridge line), so
+    // nothing happens until that interrupt is enabled
+    pipe.send(b"i");
+    let mut interrupted = false;
+    for _ in 0..100 {
+        core.exec_one().unwrap();
+        interrupted |= core.reg.pc == 0x2000 || core.reg.pc == 0x3000;
+    }
+    assert!(!interrupted);
+    core._write_u8(AccessType::System, 0xff23, 0x05).unwrap();
+    let (mut firq, mut irq) = (false, false);
+    for _ in 0..100 {
+        core.exec_one().unwrap();
+        firq |= core.reg.pc == 0x2000;
+        irq |= core.reg.pc == 0x3000;
+    }
+    assert!(firq);
+    assert!(!irq);
+    assert_eq!(core._read_u8(AccessType::System, DATA, None).unwrap(), b'i');
+}
+
+#[test]
+fn test_no_acia() {
+    let ram = Box::leak(vec![0u8; 0x10000].into_boxed_slice());
+    let pia1 = Arc::new(Mutex::new(Pia1::new()));
+    let pia0 = Arc::new(Mutex::new(Pia0::new(pia1.clone())));
+    let sam = Arc::new(Mutex::new(Sam::new()));
+    let vdg = Arc::new(Mutex::new(Vdg::with_ram(0)));
+    let mut core = Core::new(ram, sam, vdg, pia0, pia1, 0xFFFF, None);
+    assert!(core.attach_serial(Box::new(Pipe::new())).is_err());
+}
+
+#[test]
+fn test_tcp_backend() {
+    use crate::serial::{tcp::TcpSerial, SerialBackend};
+    use std::io::{Read, Write};
+    let mut serial = TcpSerial::listen(0).unwrap();
+    assert!(!serial.connected());
+    let mut client = std::net::TcpStream::connect(("127.0.0.1", serial.port())).unwrap();
+    client
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while !serial.connected() && std::time::Instant::now() < deadline {}
+    assert!(serial.connected());
+    serial.write_byte(b'o');
+    let mut buf = [0u8; 1];
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"o");
+    client.write_all(b"k").unwrap();
+    let mut received = None;
+    while received.is_none() && std::time::Instant::now() < deadline {
+        received = serial.read_byte();
+    }
+    assert_eq!(received, Some(b'k'));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_pty_backend() {
+    use crate::serial::{pty::Pty, SerialBackend};
+    use std::io::{Read, Write};
+    let mut pty = Pty::open().unwrap();
+    assert!(pty.path().starts_with("/dev/"));
+    assert!(!pty.connected());
+    let mut slave = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(pty.path())
+        .unwrap();
+    slave.write_all(b"p").unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let mut received = None;
+    while received.is_none() && std::time::Instant::now() < deadline {
+        received = pty.read_byte();
+    }
+    assert_eq!(received, Some(b'p'));
+    assert!(pty.connected());
+    pty.write_byte(b'q');
+    let mut buf = [0u8; 1];
+    slave.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"q");
+}
@@ -19,7 +19,8 @@
 //! from assembly language to machine code.
 use super::obj::*;
 // use core::panic::PanicInfo;
-use super::parse::{OperandDescriptor, Parser};
+use super::parse::{LabelResolver, OperandDescriptor, Parser};
+use super::source::{self, SourceFs};
 #[cfg(not(target_os = "none"))]
 #[cfg(not(target_os = "none"))]
 use super::test::{AddrOrVal, RegOrAddr, TestCriterion};
@@ -31,9 +32,317 @@ use super::*;
 // // use std::io::{self, BufRead};
 use crate::error::{Error, ErrorKind};
 
+/// The maximum number of diagnostics collected during a single build
+const MAX_DIAGNOSTICS: usize = 20;
+
+/// The maximum depth of nested macro (and REPT) expansions; deeper nesting is assumed to
+/// be runaway recursion
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+/// State carried through the loading of a program and any files it includes
+#[derive(Default)]
+struct LoadState {
+    macros: Map<String, Macro>,
+    mo: Option<Macro>, // the macro currently being defined (if any)
+    prog_lines: Vec<ProgramLine>,
+    files: Vec<String>, // the files currently being loaded (used to detect circular includes)
+    expansions: usize,  // the number of macro instances expanded so far (used for "\@")
+    rept: Option<Repeat>, // the REPT block whose lines are being collected (if any)
+    conds: Vec<LoadCond>, // the conditional assembly blocks open at this point
+    symbols: Map<String, LoadSymbol>, // the labels defined so far (as far as loading can tell)
+    exited: Option<Rc<Expansion>>, // the macro expansion being ended by an .EXITM (if any)
+}
+impl LoadState {
+    /// Whether the lines being loaded now will be assembled
+    fn branch(&self) -> Branch {
+        self.conds.last().map_or(Branch::Yes, |c| c.branch)
+    }
+    /// Adds a line to the program
+    fn push(
+        &mut self,
+        origin: &LineOrigin,
+        src: String,
+        label: Option<String>,
+        operation: Option<String>,
+        operand: Option<String>,
+    ) {
+        if let Some(name) = label
+            .as_ref()
+            .filter(|l| !is_local_label(l) && !is_temp_label(l))
+        {
+            match self.branch() {
+                Branch::Maybe => {
+                    self.symbols
+                        .entry(name.clone())
+                        .or_insert(LoadSymbol::Maybe);
+                }
+                _ => {
+                    self.symbols.insert(name.clone(), LoadSymbol::Defined);
+                }
+            }
+        }
+        self.prog_lines.push(ProgramLine {
+            src_line_num: origin.src_line_num,
+            file: origin.file.clone(),
+            src,
+            label,
+            operation,
+            operand,
+            obj: None,
+            obj_size: 0,
+            addr: 0,
+            dp: None,
+            expansion: origin.expansion.clone(),
+        });
+    }
+    /// Adds a line that just defines `label` (if there is one)
+    fn push_label(&mut self, origin: &LineOrigin, label: Option<String>) {
+        if let Some(label) = label {
+            self.push(origin, format!("{}:", &label), Some(label), None, None);
+        }
+    }
+}
+
+impl LabelResolver for LoadState {
+    /// Only constants (EQUs of constant expressions) have a value while loading
+    fn resolve(&self, label: &str) -> Option<u8u16> {
+        match self.symbols.get(label) {
+            Some(LoadSymbol::Const(val)) => Some(*val),
+            _ => None,
+        }
+    }
+}
+
+/// What loading has found out about a label
+#[derive(Clone, Copy)]
+enum LoadSymbol {
+    /// an EQU of a constant expression
+    Const(u8u16),
+    Defined,
+    /// defined in a conditional block that can only be decided when the program is built
+    Maybe,
+}
+
+/// Whether lines being loaded will be assembled
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Branch {
+    Yes,
+    No,
+    /// it depends on a condition that can only be decided when the program is built
+    Maybe,
+}
+
+/// A conditional assembly (IFxx) block open while loading. Blocks whose condition can be
+/// decided while loading are dealt with then (so a false branch can hold the INCLUDE of a
+/// file that doesn't exist, or guard a file against being included twice); the others are
+/// left for pre_build.
+struct LoadCond {
+    origin: LineOrigin,
+    op: String,
+    /// the condition's value if it was decided while loading
+    decided: Option<bool>,
+    parent: Branch,
+    branch: Branch, // the current branch (IF or ELSE)
+    seen_else: bool,
+}
+
+/// The conditional assembly directives that open a block
+const IF_DIRECTIVES: [&str; 12] = [
+    "IFEQ", "IFNE", "IFGT", "IFGE", "IFLT", "IFLE", "IFDEF", "IFNDEF", "IFB", "IFNB", "IFSTR",
+    "IFNUM",
+];
+
+/// Where a line being loaded came from
+#[derive(Clone)]
+struct LineOrigin {
+    file: Option<Rc<str>>,
+    src_line_num: usize,
+    expansion: Option<Rc<Expansion>>, // the macro/REPT expansion that produced the line (if any)
+}
+
+/// A REPT block whose lines are being collected
+struct Repeat {
+    count: usize,
+    lines: Vec<String>,
+    nesting: usize, // the number of REPT blocks (within this one) that are still open
+    origin: LineOrigin,
+}
+
+/// Returns true if `s` is a numeric constant (e.g. "12", "-$1F", "%101" or "'A")
+fn is_number(s: &str) -> bool {
+    let s = s.strip_prefix('-').unwrap_or(s);
+    let digits = |s: &str, radix| !s.is_empty() && s.chars().all(|c| c.is_digit(radix));
+    match s.chars().next() {
+        Some('$') => digits(&s[1..], 16),
+        Some('%') => digits(&s[1..], 2),
+        Some('\'') => s.chars().count() == 2 || (s.chars().count() == 3 && s.ends_with('\'')),
+        _ => digits(s, 10),
+    }
+}
+
+/// Splits the operand of a macro invocation into args. Commas within quotes or parentheses
+/// don't separate args, and the args end at the first whitespace (or ';') that isn't within
+/// quotes and doesn't follow a comma. Anything after that is a comment.
+fn split_macro_args(operand: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let (mut start, mut end, mut depth, mut quoted) = (0, operand.len(), 0usize, false);
+    for (i, c) in operand.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                args.push(operand[start..i].trim());
+                start = i + 1;
+            }
+            ';' => {
+                end = i;
+                break;
+            }
+            c if c.is_whitespace() && !operand[start..i].trim().is_empty() => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+    args.push(operand[start..end].trim());
+    if args.len() == 1 && args[0].is_empty() {
+        // nothing but a comment
+        args.clear();
+    }
+    args
+}
+
+/// Splits an assembly statement into its label, operation and operand (any trailing comment
+/// is left on the operand). Blank lines and comment lines yield (None, None, None).
+fn parse_statement(line: &str) -> (Option<String>, Option<String>, Option<String>) {
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('*') || trimmed.starts_with(';') {
+        return (None, None, None);
+    }
+    let mut parts = line.split_whitespace();
+    let first = parts.next();
+    let second = parts.next();
+    let remains = parts.collect::<Vec<&str>>().join(" ");
+    if line.chars().next().is_some_and(|c| !c.is_whitespace()) {
+        // line starts with a label
+        let label = first.map(|s| s.trim_end_matches(':').to_string());
+        let operation = second.map(|s| s.to_ascii_uppercase());
+        let operand = if remains.is_empty() {
+            None
+        } else {
+            Some(remains)
+        };
+        (label, operation, operand)
+    } else {
+        // no label, line starts with whitespace
+        let operation = first.map(|s| s.to_ascii_uppercase());
+        let operand = second.map(|s| {
+            let mut op_plus_remains = s.to_string();
+            if !remains.is_empty() {
+                op_plus_remains.push(' ');
+                op_plus_remains.push_str(&remains);
+            }
+            op_plus_remains
+        });
+        (None, operation, operand)
+    }
+}
+
+/// An open conditional assembly (IFxx) block
+struct CondBlock {
+    index: usize,        // index of the line containing the IFxx directive
+    parent_active: bool, // true if the enclosing block is being assembled
+    active: bool,        // true if the current branch (IF or ELSE) is being assembled
+    seen_else: bool,
+}
+
+/// The stack of nested conditional assembly blocks (tracked during pre_build)
+#[derive(Default)]
+struct Conditionals {
+    blocks: Vec<CondBlock>,
+    exited: Option<Rc<Expansion>>, // the macro expansion that was ended early by .EXITM (if any)
+    exiting: bool,                 // true if the current line is in the exited expansion
+}
+impl Conditionals {
+    /// Returns true if lines at the current position should be assembled
+    fn assembling(&self) -> bool {
+        !self.exiting && self.blocks.last().is_none_or(|b| b.active)
+    }
+}
+
+/// Gets the string from the operand of an FCC-style directive. The first non-whitespace
+/// char is the delimiter and the string ends at the next occurrence of that char.
+fn delimited_string(operand: &str) -> Option<&str> {
+    let operand = operand.trim_start();
+    let delim = operand.chars().next()?;
+    let rest = &operand[delim.len_utf8()..];
+    rest.find(delim).map(|end| &rest[..end])
+}
+
+/// Finds the first char in the operand that satisfies `pred` and isn't part of a quoted
+/// string or a character constant (e.g. `';` or `';'`).
+fn find_unquoted(operand: &str, pred: impl Fn(char) -> bool) -> Option<usize> {
+    let mut chars = operand.char_indices().peekable();
+    let mut quoted = false;
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '\'' => {
+                // the next char is the constant, and it may be followed by a closing quote
+                chars.next();
+                chars.next_if(|&(_, c)| c == '\'');
+            }
+            c if pred(c) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Removes a trailing comment (anything from a ';' that isn't quoted) from an operand
+fn strip_comment(operand: &str) -> &str {
+    match find_unquoted(operand, |c| c == ';') {
+        Some(end) => operand[..end].trim_end(),
+        None => operand,
+    }
+}
+
+/// Splits an operand (without its comment) into its comma separated values, leaving commas
+/// within quotes or character constants alone
+fn split_values(operand: &str) -> Vec<&str> {
+    let mut values = Vec::new();
+    let mut rest = operand;
+    while let Some(i) = find_unquoted(rest, |c| c == ',') {
+        values.push(&rest[..i]);
+        rest = &rest[i + 1..];
+    }
+    values.push(rest);
+    values
+}
+
+/// Gets the file name from the operand of an INCLUDE/USE directive. The name may be
+/// quoted (with "" or '') or it's the first word of the operand.
+fn include_file_name(operand: &str) -> Option<&str> {
+    let operand = operand.trim_start();
+    let name = match operand.chars().next()? {
+        q @ ('"' | '\'') => operand[1..].split(q).next()?,
+        _ => operand.split_whitespace().next()?,
+    };
+    (!name.is_empty()).then_some(name)
+}
+
 /// The container for our assembler methods.
 pub struct Assembler {
     parser: Parser,
+    // errors reported by the most recent build
+    diagnostics: core::cell::RefCell<Vec<Error>>,
+    // where source files are read from and where included files are searched for
+    fs: Box<dyn SourceFs>,
+    include_paths: Vec<String>,
     // re_result_line: Regex,           // matches test criterion
     // re_comment_or_blank_line: Regex, // matches a line that is blank or only contains a comment
     // re_statement: Regex, // matches a generic assembly statement line ([label] operation [operand [comment]])
@@ -44,6 +353,9 @@ impl Assembler {
         instructions::init();
         Assembler {
             parser: Parser::new(),
+            diagnostics: core::cell::RefCell::new(Vec::new()),
+            fs: source::default_fs(),
+            include_paths: Vec::new(),
             /*
             re_result_line: Regex::new(r"^;![ \t]*([^\s]+)[ \t]*=[ \t]*([^\s]+)[ \t]*$").unwrap(),
             re_comment_or_blank_line: Regex::new(r"^(?:[ \t]*[*;].*)|^[ \t]*$").unwrap(),
@@ -56,254 +368,609 @@ impl Assembler {
         }
     }
 
+    /// Sets the file system that source files (and the files they include) are read from.
+    pub fn set_fs(&mut self, fs: Box<dyn SourceFs>) {
+        self.fs = fs;
+    }
+
+    /// Adds a directory to search for files named by INCLUDE/USE directives.
+    /// The directory of the including file is always searched first; search paths are
+    /// then tried in the order they were added.
+    pub fn add_include_path(&mut self, path: &str) {
+        self.include_paths.push(source::normalize(path));
+    }
+
     /// Load an assembly language program using the supplied iterable container of program lines.
     /// All macros are expanded during this process. The success result contains a Program object
     /// that contains all the source lines but that has not been built.
+    /// Files named by INCLUDE/USE directives are looked for relative to the current directory
+    /// and the include paths.
     pub fn load_program<I, T>(&self, src: I) -> Result<Program, Error>
     where
         I: IntoIterator<Item = T>,
         T: Into<String>,
     {
-        let mut macros = Map::new();
-        let mut mo: Option<Macro> = None;
-        let mut src_line_num = 0usize;
-        let mut prog_lines = Vec::new();
-        let add_line = |pls: &mut Vec<ProgramLine>,
-                        src_line_num: usize,
-                        src: String,
-                        label: Option<String>,
-                        operation: Option<String>,
-                        operand: Option<String>| {
-            let pl = ProgramLine {
-                src_line_num,
-                src,
-                label,
-                operation,
-                operand,
-                obj: None,
-                obj_size: 0,
-                addr: 0,
-            };
-            pls.push(pl);
+        self.load_program_file(None, src)
+    }
+
+    /// Same as load_program but `file` names the (normalized) path of the source
+    /// so that lines and errors can refer to it and relative includes can be found.
+    fn load_program_file<I, T>(&self, file: Option<&str>, src: I) -> Result<Program, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut state = LoadState::default();
+        if let Some(path) = file {
+            state.files.push(path.to_string());
+        }
+        self.load_lines(&mut state, file.map(Rc::from), src)?;
+        // we've read through all the supplied source lines
+        if let Some(_m) = state.mo {
+            // a macro definition was begun but never ended
+            return Err(syntax_err!("no end found for macro \"{}\"", _m.name));
+        }
+        // blocks left for pre_build are checked there
+        if let Some(cond) = state.conds.iter().find(|c| c.decided.is_some()) {
+            let e = syntax_err_line!(
+                cond.origin.src_line_num,
+                "{} without a matching ENDC",
+                cond.op
+            );
+            return Err(match &cond.origin.file {
+                Some(file) => e.in_file(file),
+                None => e,
+            });
+        }
+        Ok(Program::new(state.prog_lines))
+    }
+
+    /// Reads the lines of one source file into the program being loaded (recursing into any
+    /// files that it includes).
+    fn load_lines<I, T>(
+        &self,
+        state: &mut LoadState,
+        file: Option<Rc<str>>,
+        src: I,
+    ) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut origin = LineOrigin {
+            file,
+            src_line_num: 0,
+            expansion: None,
         };
         // read each line of the program and process any macro definitions and expansions along the way
         for line in src {
-            src_line_num += 1;
-            let line = line.into();
-            let mut label: Option<String> = None;
-            let _expected_duration_cycles: Option<u64> = None;
-            let mut operation: Option<String> = None;
-            let mut operand: Option<String> = None;
-            // Manual parsing of the assembly statement: [label] [operation [operand [comment]]]
-            let trimmed = line.trim_start();
-            if trimmed.is_empty() || trimmed.starts_with('*') || trimmed.starts_with(';') {
-                // blank line or comment
+            origin.src_line_num += 1;
+            self.load_line(state, &origin, line.into())?;
+        }
+        // a REPT block must end in the file that it begins in
+        if let Some(rept) = state.rept.take() {
+            return Err(syntax_err_line!(
+                rept.origin.src_line_num,
+                "REPT without a matching ENDR"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Adds one line (from a source file or an expansion) to the program being loaded
+    fn load_line(
+        &self,
+        state: &mut LoadState,
+        origin: &LineOrigin,
+        line: String,
+    ) -> Result<(), Error> {
+        let src_line_num = origin.src_line_num;
+        let (label, operation, operand) = parse_statement(&line);
+        if state.mo.is_none() && state.rept.is_some() {
+            // we're collecting the lines of a REPT block
+            return self.collect_repeat_line(state, origin, line, operation.as_deref());
+        }
+        if state.mo.is_none() {
+            if let Some(op) = operation
+                .as_deref()
+                .filter(|op| IF_DIRECTIVES.contains(op) || matches!(*op, "ELSE" | "ENDC" | "ENDIF"))
+            {
+                return self.load_conditional(state, origin, line, label, op, operand);
+            }
+            if state.branch() == Branch::No {
+                // the line is in a block that isn't assembled; it's just a comment now
+                state.push(origin, format!("; {}", &line), None, None, None);
+                return Ok(());
+            }
+        }
+        if operation.as_deref() == Some(".MACRO") {
+            // found a ".macro" (begin macro defn) statement
+            if state.mo.is_some() {
+                return Err(syntax_err_line!(src_line_num, "illegal nested macro"));
+            }
+            // get the macro's name (case insensitive!) and any named parameters
+            let operand = operand
+                .as_deref()
+                .map(|s| s.split(';').next().unwrap_or("").trim())
+                .filter(|s| !s.is_empty())
+                .ok_or_else(|| syntax_err_line!(src_line_num, "missing macro name"))?;
+            let (name, params) = operand.split_once(' ').unwrap_or((operand, ""));
+            let name = name.to_ascii_uppercase();
+            // make sure the name hasn't already been used
+            if state.macros.contains_key(&name) {
+                return Err(syntax_err_line!(
+                    src_line_num,
+                    "duplicate definition of macro \"{}\"",
+                    &name
+                ));
+            }
+            // create a new Macro object and hold it in the mo Option
+            let mut m = Macro::new(&name);
+            for param in params.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                let (param, default) = match param.split_once('=') {
+                    Some((param, default)) => (param.trim(), Some(default.trim())),
+                    None => (param, None),
+                };
+                m.add_param(param, default)
+                    .map_err(|e| e.at_line(src_line_num))?;
+            }
+            state.mo = Some(m);
+            state.push(origin, format!("; {}", &line), None, None, None);
+            return Ok(());
+        }
+        if operation.as_deref() == Some(".ENDM") {
+            // found a ".endm" (end macro defn) statement; add completed macro
+            if let Some(m) = state.mo.take() {
+                state.macros.insert(m.name.clone(), m);
             } else {
-                let mut parts = line.split_whitespace();
-                let first = parts.next();
-                let second = parts.next();
-                let remains = parts.collect::<Vec<&str>>().join(" ");
-
-                if line.chars().next().map_or(false, |c| !c.is_whitespace()) {
-                    // line starts with a label
-                    label = first.map(|s| s.trim_end_matches(':').to_string());
-                    operation = second.map(|s| s.to_ascii_uppercase());
-                    operand = if remains.is_empty() {
-                        None
-                    } else {
-                        Some(remains)
-                    };
-                } else {
-                    // no label, line starts with whitespace
-                    operation = first.map(|s| s.to_ascii_uppercase());
-                    operand = if second.is_none() {
-                        None
-                    } else {
-                        let mut op_plus_remains = second.unwrap().to_string();
-                        if !remains.is_empty() {
-                            op_plus_remains.push(' ');
-                            op_plus_remains.push_str(&remains);
-                        }
-                        Some(op_plus_remains)
-                    };
-                }
+                return Err(syntax_err_line!(src_line_num, "invalid macro end"));
+            }
+            state.push(origin, format!("; {}", &line), None, None, None);
+            return Ok(());
+        }
+        if let Some(m) = state.mo.as_mut() {
+            // we're in a macro definition; add this line to the macro
+            m.add_line(&line).map_err(|e| e.at_line(src_line_num))?;
+            // also add this line as a comment in the program
+            state.push(origin, format!("; {}", &line), None, None, None);
+            return Ok(());
+        }
+        match operation.as_deref() {
+            Some("REPT") => {
+                // begin collecting the lines to repeat; any label is preserved on its own line
+                state.push_label(origin, label);
+                let count = self
+                    .eval_repeat_count(operand.as_deref())
+                    .map_err(|e| e.at_line(src_line_num))?;
+                state.push(origin, format!("; {}", &line), None, None, None);
+                state.rept = Some(Repeat {
+                    count,
+                    lines: Vec::new(),
+                    nesting: 0,
+                    origin: origin.clone(),
+                });
+                return Ok(());
+            }
+            Some("ENDR") => {
+                return Err(syntax_err_line!(
+                    src_line_num,
+                    "ENDR without a matching REPT"
+                ));
+            }
+            Some(".EXITM")
+                if origin
+                    .expansion
+                    .as_ref()
+                    .and_then(|e| e.innermost_macro())
+                    .is_none() =>
+            {
+                return Err(syntax_err_line!(src_line_num, ".EXITM outside of a macro"));
+            }
+            Some(".EXITM") if state.branch() == Branch::Yes => {
+                // the rest of the macro's lines are dropped (see expand); an .EXITM in a
+                // block that can't be decided yet is left for pre_build
+                state.push_label(origin, label);
+                state.push(origin, format!("; {}", &line), None, None, None);
+                state.exited = origin
+                    .expansion
+                    .as_ref()
+                    .and_then(|e| e.innermost_macro())
+                    .cloned();
+                return Ok(());
             }
-            if operation.as_deref() == Some(".MACRO") {
-                // found a ".macro" (begin macro defn) statement
-                if mo.is_some() {
-                    return Err(syntax_err_line!(src_line_num, "illegal nested macro"));
+            Some("INCLUDE" | "USE") => {
+                // found an include directive; preserve any label (on its own line) and then
+                // splice the lines of the included file into the program
+                state.push_label(origin, label);
+                let name = operand
+                    .as_deref()
+                    .and_then(include_file_name)
+                    .ok_or_else(|| syntax_err_line!(src_line_num, "missing file name"))?;
+                if state.branch() == Branch::Maybe
+                    && self.resolve_include(origin.file.as_deref(), name).is_none()
+                {
+                    // whether the file is needed is only known when the program is built
+                    // (see pre_build)
+                    state.push(origin, line, None, operation, operand);
+                    return Ok(());
                 }
-                // get the macro's name (case insensitive!)
-                if let Some(name) = operand.map(|s| s.to_ascii_uppercase()) {
-                    // make sure the name hasn't already been used
-                    if macros.contains_key(&name) {
+                state.push(origin, format!("; {}", &line), None, None, None);
+                return self.include(state, origin.file.as_deref(), name, src_line_num);
+            }
+            _ => {}
+        }
+        if let Some(m) = operation.as_ref().and_then(|s| state.macros.get(s)) {
+            // there is a macro to expand on this line
+            // collect any/all args for the macro and expand it
+            let args = operand.as_deref().map_or(Vec::new(), split_macro_args);
+            state.expansions += 1;
+            let lines = m
+                .hydrate_instance(&args, state.expansions)
+                .map_err(|e| e.at_line(src_line_num))?;
+            let name = m.name.clone();
+            // if there is also a label on this line; preserve it (on its own line) before expanding the macro
+            state.push_label(origin, label);
+            return self.expand(state, origin, Some(name), &lines, 1);
+        }
+        // the line doesn't include a macro instance, so just add it as a potential statement
+        let constant = match (&label, operation.as_deref(), &operand) {
+            (Some(_), Some("EQU"), Some(operand)) if state.branch() == Branch::Yes => {
+                self.eval_constant(state, operand)
+            }
+            _ => None,
+        };
+        state.push(origin, line, label.clone(), operation, operand);
+        if let (Some(label), Some(val)) = (label, constant) {
+            state.symbols.insert(label, LoadSymbol::Const(val));
+        }
+        Ok(())
+    }
+
+    /// Evaluates an expression that only depends on constants defined so far, or returns
+    /// None if it depends on anything else
+    fn eval_constant(&self, state: &LoadState, expr: &str) -> Option<u8u16> {
+        let expr = expr.split_whitespace().next()?;
+        let node = self.parser.str_to_value_node(expr).ok()?;
+        if node.uses_location() {
+            return None;
+        }
+        node.eval(state, 0, false).ok()
+    }
+
+    /// Loads a conditional assembly directive (IFxx/ELSE/ENDC). When the condition can be
+    /// decided now the directives become comments and so do the lines of the branch that
+    /// isn't assembled; otherwise the directives are left for pre_build.
+    fn load_conditional(
+        &self,
+        state: &mut LoadState,
+        origin: &LineOrigin,
+        line: String,
+        label: Option<String>,
+        op: &str,
+        operand: Option<String>,
+    ) -> Result<(), Error> {
+        let decided = match op {
+            "ELSE" | "ENDC" | "ENDIF" => match state.conds.last_mut() {
+                // unmatched directives are reported by pre_build
+                None => None,
+                Some(cond) if op == "ELSE" => {
+                    if cond.seen_else {
                         return Err(syntax_err_line!(
-                            format!("duplicate definition of macro \"{}\"", &name),
-                            src_line_num
+                            origin.src_line_num,
+                            "more than one ELSE for the same IF"
                         ));
                     }
-                    // create a new Macro object and hold it in the mo Option
-                    mo = Some(Macro::new(&name));
-                    add_line(
-                        &mut prog_lines,
-                        src_line_num,
-                        format!("; {}", &line),
-                        None,
-                        None,
-                        None,
-                    );
-                    continue;
-                }
-                // no name found for this macro
-                return Err(syntax_err_line!(src_line_num, "missing macro name"));
-            }
-            if operation.as_deref() == Some(".ENDM") {
-                // found a ".endm" (end macro defn) statement; add completed macro
-                if let Some(m) = mo {
-                    mo = None;
-                    macros.insert(m.name.clone(), m);
-                } else {
-                    return Err(syntax_err_line!(src_line_num, "invalid macro end"));
-                }
-                add_line(
-                    &mut prog_lines,
-                    src_line_num,
-                    format!("; {}", &line),
-                    None,
-                    None,
-                    None,
-                );
-                continue;
-            }
-            if let Some(mut m) = mo {
-                // we're in a macro definition; add this line to the macro
-                m.add_line(&line)
-                    .map_err(|e| line_err!(prog_lines.len(), e.kind, e.msg))?;
-                // also add this line as a comment in the program
-                add_line(
-                    &mut prog_lines,
-                    src_line_num,
-                    format!("; {}", &line),
-                    None,
-                    None,
-                    None,
-                );
-                mo = Some(m);
-                continue;
-            }
-            if let Some(m) = operation.as_ref().and_then(|s| macros.get(s)) {
-                // there is a macro to expand on this line
-                if label.is_some() {
-                    // there is also a label on this line; preserve it (on its own line) before expanding the macro
-                    add_line(
-                        &mut prog_lines,
-                        src_line_num,
-                        format!("{}:", label.as_ref().unwrap()),
-                        label,
-                        None,
-                        None,
-                    );
+                    cond.seen_else = true;
+                    cond.branch = match (cond.parent, cond.decided) {
+                        (Branch::No, _) | (_, Some(true)) => Branch::No,
+                        (parent, Some(false)) => parent,
+                        (_, None) => Branch::Maybe,
+                    };
+                    cond.decided
                 }
-                // add a comment with some metadata about this macro instance
-                add_line(
-                    &mut prog_lines,
-                    src_line_num,
-                    format!(
-                        "; Begin macro \"{}\" from line {} of original source",
-                        m.name, src_line_num
-                    ),
-                    None,
-                    None,
-                    None,
-                );
-                // collect any/all args for the macro
-                // let args = if let Some(a) = operand
-                //     .as_ref()
-                //     .and_then(|s| self.re_macro_args.captures(s.as_str()))
-                // {
-                //     a.get(0)
-                //         .unwrap()
-                //         .as_str()
-                //         .split(',')
-                //         .map(|s| s.trim())
-                //         .collect::<Vec<&str>>()
-                // } else {
-                // };
-                let args = operand
-                    .as_deref()
-                    .map_or(Vec::new(), |s| s.split(',').map(|s| s.trim()).collect());
-                // expand the macro and add the resulting lines to the program
-                m.hydrate_instance(args)?.into_iter().for_each(|s| {
-                    /*
-                    let (a, b, c) =
-                        self.re_statement
-                            .captures(&s)
-                            .map_or((None, None, None), |c| {
-                                (
-                                    c.get(1).map(|m| m.as_str().to_string()),
-                                    c.get(2).map(|m| m.as_str().to_ascii_uppercase()),
-                                    c.get(3).map(|m| m.as_str().to_string()),
-                                )
-                            });
-                    */
-                    let (a, b, c) = (None, None, None); // Macro expansion recursion needs similar parsing logic
-                    add_line(&mut prog_lines, src_line_num, s, a, b, c);
+                Some(_) => state.conds.pop().and_then(|c| c.decided),
+            },
+            _ => {
+                let parent = state.branch();
+                // the condition is only evaluated if the enclosing block is assembled
+                let decided = match parent {
+                    Branch::No => Some(false),
+                    _ => self
+                        .eval_condition(
+                            op,
+                            operand.as_deref().unwrap_or(""),
+                            &|name| match state.symbols.get(name) {
+                                _ if is_local_label(name) || is_temp_label(name) => None,
+                                Some(LoadSymbol::Maybe) => None,
+                                found => Some(found.is_some()),
+                            },
+                            state,
+                            None,
+                        )
+                        .unwrap_or(None),
+                };
+                let branch = match decided {
+                    Some(true) => parent,
+                    Some(false) => Branch::No,
+                    None => Branch::Maybe,
+                };
+                state.conds.push(LoadCond {
+                    origin: origin.clone(),
+                    op: op.to_string(),
+                    decided,
+                    parent,
+                    branch,
+                    seen_else: false,
                 });
-                continue;
-            }
-            // the line doesn't include a macro instance, so just add it as a potential statement
-            add_line(
-                &mut prog_lines,
-                src_line_num,
-                line.to_string(),
-                label,
-                operation,
-                operand,
+                decided
+            }
+        };
+        if decided.is_none() {
+            state.push(origin, line, label, Some(op.to_string()), operand);
+            return Ok(());
+        }
+        if label.is_some() {
+            return Err(syntax_err_line!(
+                origin.src_line_num,
+                "a label can't be defined on a {} line",
+                op
+            ));
+        }
+        state.push(origin, format!("; {}", &line), None, None, None);
+        Ok(())
+    }
+
+    /// Adds a line to the REPT block being collected. The block is expanded once its ENDR
+    /// is found (REPT blocks nested within it are expanded along with it).
+    fn collect_repeat_line(
+        &self,
+        state: &mut LoadState,
+        origin: &LineOrigin,
+        line: String,
+        operation: Option<&str>,
+    ) -> Result<(), Error> {
+        let Some(rept) = state.rept.as_mut() else {
+            return Ok(());
+        };
+        let end = match operation {
+            Some("REPT") => {
+                rept.nesting += 1;
+                false
+            }
+            Some("ENDR") if rept.nesting > 0 => {
+                rept.nesting -= 1;
+                false
+            }
+            Some("ENDR") => true,
+            _ => false,
+        };
+        if !end {
+            rept.lines.push(line.clone());
+        }
+        state.push(origin, format!("; {}", &line), None, None, None);
+        match state.rept.take_if(|_| end) {
+            Some(rept) => self.expand(state, &rept.origin, None, &rept.lines, rept.count),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds the lines of a macro (or `times` copies of the lines of a REPT block) to the
+    /// program. The lines can themselves invoke macros and contain REPT blocks.
+    fn expand(
+        &self,
+        state: &mut LoadState,
+        origin: &LineOrigin,
+        name: Option<String>,
+        lines: &[String],
+        times: usize,
+    ) -> Result<(), Error> {
+        let expansion = Rc::new(Expansion {
+            name,
+            parent: origin.expansion.clone(),
+        });
+        let what = match &expansion.name {
+            Some(name) => format!("macro \"{}\"", name),
+            None => format!("REPT block ({} times)", times),
+        };
+        let trace = expansion.to_string();
+        if expansion.depth() > MAX_EXPANSION_DEPTH {
+            return Err(syntax_err_line!(
+                origin.src_line_num,
+                "expansions nested more than {} deep (is {} recursive?)",
+                MAX_EXPANSION_DEPTH,
+                what
+            )
+            .with_trace(&trace));
+        }
+        // add a comment with some metadata about this macro instance
+        state.push(
+            origin,
+            format!(
+                "; Begin {} from line {} of original source",
+                what, origin.src_line_num
+            ),
+            None,
+            None,
+            None,
+        );
+        let inner = LineOrigin {
+            expansion: Some(expansion.clone()),
+            ..origin.clone()
+        };
+        'expanding: for _ in 0..times {
+            for line in lines {
+                self.load_line(state, &inner, line.clone())
+                    .map_err(|e| e.with_trace(&trace))?;
+                if state.exited.is_some() {
+                    // an .EXITM ended this expansion or one that it's nested in
+                    break 'expanding;
+                }
+            }
+        }
+        if state
+            .exited
+            .take_if(|e| Rc::ptr_eq(e, &expansion))
+            .is_some()
+        {
+            // the conditional blocks that the .EXITM was in end with the macro
+            let opened_within = |c: &LoadCond| {
+                let e = c.origin.expansion.as_ref();
+                e.is_some_and(|e| e.is_within(&expansion))
+            };
+            while state.conds.last().is_some_and(opened_within) {
+                state.conds.pop();
+            }
+        }
+        // a REPT block must end in the expansion that it begins in
+        if let Some(rept) = state.rept.take() {
+            return Err(
+                syntax_err_line!(rept.origin.src_line_num, "REPT without a matching ENDR")
+                    .with_trace(&trace),
             );
         }
-        // we've read through all the supplied source lines
-        if let Some(_m) = mo {
-            // a macro definition was begun but never ended
-            return Err(syntax_err!(format!(
-                "no end found for macro \"{}\"",
-                _m.name
-            )));
+        state.push(origin, format!("; End {}", what), None, None, None);
+        Ok(())
+    }
+
+    /// Evaluates the count of a REPT block. Since blocks are expanded while the program is
+    /// loaded (before any labels are defined) the count must be a constant expression.
+    fn eval_repeat_count(&self, operand: Option<&str>) -> Result<usize, Error> {
+        let operand = operand
+            .and_then(|s| s.split_whitespace().next())
+            .ok_or_else(|| syntax_err!("missing count for REPT"))?;
+        let node = self.parser.str_to_value_node(operand)?;
+        if node.negate {
+            return Err(syntax_err!("invalid count for REPT"));
         }
-        Ok(Program::new(prog_lines))
+        let val = node.eval(&ProgramLabels::new(), 0, false).map_err(|e| {
+            if e.kind == ErrorKind::Reference {
+                syntax_err!("the count for REPT must be a constant ({})", e.msg)
+            } else {
+                e
+            }
+        })?;
+        Ok(val.u16() as usize)
+    }
+
+    /// Loads the file named by an INCLUDE/USE directive on line `line_num` of `including`.
+    /// Errors found in the included file refer to that file; errors finding or reading it
+    /// refer to the line of the directive.
+    fn include(
+        &self,
+        state: &mut LoadState,
+        including: Option<&str>,
+        name: &str,
+        line_num: usize,
+    ) -> Result<(), Error> {
+        let path = self.resolve_include(including, name).ok_or_else(|| {
+            err!(
+                ErrorKind::IO,
+                None,
+                "unable to find include file \"{}\"",
+                name
+            )
+            .at_line(line_num)
+        })?;
+        if state.files.contains(&path) {
+            return Err(syntax_err_line!(
+                line_num,
+                "circular include of \"{}\" ({})",
+                path,
+                state.files.join(" -> ")
+            ));
+        }
+        let src = self.fs.read(&path).map_err(|e| e.at_line(line_num))?;
+        state.files.push(path.clone());
+        let res = self
+            .load_lines(state, Some(Rc::from(path.as_str())), src.lines())
+            .map_err(|e| e.in_file(&path));
+        state.files.pop();
+        res
+    }
+
+    /// Finds the file named by an include directive. The directory of the including
+    /// file is searched first, followed by the include paths.
+    fn resolve_include(&self, including: Option<&str>, name: &str) -> Option<String> {
+        let dir = including.map_or("", source::parent);
+        core::iter::once(dir)
+            .chain(self.include_paths.iter().map(String::as_str))
+            .map(|dir| source::join(dir, name))
+            .find(|path| self.fs.exists(path))
+    }
+
+    /// Load and build an assembly language program from the supplied source lines.
+    /// On success, the resulting Program is fully built and ready to be loaded.
+    pub fn assemble<I, T>(&self, src: I) -> Result<Program, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.assemble_source(None, src)
+    }
+
+    fn assemble_source<I, T>(&self, file: Option<&str>, src: I) -> Result<Program, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.diagnostics.borrow_mut().clear();
+        let res = self
+            .load_program_file(file, src)
+            .and_then(|mut program| self.assemble_program(&mut program).map(|_| program));
+        if let Err(e) = &res {
+            // make sure every failure is reflected in the diagnostics
+            let mut diagnostics = self.diagnostics.borrow_mut();
+            if diagnostics.is_empty() {
+                diagnostics.push(e.clone());
+            }
+        }
+        res
+    }
+
+    /// Returns (and clears) the errors reported by the most recent build.
+    /// A failed build returns the first of these as its Err result.
+    pub fn take_diagnostics(&self) -> Vec<Error> {
+        core::mem::take(&mut *self.diagnostics.borrow_mut())
+    }
+
+    /// Load and build an assembly language program from the file at `path`
+    /// (read from the assembler's file system; see set_fs).
+    pub fn assemble_file(&self, path: &str) -> Result<Program, Error> {
+        let path = source::normalize(path);
+        let res = match self.fs.read(&path) {
+            Ok(src) => self.assemble_source(Some(&path), src.lines()),
+            Err(e) => {
+                *self.diagnostics.borrow_mut() = vec![e.clone()];
+                Err(e)
+            }
+        };
+        res.map_err(|e| {
+            for d in self.diagnostics.borrow_mut().iter_mut() {
+                d.set_file(&path);
+            }
+            e.in_file(&path)
+        })
     }
 
     /// Attempt to load and build an assembly language program from a file with the given path.
-    pub fn assemble_from_file(&self, _path: &core::ffi::CStr) -> Result<Program, Error> {
-        /*
-                let src = io::BufReader::new(File::open(path)?)
-                    .lines()
-                    .collect::<Result<Vec<String>, io::Error>>()?;
-                let _function_start = self.clock_cycles;
-        program(src)?;
-                self.assemble_program(&mut program)?;
-                if unsafe { config::ARGS.write_files } {
-                    _ = program.write_output_files(path);
-                }
-                Ok(program)
-                */
-        Err(Error::new(
-            ErrorKind::General,
-            None,
-            "File assembly not supported on this platform",
-        ))
+    pub fn assemble_from_file(&self, path: &core::ffi::CStr) -> Result<Program, Error> {
+        let path = path
+            .to_str()
+            .map_err(|_| Error::new(ErrorKind::IO, None, "invalid path"))?;
+        self.assemble_file(path)
     }
 
     /// Performs the full build process to create a machine code program from the
     /// assembly language in the given Program object.
     ///
     fn assemble_program(&self, program: &mut Program) -> Result<(), Error> {
-        println!("Pre-processing...");
+        info!(target: Assembler, "Pre-processing...");
         self.pre_build(program)?;
         let mut pass_count = 0;
-        println!("Building...");
+        info!(target: Assembler, "Building...");
         loop {
             pass_count += 1;
-            println!("Build pass {}...", pass_count);
+            info!(target: Assembler, "Build pass {}...", pass_count);
             if self.build(program)? == 0 {
                 break;
             }
@@ -317,35 +984,88 @@ impl Assembler {
                 ));
             }
         }
-        println!("Post-processing...");
+        program.exec = self.entry_point(program)?;
+        info!(target: Assembler, "Post-processing...");
         #[cfg(not(target_os = "none"))]
         self.post_build(program)?;
-        println!("Build complete.");
-        if unsafe { config::ARGS.debug.load(core::sync::atomic::Ordering::Relaxed) } || unsafe { config::ARGS.trace.load(core::sync::atomic::Ordering::Relaxed) } {
-            // program.write_listing(&mut io::stdout())?;
+        info!(target: Assembler, "Build complete.");
+        if config::help_humans() {
+            let mut listing = String::new();
+            program.write_listing(&mut listing)?;
+            print!("{}", listing);
         }
         Ok(())
     }
     /// Perform the intial phase of the build process in which all labels are tracked and
     /// ObjectProducer instances are created for all instructions and directives.
     fn pre_build(&self, program: &mut Program) -> Result<(), Error> {
-        let mut pre_build_one_line = |line: &mut ProgramLine| -> Result<(), Error> {
+        let mut conds = Conditionals::default();
+        // true once the program has declared its direct page with SETDP
+        let mut dp_declared = false;
+        let mut pre_build_one_line = |index: usize, line: &mut ProgramLine| -> Result<(), Error> {
             line.addr = program.addr;
+            // the line determines which value of each SET symbol is visible
+            program.labels.set_line(index);
+            // the lines that follow an .EXITM in the same macro expansion aren't assembled
+            conds.exiting = conds
+                .exited
+                .as_ref()
+                .is_some_and(|exited| line.expansion.as_ref().is_some_and(|e| e.is_within(exited)));
+            if !conds.exiting {
+                conds.exited = None;
+            }
+            // conditional assembly directives decide whether the lines that follow are assembled
+            if self.process_conditional_line(&mut conds, &program.labels, index, line)? {
+                return Ok(());
+            }
+            if !conds.assembling() {
+                // the line is in a block that isn't being assembled; it's just a comment now
+                line.label = None;
+                line.operation = None;
+                line.operand = None;
+                return Ok(());
+            }
+            if line.get_operation() == ".EXITM" {
+                conds.exited = line
+                    .expansion
+                    .as_ref()
+                    .and_then(|e| e.innermost_macro())
+                    .cloned();
+                return Ok(());
+            }
+            // an INCLUDE is only left in the program if its file couldn't be found while
+            // loading, in a block that couldn't be decided then
+            if matches!(line.get_operation(), "INCLUDE" | "USE") {
+                let name = line.operand.as_deref().and_then(include_file_name);
+                return Err(err!(
+                    ErrorKind::IO,
+                    None,
+                    "unable to find include file \"{}\"",
+                    name.unwrap_or_default()
+                ));
+            }
+            // SET (re)defines a symbol whose value can change from line to line
+            if line.get_operation() == "SET" {
+                let val = self.eval_set(&program.labels, line)?;
+                return program.labels.set_symbol(line.get_label(), index, val);
+            }
             // Does the line contain a label?
             if let Some(label) = line.label.as_ref() {
                 program
                     .labels
-                    .new_definition(label, line.src_line_num, line.addr, None)?;
+                    .new_definition(label, line.src_line_num, line.addr, None)
+                    .map_err(|e| e.with_source(1, &line.src))?;
             }
+            // SETDP declares the direct page for the lines that follow
+            if line.get_operation() == "SETDP" {
+                program.dp = self.eval_setdp(&program.labels, line)?;
+                dp_declared = true;
+            }
+            line.dp = program.dp;
             // Does the line contain an operation (or assembler directive)?
             if line.operation.is_some() {
                 // parse the operation and potentially create the corresponding binary object
-                self.process_op_line(
-                    &mut program.segs,
-                    &mut program.labels,
-                    line,
-                    program.dp_dirty,
-                )?;
+                self.process_op_line(&mut program.segs, &mut program.labels, line)?;
                 // get any/all object address and size info
                 if let Some(obj) = line.obj.as_ref() {
                     // check to see if this object has a static address assignment
@@ -355,9 +1075,11 @@ impl Assembler {
                     }
                     line.obj_size = obj.current_size(program.addr, &program.labels)?;
                     // keep track of any potential DP register changes
-                    // (knowing this allows for automatic use of direct mode addressing)
-                    if obj.changes_dp() {
-                        program.dp_dirty = true;
+                    // (knowing this allows for automatic use of direct mode addressing).
+                    // Once the program declares its direct page with SETDP, keeping it
+                    // accurate is up to the programmer.
+                    if obj.changes_dp() && !dp_declared {
+                        program.dp = None;
                     }
                     let (new_addr, _) = program.addr.overflowing_add(line.obj_size);
                     // reserve space for the object
@@ -367,40 +1089,207 @@ impl Assembler {
                         program.labels.set_address(label, line.addr)?;
                     }
                 }
-                /*
-                // is it a result line? (i.e. lines of the form ";! <reg|addr> = <val>")
-                if let Some(c) = self.re_result_line.captures(line.src.as_str()) {
-                    if c.get(1).is_none() || c.get(2).is_none() {
-                        return Err(syntax_err!("malformed test criterion"));
-                    }
-                    program
-                        .results
-                        .push(TestCriterion::new(line.src_line_num, &c[1], &c[2]));
-                    return Ok(());
-                }
-                */
-                /*
-                // ...or is it just a whole line of comments or whitespace?
-                if self.re_comment_or_blank_line.is_match(line.src.as_str()) {
-                    // nothing to do; move on to the next line
-                    return Ok(());
-                }
-                */
-                if line.src.trim().is_empty()
-                    || line.src.trim().starts_with('*')
-                    || line.src.trim().starts_with(';')
-                {
-                    return Ok(());
-                }
-                // theoretically, we shouldn't be able to reach this point
-                unreachable!()
+                return Ok(());
+            }
+            // is it a result line? (i.e. lines of the form ";! <reg|addr> = <val>")
+            #[cfg(not(target_os = "none"))]
+            if let Some(tc) = TestCriterion::from_line(line.src_line_num, &line.src)? {
+                program.results.push(tc);
             }
             Ok(())
         };
-        for line in program.lines.iter_mut() {
-            pre_build_one_line(line).map_err(|e| line_err!(line.src_line_num, e.kind, e.msg))?;
+        for (index, line) in program.lines.iter_mut().enumerate() {
+            if let Err(e) = pre_build_one_line(index, line) {
+                self.diagnose(line, e);
+            }
         }
-        Ok(())
+        // every conditional block must be closed
+        for block in conds.blocks.iter() {
+            let line = &program.lines[block.index];
+            self.diagnose(
+                line,
+                syntax_err!("{} without a matching ENDC", line.get_operation())
+                    .with_source(line.columns().0, &line.src),
+            );
+        }
+        self.check_diagnostics()
+    }
+    /// Process a line that contains a conditional assembly directive
+    /// (IFEQ/IFNE/IFGT/IFGE/IFLT/IFLE/IFDEF/IFNDEF/ELSE/ENDC) that couldn't be decided while
+    /// the program was loaded (see load_conditional).
+    /// Conditions are evaluated when the line is reached so they can only refer to labels
+    /// defined earlier in the program.
+    ///
+    /// Results:
+    ///  - ```Ok(true)``` line was processed as a conditional directive
+    ///  - ```Ok(false)``` line is not a conditional directive
+    ///  - ```Err(Error)``` line is a conditional directive but is invalid
+    ///
+    fn process_conditional_line(
+        &self,
+        conds: &mut Conditionals,
+        labels: &ProgramLabels,
+        index: usize,
+        line: &ProgramLine,
+    ) -> Result<bool, Error> {
+        let op = line.get_operation();
+        match op {
+            "IFEQ" | "IFNE" | "IFGT" | "IFGE" | "IFLT" | "IFLE" | "IFDEF" | "IFNDEF" | "IFB"
+            | "IFNB" | "IFSTR" | "IFNUM" => {
+                let parent_active = conds.assembling();
+                // the condition is only evaluated if the enclosing block is being assembled
+                let condition = if parent_active {
+                    self.eval_condition(
+                        op,
+                        line.get_operand(),
+                        &|name| Some(labels.is_defined(name)),
+                        labels,
+                        Some(line.addr),
+                    )
+                    .map(|decided| decided.unwrap_or(false))
+                } else {
+                    Ok(false)
+                };
+                // the block is opened even if the condition is invalid so that ELSE/ENDC still match up
+                conds.blocks.push(CondBlock {
+                    index,
+                    parent_active,
+                    active: parent_active && *condition.as_ref().unwrap_or(&false),
+                    seen_else: false,
+                });
+                condition?;
+            }
+            "ELSE" => {
+                let block = conds
+                    .blocks
+                    .last_mut()
+                    .ok_or_else(|| syntax_err!("ELSE without a matching IF"))?;
+                if block.seen_else {
+                    return Err(syntax_err!("more than one ELSE for the same IF"));
+                }
+                block.seen_else = true;
+                block.active = block.parent_active && !block.active;
+            }
+            "ENDC" | "ENDIF" => {
+                conds
+                    .blocks
+                    .pop()
+                    .ok_or_else(|| syntax_err!("{} without a matching IF", op))?;
+            }
+            _ => return Ok(false),
+        }
+        if line.label.is_some() {
+            return Err(syntax_err!("a label can't be defined on a {} line", op)
+                .with_source(1, &line.src));
+        }
+        Ok(true)
+    }
+    /// Evaluates the operand of a SET directive. Only symbols defined on earlier lines
+    /// can be used (including the symbol being set, e.g. `COUNT SET COUNT+1`).
+    fn eval_set(&self, labels: &ProgramLabels, line: &ProgramLine) -> Result<u8u16, Error> {
+        if line.label.is_none() {
+            return Err(syntax_err!("SET requires a label"));
+        }
+        if line.operand.is_none() {
+            return Err(syntax_err!("no value provided for SET"));
+        }
+        let node = self.parser.str_to_value_node(line.get_operand())?;
+        node.eval(labels, line.addr, false).map_err(|e| {
+            if e.kind == ErrorKind::Reference {
+                syntax_err!("SET can't depend on a forward reference ({})", e.msg)
+            } else {
+                e
+            }
+        })
+    }
+    /// Evaluates the operand of a SETDP directive. The result is the direct page to assume
+    /// for the lines that follow or None if the operand is "OFF" (or missing), in which
+    /// case direct mode is only used when the programmer asks for it with "<".
+    fn eval_setdp(&self, labels: &ProgramLabels, line: &ProgramLine) -> Result<Option<u8>, Error> {
+        let operand = line.get_operand().split_whitespace().next().unwrap_or("");
+        if operand.is_empty() || operand.eq_ignore_ascii_case("OFF") {
+            return Ok(None);
+        }
+        let node = self.parser.str_to_value_node(operand)?;
+        let val = node.eval(labels, line.addr, false).map_err(|e| {
+            if e.kind == ErrorKind::Reference {
+                syntax_err!("SETDP can't depend on a forward reference ({})", e.msg)
+            } else {
+                e
+            }
+        })?;
+        if val.u16() > 0xff {
+            return Err(syntax_err!(
+                "invalid direct page ${:X} (must be between 0 and $FF)",
+                val.u16()
+            ));
+        }
+        Ok(Some(val.u8()))
+    }
+    /// Evaluates the condition of an IFxx directive. `defined` says whether a label has
+    /// been defined, or None if that isn't known yet. While loading (when there's no `addr`
+    /// yet) a condition that depends on something that isn't known yet is None.
+    fn eval_condition(
+        &self,
+        op: &str,
+        operand: &str,
+        defined: &dyn Fn(&str) -> Option<bool>,
+        labels: &dyn LabelResolver,
+        addr: Option<u16>,
+    ) -> Result<Option<bool>, Error> {
+        // these test the text of the operand (typically a macro arg) rather than its value
+        let text = operand
+            .split_whitespace()
+            .next()
+            .filter(|s| !s.starts_with(';'));
+        match op {
+            "IFB" => return Ok(Some(text.is_none())),
+            "IFNB" => return Ok(Some(text.is_some())),
+            "IFSTR" => return Ok(Some(text.is_some_and(|s| s.starts_with('"')))),
+            "IFNUM" => return Ok(Some(text.is_some_and(is_number))),
+            _ => {}
+        }
+        let operand = operand
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| syntax_err!("missing condition for {}", op))?;
+        match op {
+            "IFDEF" => return Ok(defined(operand)),
+            "IFNDEF" => return Ok(defined(operand).map(|d| !d)),
+            _ => {}
+        }
+        let loading = addr.is_none();
+        let node = self.parser.str_to_value_node(operand)?;
+        if loading && node.uses_location() {
+            return Ok(None);
+        }
+        let val = match node.eval(labels, addr.unwrap_or(0), false) {
+            Ok(val) => val,
+            // anything wrong is reported when the program is built
+            Err(_) if loading => return Ok(None),
+            Err(e) if e.kind == ErrorKind::Reference => {
+                return Err(syntax_err!(
+                    "{} can't depend on a forward reference ({})",
+                    op,
+                    e.msg
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        // values are unsigned unless the expression is negated (e.g. "-3")
+        let n = if node.negate {
+            val.i16() as i32
+        } else {
+            val.u16() as i32
+        };
+        Ok(Some(match op {
+            "IFEQ" => n == 0,
+            "IFNE" => n != 0,
+            "IFGT" => n > 0,
+            "IFGE" => n >= 0,
+            "IFLT" => n < 0,
+            _ => n <= 0, // IFLE
+        }))
     }
     /// Perform the main phase of the build process. This is called repeatedly until no
     /// more changes occur. These changes represent movement of objects and labels as
@@ -410,17 +1299,14 @@ impl Assembler {
         // reset the address of the program (this is often referred to as "current location")
         program.addr = 0u16;
         // process for each line
-        let mut build_one_line = |line: &mut ProgramLine| -> Result<(), Error> {
+        let mut build_one_line = |index: usize, line: &mut ProgramLine| -> Result<(), Error> {
+            program.labels.set_line(index);
             let mut expected_addr = program.addr;
             // assign the expected address to this line; it may change below
             line.addr = expected_addr;
             if let Some(op) = line.obj.as_mut() {
                 // try to build the object
-                let res = op.build(expected_addr, &program.labels, program.dp_dirty);
-                if let Err(e) = res {
-                    return Err(line_err!(line.src_line_num, e.kind, e.msg.as_str()));
-                }
-                let bob = res.unwrap();
+                let bob = op.build(expected_addr, &program.labels, line.dp)?;
                 // set our next program address based on the binary object we just built
                 let (new_addr, _) = bob.addr.overflowing_add(bob.size);
                 program.addr = new_addr;
@@ -444,7 +1330,8 @@ impl Assembler {
                 }
             }
             // if this line has a label, make sure its address is up to date
-            if let Some(label) = line.label.as_ref() {
+            // (SET symbols are values rather than addresses so they're skipped)
+            if let Some(label) = line.label.as_ref().filter(|_| line.get_operation() != "SET") {
                 // update the label's address to match the line's address
                 let old_addr = program.labels.set_address(label, line.addr)?;
                 if old_addr != line.addr {
@@ -458,14 +1345,40 @@ impl Assembler {
             }
             Ok(())
         };
-        for line in program.lines.iter_mut() {
-            if let Err(e) = build_one_line(line) {
-                return Err(line_err!(line.src_line_num, e.kind, e.msg));
+        for (index, line) in program.lines.iter_mut().enumerate() {
+            if let Err(e) = build_one_line(index, line) {
+                self.diagnose(line, e);
             }
         }
+        self.check_diagnostics()?;
         changes += program.labels.eval_all_nodes()?;
         Ok(changes)
     }
+    /// Returns the entry point given by the operand of the program's END directive (if any).
+    /// The operand is evaluated once the build is complete so it can refer to any label.
+    fn entry_point(&self, program: &mut Program) -> Result<Option<u16>, Error> {
+        let Some(index) = program
+            .lines
+            .iter()
+            .position(|l| l.get_operation() == "END" && l.operand.is_some())
+        else {
+            return Ok(None);
+        };
+        // local labels in the operand are resolved in the scope of the END line
+        program.labels.set_line(index);
+        let line = &program.lines[index];
+        let exec = self
+            .parser
+            .str_to_value_node(line.get_operand())
+            .and_then(|node| node.eval(&program.labels, line.addr, false));
+        match exec {
+            Ok(exec) => Ok(Some(exec.u16())),
+            Err(e) => {
+                self.diagnose(line, e);
+                self.check_diagnostics().map(|_| None)
+            }
+        }
+    }
     /// Perform final phase of the build process. For now, this only entails parsing
     /// any test criteria that the program contains.
     #[cfg(not(target_os = "none"))]
@@ -473,10 +1386,49 @@ impl Assembler {
         for tc in &mut program.results {
             // Each TestCriterion must be parsed AFTER build is complete so that all labels can be resolved.
             if let Err(e) = self.parser.parse_test_criterion(tc, &program.labels) {
-                return Err(line_err!(tc.line_number, e.kind, e.msg));
+                match program
+                    .lines
+                    .iter()
+                    .find(|l| l.src_line_num == tc.line_number)
+                {
+                    Some(line) => self.diagnose(line, e.with_source(0, &line.src)),
+                    None => self.diagnose_unlocated(e.at_line(tc.line_number)),
+                }
             }
         }
-        Ok(())
+        self.check_diagnostics()
+    }
+    /// Records an error for the given line so that the build can carry on and report
+    /// any other errors in the program. The error is located at the line's operand
+    /// (or operation) unless it already knows its column.
+    fn diagnose(&self, line: &ProgramLine, e: Error) {
+        let (op_column, operand_column) = line.columns();
+        let column = if operand_column > 0 {
+            operand_column
+        } else {
+            op_column
+        };
+        let mut e = e.at_line(line.src_line_num).with_source(column, &line.src);
+        if let Some(file) = &line.file {
+            e.set_file(file);
+        }
+        if let Some(expansion) = &line.expansion {
+            e = e.with_trace(&expansion.to_string());
+        }
+        self.diagnose_unlocated(e);
+    }
+    fn diagnose_unlocated(&self, e: Error) {
+        let mut diagnostics = self.diagnostics.borrow_mut();
+        if diagnostics.len() < MAX_DIAGNOSTICS {
+            diagnostics.push(e);
+        }
+    }
+    /// Returns the first diagnostic (if any) as an Err
+    fn check_diagnostics(&self) -> Result<(), Error> {
+        match self.diagnostics.borrow().first() {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
     }
     /// Process a program line that looks like an operation. The line must be a statement
     /// that contains either an assembler directive or an assembly language instruction.
@@ -487,7 +1439,6 @@ impl Assembler {
         segs: &mut ProgramSegments,
         labels: &mut ProgramLabels,
         line: &mut ProgramLine,
-        dp_dirty: bool,
     ) -> Result<(), Error> {
         // first see if this is actually an assembler directive
         if self.process_directive_line(segs, labels, line)? {
@@ -497,7 +1448,8 @@ impl Assembler {
         // it's not a directive; see if it's an instruction
         // using ok_or_else to avoid executing the format! every time this next line is executed.
         let desc = instructions::name_to_descriptor(line.get_operation()).ok_or_else(|| {
-            syntax_err!(format!("Invalid operation: \"{}\"", line.get_operation()))
+            syntax_err!("Invalid operation: \"{}\"", line.get_operation())
+                .with_source(line.columns().0, &line.src)
         })?;
         let od = if line.operand.is_none() || desc.is_inherent() {
             // the instruction uses only inherent addressing or there is no operand
@@ -507,7 +1459,7 @@ impl Assembler {
             self.parser.parse_operand(line.get_operand())?
         };
         line.obj = Some(Box::new(Instruction::try_new(
-            desc, od, line.addr, labels, dp_dirty,
+            desc, od, line.addr, labels, line.dp,
         )?));
         Ok(())
     }
@@ -570,52 +1522,98 @@ impl Assembler {
                 // all evaluate to either byte or word depending on operation
                 // todo: does anything weird happen if ValueNode contains location reference?
                 let mut nodes = Vec::new();
-                for val in line.get_operand().split(',') {
+                // (a comment after the data may contain commas too)
+                for val in split_values(strip_comment(line.get_operand())) {
+                    if val.trim_start().starts_with('"') {
+                        // a quoted string is a value for each of its chars
+                        let s = delimited_string(val)
+                            .ok_or_else(|| syntax_err!("unterminated string in {}", val.trim()))?;
+                        for c in s.chars() {
+                            nodes.push(self.parser.str_to_value_node(&format!("'{}", c))?);
+                        }
+                        continue;
+                    }
                     let node = self.parser.str_to_value_node(val)?;
                     nodes.push(node);
                 }
                 line.obj = Some(Box::new(Fxb::new(nodes, is_bytes)));
             }
-            "FCC" => {
+            "FCC" | "FCS" | "FCN" | "FCZ" => {
                 // The string following an FCC directive can be delimited by any non-whitespace char
                 // The first non-whitespace char defines the delimiter.
                 // The next occurance of that char marks the end of the string.
                 // Any characters thereafter are ignored.
+                let op = line.get_operation();
                 if line.operand.is_none() {
-                    return Err(syntax_err!("no string provided for FCC"));
+                    return Err(syntax_err!("no string provided for {}", op));
                 }
-                let mut t: Option<char> = None;
-                let mut s = String::with_capacity(line.get_operand().len());
-                for c in line.get_operand().chars() {
-                    if t.is_none() {
-                        if c.is_ascii_whitespace() {
-                            continue;
-                        }
-                        t = Some(c);
-                        continue;
-                    }
-                    if t == Some(c) {
-                        line.obj = Some(Box::new(Fcc::new(&s)));
-                        break;
-                    }
-                    s.push(c)
+                let s = delimited_string(line.get_operand())
+                    .ok_or_else(|| syntax_err!("invalid string provided for {}", op))?;
+                let fcc = match op {
+                    "FCC" => Fcc::new(s),
+                    "FCS" => Fcc::fcs(s),
+                    _ => Fcc::fcn(s),
+                };
+                line.obj = Some(Box::new(fcc));
+            }
+            "RMB" | "RMD" => {
+                if line.operand.is_none() {
+                    return Err(syntax_err!("no size specified for {}", line.get_operation()));
                 }
-                if line.obj.is_none() {
-                    return Err(syntax_err!("invalid string provided for FCC"));
+                let is_bytes = line.get_operation() == "RMB";
+                let node = self.parser.str_to_value_node(line.get_operand())?;
+                line.obj = Some(Box::new(Rmb::new(node, is_bytes)));
+            }
+            "ZMB" | "BSZ" => {
+                if line.operand.is_none() {
+                    return Err(syntax_err!("no size specified for {}", line.get_operation()));
                 }
+                let count = self.parser.str_to_value_node(line.get_operand())?;
+                line.obj = Some(Box::new(Fill::new(None, count)));
+            }
+            "FILL" => {
+                // FILL value,count
+                let (val, count) = strip_comment(line.get_operand())
+                    .split_once(',')
+                    .ok_or_else(|| syntax_err!("FILL requires a value and a count"))?;
+                let val = self.parser.str_to_value_node(val)?;
+                let count = self.parser.str_to_value_node(count)?;
+                line.obj = Some(Box::new(Fill::new(Some(val), count)));
             }
-            "RMB" => {
+            "ALIGN" => {
+                // ALIGN alignment[,fill value]
                 if line.operand.is_none() {
-                    return Err(syntax_err!("no size specified for RMB"));
+                    return Err(syntax_err!("no alignment specified for ALIGN"));
                 }
-                let node = self.parser.str_to_value_node(line.get_operand())?;
-                line.obj = Some(Box::new(Rmb::new(node)));
+                let operand = strip_comment(line.get_operand());
+                let (alignment, fill) = match operand.split_once(',') {
+                    Some((alignment, fill)) => {
+                        (alignment, Some(self.parser.str_to_value_node(fill)?))
+                    }
+                    None => (operand, None),
+                };
+                let alignment = self.parser.str_to_value_node(alignment)?;
+                line.obj = Some(Box::new(Align::new(alignment, fill)));
+            }
+            "NAM" | "TTL" | "PAG" | "PAGE" | "OPT" => {
+                // listing control; these don't affect the machine code
+                line.obj = Some(Box::new(ListingControl::new(
+                    line.get_operation(),
+                    line.get_operand(),
+                )));
+            }
+            "SET" => {
+                // the symbol was already (re)defined in pre_build; nothing else to do
+            }
+            "SETDP" => {
+                // the direct page was already set in pre_build; nothing else to do
             }
             "END" => {
+                // the optional operand is the entry point; check its syntax now but it's
+                // evaluated after the build (see entry_point) since it may be a forward reference
                 if line.operand.is_some() {
-                    return Err(syntax_err!("invalid operand for END"));
+                    self.parser.str_to_value_node(line.get_operand())?;
                 }
-                // do nothing...
             }
             _ => return Ok(false),
         }
@@ -0,0 +1,53 @@
+//! Emulates a Becker port: a simple byte pipe at $FF41-$FF42 that emulators and FPGA
+//! CoCos use to talk DriveWire without the timing constraints of a serial port.
+//! - $FF41 status: bit 1 is set when a byte is waiting to be read
+//! - $FF42 data: reads return the waiting byte and writes send one
+use super::*;
+use crate::serial::SerialBackend;
+
+pub const STATUS_ADDR: u16 = 0xff41;
+pub const DATA_ADDR: u16 = 0xff42;
+
+// status register bits
+const READ_READY: u8 = 0x02;
+
+pub struct BeckerPort {
+    backend: Box<dyn SerialBackend>,
+    // byte that has been taken from the backend but not read yet
+    waiting: Option<u8>,
+}
+
+impl BeckerPort {
+    pub fn new(backend: Box<dyn SerialBackend>) -> Self {
+        BeckerPort {
+            backend,
+            waiting: None,
+        }
+    }
+    pub fn owns_address(&self, addr: u16) -> bool {
+        addr == STATUS_ADDR || addr == DATA_ADDR
+    }
+    pub fn read(&mut self, addr: u16) -> u8 {
+        if self.waiting.is_none() {
+            self.waiting = self.backend.read_byte();
+        }
+        match addr {
+            STATUS_ADDR if self.waiting.is_some() => READ_READY,
+            STATUS_ADDR => 0,
+            // reading with nothing waiting returns 0
+            _ => self.waiting.take().unwrap_or(0),
+        }
+    }
+    pub fn write(&mut self, addr: u16, byte: u8) {
+        if addr == DATA_ADDR {
+            self.backend.write_byte(byte);
+        }
+    }
+}
+
+impl Core {
+    /// Adds a Becker port connected to `backend`, replacing any previous one.
+    pub fn attach_becker(&mut self, backend: Box<dyn SerialBackend>) {
+        self.becker = Some(core::cell::RefCell::new(BeckerPort::new(backend)));
+    }
+}
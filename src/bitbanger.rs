@@ -0,0 +1,178 @@
+//! Emulates the CoCo's built-in serial port (the "bit banger").
+//!
+//! There's no UART behind the 4-pin DIN connector. Software toggles PIA1 side A bit 1
+//! to transmit and polls PIA1 side B bit 0 to receive. BASIC also reads that bit as the
+//! printer's busy line before `LLIST` or `PRINT #-2` sends each byte.
+//!
+//! [BitBanger] watches the transmit line's transitions against the CPU's clock cycles
+//! and samples the middle of each bit to rebuild the bytes being sent (8 data bits,
+//! no parity, 1 stop bit). In the other direction it drives the receive line with the
+//! start, data and stop bits of each byte it receives.
+//! Bytes go to and come from a [SerialBackend] such as a [Printer](crate::serial::printer::Printer).
+use super::*;
+use crate::runtime::CPU_HZ;
+use crate::serial::SerialBackend;
+
+/// The slowest and fastest baud rates the bit banger decodes
+/// (BASIC's printer rates up to DriveWire's 57600 and 115200)
+pub const MIN_BAUD: u32 = 600;
+pub const MAX_BAUD: u32 = 115200;
+
+/// A byte being rebuilt from the transmit line
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    // cycle at which the start bit began
+    start: u64,
+    // number of bits sampled so far (including the start bit)
+    count: u64,
+    data: u8,
+}
+
+pub struct BitBanger {
+    baud: u32,
+    // the transmit line's level since its last transition
+    tx_level: bool,
+    tx_frame: Option<Frame>,
+    // the byte on the receive line and the cycle at which its start bit began
+    rx_frame: Option<(u8, u64)>,
+    rx_queue: VecDeque<u8>,
+    // level of the receive line when nothing is being received
+    idle_marking: bool,
+    framing_errors: usize,
+    backend: Option<Box<dyn SerialBackend>>,
+}
+
+impl BitBanger {
+    /// Creates a bit banger that decodes and sends at `baud` (600 to 115200).
+    pub fn new(baud: u32) -> Result<Self, Error> {
+        if !(MIN_BAUD..=MAX_BAUD).contains(&baud) {
+            return Err(general_err!(
+                "bit banger baud rate must be {} to {}",
+                MIN_BAUD,
+                MAX_BAUD
+            ));
+        }
+        Ok(BitBanger {
+            baud,
+            // the transmit line idles marking (high) and PIA outputs start high
+            tx_level: true,
+            tx_frame: None,
+            rx_frame: None,
+            rx_queue: VecDeque::new(),
+            idle_marking: false,
+            framing_errors: 0,
+            backend: None,
+        })
+    }
+    pub fn baud(&self) -> u32 {
+        self.baud
+    }
+    /// Connects the port to a backend, replacing any previous one.
+    pub fn set_backend(&mut self, backend: Box<dyn SerialBackend>) {
+        self.backend = Some(backend);
+    }
+    /// Disconnects the port from its backend.
+    pub fn take_backend(&mut self) -> Option<Box<dyn SerialBackend>> {
+        self.backend.take()
+    }
+    /// Sets the level of the receive line between bytes.
+    /// It's low by default, which BASIC reads as a printer that's ready for data.
+    /// Terminal programs wait for a start bit and need it to be marking (high) instead.
+    pub fn set_idle_marking(&mut self, marking: bool) {
+        self.idle_marking = marking;
+    }
+    /// Queues bytes to be sent to the CoCo ahead of anything from the backend.
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.rx_queue.extend(bytes);
+    }
+    /// The number of bytes whose stop bit wasn't where it should be.
+    /// Usually means the software's baud rate doesn't match.
+    pub fn framing_errors(&self) -> usize {
+        self.framing_errors
+    }
+    /// Samples the transmit line, which has been at tx_level since its last transition,
+    /// for every mid-bit time up to `now`.
+    fn sample_tx(&mut self, now: u64) {
+        while let Some(frame) = self.tx_frame.as_mut() {
+            // the middle of the bit
+            let at = frame.start + (2 * frame.count + 1) * CPU_HZ / (2 * self.baud as u64);
+            if at > now {
+                break;
+            }
+            frame.count += 1;
+            match frame.count {
+                // a start bit that doesn't last to its middle is a glitch
+                1 if self.tx_level => self.tx_frame = None,
+                1 => {}
+                2..=9 => frame.data |= (self.tx_level as u8) << (frame.count - 2),
+                _ => {
+                    let byte = frame.data;
+                    self.tx_frame = None;
+                    self.end_frame(byte);
+                }
+            }
+        }
+    }
+    /// Delivers a byte once its stop bit has been sampled
+    fn end_frame(&mut self, byte: u8) {
+        if self.tx_level {
+            verbose_println!(target: Pia, "bit banger sent {:02X}", byte);
+            if let Some(backend) = self.backend.as_mut() {
+                backend.write_byte(byte);
+            }
+        } else {
+            verbose_println!(target: Pia, "bit banger framing error");
+            self.framing_errors += 1;
+        }
+    }
+    /// Records the level of the transmit line (PIA1 side A bit 1) at `now`.
+    pub fn set_tx(&mut self, level: bool, now: u64) {
+        if level == self.tx_level {
+            return;
+        }
+        self.sample_tx(now);
+        if !level && self.tx_frame.is_none() {
+            // a falling edge while idle is a start bit
+            self.tx_frame = Some(Frame {
+                start: now,
+                count: 0,
+                data: 0,
+            });
+        }
+        self.tx_level = level;
+    }
+    /// The level of the receive line (PIA1 side B bit 0) at `now`.
+    /// A byte starts when the line is first looked at after the previous byte ends,
+    /// so bytes aren't lost while the CoCo isn't listening.
+    pub fn rx_level(&mut self, now: u64) -> bool {
+        loop {
+            if let Some((byte, start)) = self.rx_frame {
+                match now.saturating_sub(start) * self.baud as u64 / CPU_HZ {
+                    0 => return false,
+                    bit @ 1..=8 => return byte >> (bit - 1) & 1 == 1,
+                    9 => return true,
+                    _ => self.rx_frame = None,
+                }
+            }
+            let next = self
+                .rx_queue
+                .pop_front()
+                .or_else(|| self.backend.as_mut().and_then(|b| b.read_byte()));
+            match next {
+                Some(byte) => self.rx_frame = Some((byte, now)),
+                None => return self.idle_marking,
+            }
+        }
+    }
+    /// Finishes any byte whose stop bit has passed by `now`.
+    pub fn update(&mut self, now: u64) {
+        self.sample_tx(now);
+    }
+}
+
+impl Core {
+    /// Connects the bit banger to PIA1, replacing any previous one.
+    pub fn attach_bitbanger(&mut self, bitbanger: BitBanger) {
+        self.bitbanger = Some(core::cell::RefCell::new(bitbanger));
+    }
+}
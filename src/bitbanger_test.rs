@@ -0,0 +1,284 @@
+use crate::bitbanger::BitBanger;
+use crate::cpu_test::create_core;
+use crate::runtime::CPU_HZ;
+use crate::serial::printer::Printer;
+use crate::serial::Pipe;
+use crate::*;
+
+fn create_bitbanger(baud: u32) -> (BitBanger, Pipe) {
+    let mut bb = BitBanger::new(baud).unwrap();
+    let pipe = Pipe::new();
+    bb.set_backend(Box::new(pipe.clone()));
+    (bb, pipe)
+}
+
+/// Drives the transmit line with one 8N1 frame starting at `start` and returns the
+/// cycle at which the stop bit ends
+fn send_frame(bb: &mut BitBanger, byte: u8, start: u64) -> u64 {
+    let bit = |n: u64| start + n * CPU_HZ / bb.baud() as u64;
+    let (data, stop, end) = ((1..9).map(bit).collect::<Vec<u64>>(), bit(9), bit(10));
+    bb.set_tx(false, start);
+    for (i, t) in data.into_iter().enumerate() {
+        bb.set_tx(byte >> i & 1 == 1, t);
+    }
+    bb.set_tx(true, stop);
+    end
+}
+
+/// Assembles `src` and runs it until it exits
+fn run(core: &mut Core, src: &str) {
+    let assembler = assembler::Assembler::new(&instructions::Instance::new(0, None));
+    let program = assembler
+        .assemble(src.lines())
+        .unwrap_or_else(|e| panic!("{}\n{}", e, src));
+    core.load_image(&program.to_image()).unwrap();
+    core.reg.pc = 0x1000;
+    core.reg.s = 0x8000;
+    core.exec().unwrap();
+}
+
+/// Sets up the PIA1 data registers for the serial port (side A bit 1 out, side B in)
+const PIA_SETUP: &str = "
+    clr $ff21
+    lda #$fe
+    sta $ff20
+    lda #$34
+    sta $ff21
+    lda #2
+    sta $ff20
+    clr $ff23
+    clr $ff22
+    lda #$34
+    sta $ff23
+";
+
+#[test]
+fn test_decode() {
+    for baud in [600, 1200, 2400, 4800, 9600, 57600, 115200] {
+        let (mut bb, pipe) = create_bitbanger(baud);
+        let mut t = 1000;
+        for &b in b"Hi\x00\xff\r" {
+            t = send_frame(&mut bb, b, t);
+        }
+        bb.update(t);
+        assert_eq!(pipe.take_output(), b"Hi\x00\xff\r", "{} baud", baud);
+        assert_eq!(bb.framing_errors(), 0);
+    }
+}
+
+#[test]
+fn test_decode_errors() {
+    let (mut bb, pipe) = create_bitbanger(1200);
+    let bit = CPU_HZ / 1200;
+    // a pulse shorter than half a bit isn't a start bit
+    bb.set_tx(false, 100);
+    bb.set_tx(true, 100 + bit / 4);
+    bb.update(100 + 20 * bit);
+    assert!(pipe.take_output().is_empty());
+    // a line that stays low has no stop bit
+    bb.set_tx(false, 50 * bit);
+    bb.update(70 * bit);
+    assert_eq!(bb.framing_errors(), 1);
+    assert!(pipe.take_output().is_empty());
+    // the line has to go back to marking before the next start bit
+    bb.set_tx(true, 80 * bit);
+    let end = send_frame(&mut bb, b'A', 90 * bit);
+    bb.update(end);
+    assert_eq!(pipe.take_output(), b"A");
+    // a stop bit that's early by a bit is an error
+    let start = 200 * bit;
+    bb.set_tx(false, start);
+    bb.set_tx(true, start + 2 * bit);
+    bb.set_tx(false, start + 8 * bit);
+    bb.update(start + 10 * bit);
+    assert_eq!(bb.framing_errors(), 2);
+    assert!(BitBanger::new(300).is_err());
+    assert!(BitBanger::new(230400).is_err());
+}
+
+#[test]
+fn test_receive_line() {
+    let (mut bb, pipe) = create_bitbanger(2400);
+    // printer ready by default, marking for terminals
+    assert!(!bb.rx_level(0));
+    bb.set_idle_marking(true);
+    assert!(bb.rx_level(0));
+
+    bb.receive(&[0x5a]);
+    pipe.send(&[0x81]);
+    // the queued byte starts when the line is next read
+    let start = 1000;
+    let levels = |bb: &mut BitBanger, start: u64| {
+        assert!(!bb.rx_level(start));
+        (0..10)
+            .map(|i| bb.rx_level(start + (2 * i + 1) * CPU_HZ / (2 * 2400)) as u8)
+            .collect::<Vec<u8>>()
+    };
+    assert_eq!(levels(&mut bb, start), [0, 0, 1, 0, 1, 1, 0, 1, 0, 1]);
+    // then the one from the backend
+    let start = start + 10 * CPU_HZ / 2400 + 5;
+    assert_eq!(levels(&mut bb, start), [0, 1, 0, 0, 0, 0, 0, 0, 1, 1]);
+    assert!(bb.rx_level(start + 20 * CPU_HZ / 2400));
+}
+
+#[test]
+fn test_printer_capture() {
+    let pipe = Pipe::new();
+    let mut raw = Printer::new(Box::new(pipe.clone()), false);
+    let text = b"10 PRINT\r20 END\r\n\x1b\x0e\x12TITLE\x0c\x80\x7f\tX\n";
+    for &b in text {
+        serial::SerialBackend::write_byte(&mut raw, b);
+    }
+    assert_eq!(pipe.take_output(), text);
+
+    let mut printer = Printer::new(Box::new(pipe.clone()), true);
+    for &b in text {
+        serial::SerialBackend::write_byte(&mut printer, b);
+    }
+    assert_eq!(pipe.take_output(), b"10 PRINT\n20 END\nTITLE\x0c\tX\n");
+}
+
+#[test]
+fn test_llist_output() {
+    // the same approach as BASIC's printer driver: bits are timed with a delay loop
+    // (each bit takes 32 cycles plus 6 per count)
+    for (baud, delay) in [(600, 243), (9600, 10)] {
+        let mut core = create_core();
+        let (bb, pipe) = create_bitbanger(baud);
+        core.attach_bitbanger(bb);
+        let src = format!(
+            "
+    org $1000
+start:
+{}
+    ldu #text
+next:
+    ldb ,u+
+    beq done
+    bsr send
+    bra next
+done:
+    exit
+send:
+    stb byte
+    lda #8
+    sta count
+    clr $ff20
+    bsr delay
+bit:
+    clra
+    lsr byte
+    rola
+    asla
+    sta $ff20
+    bsr delay
+    dec count
+    bne bit
+    lda #2
+    sta $ff20
+    bsr delay
+    rts
+delay:
+    ldd #{}
+wait:
+    subd #1
+    bne wait
+    rts
+count: rmb 1
+byte: rmb 1
+text: fcc 'LIST'
+    fcb 13,0
+",
+            PIA_SETUP, delay
+        );
+        run(&mut core, &src);
+        // let the stop bit of the last byte finish
+        core.bitbanger
+            .as_ref()
+            .unwrap()
+            .borrow_mut()
+            .update(core.clock_cycles + CPU_HZ);
+        assert_eq!(pipe.take_output(), b"LIST\r", "{} baud", baud);
+        assert_eq!(
+            core.bitbanger.as_ref().unwrap().borrow().framing_errors(),
+            0
+        );
+    }
+}
+
+#[test]
+fn test_terminal_input() {
+    let mut core = create_core();
+    let (mut bb, pipe) = create_bitbanger(1200);
+    bb.set_idle_marking(true);
+    pipe.send(b"Go");
+    core.attach_bitbanger(bb);
+    // samples the middle of each bit, 60 counts being half a bit at 1200 baud
+    let src = format!(
+        "
+buffer equ $2000
+    org $1000
+start:
+{}
+    ldu #buffer
+    ldy #2
+byte:
+    lda $ff22
+    lsra
+    bcs byte
+    ldd #60
+    bsr wait
+    lda #8
+    sta count
+bit:
+    ldd #119
+    bsr wait
+    lda $ff22
+    lsra
+    ror ,u
+    dec count
+    bne bit
+    ldd #119
+    bsr wait
+    leau 1,u
+    leay -1,y
+    bne byte
+    exit
+wait:
+    subd #1
+    bne wait
+    rts
+count: rmb 1
+",
+        PIA_SETUP
+    );
+    run(&mut core, &src);
+    assert_eq!(&core.raw_ram[0x2000..0x2002], b"Go");
+}
+
+#[test]
+fn test_only_data_reads_sample_line() {
+    use crate::memory::AccessType;
+    let mut core = create_core();
+    let (mut bb, pipe) = create_bitbanger(2400);
+    bb.set_idle_marking(true);
+    pipe.send(b"x");
+    core.attach_bitbanger(bb);
+    let read = |core: &mut Core, addr: u16| core._read_u8(AccessType::System, addr, None).unwrap();
+    // reading side A, the control registers or side B's DDR doesn't look at the line, so
+    // the byte doesn't start yet
+    core._write_u8(AccessType::System, 0xff23, 0x30).unwrap();
+    read(&mut core, 0xff20);
+    read(&mut core, 0xff22);
+    read(&mut core, 0xff23);
+    core._write_u8(AccessType::System, 0xff23, 0x34).unwrap();
+    core.clock_cycles += 20 * CPU_HZ / 2400;
+    // (a mirror of $FF22) the start bit
+    assert_eq!(read(&mut core, 0xff26) & 1, 0);
+    core.clock_cycles += 3 * CPU_HZ / (2 * 2400);
+    // bit 0 of 'x' ($78)
+    assert_eq!(read(&mut core, 0xff22) & 1, 0);
+    core.clock_cycles += 4 * CPU_HZ / 2400;
+    // bit 4
+    assert_eq!(read(&mut core, 0xff22) & 1, 1);
+}
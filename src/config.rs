@@ -1,4 +1,5 @@
-use core::sync::atomic::{AtomicBool, Ordering};
+use crate::logging::{Level, SUBSYSTEM_COUNT};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
 pub struct Args {
     pub debug: AtomicBool,
@@ -6,6 +7,8 @@ pub struct Args {
     pub no_auto_sym: AtomicBool,
     pub verbose: AtomicBool,
     pub break_start: AtomicBool,
+    /// maximum log level for each logging::Subsystem (indexed by the Subsystem's value)
+    pub log_levels: [AtomicU8; SUBSYSTEM_COUNT],
 }
 
 pub static ARGS: Args = Args {
@@ -14,6 +17,7 @@ pub static ARGS: Args = Args {
     no_auto_sym: AtomicBool::new(true),
     verbose: AtomicBool::new(false),
     break_start: AtomicBool::new(false),
+    log_levels: [const { AtomicU8::new(Level::Warn as u8) }; SUBSYSTEM_COUNT],
 };
 
 pub fn auto_load_syms() -> bool {
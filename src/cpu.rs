@@ -3,8 +3,10 @@ use core::cell::{Cell, RefCell};
 #[cfg(not(target_os = "none"))]
 use super::test::TestCriterion;
 use crate::hex::{HexRecordCollection, HexRecordType};
+use crate::image::Image;
+use crate::srec;
 use crate::{acia, config, debug, instructions, pia, sam, vdg, Program};
-use crate::{Arc, BTreeMap, Duration, Error, ErrorKind, Mutex, String, Vec, VecDeque};
+use crate::{format, Arc, BTreeMap, Duration, Error, ErrorKind, Mutex, String, Vec};
 #[allow(unused)]
 #[derive(Debug, PartialEq, Eq)]
 pub enum InterruptType {
@@ -30,6 +32,40 @@ impl InterruptType {
         }
     }
 }
+/// Measures the cycles and instructions consumed between two program addresses.
+/// Timing starts the first time the instruction at `start` is about to execute
+/// and stops the next time the instruction at `end` is about to execute.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleSpan {
+    pub start: u16,
+    pub end: u16,
+    started: Option<(u64, u64)>, // (cycles, instructions) when start was reached
+    measured: Option<(u64, u64)>, // (cycles, instructions) consumed between start and end
+}
+impl CycleSpan {
+    pub fn new(start: u16, end: u16) -> Self {
+        CycleSpan {
+            start,
+            end,
+            started: None,
+            measured: None,
+        }
+    }
+    /// Returns the (cycles, instructions) consumed by the span if it has completed
+    pub fn measured(&self) -> Option<(u64, u64)> {
+        self.measured
+    }
+    pub(crate) fn check(&mut self, pc: u16, cycles: u64, instructions: u64) {
+        if self.measured.is_some() {
+            return;
+        }
+        match self.started {
+            Some((c, i)) if pc == self.end => self.measured = Some((cycles - c, instructions - i)),
+            None if pc == self.start => self.started = Some((cycles, instructions)),
+            _ => {}
+        }
+    }
+}
 /// The Core struct implements the 6809 processor and debugger.
 /// Its implementation spans multiple files: runtime.rs, debug.rs, memory.rs, registers.rs
 pub struct Core {
@@ -41,6 +77,8 @@ pub struct Core {
     pub pia1: Arc<Mutex<crate::pia::Pia1>>,
     pub reg: crate::registers::Set, // the full set of 6809 registers
     pub acia: Option<core::cell::RefCell<crate::acia::Acia>>, // ACIA simulator
+    pub bitbanger: Option<RefCell<crate::bitbanger::BitBanger>>, // serial port on PIA1 (see attach_bitbanger)
+    pub becker: Option<RefCell<crate::becker::BeckerPort>>, // DriveWire pipe at $FF41 (see attach_becker)
     pub reset_vector: Option<u16>,  // overrides the reset vector if set
     /* interrupt processing */
     pub cart_pending: bool, // true if cart is loaded but hasn't been run yet
@@ -62,16 +100,18 @@ pub struct Core {
     /* fields for debugging */
     pub in_debugger: bool,
     pub breakpoints: Vec<debug::Breakpoint>, // all current breakpoints
-    pub watch_hits: RefCell<Vec<u16>>, // tracks writes to addresses for which watch breakpoints have been set
+    pub watch_hits: RefCell<Vec<(u16, bool)>>, // watched accesses (address, write) since the last check
     pub addr_to_sym: BTreeMap<u16, Vec<String>>, // map from address to symbol
     pub sym_to_addr: BTreeMap<String, u16>, // map from symbol to address
     pub list_mode: Option<debug::ListMode>, // equals Some(ListMode) if currently in list (disassemble) mode
     pub program_start: u16, // the starting address of the program; should be equal to reset vector
     pub faulted: bool,      // true if the CPU has faulted (e.g., stack oveflow)
-    pub history: Option<VecDeque<String>>, // list of instructions that have been recently executed
+    pub tracer: Option<RefCell<crate::trace::Tracer>>, // records executed instructions (see trace.rs)
+    pub profiler: Option<crate::profile::Profiler>, // counts where cycles go (see profile.rs)
     pub step_mode: debug::StepMode, // determines current step mode (see debug.rs)
     pub next_linear_step: u16, // tracks the address of the next contiguous instruction (differs from PC when there is a branch or jump)
     pub trace: bool,           // if true then display each instruction as it's executed
+    pub spans: Vec<CycleSpan>, // address spans being timed (see CycleSpan)
 }
 impl Core {
     pub fn new(
@@ -98,6 +138,8 @@ impl Core {
             pia1,
             reg: { Default::default() },
             acia: acia_addr.map(|a| core::cell::RefCell::new(acia::Acia::new(a).expect("failed to start ACIA"))),
+            bitbanger: None,
+            becker: None,
             reset_vector: None,
             cart_pending: false,
             in_cwai: false,
@@ -129,10 +171,12 @@ impl Core {
             list_mode: None,
             program_start: 0,
             faulted: false,
-            history: None,
+            tracer: None,
+            profiler: None,
             step_mode: debug::StepMode::Off,
             next_linear_step: 0,
             trace: unsafe { config::ARGS.trace.load(core::sync::atomic::Ordering::Relaxed) },
+            spans: Vec::new(),
         }
     }
 
@@ -202,6 +246,52 @@ impl Core {
         Ok(extent)
     }
 
+    /// copies the contents of an Image into simulator memory and returns the number of
+    /// bytes loaded
+    pub fn load_image(&mut self, image: &Image) -> Result<usize, Error> {
+        let mut rom_write = false;
+        for seg in &image.segments {
+            if seg.end() > self.raw_ram.len() {
+                return Err(Error::new(
+                    ErrorKind::Memory,
+                    None,
+                    format!(
+                        "program overflowed system RAM ({} byte object at {:04X})",
+                        seg.data.len(),
+                        seg.addr
+                    )
+                    .as_str(),
+                ));
+            }
+            self.raw_ram[seg.addr as usize..seg.end()].copy_from_slice(&seg.data);
+            if seg.end() > self.ram_top as usize {
+                rom_write = true;
+            }
+        }
+        if rom_write {
+            info!("Portions of this program reside in ROM")
+        }
+        verbose_println!("loaded {} bytes", image.len());
+        Ok(image.len())
+    }
+    /// loads a Disk Extended BASIC (.BIN) file and returns its exec address
+    pub fn load_decb(&mut self, bin: &[u8]) -> Result<u16, Error> {
+        let image = crate::decb::read(bin)?;
+        self.load_image(&image)?;
+        Ok(image.exec.unwrap_or(0))
+    }
+    /// loads S-records (S19/S28) and returns the entry point (if there is one)
+    pub fn load_srec(&mut self, text: &str) -> Result<Option<u16>, Error> {
+        let image = srec::read(text)?;
+        self.load_image(&image)?;
+        Ok(image.exec)
+    }
+    /// loads a raw image (e.g. a ROM dump) at `addr`. Unlike load_bytes, it's an error for
+    /// the image to extend past the end of memory.
+    pub fn load_raw(&mut self, bytes: &[u8], addr: u16) -> Result<usize, Error> {
+        self.load_image(&Image::from_raw(addr, bytes)?)
+    }
+
     pub fn load_bytes(&mut self, bytes: &[u8], addr: u16) -> Result<usize, Error> {
         let mut addr = addr as usize;
         let mut loaded = 0;
@@ -258,9 +348,30 @@ impl Core {
         */
         Ok(extent)
     }
-    #[allow(unused)]
+    /// Evaluates all the given test criteria against the current machine state.
+    /// Every criterion is checked; the Error (if any) describes all of the failures.
     #[cfg(not(target_os = "none"))]
-    pub fn check_criteria(&self, criteria: &Vec<TestCriterion>) -> Result<(), Error> {
-        Ok(())
+    pub fn check_criteria(&self, criteria: &[TestCriterion]) -> Result<(), Error> {
+        let mut failures = Vec::new();
+        for tc in criteria {
+            if let Err(e) = tc.eval(self) {
+                failures.push(format!("line {}: {}", tc.line_number, e.msg));
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::Test,
+                Some(self.reg),
+                format!(
+                    "{} of {} test criteria failed\n{}",
+                    failures.len(),
+                    criteria.len(),
+                    failures.join("\n")
+                )
+                .as_str(),
+            ))
+        }
     }
 }
@@ -4,7 +4,13 @@ use alloc::sync::Arc;
 use alloc::vec;
 use spin::Mutex;
 
-fn create_core() -> Core {
+/// A core with 64K of RAM and no cartridge; the tests of every other module use it too
+pub(crate) fn create_core() -> Core {
+    create_core_with_acia(None)
+}
+
+/// Like create_core but with an ACIA (RS-232 Pak) mapped at `acia_addr`
+pub(crate) fn create_core_with_acia(acia_addr: Option<u16>) -> Core {
     // 64K RAM
     let ram = Box::leak(vec![0u8; 0x10000].into_boxed_slice());
     let sam = Arc::new(Mutex::new(Sam::new()));
@@ -12,7 +18,7 @@ fn create_core() -> Core {
     let pia1 = Arc::new(Mutex::new(Pia1::new()));
     let pia0 = Arc::new(Mutex::new(Pia0::new(pia1.clone())));
 
-    Core::new(ram, sam, vdg, pia0, pia1, 0xFFFF, None)
+    Core::new(ram, sam, vdg, pia0, pia1, 0xFFFF, acia_addr)
 }
 
 #[test]
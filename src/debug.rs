@@ -22,9 +22,16 @@ help!(
 help!(cmd_c, "c - Context; Display the state of all registers");
 help!(
     cmd_ba,
-    "ba <loc> [<notes>] - Breakpoint Add; add break at <loc>"
+    "ba <loc> [<notes>] [if <cond>] - Breakpoint Add; add break at <loc> (when <cond> is true)"
+);
+help!(
+    cmd_bw,
+    "bw <loc> [r|w|rw] [<notes>] [if <cond>] - Add Watch Breakpoint on reads/writes of <loc>"
+);
+help!(
+    cmd_tp,
+    "tp <loc> [<notes>] [if <cond>] - TracePoint; log hits at <loc> without stopping"
 );
-help!(cmd_bw, "bw <loc> [<notes>] - Add Watch Breakpoint on <loc>");
 help!(
     cmd_bd,
     "bd <num> - Breakpoint Delete; delete breakpoint #<num>"
@@ -86,6 +93,7 @@ static COMMAND_HELP: &[&str] = &[
     cmd_c,
     cmd_ba,
     cmd_bw,
+    cmd_tp,
     cmd_bi,
     cmd_bd,
     cmd_bl,
@@ -104,6 +112,7 @@ static COMMAND_HELP: &[&str] = &[
     cmd_h,
     cmd_sym,
     "<loc> syntax: Hex address (e.g. FF0A) or '?' followed by symbol (e.g. \"?START\")",
+    "<cond> syntax: expression of registers, symbols, hits and [<addr>] (e.g. \"a == 1 && [x] != 0\")",
 ];
 
 /// Tracks the state of the debugger's list mode.
@@ -112,18 +121,111 @@ pub struct ListMode {
     pub saved_ctx: registers::Set,
 }
 
+/// The memory accesses that trigger a watch breakpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    Access, // reads and writes
+}
+impl Watch {
+    pub fn parse(s: &str) -> Option<Watch> {
+        match s.to_ascii_lowercase().as_str() {
+            "r" => Some(Watch::Read),
+            "w" => Some(Watch::Write),
+            "rw" => Some(Watch::Access),
+            _ => None,
+        }
+    }
+    fn matches(self, write: bool) -> bool {
+        match self {
+            Watch::Read => !write,
+            Watch::Write => write,
+            Watch::Access => true,
+        }
+    }
+}
+impl core::fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Watch::Read => "r",
+            Watch::Write => "w",
+            Watch::Access => "rw",
+        })
+    }
+}
+
+/// A comparison in a breakpoint condition
+/// A condition that decides whether reaching a breakpoint counts as a hit. It's an
+/// expression with the same syntax as the assembler's (see parse.rs) that's true if it's
+/// non-zero, e.g. `a == $20 && [x] < 10`. Labels can be registers, symbols or `hits` (the
+/// number of times the breakpoint has been reached, including this one), and `[addr]` is
+/// the byte at addr.
+#[derive(Debug)]
+pub struct Condition {
+    text: String,
+    node: parse::ValueNode,
+}
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, Error> {
+        // the debugger's '?' symbol prefix is optional here, and since an expression ends
+        // at whitespace (see Parser::tokenize) it's removed
+        let expr: String = text
+            .chars()
+            .filter(|c| *c != '?' && !c.is_whitespace())
+            .collect();
+        let node = parse::Parser::with_registers_as_labels().str_to_value_node(&expr)?;
+        Ok(Condition {
+            text: text.trim().to_string(),
+            node,
+        })
+    }
+    /// Evaluates the condition given the number of times the breakpoint has been reached
+    pub fn eval(&self, core: &Core, hits: u32) -> Result<bool, Error> {
+        let lr = ConditionResolver { core, hits };
+        Ok(self.node.eval(&lr, core.reg.pc, false)?.u16() != 0)
+    }
+}
+
+/// Supplies the values of registers, symbols and the hit count to a Condition
+struct ConditionResolver<'a> {
+    core: &'a Core,
+    hits: u32,
+}
+impl parse::LabelResolver for ConditionResolver<'_> {
+    fn resolve(&self, label: &str) -> Option<u8u16> {
+        let reg = registers::Name::from_str(label);
+        if reg != registers::Name::Z {
+            return Some(self.core.reg.get_register(reg));
+        }
+        if label.eq_ignore_ascii_case("hits") {
+            return Some(u8u16::from_u16_shrink(self.hits.min(0xffff) as u16));
+        }
+        self.core.symbol_by_name(label).map(u8u16::u16)
+    }
+    fn read_u8(&self, addr: u16) -> Option<u8> {
+        Some(self.core.peek_u8(addr))
+    }
+}
+
 /// Contains all metadata and state for a single breakpoint.
 pub struct Breakpoint {
     /// true if breakpoint is active
     active: bool,
-    /// true if this is a watch breakpoint (rather than an instruction breakpoint)
-    watch: bool,
+    /// the accesses that trigger a watch breakpoint (None for an instruction breakpoint)
+    watch: Option<Watch>,
+    /// true if a hit is only logged (a tracepoint) rather than pausing execution
+    log_only: bool,
     /// address associated with this breakpoint
     addr: u16,
     /// all symbols associated with this breakpoint's address
     syms: Option<Vec<String>>,
     /// optional notes added by the user
     notes: Option<String>,
+    /// the breakpoint is only hit when this is true
+    condition: Option<Condition>,
+    /// the number of times the breakpoint has been reached (whether or not it was hit)
+    hits: u32,
 }
 
 impl PartialEq for Breakpoint {
@@ -133,45 +235,52 @@ impl PartialEq for Breakpoint {
 }
 
 impl Breakpoint {
-    pub fn new(addr: u16, watch: bool, syms: Option<&Vec<String>>, notes: Option<String>) -> Self {
+    pub fn new(
+        addr: u16,
+        watch: Option<Watch>,
+        syms: Option<&Vec<String>>,
+        notes: Option<String>,
+    ) -> Self {
         Breakpoint {
             active: true,
             watch,
+            log_only: false,
             addr,
-            syms: syms.map(|s| {
-                let mut v = Vec::new();
-                for y in s {
-                    v.push(y.clone())
-                }
-                v
-            }),
+            syms: syms.cloned(),
             notes,
+            condition: None,
+            hits: 0,
         }
     }
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
 }
 impl core::fmt::Display for Breakpoint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s1;
-        let s2;
+        let kind = match (self.watch, self.log_only) {
+            (Some(watch), _) => format!("{}", watch),
+            (None, true) => String::from("t"),
+            (None, false) => String::new(),
+        };
         write!(
             f,
-            "{:04X}{:1}{:1} {:10}{}",
+            "{:04X}{:2}{:1} {:10}",
             self.addr,
-            if self.watch { "w" } else { "" },
+            kind,
             if !self.active { "*" } else { "" },
-            if let Some(syms) = self.syms.as_ref() {
-                s1 = syms.join(",");
-                s1.as_str()
-            } else {
-                ""
-            },
-            if let Some(notes) = self.notes.as_ref() {
-                s2 = format!("  \"{}\"", notes.as_str());
-                s2.as_str()
-            } else {
-                ""
-            }
-        )
+            self.syms
+                .as_ref()
+                .map_or(String::new(), |syms| syms.join(","))
+        )?;
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition.text)?;
+        }
+        write!(f, " (hits: {})", self.hits)?;
+        if let Some(notes) = &self.notes {
+            write!(f, "  \"{}\"", notes)?;
+        }
+        Ok(())
     }
 }
 
@@ -200,44 +309,51 @@ impl Core {
         println!("Current context: [{} -> ({})]", self.reg, self.reg.cc);
         loop {
             // ... (commenting out debugger loop for no_std)
+            // each line read is run with breakpoint_command first (ba, bw, tp, bd, bl, bn, bt)
         }
         */
         self.in_debugger = false;
         Ok(())
     }
-    pub fn load_symbols(&mut self, _filename: &str) -> Result<usize, Error> {
-        /*
-        //         let path = std::path::Path::new(filename);
-        //         if let Ok(f) = std::fs::File::open(path) {
-                    self.clear_symbols();
-        //             let lines = std::io::BufReader::new(f).lines();
-                    for res in lines {
-                        if let Err(e) = res {
-                            let msg = format!("Error reading symbol file: {}", e);
-                            return Err(Error::new(ErrorKind::IO, None, msg.as_str()));
-                        }
-                        let line = res.unwrap();
-                        let comps: Vec<&str> = line.split(',').collect();
-                        if comps.len() != 2 {
-                            return Err(Error::new(
-                                ErrorKind::IO,
-                                None,
-                                "Invalid symbol file format",
-                            ));
-                        }
-                        if let Ok(addr) = u16::from_str_radix(comps[0], 16) {
-                            self.add_symbol(addr, comps[1]);
-                        } else {
-                            let msg = format!("Bad format for address in symbol file: {}", comps[1]);
-                            return Err(Error::new(ErrorKind::IO, None, msg.as_str()));
-                        }
-                    }
-                    return Ok(self.sym_to_addr.len());
-                }
-                let msg = format!("Failed to open symbol file {}", filename);
-                Err(Error::new(ErrorKind::IO, None, msg.as_str()))
-                */
-        Ok(0)
+    /// Replaces the debugger's symbols with those in a symbol file (as written by
+    /// Program::write_symbols) and returns the number of symbols loaded.
+    #[cfg(not(target_os = "none"))]
+    pub fn load_symbols(&mut self, filename: &str) -> Result<usize, Error> {
+        let text = std::fs::read_to_string(filename).map_err(|e| {
+            err!(
+                ErrorKind::IO,
+                None,
+                "failed to open symbol file {}: {}",
+                filename,
+                e
+            )
+        })?;
+        self.set_symbols(&text)
+    }
+    /// Replaces the debugger's symbols with those in `text`, one "ADDR,NAME" line per symbol
+    /// with ADDR in hex, and returns the number of symbols loaded.
+    pub fn set_symbols(&mut self, text: &str) -> Result<usize, Error> {
+        self.clear_symbols();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let Some((addr, name)) = line.split_once(',') else {
+                return Err(err!(
+                    ErrorKind::IO,
+                    None,
+                    "invalid symbol file line: {}",
+                    line
+                ));
+            };
+            let Ok(addr) = u16::from_str_radix(addr.trim(), 16) else {
+                return Err(err!(
+                    ErrorKind::IO,
+                    None,
+                    "bad address in symbol file: {}",
+                    addr
+                ));
+            };
+            self.add_symbol(addr, name.trim());
+        }
+        Ok(self.sym_to_addr.len())
     }
     /*
     pub fn try_auto_load_symbols(&mut self, path: &Path) -> Result<usize, Error> {
@@ -269,27 +385,167 @@ impl Core {
         index
     }
     pub fn get_breakpoint_by_addr(&self, addr: u16, watch_only: bool) -> Option<&Breakpoint> {
-        for i in 0..self.breakpoints.len() {
-            if addr == self.breakpoints[i].addr
-                && self.breakpoints[i].active
-                && (!watch_only || self.breakpoints[i].watch)
-            {
-                return Some(&self.breakpoints[i]);
+        self.breakpoints
+            .iter()
+            .find(|bp| addr == bp.addr && bp.active && (!watch_only || bp.watch.is_some()))
+    }
+    /// Notes a read (or write) of the address if a watch breakpoint is triggered by it.
+    /// Their conditions are checked before the next instruction is executed.
+    pub fn debug_check_for_watch_hit(&self, addr: u16, write: bool) {
+        if self
+            .breakpoints
+            .iter()
+            .any(|bp| addr == bp.addr && bp.active && bp.watch.is_some_and(|w| w.matches(write)))
+        {
+            let mut watch_hits = self.watch_hits.borrow_mut();
+            if !watch_hits.contains(&(addr, write)) {
+                watch_hits.push((addr, write));
+            }
+        }
+    }
+    /// Adds a breakpoint from the arguments of a breakpoint command:
+    /// `<loc> [r|w|rw] [if <cond>] [; <notes>]` (access types are only for watch breakpoints).
+    /// Without a ';' any words after the location (and access type) are the notes, as in
+    /// `ba <loc> <notes>`.
+    pub fn add_breakpoint(
+        &mut self,
+        args: &str,
+        watch: bool,
+        log_only: bool,
+    ) -> Result<usize, Error> {
+        let (args, mut notes) = match args.split_once(';') {
+            Some((args, notes)) => (args, Some(notes.trim().to_string())),
+            None => (args, None),
+        };
+        let (args, condition) = match args.split_once(" if ") {
+            Some((args, cond)) => (args, Some(Condition::parse(cond)?)),
+            None => (args, None),
+        };
+        let args = args.trim();
+        let (loc, mut rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        if loc.is_empty() {
+            return Err(syntax_err!("missing breakpoint location"));
+        }
+        let addr = self
+            .parse_address(loc)
+            .ok_or_else(|| syntax_err!("invalid breakpoint location \"{}\"", loc))?;
+        rest = rest.trim();
+        let watch = match watch {
+            true => {
+                let (access, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                match Watch::parse(access) {
+                    Some(watch) => {
+                        rest = after.trim();
+                        Some(watch)
+                    }
+                    None => Some(Watch::Access),
+                }
             }
+            false => None,
+        };
+        if !rest.is_empty() {
+            if notes.is_some() {
+                return Err(syntax_err!("unexpected \"{}\"", rest));
+            }
+            notes = Some(rest.to_string());
         }
-        None
+        let mut bp = Breakpoint::new(addr, watch, self.symbol_by_addr(addr), notes);
+        bp.condition = condition;
+        bp.log_only = log_only;
+        self.breakpoints.push(bp);
+        Ok(self.breakpoints.len() - 1)
     }
-    pub fn debug_check_for_watch_hit(&self, addr: u16) {
-        for bp in &self.breakpoints {
-            if addr == bp.addr && bp.active && bp.watch {
-                // println!("Hit at {:04X}", addr);
-                self.watch_hits.borrow_mut().push(addr);
-                return;
+    /// Runs a breakpoint command (ba, bw, tp, bd, bl, bn or bt).
+    /// Returns false if the command isn't one of them.
+    pub fn breakpoint_command(&mut self, line: &str) -> Result<bool, Error> {
+        let line = line.trim();
+        let (cmd, args) = line.split_once(' ').unwrap_or((line, ""));
+        let index = |core: &Core, args: &str| {
+            core.parse_breakpoint_index(args.split_whitespace().next().unwrap_or(""))
+                .filter(|&i| i < core.breakpoints.len())
+                .ok_or_else(|| syntax_err!("invalid breakpoint number"))
+        };
+        match cmd {
+            "ba" | "bw" | "tp" => {
+                let i = self.add_breakpoint(args, cmd == "bw", cmd == "tp")?;
+                println!("{:3}: {}", i, self.breakpoints[i]);
+            }
+            "bd" => {
+                let i = index(self, args)?;
+                self.breakpoints.remove(i);
+            }
+            "bl" => {
+                for (i, bp) in self.breakpoints.iter().enumerate() {
+                    println!("{:3}: {}", i, bp);
+                }
+            }
+            "bn" => {
+                let i = index(self, args)?;
+                let notes = args.trim().split_once(' ').map(|(_, notes)| notes.trim());
+                self.breakpoints[i].notes = notes.map(String::from);
+            }
+            "bt" => {
+                let i = index(self, args)?;
+                self.breakpoints[i].active = !self.breakpoints[i].active;
             }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+    /// Checks the breakpoints that are triggered by reaching `pc` (or by the watched accesses
+    /// since the last check). Tracepoints are logged. Returns true if execution should pause.
+    pub(crate) fn check_breakpoints(&mut self, pc: u16) -> bool {
+        let watch_hits = core::mem::take(&mut *self.watch_hits.borrow_mut());
+        let mut pause = false;
+        for i in 0..self.breakpoints.len() {
+            let bp = &self.breakpoints[i];
+            let triggered = match bp.watch {
+                Some(watch) => watch_hits
+                    .iter()
+                    .any(|&(addr, write)| addr == bp.addr && watch.matches(write)),
+                None => bp.addr == pc,
+            };
+            if !bp.active || !triggered {
+                continue;
+            }
+            let hits = bp.hits.saturating_add(1);
+            let hit = bp
+                .condition
+                .as_ref()
+                .map_or(Ok(true), |c| c.eval(self, hits));
+            self.breakpoints[i].hits = hits;
+            let hit = hit.unwrap_or_else(|e| {
+                // pause so the broken condition can be fixed
+                println!("Failed to evaluate breakpoint condition: {}", e);
+                true
+            });
+            if !hit {
+                continue;
+            }
+            let bp = &self.breakpoints[i];
+            if bp.log_only {
+                println!("Tracepoint: {} [{} -> ({})]", bp, self.reg, self.reg.cc);
+            } else {
+                match bp.watch {
+                    Some(_) => println!("Paused at watch breakpoint: {}", bp),
+                    None => println!("Paused at breakpoint: {}", bp),
+                }
+                pause = true;
+            }
+        }
+        pause
+    }
+    /// Reads a byte of memory without any side effects (I/O registers read as 0)
+    pub fn peek_u8(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0xfeff => self.raw_ram[addr as usize],
+            0xffe0..=0xffff => self.raw_ram[(addr - 0x4000) as usize],
+            _ => 0,
         }
     }
     fn clear_symbols(&mut self) {
         self.addr_to_sym.clear();
+        self.sym_to_addr.clear();
     }
     fn add_symbol(&mut self, addr: u16, name: &str) {
         // add symbol to addr_to_sym table
@@ -310,14 +566,28 @@ impl Core {
         self.addr_to_sym.get(&addr)
     }
     fn parse_address(&self, addr_sym: &str) -> Option<u16> {
-        if let Some(name) = addr_sym.strip_prefix('?') {
-            self.symbol_by_name(name)
+        if let Some(addr) = addr_sym
+            .strip_prefix('?')
+            .and_then(|name| self.symbol_by_name(name))
+        {
+            Some(addr)
         } else if let Ok(addr) = u16::from_str_radix(addr_sym, 16) {
             Some(addr)
         } else {
-            None
+            // an expression of registers and symbols such as "?TABLE+$10" or "x+2"
+            self.eval_expression(addr_sym).ok()
         }
     }
+    /// Evaluates an expression of registers, symbols and numbers (as in a breakpoint condition)
+    pub fn eval_expression(&self, expr: &str) -> Result<u16, Error> {
+        let expr: String = expr.chars().filter(|c| *c != '?').collect();
+        let node = parse::Parser::with_registers_as_labels().str_to_value_node(&expr)?;
+        let lr = ConditionResolver {
+            core: self,
+            hits: 0,
+        };
+        Ok(node.eval(&lr, self.reg.pc, false)?.u16())
+    }
     fn parse_number(&self, str_num: &str) -> Option<u8u16> {
         let mut number: Option<u8u16> = None;
         let mut negative = false;
@@ -347,12 +617,13 @@ impl Core {
     }
     fn show_history(&self) {
         let mut count = 0;
-        if let Some(history) = self.history.as_ref() {
-            count = history.len();
+        if let Some(tracer) = self.tracer.as_ref() {
+            let tracer = tracer.borrow();
+            count = tracer.records().count();
             if count > 0 {
                 println!("Showing executed instruction history (length = {})", count);
-                for _line in history {
-                    println!("{}", _line);
+                for record in tracer.records() {
+                    println!("{}", record.to_text(&self.addr_to_sym));
                 }
             }
         }
@@ -361,7 +632,9 @@ impl Core {
         }
     }
     fn clear_history(&mut self) {
-        self.history = None;
+        if let Some(tracer) = self.tracer.as_ref() {
+            tracer.borrow_mut().clear();
+        }
     }
     pub fn pre_instruction_debug_check(&mut self, pc: u16) -> bool {
         if let Some(lm) = self.list_mode.as_mut() {
@@ -404,28 +677,7 @@ impl Core {
                 return true;
             }
         }
-        let hit_breakpoint = || -> bool {
-            let mut breakpoint = false;
-            let watch_hits = self.watch_hits.borrow();
-            // if we hit a watch then break into the debugger
-            if !watch_hits.is_empty() {
-                for addr in watch_hits.iter() {
-                    if let Some(_bp) = self.get_breakpoint_by_addr(*addr, true) {
-                        println!("Paused at watch breakpoint: {}", _bp);
-                    }
-                }
-                breakpoint = true;
-            }
-            // if we're at a breakpoint then break into the debugger
-            for _bp in &self.breakpoints {
-                if pc == _bp.addr && _bp.active {
-                    println!("Paused at breakpoint: {}", _bp);
-                    breakpoint = true;
-                }
-            }
-            breakpoint
-        };
-        hit_breakpoint()
+        self.check_breakpoints(pc)
     }
     pub fn post_instruction_debug_check(
         &mut self,
@@ -528,12 +780,12 @@ impl Core {
                     row = count;
                     break;
                 }
-                let _b = self
+                let b = self
                     ._read_u8(memory::AccessType::System, index, None)
                     .unwrap();
                 if col < COLS_PER_ROW {
                     if i < count {
-                        print!(" {:02X}", _b);
+                        print!(" {:02X}", b);
                     } else {
                         print!("   ");
                     }
@@ -0,0 +1,205 @@
+use crate::cpu_test::create_core;
+use crate::debug::{Condition, Watch};
+use crate::*;
+use alloc::vec;
+
+#[test]
+fn test_condition() {
+    let mut core = create_core();
+    core.reg.a = 0x20;
+    core.reg.x = 0x1000;
+    core.raw_ram[0x1000] = 0x7f;
+    core.sym_to_addr.insert("COUNT".to_string(), 0x1000);
+    let eval = |core: &Core, text: &str| Condition::parse(text).unwrap().eval(core, 1).unwrap();
+    assert!(eval(&core, "a == $20"));
+    assert!(eval(&core, "A==32"));
+    assert!(!eval(&core, "a != $20"));
+    assert!(eval(&core, "x >= $1000 && x < $1001"));
+    assert!(eval(&core, "[x] == $7f"));
+    assert!(eval(&core, "[?COUNT+0] > 1"));
+    assert!(!eval(&core, "b"));
+    assert!(eval(&core, "a == 0 || [x] <= $7f"));
+    assert!(!eval(&core, "a == 0 || [x] < $7f"));
+    // the D register and arithmetic on registers
+    core.reg.set_register(registers::Name::B, u8u16::u8(0x01));
+    assert!(eval(&core, "d == $2001 && x + 1 == $1001"));
+    // the rest of the assembler's expression syntax
+    assert!(eval(&core, "(a & $f0) == $20 && !(b - 1)"));
+    assert!(eval(&core, "[x] >> 4 == 7 || hits > 5"));
+    assert!(Condition::parse("[x == 1").is_err());
+    assert!(Condition::parse("a == ").is_err());
+    assert!(Condition::parse("a == 1 && ").is_err());
+    // unknown symbols are caught when the condition is evaluated
+    assert!(Condition::parse("nowhere == 1")
+        .unwrap()
+        .eval(&core, 1)
+        .is_err());
+}
+
+#[test]
+fn test_conditional_breakpoint() {
+    let mut core = create_core();
+    core.breakpoint_command("ba 1000 if a == 3").unwrap();
+    core.reg.a = 2;
+    assert!(!core.check_breakpoints(0x1000));
+    core.reg.a = 3;
+    assert!(!core.check_breakpoints(0x1001));
+    assert!(core.check_breakpoints(0x1000));
+    assert_eq!(core.breakpoints[0].hits(), 2);
+}
+
+#[test]
+fn test_hit_count_condition() {
+    let mut core = create_core();
+    core.breakpoint_command("ba 2000 if hits == 3 ; third time")
+        .unwrap();
+    assert!(!core.check_breakpoints(0x2000));
+    assert!(!core.check_breakpoints(0x2000));
+    assert!(core.check_breakpoints(0x2000));
+    assert!(!core.check_breakpoints(0x2000));
+    // hits counts the times the breakpoint was reached whether or not it was hit
+    assert_eq!(core.breakpoints[0].hits(), 4);
+}
+
+#[test]
+fn test_watch_access_types() {
+    let mut core = create_core();
+    core.breakpoint_command("bw 3000 w").unwrap();
+    core.breakpoint_command("bw 3000 r").unwrap();
+    core.breakpoint_command("bw 3001").unwrap();
+    assert!(core.breakpoints[2].to_string().contains("rw"));
+
+    core.debug_check_for_watch_hit(0x3000, true);
+    assert!(core.check_breakpoints(0x1000));
+    assert_eq!(core.breakpoints[0].hits(), 1);
+    assert_eq!(core.breakpoints[1].hits(), 0);
+
+    core.debug_check_for_watch_hit(0x3000, false);
+    core.debug_check_for_watch_hit(0x3000, false);
+    assert!(core.check_breakpoints(0x1000));
+    // a watch is hit once per instruction however many times the address is accessed
+    assert_eq!(core.breakpoints[1].hits(), 1);
+    // watch breakpoints aren't hit by executing their address
+    assert!(!core.check_breakpoints(0x3000));
+
+    core.debug_check_for_watch_hit(0x3001, false);
+    core.debug_check_for_watch_hit(0x3001, true);
+    assert!(core.check_breakpoints(0x1000));
+    assert_eq!(core.breakpoints[2].hits(), 1);
+    assert_eq!(Watch::parse("RW"), Some(Watch::Access));
+}
+
+#[test]
+fn test_watch_condition() {
+    let mut core = create_core();
+    core.breakpoint_command("bw 4000 w if [$4000] == $ff")
+        .unwrap();
+    core.raw_ram[0x4000] = 1;
+    core.debug_check_for_watch_hit(0x4000, true);
+    assert!(!core.check_breakpoints(0x1000));
+    core.raw_ram[0x4000] = 0xff;
+    core.debug_check_for_watch_hit(0x4000, true);
+    assert!(core.check_breakpoints(0x1000));
+}
+
+#[test]
+fn test_tracepoint() {
+    let mut core = create_core();
+    core.breakpoint_command("tp 1000 if b > 0").unwrap();
+    assert!(!core.check_breakpoints(0x1000));
+    core.reg.b = 1;
+    // tracepoints are logged but never pause
+    assert!(!core.check_breakpoints(0x1000));
+    assert!(!core.check_breakpoints(0x1000));
+    assert_eq!(core.breakpoints[0].hits(), 3);
+}
+
+#[test]
+fn test_breakpoint_commands() {
+    let mut core = create_core();
+    core.sym_to_addr.insert("START".to_string(), 0x1234);
+    assert!(core.breakpoint_command("ba ?START ; entry").unwrap());
+    assert!(core.breakpoint_command("ba 1000").unwrap());
+    assert!(core.breakpoint_command("bl").unwrap());
+    assert!(core.get_breakpoint_by_addr(0x1234, false).is_some());
+    assert!(core.get_breakpoint_by_addr(0x1234, true).is_none());
+    assert!(core.breakpoints[0].to_string().contains("entry"));
+    // locations can be expressions
+    assert!(core.breakpoint_command("ba ?START+$10").unwrap());
+    assert!(core.get_breakpoint_by_addr(0x1244, false).is_some());
+    core.breakpoint_command("bd 2").unwrap();
+
+    // toggle off and back on
+    core.breakpoint_command("bt 0").unwrap();
+    assert!(!core.check_breakpoints(0x1234));
+    core.breakpoint_command("bt 0").unwrap();
+    assert!(core.check_breakpoints(0x1234));
+
+    core.breakpoint_command("bn 1 the loop").unwrap();
+    assert!(core.breakpoints[1].to_string().contains("the loop"));
+    core.breakpoint_command("bd 0").unwrap();
+    assert_eq!(core.breakpoints.len(), 1);
+
+    assert!(!core.breakpoint_command("dm 1000").unwrap());
+    assert!(core.breakpoint_command("ba").is_err());
+    assert!(core.breakpoint_command("ba ?NOWHERE").is_err());
+    assert!(core.breakpoint_command("ba 1000 extra ; notes").is_err());
+    assert!(core.breakpoint_command("ba 1000 if a ==").is_err());
+    assert!(core.breakpoint_command("bd 5").is_err());
+    assert_eq!(core.breakpoints.len(), 1);
+}
+
+#[test]
+fn test_breakpoint_notes_without_separator() {
+    let mut core = create_core();
+    // the original "ba <loc> <notes>" form
+    core.breakpoint_command("ba 1000 main loop").unwrap();
+    assert!(core.breakpoints[0].to_string().contains("\"main loop\""));
+    core.breakpoint_command("bw 2000 screen base").unwrap();
+    assert!(core.breakpoints[1].to_string().contains("rw"));
+    assert!(core.breakpoints[1].to_string().contains("\"screen base\""));
+    core.breakpoint_command("bw 2001 w the flag").unwrap();
+    assert!(core.breakpoints[2].to_string().contains("\"the flag\""));
+    core.breakpoint_command("ba 3000 a == 2").unwrap();
+    // without "if" that's a note rather than a condition
+    assert!(core.check_breakpoints(0x3000));
+}
+
+#[test]
+fn test_watch_hits_survive_delete() {
+    let mut core = create_core();
+    core.breakpoint_command("bw 3000").unwrap();
+    core.breakpoint_command("bw 4000").unwrap();
+    core.debug_check_for_watch_hit(0x4000, true);
+    // deleting a breakpoint before the hits are checked doesn't move them to another one
+    core.breakpoint_command("bd 0").unwrap();
+    core.breakpoint_command("bw 5000").unwrap();
+    assert!(core.check_breakpoints(0x1000));
+    assert_eq!(core.breakpoints[0].hits(), 1);
+    assert_eq!(core.breakpoints[1].hits(), 0);
+}
+
+#[test]
+fn test_load_symbols() {
+    let mut core = create_core();
+    let assembler = assembler::Assembler::new(&instructions::Instance::new(0, None));
+    let program = assembler
+        .assemble(["    org $3000", "data fcb 1,2", "start", "    rts"])
+        .unwrap();
+    let mut symbols = String::new();
+    program.write_symbols(&mut symbols).unwrap();
+    assert_eq!(core.set_symbols(&symbols).unwrap(), 2);
+    assert_eq!(core.symbol_by_name("start"), Some(0x3002));
+    assert_eq!(core.symbol_by_addr(0x3000), Some(&vec!["data".to_string()]));
+
+    // loading a symbol file replaces the symbols that were there
+    let path = std::env::temp_dir().join("coco_debug_test.sym");
+    std::fs::write(&path, "C000,ROM\n").unwrap();
+    assert_eq!(core.load_symbols(path.to_str().unwrap()).unwrap(), 1);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(core.symbol_by_name("start"), None);
+    assert_eq!(core.symbol_by_name("ROM"), Some(0xc000));
+
+    assert!(core.set_symbols("C000 ROM").is_err());
+    assert!(core.set_symbols("G000,ROM").is_err());
+}
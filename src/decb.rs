@@ -0,0 +1,79 @@
+//! CoCo Disk Extended BASIC (DECB) machine language files, i.e. the `.BIN` files that
+//! `LOADM` loads.
+//!
+//! A file is a sequence of records that each begin with a 5-byte header:
+//! - a preamble (`$00`, length, load address) is followed by _length_ bytes of data
+//! - the postamble (`$FF`, `$0000`, exec address) ends the file
+//!
+//! Lengths and addresses are big-endian 16-bit values.
+use crate::image::Image;
+use crate::{Error, ErrorKind, Vec};
+
+const PREAMBLE: u8 = 0x00;
+const POSTAMBLE: u8 = 0xff;
+
+/// Returns the image in DECB format. Files must have an exec address so the image's
+/// entry point is used if it has one and otherwise the address of its first byte.
+pub fn write(image: &Image) -> Vec<u8> {
+    let mut bin = Vec::with_capacity(image.len() + 5 * (image.segments.len() + 1));
+    for seg in &image.segments {
+        let mut addr = seg.addr;
+        // the length field is 16 bits so a full 64K segment takes two records
+        for chunk in seg.data.chunks(u16::MAX as usize) {
+            bin.push(PREAMBLE);
+            bin.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+            bin.extend_from_slice(&addr.to_be_bytes());
+            bin.extend_from_slice(chunk);
+            addr = addr.wrapping_add(chunk.len() as u16);
+        }
+    }
+    let exec = image
+        .exec
+        .or_else(|| image.segments.first().map(|s| s.addr))
+        .unwrap_or(0);
+    bin.push(POSTAMBLE);
+    bin.extend_from_slice(&[0, 0]);
+    bin.extend_from_slice(&exec.to_be_bytes());
+    bin
+}
+
+/// Reads a file in DECB format. Anything after the postamble is ignored.
+pub fn read(bin: &[u8]) -> Result<Image, Error> {
+    let mut image = Image::new();
+    let mut pos = 0usize;
+    loop {
+        let header = bin
+            .get(pos..pos + 5)
+            .ok_or_else(|| err!(ErrorKind::IO, None, "DECB file ends without a postamble"))?;
+        let len = u16::from_be_bytes([header[1], header[2]]) as usize;
+        let addr = u16::from_be_bytes([header[3], header[4]]);
+        pos += 5;
+        match header[0] {
+            PREAMBLE => {
+                let data = bin.get(pos..pos + len).ok_or_else(|| {
+                    err!(
+                        ErrorKind::IO,
+                        None,
+                        "DECB record at offset {} is truncated",
+                        pos - 5
+                    )
+                })?;
+                image.add(addr, data)?;
+                pos += len;
+            }
+            POSTAMBLE => {
+                image.exec = Some(addr);
+                return Ok(image);
+            }
+            t => {
+                return Err(err!(
+                    ErrorKind::IO,
+                    None,
+                    "invalid DECB record type ${:02X} at offset {}",
+                    t,
+                    pos - 5
+                ))
+            }
+        }
+    }
+}
@@ -0,0 +1,546 @@
+//! A 6809 disassembler that works on a slice of bytes rather than on the simulator's
+//! memory so it has no effect on the state of the CPU.
+//!
+//! There are two ways to disassemble:
+//! - [disassemble] decodes the bytes from start to finish (a linear sweep)
+//! - [disassemble_from] starts at one or more entry points and follows the program's flow
+//!   of control (recursive descent). Bytes that are never reached are treated as data.
+//!
+//! Either way, the result is a list of [DisasmLine]s that [to_source] turns into source
+//! that the assembler builds back into the same bytes. Branch targets and memory references
+//! within the bytes get labels (named from [Symbols] where possible) and any instruction
+//! whose encoding the assembler wouldn't choose for its operand is written out with FCB.
+use crate::instructions::{self, AddressingMode, Flavor, PPPostByte, TEPostByte, PBT};
+use crate::{format, BTreeMap, Core, String, ToString, Vec};
+use core::fmt;
+
+/// Symbol names by address
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    by_addr: BTreeMap<u16, String>,
+}
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+    /// Adds a symbol. If an address has more than one name then the last one added is used.
+    pub fn add(&mut self, name: &str, addr: u16) {
+        self.by_addr.insert(addr, name.to_string());
+    }
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(String::as_str)
+    }
+    /// Returns the address labels in an assembled program. Local labels are left out since
+    /// they're named after the scope they're in (e.g. "start@loop").
+    pub fn from_program(program: &crate::Program) -> Self {
+        let mut symbols = Symbols::new();
+        for s in program.labels.symbols(&program.lines) {
+            if s.name
+                .get(1..)
+                .is_some_and(|rest| rest.contains(['@', '.']))
+            {
+                continue;
+            }
+            if let (true, Some(value)) = (s.is_address, s.value) {
+                symbols.add(&s.name, value.u16());
+            }
+        }
+        symbols
+    }
+    /// Reads symbols in the format of a .sym file (`ADDR,NAME` lines with hex addresses)
+    pub fn from_sym(text: &str) -> Self {
+        let mut symbols = Symbols::new();
+        for (addr, name) in text.lines().filter_map(|l| l.trim().split_once(',')) {
+            if let Ok(addr) = u16::from_str_radix(addr.trim(), 16) {
+                symbols.add(name.trim(), addr);
+            }
+        }
+        symbols
+    }
+}
+
+/// What a line of disassembly holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    /// an instruction
+    Code,
+    /// bytes that aren't (known to be) instructions
+    Data,
+    /// a label for an address that isn't at the start of a line (the line has no bytes)
+    Equate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub kind: LineKind,
+    pub label: Option<String>,
+    pub operation: String,
+    pub operand: String,
+    /// the address that the instruction branches to or refers to (if known)
+    pub target: Option<u16>,
+    /// the instruction when the line holds one that's written with FCB
+    pub comment: Option<String>,
+}
+impl fmt::Display for DisasmLine {
+    /// Formats the line as assembly language source
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = self.label.as_deref().unwrap_or("");
+        let text = format!("{:<11} {:<6} {}", label, self.operation, self.operand);
+        if self.kind == LineKind::Equate {
+            return write!(f, "{}", text.trim_end());
+        }
+        write!(f, "{:<40}; {:04X}:", text, self.addr)?;
+        for b in &self.bytes {
+            write!(f, " {:02X}", b)?;
+        }
+        if let Some(comment) = &self.comment {
+            write!(f, "  {}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+/// The operand of a decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    None,
+    Immediate(u16, bool), // value, is 16 bits
+    Registers(String),    // TFR/EXG/PSH/PUL post-byte
+    Direct(u8),
+    Extended(u16),
+    Relative(u16), // branch target
+    /// indexed modes that don't refer to an address (e.g. "5,X" or ",Y++")
+    Indexed(String, bool), // operand, indirect
+    /// "[address]"
+    ExtendedIndirect(u16),
+    /// "target,PCR"
+    PcRelative(u16, bool), // target, indirect
+}
+
+/// A decoded instruction
+#[derive(Debug, Clone)]
+struct Decoded {
+    flavor: &'static Flavor,
+    size: u16,
+    operand: Operand,
+    /// false if the assembler would encode the operand differently
+    canonical: bool,
+}
+impl Decoded {
+    fn name(&self) -> &'static str {
+        self.flavor.desc.name
+    }
+    fn target(&self) -> Option<u16> {
+        match self.operand {
+            Operand::Extended(addr)
+            | Operand::Relative(addr)
+            | Operand::ExtendedIndirect(addr)
+            | Operand::PcRelative(addr, _) => Some(addr),
+            _ => None,
+        }
+    }
+    /// Returns the addresses that execution can continue at after this instruction and
+    /// whether it can fall through to the next instruction
+    fn flow(&self) -> (Option<u16>, bool) {
+        let name = self.name();
+        let jump = match self.operand {
+            Operand::Relative(target) if name != "BRN" && name != "LBRN" => Some(target),
+            Operand::Extended(target) if name == "JMP" || name == "JSR" => Some(target),
+            _ => None,
+        };
+        let falls_through = match name {
+            "BRA" | "LBRA" | "JMP" | "RTS" | "RTI" | "EXIT" => false,
+            "PULS" | "PULU" => !matches!(&self.operand, Operand::Registers(r) if r.contains("PC")),
+            _ => true,
+        };
+        (jump, falls_through)
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, which are at `addr`. Returns None if
+/// the bytes don't hold a valid instruction.
+fn decode(bytes: &[u8], addr: u16) -> Option<Decoded> {
+    let mut op = *bytes.first()? as u16;
+    let mut size = 1u16;
+    if instructions::is_high_byte_of_16bit_instruction(op as u8) {
+        op = (op << 8) | *bytes.get(1)? as u16;
+        size = 2;
+    }
+    let flavor = instructions::opcode_to_flavor(op)?;
+    let byte = |i: u16| bytes.get(i as usize).copied();
+    let word = |i: u16| Some(u16::from_be_bytes([byte(i)?, byte(i + 1)?]));
+    let mut canonical = true;
+    let operand = match flavor.mode {
+        AddressingMode::Inherent => Operand::None,
+        AddressingMode::Immediate => {
+            let is_16 = flavor.detail.sz - size == 2;
+            let val = if is_16 {
+                word(size)?
+            } else {
+                byte(size)? as u16
+            };
+            size += if is_16 { 2 } else { 1 };
+            match flavor.desc.pbt {
+                PBT::NA => Operand::Immediate(val, is_16),
+                PBT::TransferExchange if TEPostByte::to_registers(val as u8).is_some() => {
+                    Operand::Registers(TEPostByte::to_string(val as u8))
+                }
+                PBT::TransferExchange => return None,
+                // an empty register list doesn't assemble
+                PBT::PushPull if val == 0 => return None,
+                PBT::PushPull => Operand::Registers(PPPostByte::to_string(
+                    val as u8,
+                    flavor.desc.reg == crate::registers::Name::U,
+                )),
+            }
+        }
+        AddressingMode::Direct => {
+            size += 1;
+            Operand::Direct(byte(size - 1)?)
+        }
+        AddressingMode::Extended => {
+            size += 2;
+            Operand::Extended(word(size - 2)?)
+        }
+        AddressingMode::Relative => {
+            let (offset, len) = if flavor.detail.sz - size == 2 {
+                (word(size)?, 2)
+            } else {
+                (byte(size)? as i8 as u16, 1)
+            };
+            size += len;
+            Operand::Relative(addr.wrapping_add(size).wrapping_add(offset))
+        }
+        AddressingMode::Indexed => {
+            let pb = byte(size)?;
+            size += 1;
+            let reg = ["X", "Y", "U", "S"][((pb >> 5) & 3) as usize];
+            let indirect = pb & 0x90 == 0x90;
+            if pb & 0x80 == 0 {
+                // 5-bit offset (the assembler uses ",R" for a zero offset)
+                let offset = ((pb << 3) as i8) >> 3;
+                canonical = offset != 0;
+                Operand::Indexed(format!("{},{}", offset, reg), false)
+            } else {
+                match pb & 0x0f {
+                    0x0 if !indirect => Operand::Indexed(format!(",{}+", reg), false),
+                    0x1 => Operand::Indexed(format!(",{}++", reg), indirect),
+                    0x2 if !indirect => Operand::Indexed(format!(",-{}", reg), false),
+                    0x3 => Operand::Indexed(format!(",--{}", reg), indirect),
+                    0x4 => Operand::Indexed(format!(",{}", reg), indirect),
+                    0x5 => Operand::Indexed(format!("B,{}", reg), indirect),
+                    0x6 => Operand::Indexed(format!("A,{}", reg), indirect),
+                    0xb => Operand::Indexed(format!("D,{}", reg), indirect),
+                    0x8 => {
+                        let offset = byte(size)? as i8;
+                        size += 1;
+                        // the assembler uses a 5-bit offset when it can
+                        canonical = offset != 0 && (indirect || !(-16..16).contains(&offset));
+                        Operand::Indexed(format!("{},{}", offset, reg), indirect)
+                    }
+                    0x9 => {
+                        let offset = word(size)? as i16;
+                        size += 2;
+                        canonical = !(-128..128).contains(&offset);
+                        Operand::Indexed(format!("{},{}", offset, reg), indirect)
+                    }
+                    0xc | 0xd => {
+                        let (offset, len) = if pb & 1 == 0 {
+                            (byte(size)? as i8 as u16, 1)
+                        } else {
+                            (word(size)?, 2)
+                        };
+                        size += len;
+                        // the assembler measures an 8-bit offset from the end of a
+                        // shorter instruction
+                        let fits_8 = (-128..128).contains(&(offset as i16).wrapping_add(1));
+                        canonical = pb & 0x60 == 0 && (len == 1 || !fits_8);
+                        Operand::PcRelative(addr.wrapping_add(size).wrapping_add(offset), indirect)
+                    }
+                    0xf if indirect => {
+                        size += 2;
+                        canonical = pb == 0x9f;
+                        Operand::ExtendedIndirect(word(size - 2)?)
+                    }
+                    _ => return None,
+                }
+            }
+        }
+        _ => return None,
+    };
+    Some(Decoded {
+        flavor,
+        size,
+        operand,
+        canonical,
+    })
+}
+
+/// Decodes instructions and names the addresses that they refer to
+struct Disassembler<'a> {
+    bytes: &'a [u8],
+    addr: u16,
+    symbols: &'a Symbols,
+    /// the instructions found so far by their offset into bytes
+    code: BTreeMap<usize, Decoded>,
+    /// addresses that need a name
+    labels: BTreeMap<u16, String>,
+}
+impl<'a> Disassembler<'a> {
+    fn new(bytes: &'a [u8], addr: u16, symbols: &'a Symbols) -> Self {
+        instructions::init();
+        let bytes = &bytes[..bytes.len().min(0x10000 - addr as usize)];
+        Disassembler {
+            bytes,
+            addr,
+            symbols,
+            code: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        }
+    }
+    /// Returns the offset of the address into bytes (if it's within them)
+    fn offset(&self, addr: u16) -> Option<usize> {
+        (addr as usize)
+            .checked_sub(self.addr as usize)
+            .filter(|&offset| offset < self.bytes.len())
+    }
+    fn decode_at(&self, offset: usize) -> Option<Decoded> {
+        decode(&self.bytes[offset..], self.addr + offset as u16)
+    }
+    /// Names an address that an instruction refers to: addresses with symbols always get a
+    /// name; others only do if they're within the bytes being disassembled.
+    fn add_label(&mut self, addr: u16) {
+        let name = match self.symbols.name(addr) {
+            Some(name) => name.to_string(),
+            None if self.offset(addr).is_some() => format!("L{:04X}", addr),
+            None => return,
+        };
+        self.labels.insert(addr, name);
+    }
+    /// Decodes instructions one after another from the start of the bytes
+    fn sweep(&mut self) {
+        let mut offset = 0;
+        while offset < self.bytes.len() {
+            match self.decode_at(offset) {
+                Some(d) => {
+                    let size = d.size as usize;
+                    self.code.insert(offset, d);
+                    offset += size;
+                }
+                None => offset += 1,
+            }
+        }
+    }
+    /// Decodes the instructions that can be reached from the entry points
+    fn descend(&mut self, entries: &[u16]) {
+        // bytes that are part of an instruction
+        let mut covered = vec![false; self.bytes.len()];
+        let mut pending: Vec<u16> = entries.iter().rev().copied().collect();
+        while let Some(addr) = pending.pop() {
+            let Some(mut offset) = self.offset(addr) else {
+                continue;
+            };
+            while !covered[offset] {
+                let Some(d) = self.decode_at(offset) else {
+                    break;
+                };
+                let end = offset + d.size as usize;
+                if covered[offset..end].iter().any(|&c| c) {
+                    // the instruction would overlap one that's already been decoded
+                    break;
+                }
+                covered[offset..end].iter_mut().for_each(|c| *c = true);
+                let (jump, falls_through) = d.flow();
+                pending.extend(jump);
+                self.code.insert(offset, d);
+                if !falls_through || end >= self.bytes.len() {
+                    break;
+                }
+                offset = end;
+            }
+        }
+    }
+    /// Turns the decoded instructions and the bytes between them into lines
+    fn lines(mut self, entries: &[u16]) -> Vec<DisasmLine> {
+        for &addr in entries {
+            self.add_label(addr);
+        }
+        let targets: Vec<u16> = self.code.values().filter_map(Decoded::target).collect();
+        targets.into_iter().for_each(|addr| self.add_label(addr));
+        // any symbols within the bytes are labels too
+        let end = self.addr as usize + self.bytes.len();
+        for (&addr, name) in self
+            .symbols
+            .by_addr
+            .range(self.addr..)
+            .take_while(|(&a, _)| (a as usize) < end)
+        {
+            self.labels.insert(addr, name.clone());
+        }
+
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < self.bytes.len() {
+            let addr = self.addr + offset as u16;
+            let label = self.labels.get(&addr).cloned();
+            let line = match self.code.get(&offset) {
+                Some(d) => self.code_line(addr, label, d),
+                None => {
+                    // data runs until the next instruction or label (8 bytes per line at most)
+                    let len = (1..8)
+                        .take_while(|&n| {
+                            offset + n < self.bytes.len()
+                                && !self.code.contains_key(&(offset + n))
+                                && !self.labels.contains_key(&(addr + n as u16))
+                        })
+                        .count()
+                        + 1;
+                    self.data_line(addr, label, &self.bytes[offset..offset + len])
+                }
+            };
+            offset += line.bytes.len();
+            lines.push(line);
+        }
+        // labels that aren't at the start of a line are defined with EQU
+        let starts: Vec<u16> = lines.iter().map(|l| l.addr).collect();
+        let equates = self
+            .labels
+            .iter()
+            .filter(|(addr, _)| starts.binary_search(addr).is_err())
+            .map(|(&addr, name)| DisasmLine {
+                addr,
+                bytes: Vec::new(),
+                kind: LineKind::Equate,
+                label: Some(name.clone()),
+                operation: String::from("EQU"),
+                operand: format!("${:04X}", addr),
+                target: None,
+                comment: None,
+            });
+        equates.chain(lines).collect()
+    }
+    fn code_line(&self, addr: u16, label: Option<String>, d: &Decoded) -> DisasmLine {
+        let offset = (addr - self.addr) as usize;
+        let bytes = self.bytes[offset..offset + d.size as usize].to_vec();
+        let operand = self.format_operand(&d.operand);
+        if !d.canonical {
+            // the assembler wouldn't produce these bytes from the instruction
+            let comment = Some(format!("{} {}", d.name(), operand));
+            return DisasmLine {
+                comment,
+                target: d.target(),
+                kind: LineKind::Code,
+                ..self.data_line(addr, label, &bytes)
+            };
+        }
+        DisasmLine {
+            addr,
+            bytes,
+            kind: LineKind::Code,
+            label,
+            operation: String::from(d.name()),
+            operand,
+            target: d.target(),
+            comment: None,
+        }
+    }
+    fn data_line(&self, addr: u16, label: Option<String>, bytes: &[u8]) -> DisasmLine {
+        let operand = bytes
+            .iter()
+            .map(|b| format!("${:02X}", b))
+            .collect::<Vec<_>>()
+            .join(",");
+        DisasmLine {
+            addr,
+            bytes: bytes.to_vec(),
+            kind: LineKind::Data,
+            label,
+            operation: String::from("FCB"),
+            operand,
+            target: None,
+            comment: None,
+        }
+    }
+    /// Returns the name of the address or the address itself
+    fn address(&self, addr: u16) -> String {
+        match self.labels.get(&addr) {
+            Some(name) => name.clone(),
+            None => format!("${:04X}", addr),
+        }
+    }
+    fn format_operand(&self, operand: &Operand) -> String {
+        let indirect = |s: String, indirect: bool| if indirect { format!("[{}]", s) } else { s };
+        match operand {
+            Operand::None => String::new(),
+            Operand::Immediate(val, true) => format!("#${:04X}", val),
+            Operand::Immediate(val, false) => format!("#${:02X}", val),
+            Operand::Registers(regs) => regs.clone(),
+            Operand::Direct(addr) => format!("<${:02X}", addr),
+            // the assembler would use direct addressing for an address in page 0
+            Operand::Extended(addr) if addr >> 8 == 0 => format!(">{}", self.address(*addr)),
+            Operand::Extended(addr) | Operand::Relative(addr) => self.address(*addr),
+            Operand::Indexed(s, ind) => indirect(s.clone(), *ind),
+            Operand::ExtendedIndirect(addr) => format!("[{}]", self.address(*addr)),
+            Operand::PcRelative(addr, ind) => {
+                indirect(format!("{},PCR", self.address(*addr)), *ind)
+            }
+        }
+    }
+}
+
+/// Disassembles `bytes` (which are at `addr`) from start to finish. Bytes that don't
+/// hold a valid instruction are treated as data.
+pub fn disassemble(bytes: &[u8], addr: u16, symbols: &Symbols) -> Vec<DisasmLine> {
+    let mut d = Disassembler::new(bytes, addr, symbols);
+    d.sweep();
+    d.lines(&[])
+}
+
+/// Disassembles the instructions in `bytes` (which are at `addr`) that can be reached from
+/// the entry points by following branches, jumps and subroutine calls. Everything else is
+/// treated as data. Jumps through registers or memory can't be followed so their
+/// destinations should be given as entry points too.
+pub fn disassemble_from(
+    bytes: &[u8],
+    addr: u16,
+    entries: &[u16],
+    symbols: &Symbols,
+) -> Vec<DisasmLine> {
+    let mut d = Disassembler::new(bytes, addr, symbols);
+    d.descend(entries);
+    d.lines(entries)
+}
+
+/// Returns the lines as source that the assembler builds back into the original bytes
+pub fn to_source(lines: &[DisasmLine]) -> String {
+    let mut src = String::new();
+    let mut next_addr = None;
+    for line in lines {
+        if line.kind != LineKind::Equate && next_addr != Some(line.addr as usize) {
+            src.push_str(&format!("{:<11} {:<6} ${:04X}\n", "", "ORG", line.addr));
+        }
+        if line.kind != LineKind::Equate {
+            next_addr = Some(line.addr as usize + line.bytes.len());
+        }
+        src.push_str(&line.to_string());
+        src.push('\n');
+    }
+    src
+}
+
+impl Core {
+    /// Disassembles `len` bytes of memory starting at `addr` using the loaded symbols.
+    /// Unlike list mode, this has no effect on the state of the CPU.
+    pub fn disassemble(&self, addr: u16, len: usize) -> Vec<DisasmLine> {
+        let end = (addr as usize + len).min(self.raw_ram.len());
+        let mut symbols = Symbols::new();
+        for (&a, names) in &self.addr_to_sym {
+            if let Some(name) = names.last() {
+                symbols.add(name, a);
+            }
+        }
+        disassemble(&self.raw_ram[addr as usize..end], addr, &symbols)
+    }
+}
@@ -0,0 +1,164 @@
+use crate::disasm::{self, DisasmLine, LineKind, Symbols};
+use crate::*;
+
+fn assemble(src: &str) -> Program {
+    let assembler = assembler::Assembler::new(&instructions::Instance::new(0, None));
+    assembler
+        .assemble(src.lines())
+        .unwrap_or_else(|e| panic!("{}\n{}", e, src))
+}
+
+/// Checks that the source for the lines assembles into the original bytes
+fn assert_round_trip(lines: &[DisasmLine], bytes: &[u8], addr: u16) {
+    let src = disasm::to_source(lines);
+    let image = assemble(&src).to_image();
+    let mut raw = image.to_raw(addr, bytes.len(), 0).unwrap();
+    raw.truncate(bytes.len());
+    // report the line that assembled differently
+    if let Some(i) = (0..bytes.len()).find(|&i| raw[i] != bytes[i]) {
+        let line = lines
+            .iter()
+            .rfind(|l| l.kind != LineKind::Equate && l.addr as usize <= addr as usize + i)
+            .unwrap();
+        let end = (i + 4).min(bytes.len());
+        panic!("{}\nassembled to {:02X?}", line, &raw[i..end]);
+    }
+}
+
+#[test]
+fn test_linear_disassembly() {
+    let bytes = [
+        0x86, 0x55, // LDA #$55
+        0x8e, 0x12, 0x34, // LDX #$1234
+        0xa7, 0x84, // STA ,X
+        0x1f, 0x89, // TFR A,B
+        0x34, 0x16, // PSHS X,B,A
+        0x26, 0xf3, // BNE $1000
+        0x01, // not an instruction
+        0x39, // RTS
+    ];
+    let lines = disasm::disassemble(&bytes, 0x1000, &Symbols::new());
+    let text: Vec<(&str, &str)> = lines
+        .iter()
+        .map(|l| (l.operation.as_str(), l.operand.as_str()))
+        .collect();
+    assert_eq!(
+        text,
+        [
+            ("LDA", "#$55"),
+            ("LDX", "#$1234"),
+            ("STA", ",X"),
+            ("TFR", "A,B"),
+            ("PSHS", "A,B,X"),
+            ("BNE", "L1000"),
+            ("FCB", "$01"),
+            ("RTS", ""),
+        ]
+    );
+    assert_eq!(lines[0].label.as_deref(), Some("L1000"));
+    assert_eq!(lines[5].target, Some(0x1000));
+    assert_eq!(lines[6].kind, LineKind::Data);
+    assert_round_trip(&lines, &bytes, 0x1000);
+}
+
+#[test]
+fn test_symbols() {
+    let mut symbols = Symbols::from_sym("1000,start\nA000,POLCAT\n");
+    symbols.add("table", 0x1008);
+    let bytes = [
+        0xbd, 0xa0, 0x00, // JSR POLCAT
+        0xb6, 0x10, 0x09, // LDA table+1
+        0x20, 0xf8, // BRA start
+        0x00, 0x01, 0x02, // data
+    ];
+    let lines = disasm::disassemble_from(&bytes, 0x1000, &[0x1000], &symbols);
+    let src: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+    assert_eq!(src[0], "POLCAT      EQU    $A000");
+    assert!(src[1].starts_with("start       JSR    POLCAT "));
+    assert!(src[2].starts_with("            LDA    L1009 "));
+    assert!(src[3].starts_with("            BRA    start "));
+    assert!(src[4].starts_with("table       FCB    $00 "));
+    assert!(src[5].starts_with("L1009       FCB    $01,$02 "));
+    assert!(src[5].ends_with("; 1009: 01 02"));
+    assert_round_trip(&lines, &bytes, 0x1000);
+}
+
+#[test]
+fn test_recursive_descent() {
+    let program = assemble(
+        "    org $2000
+start:
+    ldx #message
+loop:
+    lda ,x+
+    beq done
+    jsr putc
+    bra loop
+done:
+    rts
+message:
+    fcc \"HI\"
+    fcb 0
+putc:
+    sta $400
+    rts
+    fcb $12,$34",
+    );
+    let image = program.to_image();
+    let bytes = &image.segments[0].data;
+    let symbols = Symbols::from_program(&program);
+    let lines = disasm::disassemble_from(bytes, 0x2000, &[0x2000], &symbols);
+    let ops: Vec<&str> = lines.iter().map(|l| l.operation.as_str()).collect();
+    assert_eq!(
+        ops,
+        ["LDX", "LDA", "BEQ", "JSR", "BRA", "RTS", "FCB", "STA", "RTS", "FCB"]
+    );
+    assert_eq!(lines[6].label.as_deref(), Some("message"));
+    assert_eq!(lines[6].bytes, b"HI\0");
+    assert_eq!(lines[7].label.as_deref(), Some("putc"));
+    assert_eq!(lines[0].operand, "#$200D");
+    assert_round_trip(&lines, bytes, 0x2000);
+}
+
+#[test]
+fn test_all_indexed_post_bytes_round_trip() {
+    for operand in [
+        [0x12, 0x34],
+        [0x80, 0x00],
+        [0x05, 0x00],
+        [0x00, 0x10],
+        [0xff, 0x80],
+    ] {
+        let mut bytes = Vec::new();
+        for pb in 0..=0xffu8 {
+            // LDA with indexed addressing and a 2-byte offset (or the next instruction)
+            bytes.extend_from_slice(&[0xa6, pb]);
+            bytes.extend_from_slice(&operand);
+        }
+        let lines = disasm::disassemble(&bytes, 0x4000, &Symbols::new());
+        assert_round_trip(&lines, &bytes, 0x4000);
+    }
+}
+
+#[test]
+fn test_all_opcodes_round_trip() {
+    // every opcode followed by a variety of operand bytes
+    for operand in [
+        [0x12, 0x34, 0x56],
+        [0x80, 0x34, 0x00],
+        [0x05, 0x00, 0x10],
+        [0x4f, 0xff, 0x00],
+    ] {
+        let mut bytes = Vec::new();
+        for op in 0..0x300u16 {
+            match op >> 8 {
+                0 => bytes.push(op as u8),
+                1 => bytes.extend_from_slice(&[0x10, op as u8]),
+                _ => bytes.extend_from_slice(&[0x11, op as u8]),
+            }
+            bytes.extend_from_slice(&operand);
+        }
+        let lines = disasm::disassemble(&bytes, 0x1000, &Symbols::new());
+        assert_round_trip(&lines, &bytes, 0x1000);
+    }
+}
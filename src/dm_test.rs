@@ -0,0 +1,119 @@
+//! dm-test: headless runner for assembly language test programs.
+//!
+//! Assembles each `.asm` file, runs it until it executes EXIT (or hits a cycle/time limit),
+//! then checks all of its `;!` criteria and prints a pass/fail report.
+//!
+//! Usage: `cargo test --test dm-test -- [options] [files|dirs|filters...]`
+//!
+//! - `--junit <path>` also write a JUnit-style XML report to _path_ (or set `DM_TEST_JUNIT`)
+//! - `--max-cycles <n>` fail any program that runs for more than _n_ cycles
+//! - `--timeout <secs>` fail any program that runs for more than _secs_ seconds of wall time
+//! - `--listing` write a listing (`.lst`), symbol file (`.sym`) and binary (`.hex`, `.bin`) for each
+//!   file into `target/dm-test` (under `CARGO_TARGET_DIR` if that is set)
+//! - `--out-dir <dir>` like `--listing` but write them into _dir_
+//!
+//! Paths that exist are run directly (directories are searched for `.asm` files).
+//! Any other argument filters the default test directory (`tests/asm`) by name.
+use coco::test::{RunLimits, TestRunner};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+fn collect_asm(dir: &Path, files: &mut Vec<PathBuf>) {
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        let mut found: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+        found.sort();
+        for path in found {
+            if path.is_dir() {
+                collect_asm(&path, files);
+            } else if path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("asm"))
+            {
+                files.push(path);
+            }
+        }
+    }
+}
+
+fn usage_err(msg: &str) -> ExitCode {
+    eprintln!("dm-test: {}", msg);
+    ExitCode::from(2)
+}
+
+fn main() -> ExitCode {
+    let mut limits = RunLimits::default();
+    let mut junit = std::env::var_os("DM_TEST_JUNIT").map(PathBuf::from);
+    let mut files = Vec::new();
+    let mut filters = Vec::new();
+    let mut output_dir = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--junit" => match args.next() {
+                Some(p) => junit = Some(PathBuf::from(p)),
+                None => return usage_err("--junit requires a path"),
+            },
+            "--max-cycles" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => limits.max_cycles = n,
+                None => return usage_err("--max-cycles requires a number"),
+            },
+            "--timeout" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(secs) => limits.max_time = Some(Duration::from_secs_f64(secs)),
+                None => return usage_err("--timeout requires a number of seconds"),
+            },
+            "--listing" => {
+                let target = std::env::var_os("CARGO_TARGET_DIR")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("target"));
+                output_dir = Some(target.join("dm-test"));
+            }
+            "--out-dir" => match args.next() {
+                Some(p) => output_dir = Some(PathBuf::from(p)),
+                None => return usage_err("--out-dir requires a path"),
+            },
+            // ignore flags intended for the libtest harness (e.g. --nocapture, --quiet)
+            _ if arg.starts_with("--") => {}
+            _ => {
+                let path = PathBuf::from(&arg);
+                if path.is_dir() {
+                    collect_asm(&path, &mut files);
+                } else if path.exists() {
+                    files.push(path);
+                } else {
+                    filters.push(arg);
+                }
+            }
+        }
+    }
+    if files.is_empty() {
+        collect_asm(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("asm"),
+            &mut files,
+        );
+        if !filters.is_empty() {
+            files.retain(|f| {
+                let name = f.to_string_lossy();
+                filters.iter().any(|filter| name.contains(filter.as_str()))
+            });
+        }
+    }
+
+    let mut runner = TestRunner::new(limits);
+    runner.output_dir = output_dir;
+    let report = runner.run_files(&files);
+    print!("{}", report);
+    if let Some(path) = junit {
+        if let Err(e) = std::fs::write(&path, report.to_junit_xml()) {
+            return usage_err(&format!("failed to write {}: {}", path.display(), e));
+        }
+    }
+    if report.all_passed() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,158 @@
+//! Virtual disks served over DriveWire.
+use super::SECTOR_SIZE;
+use crate::{Box, Error, Vec};
+
+/// Storage for a virtual disk: an array of 256-byte sectors addressed by logical
+/// sector number (LSN), like a .DSK file.
+pub trait DiskImage {
+    /// Reads sector `lsn`. Sectors past the end of the image read as zeros.
+    fn read_sector(&mut self, lsn: u32, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), Error>;
+    /// Writes sector `lsn`, growing the image if it's past the end.
+    fn write_sector(&mut self, lsn: u32, buf: &[u8; SECTOR_SIZE]) -> Result<(), Error>;
+    /// True if writes are refused
+    fn write_protected(&self) -> bool {
+        false
+    }
+}
+
+/// A disk image held in memory
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDisk {
+    pub data: Vec<u8>,
+    pub write_protect: bool,
+}
+
+impl MemoryDisk {
+    pub fn new(data: Vec<u8>) -> Self {
+        MemoryDisk {
+            data,
+            write_protect: false,
+        }
+    }
+}
+
+impl DiskImage for MemoryDisk {
+    fn read_sector(&mut self, lsn: u32, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), Error> {
+        let start = lsn as usize * SECTOR_SIZE;
+        buf.fill(0);
+        if start < self.data.len() {
+            let end = self.data.len().min(start + SECTOR_SIZE);
+            buf[..end - start].copy_from_slice(&self.data[start..end]);
+        }
+        Ok(())
+    }
+    fn write_sector(&mut self, lsn: u32, buf: &[u8; SECTOR_SIZE]) -> Result<(), Error> {
+        let start = lsn as usize * SECTOR_SIZE;
+        if self.data.len() < start + SECTOR_SIZE {
+            self.data.resize(start + SECTOR_SIZE, 0);
+        }
+        self.data[start..start + SECTOR_SIZE].copy_from_slice(buf);
+        Ok(())
+    }
+    fn write_protected(&self) -> bool {
+        self.write_protect
+    }
+}
+
+/// A disk image file that's read and written in place
+#[cfg(not(target_os = "none"))]
+pub struct FileDisk {
+    file: std::fs::File,
+    write_protect: bool,
+}
+
+#[cfg(not(target_os = "none"))]
+impl FileDisk {
+    /// Opens the image at `path`. Read-only files are served write protected.
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let io_err =
+            |e: std::io::Error| err!(crate::ErrorKind::IO, None, "disk image {}: {}", path, e);
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+        {
+            Ok(file) => Ok(FileDisk {
+                file,
+                write_protect: false,
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Ok(FileDisk {
+                file: std::fs::File::open(path).map_err(io_err)?,
+                write_protect: true,
+            }),
+            Err(e) => Err(io_err(e)),
+        }
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl DiskImage for FileDisk {
+    fn read_sector(&mut self, lsn: u32, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), Error> {
+        use std::io::{Read, Seek, SeekFrom};
+        let io_err =
+            |e: std::io::Error| err!(crate::ErrorKind::IO, None, "reading LSN {}: {}", lsn, e);
+        buf.fill(0);
+        self.file
+            .seek(SeekFrom::Start(lsn as u64 * SECTOR_SIZE as u64))
+            .map_err(io_err)?;
+        // a short read at the end of the file leaves the rest of the sector zeroed
+        let mut filled = 0;
+        while filled < SECTOR_SIZE {
+            match self.file.read(&mut buf[filled..]).map_err(io_err)? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        Ok(())
+    }
+    fn write_sector(&mut self, lsn: u32, buf: &[u8; SECTOR_SIZE]) -> Result<(), Error> {
+        use std::io::{Seek, SeekFrom, Write};
+        let io_err =
+            |e: std::io::Error| err!(crate::ErrorKind::IO, None, "writing LSN {}: {}", lsn, e);
+        self.file
+            .seek(SeekFrom::Start(lsn as u64 * SECTOR_SIZE as u64))
+            .map_err(io_err)?;
+        self.file.write_all(buf).map_err(io_err)
+    }
+    fn write_protected(&self) -> bool {
+        self.write_protect
+    }
+}
+
+/// Where disks mounted by name (OP_NAMEOBJ_MOUNT and OP_NAMEOBJ_CREATE) come from
+pub trait ObjectStore {
+    /// Opens the disk called `name`, creating an empty one first if `create` is set.
+    fn open(&mut self, name: &str, create: bool) -> Result<Box<dyn DiskImage>, Error>;
+}
+
+/// Serves the image files in a directory by name
+#[cfg(not(target_os = "none"))]
+pub struct DirStore {
+    dir: std::path::PathBuf,
+}
+
+#[cfg(not(target_os = "none"))]
+impl DirStore {
+    pub fn new(dir: &str) -> Self {
+        DirStore { dir: dir.into() }
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl ObjectStore for DirStore {
+    fn open(&mut self, name: &str, create: bool) -> Result<Box<dyn DiskImage>, Error> {
+        // names come from the guest and mustn't reach outside the directory
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(general_err!("invalid disk name {:?}", name));
+        }
+        let path = self.dir.join(name);
+        if create {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map_err(|e| err!(crate::ErrorKind::IO, None, "disk image {}: {}", name, e))?;
+        }
+        Ok(Box::new(FileDisk::open(&path.to_string_lossy())?))
+    }
+}
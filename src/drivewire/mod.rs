@@ -0,0 +1,523 @@
+//! DriveWire 4, the protocol DriveWire-aware ROMs and NitrOS-9 drivers use to reach
+//! virtual disks on a host computer over a serial line.
+//!
+//! The CoCo is the client: it sends an opcode followed by its arguments and the
+//! server answers. The CoCo side of the link can be the bit banger, the ACIA or a
+//! [Becker port](crate::becker::BeckerPort), and the host side can be a DriveWire
+//! server on another machine (see [TcpClient](crate::serial::tcp::TcpClient)) or the
+//! built-in [Server], which is a [SerialBackend] itself.
+//!
+//! Sector transfers use 256-byte sectors addressed by a 24-bit logical sector number
+//! (LSN) and are protected by a checksum: the 16-bit sum of the sector's bytes.
+//!
+//! Besides disks, the server answers time requests, mounts disks by name from an
+//! [ObjectStore] and carries [virtual serial channels](Server::attach_channel), which
+//! NitrOS-9's /N and /T devices use for network connections and terminals.
+pub mod disk;
+
+use crate::serial::SerialBackend;
+use crate::{Box, Map, String, Vec, VecDeque};
+use disk::{DiskImage, ObjectStore};
+
+pub const SECTOR_SIZE: usize = 256;
+
+/// The port DriveWire servers listen on
+pub const DEFAULT_PORT: u16 = 65504;
+
+// opcodes
+pub const OP_NOP: u8 = 0x00;
+pub const OP_DWINIT: u8 = 0x5a;
+pub const OP_INIT: u8 = 0x49;
+pub const OP_TERM: u8 = 0x54;
+pub const OP_READEX: u8 = 0xd2;
+pub const OP_REREADEX: u8 = 0xf2;
+pub const OP_WRITE: u8 = 0x57;
+pub const OP_REWRITE: u8 = 0x77;
+pub const OP_RESET1: u8 = 0xfe;
+pub const OP_RESET2: u8 = 0xff;
+pub const OP_RESET3: u8 = 0xf8;
+pub const OP_GETSTAT: u8 = 0x47;
+pub const OP_SETSTAT: u8 = 0x53;
+pub const OP_TIME: u8 = 0x23;
+pub const OP_NAMEOBJ_MOUNT: u8 = 0x01;
+pub const OP_NAMEOBJ_CREATE: u8 = 0x02;
+pub const OP_SERREAD: u8 = 0x43;
+pub const OP_SERREADM: u8 = 0x63;
+pub const OP_SERWRITE: u8 = 0xc3;
+pub const OP_SERWRITEM: u8 = 0x64;
+pub const OP_SERGETSTAT: u8 = 0x44;
+pub const OP_SERSETSTAT: u8 = 0xc4;
+pub const OP_SERINIT: u8 = 0x45;
+pub const OP_SERTERM: u8 = 0xc5;
+// requests the server reads in full but ignores
+pub const OP_READ: u8 = 0x52;
+pub const OP_REREAD: u8 = 0x72;
+pub const OP_PRINT: u8 = 0x50;
+pub const OP_PRINTFLUSH: u8 = 0x46;
+pub const OP_WIREBUG_MODE: u8 = 0x42;
+/// OP_FASTWRITE + channel (0-15) sends one byte to that channel
+pub const OP_FASTWRITE: u8 = 0x80;
+
+// error codes returned after sector transfers
+pub const E_OK: u8 = 0x00;
+pub const E_WP: u8 = 0xf2; // write protected
+pub const E_CRC: u8 = 0xf3; // checksum mismatch
+pub const E_READ: u8 = 0xf4;
+pub const E_WRITE: u8 = 0xf5;
+pub const E_NOTRDY: u8 = 0xf6; // no disk in the drive
+
+/// The version reported in answer to OP_DWINIT
+const SERVER_VERSION: u8 = 0x04;
+
+/// The number of virtual serial channels
+pub const CHANNELS: usize = 16;
+
+// OP_SERREAD answers: nothing waiting, one byte waiting on channel n - 1,
+// the channel in the second byte closed, or several bytes waiting on channel n - 17
+const SERREAD_NONE: u8 = 0;
+const SERREAD_BYTE: u8 = 1;
+const SERREAD_CLOSED: u8 = 16;
+const SERREAD_MANY: u8 = 17;
+
+/// OP_SERSETSTAT code that's followed by a 26-byte device descriptor
+const SS_COMST: u8 = 0x28;
+
+/// The most bytes buffered from a channel's backend
+const CHANNEL_BUFFER: usize = 256;
+
+/// Returns the DriveWire checksum of a sector
+pub fn checksum(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16))
+}
+
+/// A date and time as sent in answer to OP_TIME
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1-12
+    pub month: u8,
+    /// 1-31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Default for DateTime {
+    fn default() -> Self {
+        DateTime {
+            year: 2000,
+            month: 1,
+            day: 1,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        }
+    }
+}
+
+impl DateTime {
+    /// The date and time `secs` seconds after 1970-01-01 00:00:00
+    pub fn from_unix(secs: u64) -> Self {
+        let (days, secs) = (secs / 86400, secs % 86400);
+        // days to a civil date, from Howard Hinnant's date algorithms
+        let z = days + 719468;
+        let (era, doe) = (z / 146097, z % 146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        DateTime {
+            year: (yoe + era * 400 + (month <= 2) as u64) as u16,
+            month: month as u8,
+            day: (doy - (153 * mp + 2) / 5 + 1) as u8,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+    /// The current time (UTC)
+    #[cfg(not(target_os = "none"))]
+    pub fn now() -> Self {
+        let since_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Self::from_unix(since_epoch.as_secs())
+    }
+    /// The OP_TIME answer: the year since 1900, month, day, hour, minute and second
+    pub fn to_bytes(&self) -> [u8; 6] {
+        [
+            self.year.saturating_sub(1900).min(255) as u8,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+        ]
+    }
+}
+
+/// A virtual serial channel
+#[derive(Default)]
+struct Channel {
+    backend: Option<Box<dyn SerialBackend>>,
+    // bytes from the backend that haven't been sent to the CoCo
+    input: VecDeque<u8>,
+    // opened by the CoCo with OP_SERINIT
+    open: bool,
+    // the backend disconnected and the CoCo hasn't been told
+    hung_up: bool,
+}
+
+/// What the server is waiting for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// the next opcode
+    Command,
+    /// the client's checksum of the sector that was just sent to it, to be compared
+    /// with ours; the error code from reading the sector is sent once it arrives
+    ReadChecksum { sum: u16, error: u8 },
+}
+
+/// A DriveWire server with virtual disks in drives 0-255.
+/// Bytes written to it are requests from the CoCo; its answers are read back.
+pub struct Server {
+    drives: Map<u8, Box<dyn DiskImage>>,
+    names: Map<u8, String>,
+    objects: Option<Box<dyn ObjectStore>>,
+    channels: [Channel; CHANNELS],
+    clock: Box<dyn FnMut() -> DateTime>,
+    state: State,
+    request: Vec<u8>,
+    response: VecDeque<u8>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server {
+            drives: Map::new(),
+            names: Map::new(),
+            objects: None,
+            channels: Default::default(),
+            #[cfg(not(target_os = "none"))]
+            clock: Box::new(DateTime::now),
+            #[cfg(target_os = "none")]
+            clock: Box::new(DateTime::default),
+            state: State::Command,
+            request: Vec::new(),
+            response: VecDeque::new(),
+        }
+    }
+    /// Puts a disk in `drive`, replacing any disk that was there.
+    pub fn mount(&mut self, drive: u8, disk: Box<dyn DiskImage>) {
+        self.names.remove(&drive);
+        self.drives.insert(drive, disk);
+    }
+    /// Takes the disk out of `drive`
+    pub fn eject(&mut self, drive: u8) -> Option<Box<dyn DiskImage>> {
+        self.names.remove(&drive);
+        self.drives.remove(&drive)
+    }
+    /// True if there's a disk in `drive`
+    pub fn is_mounted(&self, drive: u8) -> bool {
+        self.drives.contains_key(&drive)
+    }
+    /// The name of the disk in `drive` if it was mounted by name
+    pub fn mounted_name(&self, drive: u8) -> Option<&str> {
+        self.names.get(&drive).map(|n| n.as_str())
+    }
+    /// Sets where disks the CoCo asks for by name are found
+    pub fn set_object_store(&mut self, store: Box<dyn ObjectStore>) {
+        self.objects = Some(store);
+    }
+    /// Sets where the time sent in answer to OP_TIME comes from.
+    /// It's the host's clock by default (and 2000-01-01 on the Pico, which has none).
+    pub fn set_clock(&mut self, clock: Box<dyn FnMut() -> DateTime>) {
+        self.clock = clock;
+    }
+    /// Connects virtual serial channel `channel` (0-15) to `backend`
+    pub fn attach_channel(&mut self, channel: usize, backend: Box<dyn SerialBackend>) {
+        self.channels[channel] = Channel {
+            backend: Some(backend),
+            ..Default::default()
+        };
+    }
+    /// Disconnects virtual serial channel `channel` from its backend
+    pub fn detach_channel(&mut self, channel: usize) -> Option<Box<dyn SerialBackend>> {
+        let ch = &mut self.channels[channel];
+        ch.hung_up = ch.open;
+        ch.backend.take()
+    }
+    /// True if the CoCo has opened virtual serial channel `channel`
+    pub fn channel_open(&self, channel: usize) -> bool {
+        self.channels[channel].open
+    }
+    /// Creates a server with the .dsk files in `dir` mounted in drives 0, 1, ...
+    /// in order of their names. Disks mounted by name come from `dir` too.
+    #[cfg(not(target_os = "none"))]
+    pub fn from_dir(dir: &str) -> Result<Self, crate::Error> {
+        let entries = std::fs::read_dir(dir).map_err(|e| {
+            err!(
+                crate::ErrorKind::IO,
+                None,
+                "DriveWire directory {}: {}",
+                dir,
+                e
+            )
+        })?;
+        let mut paths = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("dsk"))
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        let mut server = Server::new();
+        server.set_object_store(Box::new(disk::DirStore::new(dir)));
+        for (drive, path) in paths.iter().take(256).enumerate() {
+            let path = path.to_string_lossy();
+            info!("DriveWire drive {}: {}", drive, path);
+            server.mount(drive as u8, Box::new(disk::FileDisk::open(&path)?));
+        }
+        Ok(server)
+    }
+    /// The number of bytes the request so far in `req` needs (including the opcode),
+    /// or None if its opcode isn't a DriveWire 4 opcode. Requests whose length is in the
+    /// request report how many bytes are needed to get to it first.
+    fn request_len(&self, req: &[u8]) -> Option<usize> {
+        let arg = |i: usize| req.get(i).map(|&b| b as usize);
+        match self.state {
+            State::ReadChecksum { .. } => Some(2),
+            State::Command => match req[0] {
+                OP_NOP | OP_INIT | OP_TERM | OP_RESET1 | OP_RESET2 | OP_RESET3 | OP_TIME
+                | OP_SERREAD | OP_PRINTFLUSH => Some(1),
+                OP_DWINIT | OP_SERINIT | OP_SERTERM | OP_PRINT => Some(2),
+                OP_GETSTAT | OP_SETSTAT | OP_SERREADM | OP_SERWRITE | OP_SERGETSTAT => Some(3),
+                OP_READEX | OP_REREADEX | OP_READ | OP_REREAD => Some(5),
+                // the CoCo and CPU types and 21 reserved bytes
+                OP_WIREBUG_MODE => Some(24),
+                OP_WRITE | OP_REWRITE => Some(5 + SECTOR_SIZE + 2),
+                OP_NAMEOBJ_MOUNT | OP_NAMEOBJ_CREATE => Some(arg(1).map_or(2, |n| 2 + n)),
+                OP_SERWRITEM => Some(arg(2).map_or(3, |n| 3 + n)),
+                OP_SERSETSTAT => match arg(2) {
+                    Some(c) if c == SS_COMST as usize => Some(3 + 26),
+                    _ => Some(3),
+                },
+                op if op & 0xf0 == OP_FASTWRITE => Some(2),
+                _ => None,
+            },
+        }
+    }
+    /// Mounts the disk called `name` in the first free drive after 0 and returns the
+    /// drive number, or 0 if it couldn't be mounted.
+    fn mount_name(&mut self, name: &str, create: bool) -> u8 {
+        if let Some((&drive, _)) = self.names.iter().find(|(_, n)| *n == name) {
+            return drive;
+        }
+        let Some(drive) = (1..=255).find(|d| !self.drives.contains_key(d)) else {
+            warn!("DriveWire has no free drive for {}", name);
+            return 0;
+        };
+        let Some(store) = self.objects.as_mut() else {
+            warn!("DriveWire can't mount {}: no object store", name);
+            return 0;
+        };
+        match store.open(name, create) {
+            Ok(disk) => {
+                info!("DriveWire drive {}: {}", drive, name);
+                self.drives.insert(drive, disk);
+                self.names.insert(drive, name.into());
+                drive
+            }
+            Err(e) => {
+                warn!("DriveWire can't mount {}: {}", name, e);
+                0
+            }
+        }
+    }
+    /// Answers OP_SERREAD with what's waiting on the virtual serial channels
+    fn poll_channels(&mut self) -> [u8; 2] {
+        for (n, ch) in self.channels.iter_mut().enumerate() {
+            if let Some(backend) = ch.backend.as_mut() {
+                while ch.input.len() < CHANNEL_BUFFER {
+                    match backend.read_byte() {
+                        Some(b) => ch.input.push_back(b),
+                        None => break,
+                    }
+                }
+                if ch.open && !backend.connected() && ch.input.is_empty() {
+                    ch.hung_up = true;
+                }
+            }
+            if !ch.open {
+                continue;
+            }
+            match ch.input.len() {
+                0 if ch.hung_up => {
+                    ch.hung_up = false;
+                    ch.open = false;
+                    return [SERREAD_CLOSED, n as u8];
+                }
+                0 => {}
+                1 => return [SERREAD_BYTE + n as u8, ch.input.pop_front().unwrap()],
+                len => return [SERREAD_MANY + n as u8, len.min(255) as u8],
+            }
+        }
+        [SERREAD_NONE, 0]
+    }
+    /// Sends a byte from the CoCo to virtual serial channel `channel`
+    fn channel_write(&mut self, channel: u8, byte: u8) {
+        match self.channels.get_mut(channel as usize) {
+            Some(Channel {
+                backend: Some(backend),
+                ..
+            }) => backend.write_byte(byte),
+            _ => verbose_println!("DriveWire channel {} dropped {:02X}", channel, byte),
+        }
+    }
+    /// Handles a complete request
+    fn dispatch(&mut self) {
+        let req = core::mem::take(&mut self.request);
+        if let State::ReadChecksum { sum, error } = self.state {
+            self.state = State::Command;
+            let theirs = u16::from_be_bytes([req[0], req[1]]);
+            let error = if error == E_OK && theirs != sum {
+                E_CRC
+            } else {
+                error
+            };
+            self.response.push_back(error);
+            return;
+        }
+        let lsn = |req: &[u8]| u32::from_be_bytes([0, req[2], req[3], req[4]]);
+        match req[0] {
+            OP_DWINIT => self.response.push_back(SERVER_VERSION),
+            OP_READEX | OP_REREADEX => {
+                let (drive, lsn) = (req[1], lsn(&req));
+                let mut sector = [0u8; SECTOR_SIZE];
+                let error = match self.drives.get_mut(&drive) {
+                    None => E_NOTRDY,
+                    Some(disk) => match disk.read_sector(lsn, &mut sector) {
+                        Ok(()) => E_OK,
+                        Err(e) => {
+                            warn!(
+                                "DriveWire read of drive {} LSN {} failed: {}",
+                                drive, lsn, e
+                            );
+                            sector.fill(0);
+                            E_READ
+                        }
+                    },
+                };
+                verbose_println!("DriveWire read drive {} LSN {}: {:02X}", drive, lsn, error);
+                self.response.extend(sector);
+                self.state = State::ReadChecksum {
+                    sum: checksum(&sector),
+                    error,
+                };
+            }
+            OP_WRITE | OP_REWRITE => {
+                let (drive, lsn) = (req[1], lsn(&req));
+                let sector: &[u8; SECTOR_SIZE] = req[5..5 + SECTOR_SIZE].try_into().unwrap();
+                let theirs = u16::from_be_bytes([req[5 + SECTOR_SIZE], req[6 + SECTOR_SIZE]]);
+                let error = match self.drives.get_mut(&drive) {
+                    _ if theirs != checksum(sector) => E_CRC,
+                    None => E_NOTRDY,
+                    Some(disk) if disk.write_protected() => E_WP,
+                    Some(disk) => match disk.write_sector(lsn, sector) {
+                        Ok(()) => E_OK,
+                        Err(e) => {
+                            warn!(
+                                "DriveWire write of drive {} LSN {} failed: {}",
+                                drive, lsn, e
+                            );
+                            E_WRITE
+                        }
+                    },
+                };
+                verbose_println!("DriveWire write drive {} LSN {}: {:02X}", drive, lsn, error);
+                self.response.push_back(error);
+            }
+            OP_RESET1 | OP_RESET2 | OP_RESET3 => {
+                // the CoCo restarted; anything it was in the middle of is abandoned
+                self.response.clear();
+                for ch in self.channels.iter_mut() {
+                    ch.open = false;
+                    ch.hung_up = false;
+                }
+            }
+            OP_GETSTAT | OP_SETSTAT => {
+                // informational only; RBF handles the status codes itself
+                verbose_println!(
+                    "DriveWire {}STAT drive {} code {:02X}",
+                    if req[0] == OP_GETSTAT { "GET" } else { "SET" },
+                    req[1],
+                    req[2]
+                );
+            }
+            OP_TIME => self.response.extend((self.clock)().to_bytes()),
+            OP_NAMEOBJ_MOUNT | OP_NAMEOBJ_CREATE => {
+                let name = String::from_utf8_lossy(&req[2..]);
+                let drive = self.mount_name(&name, req[0] == OP_NAMEOBJ_CREATE);
+                self.response.push_back(drive);
+            }
+            OP_SERINIT | OP_SERTERM => {
+                if let Some(ch) = self.channels.get_mut(req[1] as usize) {
+                    ch.open = req[0] == OP_SERINIT;
+                    ch.hung_up = false;
+                }
+            }
+            OP_SERREAD => {
+                let answer = self.poll_channels();
+                self.response.extend(answer);
+            }
+            OP_SERREADM => {
+                let channel = req[1] as usize;
+                // the CoCo gets as many bytes as it asks for, padded if fewer were waiting
+                for _ in 0..req[2] {
+                    let byte = self
+                        .channels
+                        .get_mut(channel)
+                        .and_then(|ch| ch.input.pop_front());
+                    self.response.push_back(byte.unwrap_or(0));
+                }
+            }
+            OP_SERWRITE => self.channel_write(req[1], req[2]),
+            OP_SERWRITEM => {
+                for &b in &req[3..] {
+                    self.channel_write(req[1], b);
+                }
+            }
+            OP_SERGETSTAT | OP_SERSETSTAT => {}
+            op if op & 0xf0 == OP_FASTWRITE => self.channel_write(op & 0x0f, req[1]),
+            OP_READ | OP_REREAD | OP_PRINT | OP_PRINTFLUSH | OP_WIREBUG_MODE => {
+                verbose_println!("DriveWire ignored opcode {:02X}", req[0]);
+            }
+            // nothing to answer
+            _ => {}
+        }
+    }
+}
+
+impl SerialBackend for Server {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.response.pop_front()
+    }
+    fn write_byte(&mut self, byte: u8) {
+        if self.request.is_empty() && self.request_len(&[byte]).is_none() {
+            warn!("DriveWire opcode {:02X} is not supported", byte);
+            return;
+        }
+        self.request.push(byte);
+        if Some(self.request.len()) == self.request_len(&self.request) {
+            self.dispatch();
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use crate::cpu_test::create_core;
+use crate::drivewire::disk::MemoryDisk;
+use crate::drivewire::*;
+use crate::memory::AccessType;
+use crate::serial::SerialBackend;
+use crate::*;
+use alloc::vec;
+
+/// A disk whose sector n is filled with byte n
+fn numbered_disk(sectors: usize) -> MemoryDisk {
+    MemoryDisk::new((0..sectors).flat_map(|n| [n as u8; SECTOR_SIZE]).collect())
+}
+
+fn send(server: &mut Server, bytes: &[u8]) {
+    bytes.iter().for_each(|&b| server.write_byte(b));
+}
+
+fn receive(server: &mut Server) -> Vec<u8> {
+    core::iter::from_fn(|| server.read_byte()).collect()
+}
+
+#[test]
+fn test_readex() {
+    let mut server = Server::new();
+    server.mount(1, Box::new(numbered_disk(4)));
+    send(&mut server, &[OP_DWINIT, 0x00]);
+    assert_eq!(receive(&mut server), [4]);
+
+    send(&mut server, &[OP_READEX, 1, 0, 0, 2]);
+    let sector = receive(&mut server);
+    assert_eq!(sector, [2; SECTOR_SIZE]);
+    // the error code only comes once the client has sent its checksum
+    send(&mut server, &checksum(&sector).to_be_bytes());
+    assert_eq!(receive(&mut server), [E_OK]);
+
+    // a bad checksum asks for a retry
+    send(&mut server, &[OP_READEX, 1, 0, 0, 3]);
+    assert_eq!(receive(&mut server), [3; SECTOR_SIZE]);
+    send(&mut server, &[0, 0]);
+    assert_eq!(receive(&mut server), [E_CRC]);
+    send(&mut server, &[OP_REREADEX, 1, 0, 0, 3]);
+    assert_eq!(receive(&mut server).len(), SECTOR_SIZE);
+    send(&mut server, &(3 * SECTOR_SIZE as u16).to_be_bytes());
+    assert_eq!(receive(&mut server), [E_OK]);
+
+    // past the end of the image
+    send(&mut server, &[OP_READEX, 1, 0x01, 0, 0]);
+    assert_eq!(receive(&mut server), [0; SECTOR_SIZE]);
+    send(&mut server, &[0, 0]);
+    assert_eq!(receive(&mut server), [E_OK]);
+
+    // no disk in the drive
+    send(&mut server, &[OP_READEX, 0, 0, 0, 0]);
+    assert_eq!(receive(&mut server), [0; SECTOR_SIZE]);
+    send(&mut server, &[0, 0]);
+    assert_eq!(receive(&mut server), [E_NOTRDY]);
+}
+
+#[test]
+fn test_ignored_requests() {
+    let mut server = Server::new();
+    server.mount(1, Box::new(numbered_disk(4)));
+    // the whole of each request is read, so its arguments aren't taken for opcodes
+    // (OP_PRINT's byte is OP_READEX here)
+    send(&mut server, &[OP_PRINT, OP_READEX, OP_PRINTFLUSH]);
+    send(&mut server, &[OP_WIREBUG_MODE]);
+    send(&mut server, &[OP_TIME; 23]);
+    assert!(receive(&mut server).is_empty());
+    send(&mut server, &[OP_READEX, 1, 0, 0, 1]);
+    assert_eq!(receive(&mut server), [1; SECTOR_SIZE]);
+    send(&mut server, &(SECTOR_SIZE as u16).to_be_bytes());
+    assert_eq!(receive(&mut server), [E_OK]);
+}
+
+#[test]
+fn test_write() {
+    let mut server = Server::new();
+    server.mount(0, Box::new(numbered_disk(1)));
+    let sector = [0x5a; SECTOR_SIZE];
+    let write = |op, lsn, sum: u16| {
+        let mut req = vec![op, 0, 0, 0, lsn];
+        req.extend(sector);
+        req.extend(sum.to_be_bytes());
+        req
+    };
+    send(&mut server, &write(OP_WRITE, 2, checksum(&sector)));
+    assert_eq!(receive(&mut server), [E_OK]);
+    send(&mut server, &write(OP_WRITE, 1, 0));
+    assert_eq!(receive(&mut server), [E_CRC]);
+    send(&mut server, &write(OP_REWRITE, 1, checksum(&sector)));
+    assert_eq!(receive(&mut server), [E_OK]);
+
+    // the image grew to hold sector 2
+    let mut disk = server.eject(0).unwrap();
+    let mut buf = [0u8; SECTOR_SIZE];
+    for (lsn, expected) in [(0, 0), (1, 0x5a), (2, 0x5a)] {
+        disk.read_sector(lsn, &mut buf).unwrap();
+        assert_eq!(buf, [expected; SECTOR_SIZE]);
+    }
+    send(&mut server, &write(OP_WRITE, 0, checksum(&sector)));
+    assert_eq!(receive(&mut server), [E_NOTRDY]);
+
+    let mut protected = numbered_disk(1);
+    protected.write_protect = true;
+    server.mount(0, Box::new(protected));
+    send(&mut server, &write(OP_WRITE, 0, checksum(&sector)));
+    assert_eq!(receive(&mut server), [E_WP]);
+}
+
+#[test]
+fn test_reset() {
+    let mut server = Server::new();
+    server.mount(0, Box::new(numbered_disk(1)));
+    // unknown opcodes are ignored
+    send(&mut server, &[0x99, OP_NOP, OP_INIT]);
+    assert!(receive(&mut server).is_empty());
+    // the CoCo restarts in the middle of a read
+    send(&mut server, &[OP_READEX, 0, 0, 0, 0]);
+    assert_eq!(server.read_byte(), Some(0));
+    send(&mut server, &[0, 0, OP_RESET1]);
+    assert!(receive(&mut server).is_empty());
+    send(&mut server, &[OP_DWINIT, 0x00]);
+    assert_eq!(receive(&mut server), [4]);
+}
+
+#[test]
+fn test_from_dir() {
+    let dir = std::env::temp_dir().join(format!("coco-dw-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("b.dsk"), [0x22; SECTOR_SIZE]).unwrap();
+    std::fs::write(dir.join("a.DSK"), [0x11; SECTOR_SIZE]).unwrap();
+    std::fs::write(dir.join("notes.txt"), b"not a disk").unwrap();
+    let mut server = Server::from_dir(dir.to_str().unwrap()).unwrap();
+    assert!(server.is_mounted(0) && server.is_mounted(1) && !server.is_mounted(2));
+
+    send(&mut server, &[OP_READEX, 1, 0, 0, 0]);
+    assert_eq!(receive(&mut server), [0x22; SECTOR_SIZE]);
+    send(&mut server, &(0x22 * SECTOR_SIZE as u16).to_be_bytes());
+    assert_eq!(receive(&mut server), [E_OK]);
+
+    // writes go to the file
+    let mut req = vec![OP_WRITE, 0, 0, 0, 1];
+    req.extend([0x33; SECTOR_SIZE]);
+    req.extend((0x33 * SECTOR_SIZE as u16).to_be_bytes());
+    send(&mut server, &req);
+    assert_eq!(receive(&mut server), [E_OK]);
+    drop(server);
+    let a = std::fs::read(dir.join("a.DSK")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(a.len(), 2 * SECTOR_SIZE);
+    assert_eq!(a[SECTOR_SIZE..], [0x33; SECTOR_SIZE]);
+}
+
+#[test]
+fn test_becker_port() {
+    let mut server = Server::new();
+    server.mount(0, Box::new(numbered_disk(2)));
+    let mut core = create_core();
+    core.attach_becker(Box::new(server));
+    assert_eq!(core._read_u8(AccessType::System, 0xff41, None).unwrap(), 0);
+
+    // reads LSN 1 of drive 0 into $2000 and its error code into $2100
+    let src = "
+    org $1000
+    lda #$d2
+    sta $ff42
+    clra
+    sta $ff42
+    sta $ff42
+    sta $ff42
+    inca
+    sta $ff42
+    ldx #$2000
+    ldu #0
+loop
+    lda $ff41
+    bita #2
+    beq loop
+    ldb $ff42
+    stb ,x+
+    clra
+    leau d,u
+    cmpx #$2100
+    bne loop
+    tfr u,d
+    sta $ff42
+    stb $ff42
+wait
+    lda $ff41
+    bita #2
+    beq wait
+    lda $ff42
+    sta $2100
+    exit
+";
+    let assembler = assembler::Assembler::new(&instructions::Instance::new(0, None));
+    let program = assembler.assemble(src.lines()).unwrap();
+    core.load_image(&program.to_image()).unwrap();
+    core.reg.pc = 0x1000;
+    core.reg.s = 0x8000;
+    core.exec().unwrap();
+
+    for addr in 0x2000..0x2100 {
+        assert_eq!(core._read_u8(AccessType::System, addr, None).unwrap(), 1);
+    }
+    assert_eq!(
+        core._read_u8(AccessType::System, 0x2100, None).unwrap(),
+        E_OK
+    );
+    // nothing left to read
+    assert_eq!(core._read_u8(AccessType::System, 0xff41, None).unwrap(), 0);
+    assert_eq!(core._read_u8(AccessType::System, 0xff42, None).unwrap(), 0);
+}
+
+#[test]
+fn test_tcp_client() {
+    use crate::serial::tcp::TcpClient;
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let mut client = TcpClient::connect(&addr).unwrap();
+    assert!(client.connected());
+    let (mut stream, _) = listener.accept().unwrap();
+    stream
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    client.write_byte(OP_DWINIT);
+    let mut buf = [0u8; 1];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [OP_DWINIT]);
+    stream.write_all(&[4]).unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let mut received = None;
+    while received.is_none() && std::time::Instant::now() < deadline {
+        received = client.read_byte();
+    }
+    assert_eq!(received, Some(4));
+
+    // the server going away is noticed on the next read
+    drop(stream);
+    while client.connected() && std::time::Instant::now() < deadline {
+        client.read_byte();
+    }
+    assert!(!client.connected());
+    drop(listener);
+    assert!(TcpClient::connect(&addr).is_err());
+}
+
+#[test]
+fn test_time() {
+    assert_eq!(DateTime::from_unix(0).to_bytes(), [70, 1, 1, 0, 0, 0]);
+    // a leap day
+    assert_eq!(
+        DateTime::from_unix(1_709_217_045),
+        DateTime {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hour: 14,
+            minute: 30,
+            second: 45
+        }
+    );
+    let mut server = Server::new();
+    server.set_clock(Box::new(|| DateTime::from_unix(951_782_400)));
+    send(&mut server, &[OP_TIME]);
+    assert_eq!(receive(&mut server), [100, 2, 29, 0, 0, 0]);
+    // status calls have no answer
+    send(&mut server, &[OP_GETSTAT, 0, 0x26, OP_SETSTAT, 0, 0x29]);
+    assert!(receive(&mut server).is_empty());
+}
+
+#[test]
+fn test_named_objects() {
+    let dir = std::env::temp_dir().join(format!("coco-dw-names-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("nos9.dsk"), [0x44; SECTOR_SIZE]).unwrap();
+    let mut server = Server::from_dir(dir.to_str().unwrap()).unwrap();
+    // nos9.dsk is in drive 0 and the names go in the free drives after it
+    let mount = |server: &mut Server, op: u8, name: &str| {
+        let mut req = vec![op, name.len() as u8];
+        req.extend(name.bytes());
+        send(server, &req);
+        receive(server)
+    };
+    assert_eq!(mount(&mut server, OP_NAMEOBJ_MOUNT, "nos9.dsk"), [1]);
+    assert_eq!(server.mounted_name(1), Some("nos9.dsk"));
+    assert_eq!(mount(&mut server, OP_NAMEOBJ_MOUNT, "nos9.dsk"), [1]);
+    assert_eq!(mount(&mut server, OP_NAMEOBJ_MOUNT, "missing.dsk"), [0]);
+    assert_eq!(mount(&mut server, OP_NAMEOBJ_CREATE, "new.dsk"), [2]);
+    assert_eq!(mount(&mut server, OP_NAMEOBJ_CREATE, "new.dsk"), [2]);
+    assert_eq!(mount(&mut server, OP_NAMEOBJ_MOUNT, "../nos9.dsk"), [0]);
+    assert_eq!(mount(&mut server, OP_NAMEOBJ_MOUNT, ""), [0]);
+
+    send(&mut server, &[OP_READEX, 1, 0, 0, 0]);
+    assert_eq!(receive(&mut server), [0x44; SECTOR_SIZE]);
+    send(&mut server, &(0x44 * SECTOR_SIZE as u16).to_be_bytes());
+    assert_eq!(receive(&mut server), [E_OK]);
+    let mut req = vec![OP_WRITE, 2, 0, 0, 0];
+    req.extend([0x55; SECTOR_SIZE]);
+    req.extend((0x55 * SECTOR_SIZE as u16).to_be_bytes());
+    send(&mut server, &req);
+    assert_eq!(receive(&mut server), [E_OK]);
+    assert!(server.eject(2).is_some());
+    assert_eq!(server.mounted_name(2), None);
+    let created = std::fs::read(dir.join("new.dsk")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(created, [0x55; SECTOR_SIZE]);
+
+    // without an object store nothing can be mounted by name
+    assert_eq!(mount(&mut Server::new(), OP_NAMEOBJ_MOUNT, "nos9.dsk"), [0]);
+}
+
+#[test]
+fn test_serial_channels() {
+    use crate::serial::Pipe;
+    let mut server = Server::new();
+    let pipe = Pipe::new();
+    server.attach_channel(2, Box::new(pipe.clone()));
+    // nothing is sent on a channel the CoCo hasn't opened
+    pipe.send(b"x");
+    send(&mut server, &[OP_SERREAD]);
+    assert_eq!(receive(&mut server), [0, 0]);
+    send(&mut server, &[OP_SERINIT, 2]);
+    assert!(server.channel_open(2));
+    send(&mut server, &[OP_SERREAD]);
+    assert_eq!(receive(&mut server), [3, b'x']);
+    send(&mut server, &[OP_SERREAD]);
+    assert_eq!(receive(&mut server), [0, 0]);
+
+    pipe.send(b"hello");
+    send(&mut server, &[OP_SERREAD]);
+    assert_eq!(receive(&mut server), [19, 5]);
+    send(&mut server, &[OP_SERREADM, 2, 3]);
+    assert_eq!(receive(&mut server), b"hel");
+    send(&mut server, &[OP_SERREADM, 2, 3]);
+    assert_eq!(receive(&mut server), b"lo\0");
+
+    // the device descriptor sent with SS.ComSt isn't taken for opcodes
+    let mut setstat = vec![OP_SERSETSTAT, 2, 0x28];
+    setstat.extend([OP_SERWRITE; 26]);
+    send(&mut server, &setstat);
+    send(&mut server, &[OP_SERGETSTAT, 2, 0x01]);
+    send(
+        &mut server,
+        &[OP_SERWRITE, 2, b'a', OP_SERWRITEM, 2, 2, b'b', b'c'],
+    );
+    send(
+        &mut server,
+        &[OP_FASTWRITE + 2, b'd', OP_FASTWRITE + 3, b'e'],
+    );
+    assert!(receive(&mut server).is_empty());
+    assert_eq!(pipe.take_output(), b"abcd");
+
+    // the CoCo is told when the other end goes away
+    assert!(server.detach_channel(2).is_some());
+    send(&mut server, &[OP_SERREAD]);
+    assert_eq!(receive(&mut server), [16, 2]);
+    assert!(!server.channel_open(2));
+    send(&mut server, &[OP_SERREAD]);
+    assert_eq!(receive(&mut server), [0, 0]);
+}
@@ -1,14 +1,35 @@
-use crate::{registers, String};
+use crate::{registers, Box, String};
 use core::{convert::From, fmt};
 
-/// Simple custom Error for the 6809 project
+/// The text of an Error message.
+/// On the host this is a String. On the Pico it's a fixed-size inline buffer so that
+/// formatting a message (e.g. for a runtime error raised while the CPU is running) never
+/// allocates beyond the Error's one box.
+#[cfg(not(target_os = "none"))]
+pub type ErrorText = String;
+#[cfg(target_os = "none")]
+pub type ErrorText = InlineText<ERROR_TEXT_CAPACITY>;
+
+/// Maximum length of an error message on platforms that use InlineText
+pub const ERROR_TEXT_CAPACITY: usize = 80;
+
+/// Simple custom Error for the 6809 project.
+/// Only the kind is kept inline; the rest (see [ErrorData], which Error derefs to) is
+/// boxed so that a `Result<_, Error>` stays small on the CPU's hot path.
+#[derive(Clone)]
 pub struct Error {
     pub kind: ErrorKind,
+    data: Box<ErrorData>,
+}
+/// The message, register context and source location of an [Error]
+#[derive(Clone)]
+pub struct ErrorData {
     pub ctx: Option<registers::Set>,
-    pub msg: String,
+    pub msg: ErrorText,
+    pub loc: Option<SourceLoc>, // where in the source the error occurred (if known)
 }
 #[allow(unused)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorKind {
     /// error in syntax of assembly code
     Syntax,
@@ -28,24 +49,135 @@ pub enum ErrorKind {
     General,
 }
 
+/// The location in an assembly language source file that an Error refers to.
+/// Line and column numbers start at 1; a column of 0 means the column is unknown.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceLoc {
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub source: Option<String>, // the text of the offending line (used to show an excerpt)
+    pub trace: Option<String>,  // the macro expansion(s) that produced the line (if any)
+}
+
 impl Error {
     pub fn new(kind: ErrorKind, ctx: Option<registers::Set>, message: &str) -> Error {
         Error {
             kind,
-            ctx,
-            msg: String::from(message),
+            data: Box::new(ErrorData {
+                ctx,
+                msg: ErrorText::from(message),
+                loc: None,
+            }),
+        }
+    }
+    /// Creates an Error with a formatted message (this is what the error macros use).
+    pub fn from_args(kind: ErrorKind, ctx: Option<registers::Set>, args: fmt::Arguments) -> Error {
+        let mut msg = ErrorText::new();
+        // writing to ErrorText can't fail (InlineText truncates instead)
+        let _ = fmt::Write::write_fmt(&mut msg, args);
+        Error {
+            kind,
+            data: Box::new(ErrorData {
+                ctx,
+                msg,
+                loc: None,
+            }),
+        }
+    }
+    /// Sets the source line for this error unless it already has one.
+    pub fn at_line(mut self, line: usize) -> Error {
+        let loc = self.loc.get_or_insert_with(Default::default);
+        if loc.line == 0 {
+            loc.line = line;
         }
+        self
+    }
+    /// Sets the column and the text of the source line (used to show an excerpt).
+    pub fn with_source(mut self, column: usize, source: &str) -> Error {
+        let loc = self.loc.get_or_insert_with(Default::default);
+        if loc.source.is_none() {
+            loc.column = column;
+            loc.source = Some(String::from(source));
+        }
+        self
+    }
+    /// Sets the name of the source file for this error unless it already has one.
+    pub fn in_file(mut self, file: &str) -> Error {
+        self.set_file(file);
+        self
+    }
+    pub fn set_file(&mut self, file: &str) {
+        let loc = self.loc.get_or_insert_with(Default::default);
+        if loc.file.is_none() {
+            loc.file = Some(String::from(file));
+        }
+    }
+    /// Notes the macro expansion(s) that produced the offending line unless a note has
+    /// already been made (the innermost expansion is the one that matters).
+    pub fn with_trace(mut self, trace: &str) -> Error {
+        let loc = self.loc.get_or_insert_with(Default::default);
+        if loc.trace.is_none() {
+            loc.trace = Some(String::from(trace));
+        }
+        self
+    }
+    /// Writes the location of the error followed by the message and, when the text of the
+    /// source line is known, an excerpt with a caret pointing at the offending column.
+    fn write_located(&self, f: &mut fmt::Formatter, loc: &SourceLoc) -> fmt::Result {
+        if let Some(file) = &loc.file {
+            write!(f, "{}:", file)?;
+        } else {
+            write!(f, "line ")?;
+        }
+        write!(f, "{}", loc.line)?;
+        if loc.column > 0 {
+            write!(f, ":{}", loc.column)?;
+        }
+        write!(f, ": {}", self.msg)?;
+        if let Some(source) = &loc.source {
+            let gutter = loc.line.checked_ilog10().unwrap_or(0) as usize + 1;
+            write!(f, "\n{:w$} |", "", w = gutter)?;
+            write!(f, "\n{} | {}", loc.line, source)?;
+            if loc.column > 0 {
+                // expand tabs in the same positions so that the caret lines up
+                write!(f, "\n{:w$} | ", "", w = gutter)?;
+                for c in source.chars().take(loc.column - 1) {
+                    f.write_str(if c == '\t' { "\t" } else { " " })?;
+                }
+                f.write_str("^")?;
+            }
+        }
+        if let Some(trace) = &loc.trace {
+            write!(f, "\n  = note: {}", trace)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::ops::Deref for Error {
+    type Target = ErrorData;
+    fn deref(&self) -> &ErrorData {
+        &self.data
+    }
+}
+impl core::ops::DerefMut for Error {
+    fn deref_mut(&mut self) -> &mut ErrorData {
+        &mut self.data
     }
 }
 
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {}", red!("cpu::Error"), self.msg)
+        write!(f, "{}: {}", red!("cpu::Error"), self)
     }
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut res = write!(f, "{}", self.msg);
+        let mut res = match &self.loc {
+            Some(loc) => self.write_located(f, loc),
+            None => write!(f, "{}", self.msg),
+        };
         if res.is_ok() {
             if let Some(ctx) = self.ctx {
                 res = write!(f, "\nContext: {} -> ({})", ctx, ctx.cc);
@@ -54,4 +186,74 @@ impl fmt::Display for Error {
         res
     }
 }
+impl From<fmt::Error> for Error {
+    /// Formatting errors come from writing output such as listings
+    fn from(_: fmt::Error) -> Self {
+        Error::new(ErrorKind::IO, None, "formatting error")
+    }
+}
 
+/// A string stored inline in a fixed-size buffer. Text that doesn't fit is truncated
+/// (at a char boundary) rather than allocating.
+#[derive(Clone, Copy)]
+pub struct InlineText<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+impl<const N: usize> InlineText<N> {
+    pub const fn new() -> Self {
+        InlineText {
+            buf: [0u8; N],
+            len: 0,
+        }
+    }
+    pub fn as_str(&self) -> &str {
+        // only whole chars are ever copied into buf so this can't fail
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+impl<const N: usize> Default for InlineText<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<const N: usize> fmt::Write for InlineText<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut end = (N - self.len).min(s.len());
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buf[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+impl<const N: usize> From<&str> for InlineText<N> {
+    fn from(s: &str) -> Self {
+        let mut text = Self::new();
+        let _ = fmt::Write::write_str(&mut text, s);
+        text
+    }
+}
+impl<const N: usize> core::ops::Deref for InlineText<N> {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+impl<const N: usize> fmt::Display for InlineText<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl<const N: usize> fmt::Debug for InlineText<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
@@ -0,0 +1,132 @@
+//! A format-neutral binary image of a program: runs of bytes at given addresses plus an
+//! optional entry (exec) address.
+//!
+//! An [Image] is what the object file formats have in common so it's what they're written
+//! from and read into: Intel hex (see [crate::hex]), CoCo Disk Extended BASIC `.BIN` files
+//! (see [crate::decb]), Motorola S-records (see [crate::srec]) and raw ROM images (below).
+use crate::hex::{HexRecord, HexRecordCollection, HexRecordType};
+use crate::{Error, ErrorKind, Vec};
+
+/// A run of bytes at contiguous addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: u16,
+    pub data: Vec<u8>,
+}
+impl Segment {
+    /// The address just past the end of the segment (as a usize so it can be 0x10000)
+    pub fn end(&self) -> usize {
+        self.addr as usize + self.data.len()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub exec: Option<u16>, // the entry point (if known)
+}
+impl Image {
+    pub fn new() -> Self {
+        Image::default()
+    }
+    /// Adds bytes at the given address. Bytes that continue the last segment are appended
+    /// to it; otherwise they start a new segment.
+    pub fn add(&mut self, addr: u16, data: &[u8]) -> Result<(), Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if addr as usize + data.len() > 0x10000 {
+            return Err(err!(
+                ErrorKind::Memory,
+                None,
+                "{} bytes at ${:04X} extend past the end of memory",
+                data.len(),
+                addr
+            ));
+        }
+        match self.segments.last_mut() {
+            Some(seg) if seg.end() == addr as usize => seg.data.extend_from_slice(data),
+            _ => self.segments.push(Segment {
+                addr,
+                data: data.to_vec(),
+            }),
+        }
+        Ok(())
+    }
+    /// The total number of bytes in the image
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|s| s.data.len()).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns the lowest address and the address just past the highest byte in the image
+    pub fn extent(&self) -> Option<(u16, usize)> {
+        let start = self.segments.iter().map(|s| s.addr).min()?;
+        let end = self.segments.iter().map(Segment::end).max()?;
+        Some((start, end))
+    }
+
+    /// Returns a raw image of `size` bytes that starts at `base` (e.g. a cartridge ROM).
+    /// Addresses that the image doesn't cover are filled with `fill`. It's an error for any
+    /// of the image's bytes to fall outside of the raw image.
+    pub fn to_raw(&self, base: u16, size: usize, fill: u8) -> Result<Vec<u8>, Error> {
+        let mut raw = vec![fill; size];
+        for seg in &self.segments {
+            let start = (seg.addr as usize)
+                .checked_sub(base as usize)
+                .filter(|start| start + seg.data.len() <= size)
+                .ok_or_else(|| {
+                    err!(
+                        ErrorKind::Memory,
+                        None,
+                        "{} bytes at ${:04X} don't fit in a {} byte image at ${:04X}",
+                        seg.data.len(),
+                        seg.addr,
+                        size,
+                        base
+                    )
+                })?;
+            raw[start..start + seg.data.len()].copy_from_slice(&seg.data);
+        }
+        Ok(raw)
+    }
+    /// Returns an image that holds the raw bytes at `addr`
+    pub fn from_raw(addr: u16, bytes: &[u8]) -> Result<Image, Error> {
+        let mut image = Image::new();
+        image.add(addr, bytes)?;
+        Ok(image)
+    }
+
+    /// Returns the image as Intel hex records (which Core::load_hex can load).
+    /// I8HEX has no record for an entry point so `exec` isn't included.
+    pub fn to_hex(&self) -> HexRecordCollection {
+        const BYTES_PER_RECORD: usize = 16;
+        let mut hex = HexRecordCollection::new();
+        for seg in &self.segments {
+            let mut addr = seg.addr;
+            for chunk in seg.data.chunks(BYTES_PER_RECORD) {
+                // can't fail; there's no EOF record yet
+                let _ = hex.add_record(HexRecord::from_data(addr, chunk));
+                addr = addr.wrapping_add(chunk.len() as u16);
+            }
+        }
+        hex.add_eof();
+        hex
+    }
+    pub fn from_hex(hex: &HexRecordCollection) -> Result<Image, Error> {
+        let mut image = Image::new();
+        for r in hex.iter() {
+            match r.record_type {
+                HexRecordType::Data => {
+                    if let Some(data) = r.data.as_ref() {
+                        image.add(r.address, data)?;
+                    }
+                }
+                HexRecordType::EndOfFile => break,
+                _ => {}
+            }
+        }
+        Ok(image)
+    }
+}
@@ -0,0 +1,213 @@
+use crate::cpu_test::create_core;
+use crate::image::Image;
+use crate::srec::SrecFormat;
+use crate::*;
+
+fn read_u8(core: &Core, addr: u16) -> u8 {
+    core._read_u8(crate::memory::AccessType::Generic, addr, None)
+        .unwrap()
+}
+
+/// Two segments with a gap between them and an entry point
+fn sample_image() -> Image {
+    let mut image = Image::new();
+    image.add(0x1000, &[0x86, 0x55]).unwrap();
+    // contiguous with the first segment
+    image.add(0x1002, &[0x39]).unwrap();
+    image.add(0x2000, &(0..40).collect::<Vec<u8>>()).unwrap();
+    image.exec = Some(0x1000);
+    image
+}
+
+#[test]
+fn test_image_merges_contiguous_data() {
+    let image = sample_image();
+    assert_eq!(image.segments.len(), 2);
+    assert_eq!(image.segments[0].data, [0x86, 0x55, 0x39]);
+    assert_eq!(image.len(), 43);
+    assert_eq!(image.extent(), Some((0x1000, 0x2028)));
+    assert!(Image::new().add(0xfff0, &[0; 17]).is_err());
+}
+
+#[test]
+fn test_decb_round_trip() {
+    let image = sample_image();
+    let bin = decb::write(&image);
+    assert_eq!(&bin[..8], [0x00, 0x00, 0x03, 0x10, 0x00, 0x86, 0x55, 0x39]);
+    assert_eq!(&bin[bin.len() - 5..], [0xff, 0x00, 0x00, 0x10, 0x00]);
+    assert_eq!(decb::read(&bin).unwrap(), image);
+}
+
+#[test]
+fn test_decb_errors() {
+    let bin = decb::write(&sample_image());
+    // no postamble
+    assert!(decb::read(&bin[..bin.len() - 5]).is_err());
+    // truncated data
+    assert!(decb::read(&bin[..6]).is_err());
+    // unknown record type
+    let mut bad = bin.clone();
+    bad[0] = 0x55;
+    assert!(decb::read(&bad).is_err());
+}
+
+#[test]
+fn test_srec_round_trip() {
+    let image = sample_image();
+    let s19 = srec::write(&image, SrecFormat::S19, "TEST");
+    let lines: Vec<&str> = s19.lines().collect();
+    assert_eq!(lines[0], "S007000054455354B8");
+    assert_eq!(lines[1], "S1061000865539D5");
+    assert_eq!(lines[lines.len() - 2], "S5030004F8");
+    assert_eq!(lines[lines.len() - 1], "S9031000EC");
+    assert_eq!(srec::read(&s19).unwrap(), image);
+
+    let s28 = srec::write(&image, SrecFormat::S28, "TEST");
+    assert!(s28.lines().nth(1).unwrap().starts_with("S207001000"));
+    assert!(s28.lines().last().unwrap().starts_with("S804001000"));
+    assert_eq!(srec::read(&s28).unwrap(), image);
+}
+
+#[test]
+fn test_srec_errors() {
+    // bad checksum
+    assert!(srec::read("S1061000865539D6").is_err());
+    // wrong byte count
+    assert!(srec::read("S1071000865539D5").is_err());
+    // address that doesn't fit in 16 bits
+    assert!(srec::read("S20501000000F9").is_err());
+    // invalid record type
+    assert!(srec::read("X1061000865539D5").is_err());
+}
+
+#[test]
+fn test_raw_image() {
+    let image = sample_image();
+    let raw = image.to_raw(0x1000, 0x2000, 0xff).unwrap();
+    assert_eq!(raw.len(), 0x2000);
+    assert_eq!(&raw[..4], [0x86, 0x55, 0x39, 0xff]);
+    assert_eq!(raw[0x1027], 39);
+    assert_eq!(raw[0x1028], 0xff);
+    // the second segment doesn't fit in a 4K image
+    assert!(image.to_raw(0x1000, 0x1000, 0xff).is_err());
+    let from_raw = Image::from_raw(0xc000, &raw).unwrap();
+    assert_eq!(from_raw.extent(), Some((0xc000, 0xe000)));
+}
+
+#[test]
+fn test_hex_round_trip() {
+    let mut image = sample_image();
+    let hex = image.to_hex();
+    // I8HEX doesn't hold the entry point
+    image.exec = None;
+    assert_eq!(Image::from_hex(&hex).unwrap(), image);
+}
+
+#[test]
+fn test_core_loaders() {
+    let image = sample_image();
+    let mut core = create_core();
+    assert_eq!(core.load_decb(&decb::write(&image)).unwrap(), 0x1000);
+    assert_eq!(read_u8(&core, 0x1001), 0x55);
+    assert_eq!(read_u8(&core, 0x2027), 39);
+
+    let mut core = create_core();
+    let s19 = srec::write(&image, SrecFormat::S19, "");
+    assert_eq!(core.load_srec(&s19).unwrap(), Some(0x1000));
+    assert_eq!(read_u8(&core, 0x1002), 0x39);
+
+    let mut core = create_core();
+    assert_eq!(core.load_raw(&[1, 2, 3], 0xfffd).unwrap(), 3);
+    // (the top of the address space is mapped to the vectors so look at RAM directly)
+    assert_eq!(core.raw_ram[0xffff], 3);
+    assert!(core.load_raw(&[1, 2, 3], 0xfffe).is_err());
+}
+
+#[test]
+fn test_program_image() {
+    let assembler = assembler::Assembler::new(&instructions::Instance::new(0, None));
+    let program = assembler
+        .assemble([
+            "    org $3000",
+            "data fcb 1,2",
+            "start",
+            "    lda data",
+            "    rts",
+            "    end start",
+        ])
+        .unwrap();
+    let image = program.to_image();
+    assert_eq!(image.exec, Some(0x3002));
+    assert_eq!(image.segments[0].data, [1, 2, 0xb6, 0x30, 0x00, 0x39]);
+
+    let mut core = create_core();
+    assert_eq!(core.load_decb(&decb::write(&image)).unwrap(), 0x3002);
+    assert_eq!(read_u8(&core, 0x3002), 0xb6);
+}
+
+fn sample_program() -> program::Program {
+    let assembler = assembler::Assembler::new(&instructions::Instance::new(0, None));
+    assembler
+        .assemble([
+            "count equ 2",
+            "    org $3000",
+            "data fcb 1,2,3,4,5",
+            "start",
+            "    lda data+count",
+            "    rts",
+            "    end start",
+        ])
+        .unwrap()
+}
+
+#[test]
+fn test_program_listing() {
+    let mut listing = String::new();
+    sample_program().write_listing(&mut listing).unwrap();
+    let expected = [
+        "LINE   ADDR  BYTES        CYC  SOURCE",
+        "    1  =0002                   count equ 2",
+        "    2  3000                        org $3000",
+        "    3  3000  01 02 03 04       data fcb 1,2,3,4,5",
+        // bytes that don't fit on the line get a row of their own
+        "       3004  05",
+        "    4  3005                    start",
+        "    5  3005  B6 30 02       4      lda data+count",
+        "    6  3008  39             1      rts",
+        "    7  3009                        end start",
+        "",
+        "SYMBOL  VALUE  DEFINED  REFERENCES",
+        "count   0002        1  5",
+        "data    3000        3  5",
+        "start   3005        4  7",
+    ];
+    assert_eq!(listing.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn test_program_symbol_table() {
+    let mut table = String::new();
+    sample_program().write_symbol_table(&mut table).unwrap();
+    let expected = [
+        "SYMBOL  VALUE  DEFINED  REFERENCES",
+        "count   0002        1  5",
+        "data    3000        3  5",
+        "start   3005        4  7",
+    ];
+    assert_eq!(table.lines().collect::<Vec<_>>(), expected);
+
+    let mut symbols = String::new();
+    sample_program().write_symbols(&mut symbols).unwrap();
+    // only labels that name an address go in the symbol file
+    assert_eq!(symbols, "3000,data\n3005,start\n");
+}
+
+#[test]
+fn test_program_hex() {
+    let hex: String = sample_program()
+        .to_hex()
+        .iter()
+        .map(|r| r.to_string())
+        .collect();
+    assert_eq!(hex, ":093000000102030405b630023997\n:00000001ff\n");
+}
@@ -14,3 +14,16 @@ pub trait InputDevice {
     /// Poll the device for new input.
     fn poll(&mut self) -> Option<InputEvent>;
 }
+
+// Keys are reported as ASCII codes. Keys that have none are reported as these codes.
+pub const KEY_UP: u8 = 0x80;
+pub const KEY_DOWN: u8 = 0x81;
+pub const KEY_LEFT: u8 = 0x82;
+pub const KEY_RIGHT: u8 = 0x83;
+pub const KEY_HOME: u8 = 0x84;
+/// F1 to F12 are KEY_F1 to KEY_F1 + 11
+pub const KEY_F1: u8 = 0x90;
+pub const KEY_F12: u8 = KEY_F1 + 11;
+pub const KEY_ESC: u8 = 0x1b;
+/// Opens and closes the on-screen menu (see [crate::osd])
+pub const MENU_KEY: u8 = KEY_F12;
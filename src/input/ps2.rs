@@ -1,4 +1,4 @@
-use super::{InputDevice, InputEvent};
+use super::*;
 use hal::pio::{PIOExt, StateMachineIndex, UninitStateMachine};
 use pio::pio_asm;
 use rp235x_hal as hal;
@@ -7,6 +7,8 @@ pub struct Ps2Keyboard<P: PIOExt, SM: StateMachineIndex> {
     rx: hal::pio::Rx<(P, SM)>,
     shift_reg: u16,
     break_code: bool,
+    // the next code follows an 0xE0 prefix
+    extended: bool,
 }
 
 impl<P: PIOExt, SM: StateMachineIndex> Ps2Keyboard<P, SM> {
@@ -63,6 +65,7 @@ impl<P: PIOExt, SM: StateMachineIndex> Ps2Keyboard<P, SM> {
             rx,
             shift_reg: 0,
             break_code: false,
+            extended: false,
         }
     }
 
@@ -107,22 +110,58 @@ impl<P: PIOExt, SM: StateMachineIndex> Ps2Keyboard<P, SM> {
             0x5A => Some(b'\n'), // Enter
             0x29 => Some(b' '),  // Space
             0x66 => Some(0x08),  // Backspace
+            0x76 => Some(KEY_ESC),
+            0x05 => Some(KEY_F1),
+            0x06 => Some(KEY_F1 + 1),
+            0x04 => Some(KEY_F1 + 2),
+            0x0C => Some(KEY_F1 + 3),
+            0x03 => Some(KEY_F1 + 4),
+            0x0B => Some(KEY_F1 + 5),
+            0x83 => Some(KEY_F1 + 6),
+            0x0A => Some(KEY_F1 + 7),
+            0x01 => Some(KEY_F1 + 8),
+            0x09 => Some(KEY_F1 + 9),
+            0x78 => Some(KEY_F1 + 10),
+            0x07 => Some(KEY_F12),
+            _ => None,
+        }
+    }
+
+    // codes that follow 0xE0
+    fn map_extended(&self, code: u8) -> Option<u8> {
+        match code {
+            0x75 => Some(KEY_UP),
+            0x72 => Some(KEY_DOWN),
+            0x6B => Some(KEY_LEFT),
+            0x74 => Some(KEY_RIGHT),
+            0x6C => Some(KEY_HOME),
+            0x5A => Some(b'\n'), // keypad Enter
             _ => None,
         }
     }
 
     fn decode_scancode(&mut self, code: u8) -> Option<InputEvent> {
+        if code == 0xE0 {
+            self.extended = true;
+            return None;
+        }
         if code == 0xF0 {
             self.break_code = true;
             return None;
         }
+        let key = if self.extended {
+            self.map_extended(code)
+        } else {
+            self.map_scancode(code)
+        };
+        self.extended = false;
         if self.break_code {
             self.break_code = false;
             // Key Release
-            return self.map_scancode(code).map(InputEvent::Release);
+            return key.map(InputEvent::Release);
         }
         // Key Press
-        self.map_scancode(code).map(InputEvent::Press)
+        key.map(InputEvent::Press)
     }
 }
 
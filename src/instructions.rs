@@ -232,7 +232,7 @@ pub mod PPPostByte {
                 if count > 1 {
                     out.push(',');
                 }
-                if using_user_stack && bit == SU {
+                if using_user_stack && mask == SU {
                     out.push('S');
                 } else {
                     out.push_str(_STR[bit as usize]);
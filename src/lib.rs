@@ -11,29 +11,71 @@ pub mod term;
 
 pub mod acia;
 pub mod assembler;
+pub mod becker;
+pub mod bitbanger;
 pub mod config;
 pub mod cpu;
 pub mod debug;
+pub mod decb;
 pub mod devmgr;
+pub mod disasm;
+pub mod drivewire;
 pub mod error;
 pub mod hex;
+pub mod image;
 pub mod input;
 pub mod instructions;
+pub mod logging;
 pub mod memory;
 pub mod obj;
+pub mod osd;
 pub mod parse;
 pub mod pia;
 #[cfg(test)]
 pub mod cpu_test;
+pub mod profile;
 pub mod program;
 pub mod registers;
+pub mod romdb;
 pub mod runtime;
 pub mod sam;
+pub mod savestate;
+pub mod serial;
+pub mod source;
+pub mod srec;
+pub mod storage;
 #[cfg(not(target_os = "none"))]
 pub mod test;
+pub mod trace;
 pub mod u8oru16;
 pub mod vdg;
 #[cfg(test)]
+pub mod acia_test;
+#[cfg(test)]
+pub mod bitbanger_test;
+#[cfg(test)]
+pub mod debug_test;
+#[cfg(test)]
+pub mod disasm_test;
+#[cfg(test)]
+pub mod drivewire_test;
+#[cfg(test)]
+pub mod image_test;
+#[cfg(test)]
+pub mod osd_test;
+#[cfg(test)]
+pub mod parse_test;
+#[cfg(test)]
+pub mod profile_test;
+#[cfg(test)]
+pub mod romdb_test;
+#[cfg(test)]
+pub mod storage_test;
+#[cfg(test)]
+pub mod test_test;
+#[cfg(test)]
+pub mod trace_test;
+#[cfg(test)]
 pub mod vdg_test;
 
 // Re-export common types for external use (like main.rs) and internal modules via use super::*;
@@ -0,0 +1,297 @@
+//! Logging facade used by the `info!`, `warn!`, `verbose_println!` and `acia_dbg!` macros.
+//!
+//! Every message belongs to a [Subsystem] and has a [Level]. Each subsystem has its own
+//! maximum level (stored in `config::ARGS`) so that, for instance, ACIA traffic can be
+//! traced without also tracing every SAM register write. Messages that pass the level
+//! check are handed to the current [LogSink]:
+//! - on the RP2350 the default sink writes to `defmt`
+//! - on the host the default sink writes to stderr (or to the `log` crate when the
+//!   `log` feature is enabled)
+//!
+//! Levels can be set with [set_level] or with a spec string such as `"info,cpu=debug,acia=trace"`
+//! (see [apply_spec]). On the RP2350 the Pico build also sets them at boot from the `log:`
+//! section of the coco.yaml on its SD card (see [LogConfig]); nothing on the host reads it.
+use crate::config;
+use core::fmt;
+use core::sync::atomic::Ordering;
+use serde::Deserialize;
+
+/// The severity of a log message (or the maximum severity that a subsystem will log)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+impl Level {
+    pub fn from_name(s: &str) -> Option<Level> {
+        const LEVELS: [Level; 6] = [
+            Level::Off,
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ];
+        LEVELS
+            .into_iter()
+            .find(|level| level.name().eq_ignore_ascii_case(s))
+    }
+    fn from_u8(n: u8) -> Level {
+        match n {
+            0 => Level::Off,
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The parts of the emulator that can be logged (and configured) separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Subsystem {
+    General = 0,
+    Cpu,
+    Sam,
+    Pia,
+    Vdg,
+    Acia,
+    Assembler,
+}
+/// The number of Subsystem variants
+pub const SUBSYSTEM_COUNT: usize = 7;
+impl Subsystem {
+    pub const ALL: [Subsystem; SUBSYSTEM_COUNT] = [
+        Subsystem::General,
+        Subsystem::Cpu,
+        Subsystem::Sam,
+        Subsystem::Pia,
+        Subsystem::Vdg,
+        Subsystem::Acia,
+        Subsystem::Assembler,
+    ];
+    pub fn from_name(s: &str) -> Option<Subsystem> {
+        Subsystem::ALL
+            .into_iter()
+            .find(|sub| sub.name().eq_ignore_ascii_case(s))
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Subsystem::General => "coco",
+            Subsystem::Cpu => "cpu",
+            Subsystem::Sam => "sam",
+            Subsystem::Pia => "pia",
+            Subsystem::Vdg => "vdg",
+            Subsystem::Acia => "acia",
+            Subsystem::Assembler => "assembler",
+        }
+    }
+}
+
+/// The current maximum level for a subsystem.
+/// Setting `config::ARGS.verbose` raises every subsystem to at least Debug.
+pub fn level(sub: Subsystem) -> Level {
+    let level = Level::from_u8(config::ARGS.log_levels[sub as usize].load(Ordering::Relaxed));
+    if config::verbose() {
+        level.max(Level::Debug)
+    } else {
+        level
+    }
+}
+
+pub fn set_level(sub: Subsystem, level: Level) {
+    config::ARGS.log_levels[sub as usize].store(level as u8, Ordering::Relaxed);
+}
+
+/// Sets the level of every subsystem
+pub fn set_all_levels(level: Level) {
+    Subsystem::ALL
+        .into_iter()
+        .for_each(|sub| set_level(sub, level));
+}
+
+#[inline(always)]
+pub fn enabled(sub: Subsystem, level: Level) -> bool {
+    level != Level::Off && level <= self::level(sub)
+}
+
+/// Applies a comma-separated list of levels, e.g. `"warn,cpu=debug,acia=trace"`.
+/// A bare level applies to every subsystem; later entries override earlier ones.
+pub fn apply_spec(spec: &str) -> Result<(), crate::Error> {
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (sub, level) = match entry.split_once('=') {
+            Some((sub, level)) => (Some(sub.trim()), level.trim()),
+            None => (None, entry),
+        };
+        let level = Level::from_name(level)
+            .ok_or_else(|| general_err!("invalid log level \"{}\"", level))?;
+        match sub {
+            Some(name) => set_level(
+                Subsystem::from_name(name)
+                    .ok_or_else(|| general_err!("invalid log subsystem \"{}\"", name))?,
+                level,
+            ),
+            None => set_all_levels(level),
+        }
+    }
+    Ok(())
+}
+
+/// The `log:` section of the coco.yaml on the Pico's SD card (applied by storage::boot), e.g.
+/// ```yaml
+/// log:
+///   level: warn
+///   acia: trace
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: Option<alloc::string::String>,
+    pub cpu: Option<alloc::string::String>,
+    pub sam: Option<alloc::string::String>,
+    pub pia: Option<alloc::string::String>,
+    pub vdg: Option<alloc::string::String>,
+    pub acia: Option<alloc::string::String>,
+    pub assembler: Option<alloc::string::String>,
+}
+impl LogConfig {
+    /// Applies the configured levels (the general level first so that subsystems can override it)
+    pub fn apply(&self) -> Result<(), crate::Error> {
+        if let Some(level) = &self.level {
+            apply_spec(level)?;
+        }
+        let subs = [
+            (Subsystem::Cpu, &self.cpu),
+            (Subsystem::Sam, &self.sam),
+            (Subsystem::Pia, &self.pia),
+            (Subsystem::Vdg, &self.vdg),
+            (Subsystem::Acia, &self.acia),
+            (Subsystem::Assembler, &self.assembler),
+        ];
+        for (sub, level) in subs {
+            if let Some(level) = level {
+                let level = Level::from_name(level)
+                    .ok_or_else(|| general_err!("invalid log level \"{}\"", level))?;
+                set_level(sub, level);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Destination for log messages
+pub trait LogSink: Sync {
+    fn log(&self, sub: Subsystem, level: Level, args: fmt::Arguments);
+}
+
+static SINK: spin::RwLock<Option<&'static dyn LogSink>> = spin::RwLock::new(None);
+
+/// Replaces the default sink for the platform
+pub fn set_sink(sink: &'static dyn LogSink) {
+    *SINK.write() = Some(sink);
+}
+
+/// Sends a message to the current sink. The caller is expected to have checked [enabled].
+pub fn log(sub: Subsystem, level: Level, args: fmt::Arguments) {
+    match *SINK.read() {
+        Some(sink) => sink.log(sub, level, args),
+        None => DEFAULT_SINK.log(sub, level, args),
+    }
+}
+
+/// Writes console output (from `print!`/`println!`), e.g. the debugger's responses.
+/// defmt frames are always whole lines so `newline` is ignored on the Pico.
+pub fn console(args: fmt::Arguments, newline: bool) {
+    #[cfg(not(target_os = "none"))]
+    {
+        use std::io::Write;
+        let mut out = std::io::stdout().lock();
+        let _ = out.write_fmt(args);
+        if newline {
+            let _ = out.write_all(b"\n");
+        } else {
+            let _ = out.flush();
+        }
+    }
+    #[cfg(target_os = "none")]
+    {
+        let _ = newline;
+        defmt::println!("{}", defmt::Display2Format(&args));
+    }
+}
+
+#[cfg(target_os = "none")]
+static DEFAULT_SINK: DefmtSink = DefmtSink;
+#[cfg(all(not(target_os = "none"), feature = "log"))]
+static DEFAULT_SINK: LogCrateSink = LogCrateSink;
+#[cfg(all(not(target_os = "none"), not(feature = "log")))]
+static DEFAULT_SINK: StderrSink = StderrSink;
+
+/// Sends messages to the host over defmt
+#[cfg(target_os = "none")]
+pub struct DefmtSink;
+#[cfg(target_os = "none")]
+impl LogSink for DefmtSink {
+    fn log(&self, sub: Subsystem, level: Level, args: fmt::Arguments) {
+        let msg = defmt::Display2Format(&args);
+        let sub = sub.name();
+        match level {
+            Level::Off => {}
+            Level::Error => defmt::error!("[{=str}] {}", sub, msg),
+            Level::Warn => defmt::warn!("[{=str}] {}", sub, msg),
+            Level::Info => defmt::info!("[{=str}] {}", sub, msg),
+            Level::Debug => defmt::debug!("[{=str}] {}", sub, msg),
+            Level::Trace => defmt::trace!("[{=str}] {}", sub, msg),
+        }
+    }
+}
+
+/// Writes messages to stderr
+#[cfg(not(target_os = "none"))]
+pub struct StderrSink;
+#[cfg(not(target_os = "none"))]
+impl LogSink for StderrSink {
+    fn log(&self, sub: Subsystem, level: Level, args: fmt::Arguments) {
+        std::eprintln!("[{} {}] {}", level, sub.name(), args);
+    }
+}
+
+/// Forwards messages to the `log` crate (using the subsystem name as the target)
+#[cfg(all(not(target_os = "none"), feature = "log"))]
+pub struct LogCrateSink;
+#[cfg(all(not(target_os = "none"), feature = "log"))]
+impl LogSink for LogCrateSink {
+    fn log(&self, sub: Subsystem, level: Level, args: fmt::Arguments) {
+        let level = match level {
+            Level::Off => return,
+            Level::Error => log::Level::Error,
+            Level::Warn => log::Level::Warn,
+            Level::Info => log::Level::Info,
+            Level::Debug => log::Level::Debug,
+            Level::Trace => log::Level::Trace,
+        };
+        log::log!(target: sub.name(), level, "{}", args);
+    }
+}
@@ -1,78 +1,99 @@
 
+// The logging macros take an optional subsystem, e.g. `info!(target: Cpu, "PC={:04X}", pc)`.
+// Messages without a target are logged under Subsystem::General. Nothing is formatted
+// unless the subsystem's level (see logging::enabled) lets the message through.
+#[doc(hidden)]
 #[macro_export]
-macro_rules! verbose_println {
-    ($($p:expr),+ $(,)?) => {{}};
-}
-
-#[macro_export]
-macro_rules! println {
-    ($($p:expr),*) => {{}};
+macro_rules! log_at {
+    ($sub:ident, $level:ident, $($arg:tt)+) => {{
+        let sub = $crate::logging::Subsystem::$sub;
+        let level = $crate::logging::Level::$level;
+        if $crate::logging::enabled(sub, level) {
+            $crate::logging::log(sub, level, format_args!($($arg)+));
+        }
+    }};
 }
 
 #[macro_export]
-macro_rules! print {
-    ($($p:expr),*) => {{}};
+macro_rules! verbose_println {
+    (target: $sub:ident, $($arg:tt)+) => { $crate::log_at!($sub, Debug, $($arg)+) };
+    ($($arg:tt)+) => { $crate::log_at!(General, Debug, $($arg)+) };
 }
 
 #[macro_export]
 macro_rules! info {
-    ($($p:expr),+ $(,)?) => {{}};
+    (target: $sub:ident, $($arg:tt)+) => { $crate::log_at!($sub, Info, $($arg)+) };
+    ($($arg:tt)+) => { $crate::log_at!(General, Info, $($arg)+) };
 }
 
 #[macro_export]
 macro_rules! warn {
-    ($($p:expr),+ $(,)?) => {{}};
+    (target: $sub:ident, $($arg:tt)+) => { $crate::log_at!($sub, Warn, $($arg)+) };
+    ($($arg:tt)+) => { $crate::log_at!(General, Warn, $($arg)+) };
 }
 
 macro_rules! acia_dbg {
-    ($($e:expr),+ $(,)?) => {{}};
+    ($($arg:tt)+) => { $crate::log_at!(Acia, Debug, $($arg)+) };
+}
+
+// print! and println! are console output (e.g. the debugger's responses) rather than logging
+// so they're never filtered.
+#[macro_export]
+macro_rules! println {
+    () => { $crate::logging::console(format_args!(""), true) };
+    ($($arg:tt)+) => { $crate::logging::console(format_args!($($arg)+), true) };
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)+) => { $crate::logging::console(format_args!($($arg)+), false) };
 }
 
-macro_rules! line_err {
-    ($line:expr, $kind:expr, $msg:expr $(,)?) => {
-        Error::new($kind, None, "")
+// The error macros accept either a format string and its args, e.g.
+// `syntax_err!("bad operand {}", op)`, or any single expression that implements Display.
+// The message is formatted directly into the Error so no temporary String is needed.
+macro_rules! err {
+    ($kind:expr, $ctx:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {
+        $crate::Error::from_args($kind, $ctx, format_args!($fmt $(, $arg)*))
+    };
+    ($kind:expr, $ctx:expr, $msg:expr $(,)?) => {
+        $crate::Error::from_args($kind, $ctx, format_args!("{}", $msg))
     };
 }
 
 macro_rules! general_err {
-    ($($msg:expr),* $(,)?) => {
-        Error::new($crate::ErrorKind::General, None, "")
+    ($($msg:tt)+) => {
+        err!($crate::ErrorKind::General, None, $($msg)+)
     };
 }
 
 macro_rules! syntax_err {
-    ($msg:expr $(,)?) => {
-        Error::new($crate::ErrorKind::Syntax, None, "")
+    ($($msg:tt)+) => {
+        err!($crate::ErrorKind::Syntax, None, $($msg)+)
     };
 }
 
 macro_rules! syntax_err_line {
-    ($line:expr, $msg:expr $(,)?) => {
-        Error::new($crate::ErrorKind::Syntax, None, "")
+    ($line:expr, $($msg:tt)+) => {
+        err!($crate::ErrorKind::Syntax, None, $($msg)+).at_line($line)
     };
 }
 
 macro_rules! syntax_err_ctx {
-    ($ctx:expr,$msg:expr $(,)?) => {
-        Error::new($crate::ErrorKind::Syntax, $ctx, "")
+    ($ctx:expr, $($msg:tt)+) => {
+        err!($crate::ErrorKind::Syntax, $ctx, $($msg)+)
     };
 }
 
 macro_rules! instruction_invalid {
-    ($ctx:expr, $($msg:expr),* $(,)?) => {
-        Error::new($crate::ErrorKind::Runtime, $ctx, "")
+    ($ctx:expr, $($msg:tt)+) => {
+        err!($crate::ErrorKind::Runtime, $ctx, $($msg)+)
     };
 }
 
 macro_rules! runtime_err {
-    ($ctx:expr, $($msg:expr),* $(,)?) => {
-        Error::new($crate::ErrorKind::Runtime, $ctx, "")
-    };
-}
-
-macro_rules! err {
-    ($kind:expr,$ctx:expr, $($msg:expr),* $(,)?) => {
-        Error::new($kind, $ctx, "")
+    ($ctx:expr, $($msg:tt)+) => {
+        err!($crate::ErrorKind::Runtime, $ctx, $($msg)+)
     };
 }
 
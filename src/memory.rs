@@ -19,7 +19,7 @@ pub enum AccessType {
 impl Core {
     // reads one byte from RAM
     #[inline(always)]
-    pub fn _read_u8(&self, _: AccessType, addr: u16, data: Option<&mut u8>) -> Result<u8, Error> {
+    pub fn _read_u8(&self, at: AccessType, addr: u16, data: Option<&mut u8>) -> Result<u8, Error> {
         // first check to see if this address is overridden by the ACIA
         if let Some(acia_rc) = self.acia.as_ref() {
             let mut acia = acia_rc.borrow_mut();
@@ -27,9 +27,15 @@ impl Core {
                 return acia.read(addr);
             }
         }
+        if let Some(becker) = self.becker.as_ref() {
+            let mut becker = becker.borrow_mut();
+            if becker.owns_address(addr) {
+                return Ok(becker.read(addr));
+            }
+        }
         // if the debugger is enabled then check to see if this read should trigger a breakpoint
         if config::debug() {
-            self.debug_check_for_watch_hit(addr);
+            self.debug_check_for_watch_hit(addr, false);
         }
         let byte = match addr {
             0x0000..=0xfeff => {
@@ -45,7 +51,12 @@ impl Core {
             0xff20..=0xff3f => {
                 // pia1
                 let mut pia = self.pia1.lock();
-                pia.read((addr - 0xff20) as usize)
+                let reg_num = (addr - 0xff20) as usize;
+                // the RS-232 input only needs sampling when side B's data register is read
+                if let (Some(bb), true) = (self.bitbanger.as_ref(), pia.reads_rs232_in(reg_num)) {
+                    pia.set_rs232_in(bb.borrow_mut().rx_level(self.clock_cycles));
+                }
+                pia.read(reg_num)
             }
             0xffc0..=0xffdf => {
                 // sam (write-only)
@@ -56,10 +67,14 @@ impl Core {
                 self.raw_ram[(addr - 0x4000) as usize]
             }
             _ => {
-                warn!("Read at unimplemented addres {:04x}", addr);
+                warn!(target: Cpu, "Read at unimplemented address {:04x}", addr);
                 0
             }
         };
+        // instruction fetches are part of a trace record already
+        if let (Some(tracer), false) = (self.tracer.as_ref(), at == AccessType::Program) {
+            tracer.borrow_mut().access(addr, byte, false);
+        }
         if let Some(data) = data {
             *data = byte;
         }
@@ -125,9 +140,19 @@ impl Core {
                 return acia.write(addr, data);
             }
         }
+        if let Some(becker) = self.becker.as_ref() {
+            let mut becker = becker.borrow_mut();
+            if becker.owns_address(addr) {
+                becker.write(addr, data);
+                return Ok(());
+            }
+        }
         // if the debugger is enabled then check to see if this write should trigger a breakpoint
         if config::debug() {
-            self.debug_check_for_watch_hit(addr);
+            self.debug_check_for_watch_hit(addr, true);
+        }
+        if let Some(tracer) = self.tracer.as_ref() {
+            tracer.borrow_mut().access(addr, data, true);
         }
         match addr {
             0x0000..=0xfeff => {
@@ -147,6 +172,9 @@ impl Core {
                 // pia1
                 let mut pia = self.pia1.lock();
                 pia.write((addr - 0xff20) as usize, data);
+                if let Some(bb) = self.bitbanger.as_ref() {
+                    bb.borrow_mut().set_tx(pia.rs232_out(), self.clock_cycles);
+                }
             }
             0xffc0..=0xffdf => {
                 // sam
@@ -161,7 +189,7 @@ impl Core {
                 // remap interrupt vectors to 0xbfe0-0xbfff
                 self.raw_ram[(addr - 0x4000) as usize] = data;
             }
-            _ => warn!("Write at unimplemented address {:04x}", addr),
+            _ => warn!(target: Cpu, "Write at unimplemented address {:04x}", addr),
         }
         Ok(())
     }
@@ -54,12 +54,12 @@ pub trait ObjectProducer: core::fmt::Debug + core::fmt::Display {
     fn current_size(&self, _: u16, _: &dyn LabelResolver) -> Result<u16, Error> {
         Ok(0u16)
     }
-    // given an address and label definitions, produce this object
+    // given an address, label definitions and the assumed direct page (if known), produce this object
     fn build(
         &mut self,
         addr: u16,
         lr: &dyn LabelResolver,
-        dp_dirty: bool,
+        dp: Option<u8>,
     ) -> Result<&BinaryObject, Error>;
 
     // returns true if the object results in potential DP register change
@@ -69,6 +69,11 @@ pub trait ObjectProducer: core::fmt::Debug + core::fmt::Display {
 
     // get a ref to this producer's object (if there is one)
     fn bob_ref(&self) -> Option<&BinaryObject>;
+
+    // the (minimum) number of clock cycles taken by this object if it's an instruction
+    fn cycles(&self) -> Option<u8> {
+        None
+    }
 }
 
 /// Builds a BinaryObject for a given 6809 assembly language instruction.
@@ -89,7 +94,7 @@ impl Instruction {
         od: OperandDescriptor,
         addr: u16,
         lr: &dyn LabelResolver,
-        dp_dirty: bool,
+        dp: Option<u8>,
     ) -> Result<Self, Error> {
         // translate from the assembler's addressing mode to the runtime addressing mode
         let mut dp_changed = false;
@@ -131,12 +136,12 @@ impl Instruction {
                     && id.get_mode_detail(AddressingMode::Relative).is_some()
                 {
                     AddressingMode::Relative
-                // if the DP hasn't changed yet and the address fits in 8 bits then try using Direct mode.
+                // if the DP is known and the address lies in the direct page then try using Direct mode.
                 // if it doesn't work then we'll have to change at build time
-                } else if !dp_dirty
-                    && !od.force_mode
-                    && od.value.as_ref().map_or(false, |v| {
-                        v.eval(lr, addr, false).map_or(false, |u| u.u16() < 0x100)
+                } else if !od.force_mode
+                    && od.value.as_ref().is_some_and(|v| {
+                        v.eval(lr, addr, false)
+                            .is_ok_and(|u| dp == Some((u.u16() >> 8) as u8))
                     })
                 {
                     trying_direct = true;
@@ -246,7 +251,7 @@ impl Instruction {
                     } else {
                         // check to see if the offset fits in 5 bits
                         let x = val.sign_extended().u16() & 0xfff0;
-                        if !indirect && x == 0xfff0 || x == 0 {
+                        if !indirect && (x == 0xfff0 || x == 0) {
                             // offset fits in 5-bits, mode is not indirect, not indexing based on PC
                             post_byte |= val.u8() & 0b11111; // store offset in bottom 5 bits
                             post_byte &= 0x7f;
@@ -254,9 +259,13 @@ impl Instruction {
                         }
                     }
                 }
-                if add_offset && !val.is_u8() {
-                    // offset requires 2 bytes
-                    post_byte |= 1;
+                if add_offset {
+                    // 8-bit offset (PC relative modes already have this bit set)
+                    post_byte |= 0b1000;
+                    if !val.is_u8() {
+                        // offset requires 2 bytes
+                        post_byte |= 1;
+                    }
                 }
                 data.push(u8u16::u8(post_byte));
                 if add_offset {
@@ -371,6 +380,9 @@ impl ObjectProducer for Instruction {
         }
         Some(&self.bob)
     }
+    fn cycles(&self) -> Option<u8> {
+        Some(self.flavor.detail.clk)
+    }
     /// This is one of the uglier and more confusing functions in the codebase.
     /// It's probably a good candidate for rethinking and refactoring.
     /// On the other hand, it seems to work so I'm not very motivated to mess with it.
@@ -384,7 +396,7 @@ impl ObjectProducer for Instruction {
         &mut self,
         addr: u16,
         lr: &dyn LabelResolver,
-        dp_dirty: bool,
+        dp: Option<u8>,
     ) -> Result<&BinaryObject, Error> {
         let mut val = u8u16::u8(0);
         let mut sval = u8u16::u8(0);
@@ -417,9 +429,9 @@ impl ObjectProducer for Instruction {
             return Err(syntax_err!("missing value in operand"));
         }
         // should we try to optimize for Direct mode addressing?
-        if !dp_dirty
-            && !self.od.force_mode
-            && (val.u16() < 0x100)
+        // (only if the address lies in the direct page and the programmer didn't force extended mode)
+        if !self.od.force_mode
+            && dp == Some((val.u16() >> 8) as u8)
             && (self.flavor.mode == AddressingMode::Extended
                 || self.flavor.mode == AddressingMode::Direct)
         {
@@ -429,11 +441,13 @@ impl ObjectProducer for Instruction {
                     mode: AddressingMode::Direct,
                     detail,
                 };
+                // unless the programmer asked for direct mode ("<") we may need to switch back later
+                self.trying_direct = self.od.mode != AddressingMode::Direct;
                 val = u8u16::u8(val.lsb());
                 min_size = self.flavor.detail.sz;
                 working_size = self.flavor.detail.op_size() + 1;
             }
-        } else if (self.flavor.mode == AddressingMode::Direct) && (dp_dirty || (val.u16() > 0xff)) {
+        } else if self.flavor.mode == AddressingMode::Direct {
             if self.trying_direct {
                 if let Some(detail) = self.id.get_mode_detail(AddressingMode::Extended) {
                     // failed to optimize into direct mode; switch back to extended
@@ -449,9 +463,17 @@ impl ObjectProducer for Instruction {
                 } else {
                     panic!("Is there an instruction that supports Direct mode but not Extended?")
                 }
+            } else if let Some(page) = dp.filter(|_| val.u16() > 0xff) {
+                // programmer forced direct mode ("<") for an address outside the direct page
+                return Err(syntax_err!(
+                    "address ${:04X} is not in the direct page (${:02X})",
+                    val.u16(),
+                    page
+                ));
             } else {
-                // programmer tried to force direct mode but it failed
-                return Err(syntax_err!("invalid use of direct mode addressing"));
+                // programmer forced direct mode ("<"); the operand is the low byte of the address
+                val = u8u16::u8(val.lsb());
+                working_size = self.flavor.detail.op_size() + 1;
             }
         }
         if self.flavor.mode == AddressingMode::Indexed || self.od.mode == AddressingMode::Register {
@@ -467,7 +489,8 @@ impl ObjectProducer for Instruction {
                 && (val.size() == 1)
             {
                 // this is a 16-bit immediate mode instruction, so we need a 16=bit value
-                val = u8u16::u16(val.u16());
+                // (negative 8-bit values are sign-extended)
+                val = u8u16::u16(self.od.value.as_ref().map_or(val.u16(), |v| v.widen(val)));
             } else {
                 // for modes other than those we've explicitly checked above,
                 // min_size should be equal to actual_size at this point
@@ -519,6 +542,7 @@ impl ObjectProducer for Instruction {
                         */
                         if true {
                             verbose_println!(
+                                target: Assembler,
                                 "Converting Bxx to LBxx (pc:{:X},addr:{:x},diff:{})",
                                 pc,
                                 val.u16(),
@@ -533,7 +557,7 @@ impl ObjectProducer for Instruction {
                                 self.flavor.desc = desc;
                                 self.flavor.detail =
                                     desc.get_mode_detail(AddressingMode::Relative).unwrap();
-                                return self.build(addr, lr, dp_dirty);
+                                return self.build(addr, lr, dp);
                             }
                         }
                         panic!("failed to convert Branch operation to LongBranch")
@@ -563,18 +587,21 @@ impl ObjectProducer for Instruction {
         self.dp_changed
     }
 }
-/// Builds a BinaryObject given the operand of an RMB (Reserve Memory Bytes) statement.
+/// Builds a BinaryObject given the operand of an RMB (Reserve Memory Bytes)
+/// or RMD (Reserve Memory Double bytes) statement.
 #[derive(Debug)]
 pub struct Rmb {
     node: ValueNode,
+    bytes_per_unit: u16,
     size: Option<u16>,
     bob: BinaryObject,
     built: bool,
 }
 impl Rmb {
-    pub fn new(node: ValueNode) -> Self {
+    pub fn new(node: ValueNode, is_bytes: bool) -> Self {
         Rmb {
             node,
+            bytes_per_unit: if is_bytes { 1u16 } else { 2u16 },
             size: None,
             bob: BinaryObject {
                 addr: 0,
@@ -585,6 +612,13 @@ impl Rmb {
             built: false,
         }
     }
+    fn eval_size(&self, addr: u16, lr: &dyn LabelResolver) -> Result<u16, Error> {
+        self.node
+            .eval(lr, addr, false)?
+            .u16()
+            .checked_mul(self.bytes_per_unit)
+            .ok_or_else(|| syntax_err!("size of reserved memory is too large"))
+    }
 }
 impl ObjectProducer for Rmb {
     fn bob_ref(&self) -> Option<&BinaryObject> {
@@ -597,10 +631,10 @@ impl ObjectProducer for Rmb {
         &mut self,
         addr: u16,
         lr: &dyn LabelResolver,
-        _: bool,
+        _: Option<u8>,
     ) -> Result<&BinaryObject, Error> {
         // if this value node can't be evaluated then it's invalid
-        let u = self.node.eval(lr, addr, false)?.u16();
+        let u = self.eval_size(addr, lr)?;
         self.size = Some(u);
         self.bob.addr = addr;
         self.bob.size = u;
@@ -614,16 +648,20 @@ impl ObjectProducer for Rmb {
         // for RMB, max_size == size always
         // but we don't know size unless we can evaluate our operand
         // so this is the same as build (except self is mut, so we can't set self.size)
-        let u = self.node.eval(lr, addr, false)?.u16();
-        Ok(u)
+        self.eval_size(addr, lr)
     }
 }
 impl fmt::Display for Rmb {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = if self.bytes_per_unit == 1 {
+            "RMB"
+        } else {
+            "RMD"
+        };
         if let Some(size) = self.size {
-            write!(f, "RMB {} bytes", size)
+            write!(f, "{} {} bytes", op, size)
         } else {
-            write!(f, "RMB {}", self.node)
+            write!(f, "{} {}", op, self.node)
         }
     }
 }
@@ -665,7 +703,7 @@ impl ObjectProducer for Fxb {
         &mut self,
         addr: u16,
         lr: &dyn LabelResolver,
-        _: bool,
+        _: Option<u8>,
     ) -> Result<&BinaryObject, Error> {
         // Fxb renders one or more bytes at the current address
         let mut data = Vec::new();
@@ -675,7 +713,8 @@ impl ObjectProducer for Fxb {
             if val.size() > self.bytes_per_node {
                 return Err(syntax_err!("16-bit data in FCB is invalid"));
             } else if val.size() < self.bytes_per_node {
-                data.push(u8u16::u16(val.u16()));
+                // (negative 8-bit values are sign-extended)
+                data.push(u8u16::u16(node.widen(val)));
             } else {
                 data.push(val);
             }
@@ -740,7 +779,7 @@ impl ObjectProducer for Org {
         &mut self,
         addr: u16,
         lr: &dyn LabelResolver,
-        _: bool,
+        _: Option<u8>,
     ) -> Result<&BinaryObject, Error> {
         // if this value node can't be evaluated then it's invalid
         self.bob.addr = self.node.eval(lr, addr, false)?.u16();
@@ -753,21 +792,37 @@ impl fmt::Display for Org {
         write!(f, "ORG {}", self.node)
     }
 }
-/// Builds a BinaryObject given the operand of an FCC statement.
+/// Builds a BinaryObject given the operand of an FCC, FCS or FCN/FCZ statement.
 #[derive(Debug)]
 pub struct Fcc {
-    source: String, // saving a copy of the source string for debugging/printing
+    op: &'static str, // the directive (for debugging/printing)
+    source: String,   // saving a copy of the source string for debugging/printing
     bob: BinaryObject,
     built: bool,
 }
 impl Fcc {
     pub fn new(s: &str) -> Self {
-        let size = s.len() as u16;
-        let mut data = Vec::with_capacity(size as usize);
-        for b in s.bytes() {
-            data.push(u8u16::u8(b));
+        Self::with_bytes("FCC", s, s.bytes().collect())
+    }
+    /// FCS: the last character of the string has bit 7 set
+    pub fn fcs(s: &str) -> Self {
+        let mut bytes: Vec<u8> = s.bytes().collect();
+        if let Some(last) = bytes.last_mut() {
+            *last |= 0x80;
         }
+        Self::with_bytes("FCS", s, bytes)
+    }
+    /// FCN/FCZ: the string is followed by a null (0) byte
+    pub fn fcn(s: &str) -> Self {
+        let mut bytes: Vec<u8> = s.bytes().collect();
+        bytes.push(0);
+        Self::with_bytes("FCN", s, bytes)
+    }
+    fn with_bytes(op: &'static str, s: &str, bytes: Vec<u8>) -> Self {
+        let size = bytes.len() as u16;
+        let data = bytes.into_iter().map(u8u16::u8).collect();
         Fcc {
+            op,
             source: s.to_string(),
             bob: BinaryObject {
                 addr: 0,
@@ -786,7 +841,15 @@ impl ObjectProducer for Fcc {
         }
         Some(&self.bob)
     }
-    fn build(&mut self, addr: u16, _: &dyn LabelResolver, _: bool) -> Result<&BinaryObject, Error> {
+    fn current_size(&self, _: u16, _: &dyn LabelResolver) -> Result<u16, Error> {
+        Ok(self.bob.size)
+    }
+    fn build(
+        &mut self,
+        addr: u16,
+        _: &dyn LabelResolver,
+        _: Option<u8>,
+    ) -> Result<&BinaryObject, Error> {
         self.bob.addr = addr;
         self.built = true;
         Ok(&self.bob)
@@ -794,6 +857,191 @@ impl ObjectProducer for Fcc {
 }
 impl fmt::Display for Fcc {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "FCC {}", self.source)
+        write!(f, "{} {}", self.op, self.source)
+    }
+}
+/// Builds a BinaryObject given the operand of a FILL, ZMB or BSZ statement.
+/// The object is `count` copies of a byte value (0 for ZMB/BSZ).
+#[derive(Debug)]
+pub struct Fill {
+    val: Option<ValueNode>, // None means fill with zeros
+    count: ValueNode,
+    bob: BinaryObject,
+    built: bool,
+}
+impl Fill {
+    pub fn new(val: Option<ValueNode>, count: ValueNode) -> Self {
+        Fill {
+            val,
+            count,
+            bob: BinaryObject {
+                addr: 0,
+                is_static_addr: false,
+                size: 0,
+                data: None,
+            },
+            built: false,
+        }
+    }
+}
+impl ObjectProducer for Fill {
+    fn bob_ref(&self) -> Option<&BinaryObject> {
+        if !self.built {
+            return None;
+        }
+        Some(&self.bob)
+    }
+    fn current_size(&self, addr: u16, lr: &dyn LabelResolver) -> Result<u16, Error> {
+        Ok(self.count.eval(lr, addr, false)?.u16())
+    }
+    fn build(
+        &mut self,
+        addr: u16,
+        lr: &dyn LabelResolver,
+        _: Option<u8>,
+    ) -> Result<&BinaryObject, Error> {
+        let count = self.count.eval(lr, addr, false)?.u16();
+        let val = match self.val.as_ref() {
+            Some(node) => node.eval(lr, addr, false)?,
+            None => u8u16::u8(0),
+        };
+        if !val.is_u8() {
+            return Err(syntax_err!("fill value must be 8 bits"));
+        }
+        self.bob.addr = addr;
+        self.bob.data = Some(vec![val; count as usize]);
+        self.bob.calc_size();
+        self.built = true;
+        Ok(&self.bob)
+    }
+}
+impl fmt::Display for Fill {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.val.as_ref() {
+            Some(val) => write!(f, "FILL {}, {}", val, self.count),
+            None => write!(f, "ZMB {}", self.count),
+        }
+    }
+}
+/// Builds a BinaryObject given the operand of an ALIGN statement.
+/// The object pads the program to the next multiple of the alignment. The padding is
+/// only written to memory if a fill value was given; otherwise it's just reserved (like RMB).
+#[derive(Debug)]
+pub struct Align {
+    alignment: ValueNode,
+    fill: Option<ValueNode>,
+    bob: BinaryObject,
+    built: bool,
+}
+impl Align {
+    pub fn new(alignment: ValueNode, fill: Option<ValueNode>) -> Self {
+        Align {
+            alignment,
+            fill,
+            bob: BinaryObject {
+                addr: 0,
+                is_static_addr: false,
+                size: 0,
+                data: None,
+            },
+            built: false,
+        }
+    }
+    /// the number of bytes needed to align `addr`
+    fn padding(&self, addr: u16, lr: &dyn LabelResolver) -> Result<u16, Error> {
+        let alignment = self.alignment.eval(lr, addr, false)?.u16();
+        if alignment == 0 {
+            return Err(syntax_err!("alignment must be greater than 0"));
+        }
+        Ok((alignment - addr % alignment) % alignment)
+    }
+}
+impl ObjectProducer for Align {
+    fn bob_ref(&self) -> Option<&BinaryObject> {
+        if !self.built {
+            return None;
+        }
+        Some(&self.bob)
+    }
+    fn current_size(&self, addr: u16, lr: &dyn LabelResolver) -> Result<u16, Error> {
+        self.padding(addr, lr)
+    }
+    fn build(
+        &mut self,
+        addr: u16,
+        lr: &dyn LabelResolver,
+        _: Option<u8>,
+    ) -> Result<&BinaryObject, Error> {
+        let size = self.padding(addr, lr)?;
+        self.bob.data = match self.fill.as_ref() {
+            Some(node) => {
+                let val = node.eval(lr, addr, false)?;
+                if !val.is_u8() {
+                    return Err(syntax_err!("fill value must be 8 bits"));
+                }
+                Some(vec![val; size as usize])
+            }
+            None => None,
+        };
+        self.bob.addr = addr;
+        self.bob.size = size;
+        self.built = true;
+        Ok(&self.bob)
+    }
+}
+impl fmt::Display for Align {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ALIGN {}", self.alignment)?;
+        if let Some(fill) = self.fill.as_ref() {
+            write!(f, ", {}", fill)?;
+        }
+        Ok(())
+    }
+}
+/// Holds a listing-control statement (NAM/TTL, PAG or OPT). These don't produce any
+/// machine code; they only affect how the program listing is presented.
+#[derive(Debug)]
+pub struct ListingControl {
+    pub op: String,   // the directive
+    pub text: String, // its operand (e.g. the title for NAM/TTL)
+    bob: BinaryObject,
+    built: bool,
+}
+impl ListingControl {
+    pub fn new(op: &str, text: &str) -> Self {
+        ListingControl {
+            op: op.to_string(),
+            text: text.to_string(),
+            bob: BinaryObject {
+                addr: 0,
+                is_static_addr: false,
+                size: 0,
+                data: None,
+            },
+            built: false,
+        }
+    }
+}
+impl ObjectProducer for ListingControl {
+    fn bob_ref(&self) -> Option<&BinaryObject> {
+        if !self.built {
+            return None;
+        }
+        Some(&self.bob)
+    }
+    fn build(
+        &mut self,
+        addr: u16,
+        _: &dyn LabelResolver,
+        _: Option<u8>,
+    ) -> Result<&BinaryObject, Error> {
+        self.bob.addr = addr;
+        self.built = true;
+        Ok(&self.bob)
+    }
+}
+impl fmt::Display for ListingControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.op, self.text)
     }
 }
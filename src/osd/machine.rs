@@ -0,0 +1,182 @@
+//! Does what the menu asks to a [Core], with media from a FAT volume (the SD card on
+//! the Pico) and settings and saved states in flash.
+use super::{Action, Media, OsdHost};
+use crate::drivewire::Server;
+use crate::storage::boot::{CART_ADDR, CART_SIZE};
+use crate::storage::fat::{FatDisk, FatVolume};
+use crate::storage::flash::{Flash, SECTOR_SIZE};
+use crate::storage::BlockDevice;
+use crate::{format, Box, Core, Error, Map, Rc, String, ToString, Vec};
+use core::cell::RefCell;
+
+/// The number of slots states can be saved in
+pub const STATE_SLOTS: usize = 2;
+/// The room for a saved state: 64K of RAM and a little more
+const STATE_SECTORS: usize = 17;
+/// The flash the menu needs: the settings' sector, then the state slots
+pub const FLASH_SECTORS: usize = 1 + STATE_SLOTS * STATE_SECTORS;
+
+fn state_offset(slot: usize) -> usize {
+    (1 + slot * STATE_SECTORS) * SECTOR_SIZE
+}
+
+/// What's in the machine, by path on the card
+#[derive(Debug, Default)]
+pub struct Loaded {
+    pub cart: Option<String>,
+    pub disks: Map<u8, String>,
+    /// the tape's path and contents
+    pub tape: Option<(String, Vec<u8>)>,
+}
+
+pub struct Machine<'a, D: BlockDevice> {
+    pub core: &'a mut Core,
+    /// where images are found; with no card, nothing can be mounted
+    pub volume: Option<Rc<RefCell<FatVolume<D>>>>,
+    /// the DriveWire server that disks are mounted on
+    pub server: Option<Rc<RefCell<Server>>>,
+    pub loaded: &'a mut Loaded,
+    pub flash: &'a mut dyn Flash,
+}
+
+impl<D: BlockDevice + 'static> Machine<'_, D> {
+    fn volume(&self) -> Result<&Rc<RefCell<FatVolume<D>>>, Error> {
+        self.volume
+            .as_ref()
+            .ok_or_else(|| general_err!("there's no SD card"))
+    }
+    fn mount(&mut self, media: Media, path: &str) -> Result<(), Error> {
+        let volume = self.volume()?.clone();
+        match media {
+            Media::Cart => {
+                let data = volume.borrow_mut().read_file(path)?;
+                if data.len() > CART_SIZE {
+                    return Err(general_err!("{} is too big for a cartridge", path));
+                }
+                self.clear_cart();
+                self.core.load_bytes(&data, CART_ADDR)?;
+                self.loaded.cart = Some(path.to_string());
+                self.core.cold_boot(true)?;
+            }
+            Media::Disk(drive) => {
+                let server = self
+                    .server
+                    .as_ref()
+                    .ok_or_else(|| general_err!("no DriveWire"))?;
+                let disk = FatDisk::open(volume, path)?;
+                server.borrow_mut().mount(drive, Box::new(disk));
+                self.loaded.disks.insert(drive, path.to_string());
+            }
+            Media::Tape => {
+                let data = volume.borrow_mut().read_file(path)?;
+                self.loaded.tape = Some((path.to_string(), data));
+            }
+        }
+        info!("{:?}: {}", media, path);
+        Ok(())
+    }
+    fn eject(&mut self, media: Media) -> Result<(), Error> {
+        match media {
+            Media::Cart => {
+                if self.loaded.cart.take().is_some() {
+                    self.clear_cart();
+                    self.core.cold_boot(false)?;
+                }
+            }
+            Media::Disk(drive) => {
+                if let Some(server) = &self.server {
+                    server.borrow_mut().eject(drive);
+                }
+                self.loaded.disks.remove(&drive);
+            }
+            Media::Tape => self.loaded.tape = None,
+        }
+        Ok(())
+    }
+    /// Empties the cartridge's address range, which reads as $FF with no cartridge
+    fn clear_cart(&mut self) {
+        let end = (CART_ADDR as usize + CART_SIZE).min(self.core.raw_ram.len());
+        self.core.raw_ram[CART_ADDR as usize..end].fill(0xff);
+    }
+    fn save_state(&mut self, slot: usize) -> Result<(), Error> {
+        let state = self.core.save_state();
+        if slot >= STATE_SLOTS || state.len() + 4 > STATE_SECTORS * SECTOR_SIZE {
+            return Err(general_err!("can't save in slot {}", slot + 1));
+        }
+        let mut data = Vec::with_capacity(state.len() + 4);
+        data.extend_from_slice(&(state.len() as u32).to_be_bytes());
+        data.extend_from_slice(&state);
+        self.flash.write(state_offset(slot), &data)
+    }
+    fn load_state(&mut self, slot: usize) -> Result<(), Error> {
+        let empty = || general_err!("slot {} is empty", slot + 1);
+        if slot >= STATE_SLOTS {
+            return Err(empty());
+        }
+        let mut len = [0u8; 4];
+        self.flash.read(state_offset(slot), &mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len + 4 > STATE_SECTORS * SECTOR_SIZE {
+            return Err(empty());
+        }
+        let mut state = vec![0u8; len];
+        self.flash.read(state_offset(slot) + 4, &mut state)?;
+        self.core.load_state(&state)
+    }
+}
+
+impl<D: BlockDevice + 'static> OsdHost for Machine<'_, D> {
+    fn files(&mut self, media: Media) -> Vec<String> {
+        let Ok(volume) = self.volume() else {
+            return Vec::new();
+        };
+        let mut volume = volume.borrow_mut();
+        let is_image = |name: &str| {
+            let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+            media
+                .extensions()
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(ext))
+        };
+        // images in the root directory and the directories in it
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        for entry in volume.read_dir("").unwrap_or_default() {
+            if entry.is_dir {
+                dirs.push(entry.name);
+            } else if is_image(&entry.name) {
+                files.push(entry.name);
+            }
+        }
+        for dir in dirs {
+            for entry in volume.read_dir(&dir).unwrap_or_default() {
+                if !entry.is_dir && is_image(&entry.name) {
+                    files.push(format!("{}/{}", dir, entry.name));
+                }
+            }
+        }
+        files.sort_by_key(|f| f.to_ascii_uppercase());
+        files
+    }
+    fn mounted(&mut self, media: Media) -> Option<String> {
+        match media {
+            Media::Cart => self.loaded.cart.clone(),
+            Media::Disk(drive) => self.loaded.disks.get(&drive).cloned(),
+            Media::Tape => self.loaded.tape.as_ref().map(|(path, _)| path.clone()),
+        }
+    }
+    fn perform(&mut self, action: &Action) -> Result<(), Error> {
+        match action {
+            Action::Reset => self.core.reset(),
+            Action::ColdBoot => self.core.cold_boot(self.loaded.cart.is_some()),
+            Action::Mount(media, path) => self.mount(*media, path),
+            Action::Eject(media) => self.eject(*media),
+            Action::SaveState(slot) => self.save_state(*slot),
+            Action::LoadState(slot) => self.load_state(*slot),
+            Action::ApplySettings(settings) => {
+                self.core._vdg.lock().set_artifact(settings.artifact);
+                settings.save(self.flash)
+            }
+        }
+    }
+}
@@ -0,0 +1,462 @@
+//! An on-screen menu for the Pico, which has no host window to hang menus on.
+//!
+//! [MENU_KEY] opens the menu over the CoCo's display. While it's open it takes all
+//! keys: up and down choose, Enter or right picks, Esc or left goes back. What the
+//! menu asks for (resetting, mounting media, saving states, ...) is done through an
+//! [OsdHost], which [Machine] implements for a [Core](crate::Core) and an SD card.
+pub mod machine;
+pub mod settings;
+
+pub use machine::{Loaded, Machine, FLASH_SECTORS, STATE_SLOTS};
+pub use settings::Settings;
+
+use crate::input::*;
+use crate::vdg::{font_glyph, Artifact, BLOCK_DIM_X, BLOCK_DIM_Y, SCREEN_DIM_X};
+use crate::{format, Color, Error, String, ToString, Vec};
+
+/// The number of DriveWire drives the menu offers
+pub const DRIVES: u8 = 4;
+
+// the menu's panel, in character cells
+const PANEL_X: usize = 1;
+const PANEL_Y: usize = 1;
+const PANEL_COLS: usize = 30;
+const PANEL_ROWS: usize = 14;
+/// The number of items shown at once; longer lists scroll
+const VISIBLE_ITEMS: usize = PANEL_ROWS - 4;
+
+/// Something that can be put in the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Media {
+    Cart,
+    /// a disk in a DriveWire drive
+    Disk(u8),
+    Tape,
+}
+
+impl Media {
+    /// The file extensions of images of this kind of media
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Media::Cart => &["ROM", "CCC"],
+            Media::Disk(_) => &["DSK", "VDK", "OS9"],
+            Media::Tape => &["CAS"],
+        }
+    }
+    fn label(&self) -> String {
+        match self {
+            Media::Cart => "CARTRIDGE".to_string(),
+            Media::Disk(drive) => format!("DRIVE {}", drive),
+            Media::Tape => "TAPE".to_string(),
+        }
+    }
+}
+
+/// What the menu asks the [OsdHost] to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Reset,
+    ColdBoot,
+    /// puts the image at a path on the card in the machine
+    Mount(Media, String),
+    Eject(Media),
+    SaveState(usize),
+    LoadState(usize),
+    /// the settings were changed: use them, and keep them in flash
+    ApplySettings(Settings),
+}
+
+impl Action {
+    /// Whether the menu closes after the action, to show what it did
+    fn closes_menu(&self) -> bool {
+        matches!(
+            self,
+            Action::Reset | Action::ColdBoot | Action::LoadState(_) | Action::Mount(Media::Cart, _)
+        )
+    }
+    fn done(&self) -> String {
+        match self {
+            Action::Mount(media, path) => format!("{}: {}", media.label(), path),
+            Action::Eject(media) => format!("{} EJECTED", media.label()),
+            Action::SaveState(slot) => format!("SAVED TO SLOT {}", slot + 1),
+            Action::ApplySettings(_) => "SETTINGS SAVED".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// What the menu needs from the emulator
+pub trait OsdHost {
+    /// The paths of the images that `media` could be loaded from
+    fn files(&mut self, media: Media) -> Vec<String>;
+    /// The path of the image in `media`, if there is one
+    fn mounted(&mut self, media: Media) -> Option<String>;
+    fn perform(&mut self, action: &Action) -> Result<(), Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Page {
+    Main,
+    Media,
+    Files(Media),
+    Video,
+    Keys,
+    /// waiting for the host key to map
+    KeyFrom,
+    /// waiting for the CoCo key to map a host key to
+    KeyTo(u8),
+    SaveState,
+    LoadState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Choice {
+    Resume,
+    Open(Page),
+    Perform(Action),
+    NextArtifact,
+    Unmap(u8),
+    ClearKeys,
+}
+
+#[derive(Debug, Clone)]
+struct Item {
+    label: String,
+    choice: Choice,
+}
+
+fn item(label: &str, choice: Choice) -> Item {
+    Item {
+        label: label.to_string(),
+        choice,
+    }
+}
+
+/// A page of the menu as it's shown
+#[derive(Debug, Clone)]
+struct Screen {
+    page: Page,
+    title: String,
+    items: Vec<Item>,
+    selected: usize,
+}
+
+/// The name of a key, as shown in the menu
+pub fn key_name(key: u8) -> String {
+    match key {
+        b'\n' | b'\r' => "ENTER".to_string(),
+        b' ' => "SPACE".to_string(),
+        0x08 => "BACKSPACE".to_string(),
+        KEY_ESC => "ESC".to_string(),
+        KEY_UP => "UP".to_string(),
+        KEY_DOWN => "DOWN".to_string(),
+        KEY_LEFT => "LEFT".to_string(),
+        KEY_RIGHT => "RIGHT".to_string(),
+        KEY_HOME => "HOME".to_string(),
+        k if (KEY_F1..=KEY_F12).contains(&k) => format!("F{}", k - KEY_F1 + 1),
+        k if k.is_ascii_graphic() => (k.to_ascii_uppercase() as char).to_string(),
+        k => format!("${:02X}", k),
+    }
+}
+
+fn artifact_name(artifact: Artifact) -> &'static str {
+    match artifact {
+        Artifact::Off => "OFF",
+        Artifact::BlueRed => "BLUE-RED",
+        Artifact::RedBlue => "RED-BLUE",
+    }
+}
+
+pub struct Osd {
+    settings: Settings,
+    /// the open pages, the current one last; empty when the menu is closed
+    screens: Vec<Screen>,
+    /// a line under the menu, e.g. what the last action did
+    status: String,
+}
+
+impl Osd {
+    pub fn new(settings: Settings) -> Self {
+        Osd {
+            settings,
+            screens: Vec::new(),
+            status: String::new(),
+        }
+    }
+    pub fn is_open(&self) -> bool {
+        !self.screens.is_empty()
+    }
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+    /// The title of the current page and its items, with the selected one's index
+    pub fn page(&self) -> Option<(&str, Vec<&str>, usize)> {
+        self.screens.last().map(|s| {
+            let items = s.items.iter().map(|i| i.label.as_str()).collect();
+            (s.title.as_str(), items, s.selected)
+        })
+    }
+    pub fn open(&mut self, host: &mut dyn OsdHost) {
+        self.screens.clear();
+        self.status.clear();
+        self.push(Page::Main, host);
+    }
+    pub fn close(&mut self) {
+        self.screens.clear();
+    }
+    /// Handles a key from the keyboard. Returns true if the menu took it, in which
+    /// case the CoCo shouldn't see it.
+    pub fn handle(&mut self, event: InputEvent, host: &mut dyn OsdHost) -> bool {
+        let key = match event {
+            InputEvent::Press(key) => key,
+            // releases go to the CoCo if it saw the press
+            InputEvent::Release(key) => return self.is_open() || key == MENU_KEY,
+        };
+        if key == MENU_KEY {
+            if self.is_open() {
+                self.close();
+            } else {
+                self.open(host);
+            }
+            return true;
+        }
+        let Some(screen) = self.screens.last_mut() else {
+            return false;
+        };
+        match screen.page {
+            Page::KeyFrom if key != KEY_ESC => {
+                self.back(host);
+                self.push(Page::KeyTo(key), host);
+                return true;
+            }
+            Page::KeyTo(from) if key != KEY_ESC => {
+                self.back(host);
+                let mut settings = self.settings.clone();
+                match settings.set_key(from, key) {
+                    Ok(()) => self.apply_settings(settings, host),
+                    Err(e) => self.status = e.to_string(),
+                }
+                return true;
+            }
+            _ => {}
+        }
+        let count = screen.items.len();
+        match key {
+            KEY_UP if count > 0 => screen.selected = (screen.selected + count - 1) % count,
+            KEY_DOWN if count > 0 => screen.selected = (screen.selected + 1) % count,
+            b'\n' | b'\r' | b' ' | KEY_RIGHT => {
+                if let Some(item) = screen.items.get(screen.selected) {
+                    let choice = item.choice.clone();
+                    self.choose(choice, host);
+                }
+            }
+            KEY_ESC | KEY_LEFT | 0x08 => self.back(host),
+            _ => {}
+        }
+        true
+    }
+    fn choose(&mut self, choice: Choice, host: &mut dyn OsdHost) {
+        self.status.clear();
+        match choice {
+            Choice::Resume => self.close(),
+            Choice::Open(page) => self.push(page, host),
+            Choice::Perform(action) => self.perform(action, host),
+            Choice::NextArtifact => {
+                let mut settings = self.settings.clone();
+                settings.artifact = match settings.artifact {
+                    Artifact::Off => Artifact::BlueRed,
+                    Artifact::BlueRed => Artifact::RedBlue,
+                    Artifact::RedBlue => Artifact::Off,
+                };
+                self.apply_settings(settings, host);
+            }
+            Choice::Unmap(from) => {
+                let mut settings = self.settings.clone();
+                settings.keys.retain(|(f, _)| *f != from);
+                self.apply_settings(settings, host);
+            }
+            Choice::ClearKeys => {
+                let mut settings = self.settings.clone();
+                settings.keys.clear();
+                self.apply_settings(settings, host);
+            }
+        }
+    }
+    fn perform(&mut self, action: Action, host: &mut dyn OsdHost) {
+        match host.perform(&action) {
+            Ok(()) if action.closes_menu() => self.close(),
+            Ok(()) => {
+                // back to where the action was chosen from, e.g. from the files to
+                // the media, or stay put if it was chosen there
+                if matches!(action, Action::Mount(..) | Action::Eject(_)) {
+                    self.back(host);
+                }
+                self.refresh(host);
+                self.status = action.done();
+            }
+            Err(e) => self.status = e.to_string(),
+        }
+    }
+    fn apply_settings(&mut self, settings: Settings, host: &mut dyn OsdHost) {
+        let action = Action::ApplySettings(settings.clone());
+        // the new settings are used even if they couldn't be saved
+        self.settings = settings;
+        self.perform(action, host);
+    }
+    /// Goes back a page, closing the menu from the main page
+    fn back(&mut self, host: &mut dyn OsdHost) {
+        self.screens.pop();
+        self.refresh(host);
+    }
+    fn push(&mut self, page: Page, host: &mut dyn OsdHost) {
+        let (title, items) = self.build(page, host);
+        self.screens.push(Screen {
+            page,
+            title,
+            items,
+            selected: 0,
+        });
+    }
+    /// Rebuilds the current page, whose items may have changed
+    fn refresh(&mut self, host: &mut dyn OsdHost) {
+        if let Some(page) = self.screens.last().map(|s| s.page) {
+            let (title, items) = self.build(page, host);
+            let screen = self.screens.last_mut().unwrap();
+            screen.selected = screen.selected.min(items.len().saturating_sub(1));
+            (screen.title, screen.items) = (title, items);
+        }
+    }
+    fn build(&self, page: Page, host: &mut dyn OsdHost) -> (String, Vec<Item>) {
+        let mut items = Vec::new();
+        let title = match page {
+            Page::Main => {
+                items.push(item("RESUME", Choice::Resume));
+                items.push(item("RESET", Choice::Perform(Action::Reset)));
+                items.push(item("COLD BOOT", Choice::Perform(Action::ColdBoot)));
+                items.push(item("MEDIA", Choice::Open(Page::Media)));
+                items.push(item("VIDEO", Choice::Open(Page::Video)));
+                items.push(item("KEYBOARD", Choice::Open(Page::Keys)));
+                items.push(item("SAVE STATE", Choice::Open(Page::SaveState)));
+                items.push(item("LOAD STATE", Choice::Open(Page::LoadState)));
+                "COCO"
+            }
+            Page::Media => {
+                let media = [Media::Cart]
+                    .into_iter()
+                    .chain((0..DRIVES).map(Media::Disk))
+                    .chain([Media::Tape]);
+                for media in media {
+                    let name = host.mounted(media).unwrap_or_else(|| "-".to_string());
+                    let label = format!("{}: {}", media.label(), name);
+                    items.push(item(&label, Choice::Open(Page::Files(media))));
+                }
+                "MEDIA"
+            }
+            Page::Files(media) => {
+                items.push(item("EJECT", Choice::Perform(Action::Eject(media))));
+                for path in host.files(media) {
+                    items.push(item(
+                        &path,
+                        Choice::Perform(Action::Mount(media, path.clone())),
+                    ));
+                }
+                return (media.label(), items);
+            }
+            Page::Video => {
+                let label = format!(
+                    "ARTIFACT COLOURS: {}",
+                    artifact_name(self.settings.artifact)
+                );
+                items.push(item(&label, Choice::NextArtifact));
+                "VIDEO"
+            }
+            Page::Keys => {
+                items.push(item("MAP A KEY", Choice::Open(Page::KeyFrom)));
+                items.push(item("CLEAR ALL", Choice::ClearKeys));
+                for &(from, to) in &self.settings.keys {
+                    let label = format!("{} = {}", key_name(from), key_name(to));
+                    items.push(item(&label, Choice::Unmap(from)));
+                }
+                "KEYBOARD (ENTER UNMAPS)"
+            }
+            Page::KeyFrom => "PRESS THE KEY TO MAP",
+            Page::KeyTo(from) => return (format!("PRESS THE KEY FOR {}", key_name(from)), items),
+            Page::SaveState | Page::LoadState => {
+                let save = page == Page::SaveState;
+                for slot in 0..STATE_SLOTS {
+                    let action = if save {
+                        Action::SaveState(slot)
+                    } else {
+                        Action::LoadState(slot)
+                    };
+                    items.push(item(&format!("SLOT {}", slot + 1), Choice::Perform(action)));
+                }
+                if save {
+                    "SAVE STATE"
+                } else {
+                    "LOAD STATE"
+                }
+            }
+        };
+        (title.to_string(), items)
+    }
+    /// Draws the menu over `display` (SCREEN_DIM_X by SCREEN_DIM_Y RGB555 pixels).
+    /// Does nothing if the menu is closed.
+    pub fn render(&self, display: &mut [u16]) {
+        let Some(screen) = self.screens.last() else {
+            return;
+        };
+        let (text, bg, title, highlight) = (Color::Buff, Color::Blue, Color::Yellow, Color::Cyan);
+        for row in 0..PANEL_ROWS {
+            draw_text(display, 0, row, "", text, bg);
+        }
+        draw_text(display, 1, 0, &screen.title, title, bg);
+        // scroll so the selected item is in view
+        let top = (screen.selected + 1).saturating_sub(VISIBLE_ITEMS);
+        for (i, item) in screen
+            .items
+            .iter()
+            .enumerate()
+            .skip(top)
+            .take(VISIBLE_ITEMS)
+        {
+            let row = 2 + i - top;
+            if i == screen.selected {
+                draw_text(display, 0, row, "", bg, highlight);
+                draw_text(display, 1, row, &item.label, Color::Black, highlight);
+            } else {
+                draw_text(display, 1, row, &item.label, text, bg);
+            }
+        }
+        draw_text(display, 1, PANEL_ROWS - 1, &self.status, highlight, bg);
+    }
+}
+
+/// Draws `text` starting at column `col` of panel row `row`, filling the rest of the
+/// row with `bg` (so an empty string clears the row). Text that doesn't fit is cut off.
+fn draw_text(display: &mut [u16], col: usize, row: usize, text: &str, fg: Color, bg: Color) {
+    let (fg, bg) = (fg.to_rgb555(), bg.to_rgb555());
+    let y0 = (PANEL_Y + row) * BLOCK_DIM_Y;
+    let x0 = PANEL_X * BLOCK_DIM_X;
+    let mut chars = text.bytes();
+    for cell in 0..PANEL_COLS {
+        let glyph = match cell >= col {
+            true => chars.next().map(font_glyph),
+            false => None,
+        };
+        for (y, line) in display[y0 * SCREEN_DIM_X..]
+            .chunks_exact_mut(SCREEN_DIM_X)
+            .take(BLOCK_DIM_Y)
+            .enumerate()
+        {
+            let bits = glyph.map_or(0, |g| g[y]);
+            let x = x0 + cell * BLOCK_DIM_X;
+            for (i, pixel) in line[x..x + BLOCK_DIM_X].iter_mut().enumerate() {
+                *pixel = if bits & (0x80 >> i) != 0 { fg } else { bg };
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Settings changed through the menu, kept in flash between power cycles.
+use crate::storage::flash::Flash;
+use crate::vdg::Artifact;
+use crate::{Error, ErrorKind, Vec};
+
+const MAGIC: &[u8; 4] = b"CCSE";
+const VERSION: u8 = 1;
+/// More than anyone will map, and few enough to fit in a sector
+pub const MAX_KEY_MAPPINGS: usize = 64;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub artifact: Artifact,
+    /// (host key, CoCo key) pairs: pressing the host key presses the CoCo key instead.
+    /// Keys are codes as reported by an [InputDevice](crate::input::InputDevice).
+    pub keys: Vec<(u8, u8)>,
+}
+
+fn settings_err(msg: &str) -> Error {
+    err!(ErrorKind::General, None, "settings: {}", msg)
+}
+
+/// A simple checksum, enough to tell settings from whatever else was in flash
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.rotate_left(1) ^ b)
+}
+
+impl Settings {
+    /// The key the CoCo sees when `key` is pressed on the host
+    pub fn map_key(&self, key: u8) -> u8 {
+        self.keys
+            .iter()
+            .find(|(from, _)| *from == key)
+            .map_or(key, |&(_, to)| to)
+    }
+    /// Makes the host key `from` press `to`. Mapping a key to itself removes its mapping.
+    pub fn set_key(&mut self, from: u8, to: u8) -> Result<(), Error> {
+        self.keys.retain(|(f, _)| *f != from);
+        if from != to {
+            if self.keys.len() == MAX_KEY_MAPPINGS {
+                return Err(settings_err("too many key mappings"));
+            }
+            self.keys.push((from, to));
+        }
+        Ok(())
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::from(&MAGIC[..]);
+        out.push(VERSION);
+        out.push(self.artifact as u8);
+        out.push(self.keys.len() as u8);
+        for &(from, to) in &self.keys {
+            out.extend_from_slice(&[from, to]);
+        }
+        out.push(checksum(&out));
+        out
+    }
+    /// Reads settings written by [Settings::to_bytes]. Anything after them is ignored.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < 8 || &data[..4] != MAGIC {
+            return Err(settings_err("not found"));
+        }
+        if data[4] != VERSION {
+            return Err(settings_err("saved by a different version"));
+        }
+        let artifact = match data[5] {
+            0 => Artifact::Off,
+            1 => Artifact::BlueRed,
+            2 => Artifact::RedBlue,
+            _ => return Err(settings_err("bad artifact setting")),
+        };
+        let count = data[6] as usize;
+        let end = 7 + 2 * count;
+        if count > MAX_KEY_MAPPINGS || data.len() <= end {
+            return Err(settings_err("bad key mappings"));
+        }
+        if checksum(&data[..end]) != data[end] {
+            return Err(settings_err("corrupted"));
+        }
+        let keys = data[7..end].chunks(2).map(|p| (p[0], p[1])).collect();
+        Ok(Settings { artifact, keys })
+    }
+    /// Reads the settings kept at the start of `flash`. Erased flash (nothing saved
+    /// yet) gives the defaults.
+    pub fn load(flash: &mut dyn Flash) -> Result<Self, Error> {
+        let mut data = vec![0u8; 8 + 2 * MAX_KEY_MAPPINGS];
+        flash.read(0, &mut data)?;
+        if data[..4] == [0xff; 4] {
+            return Ok(Settings::default());
+        }
+        Settings::from_bytes(&data)
+    }
+    /// Writes the settings at the start of `flash`
+    pub fn save(&self, flash: &mut dyn Flash) -> Result<(), Error> {
+        flash.write(0, &self.to_bytes())
+    }
+}
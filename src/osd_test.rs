@@ -0,0 +1,339 @@
+use crate::cpu_test::create_core;
+use crate::drivewire::Server;
+use crate::input::{InputEvent, KEY_DOWN, KEY_ESC, KEY_F1, KEY_UP, MENU_KEY};
+use crate::osd::{Action, Loaded, Machine, Media, Osd, OsdHost, Settings, FLASH_SECTORS};
+use crate::storage::fat::{FatKind, FatVolume};
+use crate::storage::flash::{Flash, MemFlash, SECTOR_SIZE};
+use crate::storage::MemBlockDevice;
+use crate::storage_test::Formatter;
+use crate::vdg::{Artifact, SCREEN_DIM_X, SCREEN_DIM_Y};
+use crate::*;
+use alloc::vec;
+use core::cell::RefCell;
+
+/// Records what the menu asks for
+#[derive(Default)]
+struct TestHost {
+    actions: Vec<Action>,
+    fail: bool,
+}
+
+impl OsdHost for TestHost {
+    fn files(&mut self, media: Media) -> Vec<String> {
+        match media {
+            Media::Disk(_) => vec!["A.DSK".to_string(), "B.DSK".to_string()],
+            _ => Vec::new(),
+        }
+    }
+    fn mounted(&mut self, media: Media) -> Option<String> {
+        self.actions.iter().rev().find_map(|a| match a {
+            Action::Mount(m, path) if *m == media => Some(path.clone()),
+            Action::Eject(m) if *m == media => Some("-".to_string()),
+            _ => None,
+        })
+    }
+    fn perform(&mut self, action: &Action) -> Result<(), Error> {
+        if self.fail {
+            return Err(general_err!("failed"));
+        }
+        self.actions.push(action.clone());
+        Ok(())
+    }
+}
+
+fn press(osd: &mut Osd, host: &mut TestHost, keys: &[u8]) {
+    for &key in keys {
+        assert!(osd.handle(InputEvent::Press(key), host));
+    }
+}
+
+fn selected(osd: &Osd) -> String {
+    let (_, items, i) = osd.page().unwrap();
+    items[i].to_string()
+}
+
+#[test]
+fn test_menu() {
+    let mut osd = Osd::new(Settings::default());
+    let mut host = TestHost::default();
+    // keys go to the CoCo until the menu is opened
+    assert!(!osd.handle(InputEvent::Press(b'a'), &mut host));
+    assert!(!osd.handle(InputEvent::Release(b'a'), &mut host));
+    press(&mut osd, &mut host, &[MENU_KEY]);
+    assert!(osd.is_open());
+    assert!(osd.handle(InputEvent::Release(MENU_KEY), &mut host));
+    assert!(osd.handle(InputEvent::Press(b'a'), &mut host));
+    assert_eq!(selected(&osd), "RESUME");
+    // the selection wraps around
+    press(&mut osd, &mut host, &[KEY_UP]);
+    assert_eq!(selected(&osd), "LOAD STATE");
+    press(&mut osd, &mut host, &[KEY_DOWN, KEY_DOWN, KEY_DOWN]);
+    assert_eq!(selected(&osd), "COLD BOOT");
+    press(&mut osd, &mut host, b"\n");
+    assert_eq!(host.actions, [Action::ColdBoot]);
+    assert!(!osd.is_open());
+
+    // mounting a disk goes back to the media page, which shows it
+    press(
+        &mut osd,
+        &mut host,
+        &[MENU_KEY, KEY_DOWN, KEY_DOWN, KEY_DOWN, b'\n'],
+    );
+    assert_eq!(osd.page().unwrap().0, "MEDIA");
+    press(&mut osd, &mut host, &[KEY_DOWN, KEY_DOWN, b'\n']);
+    assert_eq!(osd.page().unwrap().1, ["EJECT", "A.DSK", "B.DSK"]);
+    press(&mut osd, &mut host, &[KEY_DOWN, KEY_DOWN, b'\n']);
+    assert_eq!(
+        host.actions[1],
+        Action::Mount(Media::Disk(1), "B.DSK".into())
+    );
+    assert!(osd.is_open());
+    assert_eq!(osd.status(), "DRIVE 1: B.DSK");
+    assert_eq!(selected(&osd), "DRIVE 1: B.DSK");
+    // Esc goes back a page, and out of the menu from the main page
+    press(&mut osd, &mut host, &[KEY_ESC]);
+    assert_eq!(osd.page().unwrap().0, "COCO");
+    press(&mut osd, &mut host, &[KEY_ESC]);
+    assert!(!osd.is_open());
+
+    // errors are shown and the menu stays open
+    host.fail = true;
+    press(&mut osd, &mut host, &[MENU_KEY, KEY_DOWN, b'\n']);
+    assert!(osd.is_open());
+    assert!(osd.status().contains("failed"));
+    host.fail = false;
+    press(&mut osd, &mut host, &[MENU_KEY]);
+    assert!(!osd.is_open());
+}
+
+#[test]
+fn test_menu_settings() {
+    let mut osd = Osd::new(Settings::default());
+    let mut host = TestHost::default();
+    // video: each press picks the next artifact setting
+    press(
+        &mut osd,
+        &mut host,
+        &[MENU_KEY, KEY_UP, KEY_UP, KEY_UP, KEY_UP, b'\n'],
+    );
+    assert_eq!(osd.page().unwrap().0, "VIDEO");
+    press(&mut osd, &mut host, b"\n");
+    assert_eq!(osd.settings().artifact, Artifact::BlueRed);
+    assert_eq!(selected(&osd), "ARTIFACT COLOURS: BLUE-RED");
+    let expected = Settings {
+        artifact: Artifact::BlueRed,
+        keys: vec![],
+    };
+    assert_eq!(host.actions, [Action::ApplySettings(expected)]);
+
+    // keyboard: map F1 to X, then unmap it
+    press(&mut osd, &mut host, &[KEY_ESC, KEY_DOWN, b'\n']);
+    assert_eq!(osd.page().unwrap().1, ["MAP A KEY", "CLEAR ALL"]);
+    press(&mut osd, &mut host, &[b'\n', KEY_F1]);
+    assert_eq!(osd.page().unwrap().0, "PRESS THE KEY FOR F1");
+    press(&mut osd, &mut host, &[KEY_ESC]);
+    // Esc cancels, so it can't be mapped
+    assert_eq!(osd.page().unwrap().1, ["MAP A KEY", "CLEAR ALL"]);
+    press(&mut osd, &mut host, &[b'\n', KEY_F1, b'x']);
+    assert_eq!(osd.settings().map_key(KEY_F1), b'x');
+    assert_eq!(osd.page().unwrap().1, ["MAP A KEY", "CLEAR ALL", "F1 = X"]);
+    assert_eq!(osd.status(), "SETTINGS SAVED");
+    press(&mut osd, &mut host, &[KEY_DOWN, KEY_DOWN, b'\n']);
+    assert_eq!(osd.settings().map_key(KEY_F1), KEY_F1);
+    assert_eq!(osd.page().unwrap().1, ["MAP A KEY", "CLEAR ALL"]);
+    assert_eq!(host.actions.len(), 3);
+}
+
+#[test]
+fn test_render() {
+    let mut display = vec![0u16; SCREEN_DIM_X * SCREEN_DIM_Y];
+    let mut osd = Osd::new(Settings::default());
+    osd.render(&mut display);
+    assert!(display.iter().all(|&p| p == 0));
+    osd.open(&mut TestHost::default());
+    osd.render(&mut display);
+    let at = |x: usize, y: usize| display[y * SCREEN_DIM_X + x];
+    // the panel starts a character in and down, and the first item is highlighted
+    assert_eq!(at(7, 11), 0);
+    assert_eq!(at(8, 12), Color::Blue.to_rgb555());
+    assert_eq!(at(8, 3 * 12), Color::Cyan.to_rgb555());
+    assert_eq!(at(8, 4 * 12), Color::Blue.to_rgb555());
+    // "RESUME" is drawn in black on the highlight
+    let row: Vec<u16> = (16..64).map(|x| at(x, 3 * 12 + 6)).collect();
+    assert!(row.contains(&Color::Black.to_rgb555()));
+    assert_eq!(at(248, 100), 0);
+}
+
+#[test]
+fn test_settings() {
+    let mut settings = Settings::default();
+    assert_eq!(settings.map_key(b'a'), b'a');
+    settings.set_key(b'a', b'b').unwrap();
+    settings.set_key(KEY_F1, KEY_ESC).unwrap();
+    settings.set_key(b'a', b'c').unwrap();
+    assert_eq!(settings.map_key(b'a'), b'c');
+    assert_eq!(settings.keys.len(), 2);
+    settings.artifact = Artifact::RedBlue;
+    let bytes = settings.to_bytes();
+    assert_eq!(Settings::from_bytes(&bytes).unwrap(), settings);
+    let mut bad = bytes.clone();
+    bad[8] ^= 1;
+    assert!(Settings::from_bytes(&bad).is_err());
+    assert!(Settings::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+    let mut flash = MemFlash::new(1);
+    // nothing saved yet
+    assert_eq!(Settings::load(&mut flash).unwrap(), Settings::default());
+    settings.save(&mut flash).unwrap();
+    assert_eq!(Settings::load(&mut flash).unwrap(), settings);
+    flash.write(0, b"junk").unwrap();
+    assert!(Settings::load(&mut flash).is_err());
+
+    // writes erase whole sectors and must start at one
+    let mut flash = MemFlash::new(2);
+    flash.write(SECTOR_SIZE, &[1, 2, 3]).unwrap();
+    let mut buf = [0u8; 5];
+    flash.read(SECTOR_SIZE, &mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 3, 0xff, 0xff]);
+    assert!(flash.write(1, &[0]).is_err());
+    assert!(flash.write(SECTOR_SIZE, &[0; SECTOR_SIZE + 1]).is_err());
+    assert!(flash.read(2 * SECTOR_SIZE - 1, &mut buf).is_err());
+}
+
+#[test]
+fn test_save_state() {
+    let mut core = create_core();
+    let program = assembler::Assembler::new(&instructions::Instance::new(0, None))
+        .assemble(
+            "    org $1000
+    lda #$12
+    ldb #$34
+    ldx #$5678
+    orcc #$50
+    sta $2000
+    exit"
+                .lines(),
+        )
+        .unwrap();
+    core.load_image(&program.to_image()).unwrap();
+    core.reg.pc = 0x1000;
+    core.reg.s = 0x8000;
+    core.exec().unwrap();
+    core.sam.lock().write(3);
+    let state = core.save_state();
+    let regs = core.reg;
+    let cycles = core.clock_cycles;
+
+    let mut other = create_core();
+    other.load_state(&state).unwrap();
+    assert_eq!(other.reg.d, 0x1234);
+    assert_eq!((other.reg.x, other.reg.pc), (regs.x, regs.pc));
+    assert_eq!(other.reg.cc.reg, regs.cc.reg);
+    assert_eq!(other.clock_cycles, cycles);
+    assert_eq!(other.sam.lock().get_raw_config(), 2);
+    assert_eq!(other.raw_ram[0x2000], 0x12);
+    assert!(other.save_state() == state);
+
+    // a bad state changes nothing
+    let mut other = create_core();
+    assert!(other.load_state(&state[..state.len() - 1]).is_err());
+    assert!(other.load_state(b"junk").is_err());
+    assert_eq!(other.raw_ram[0x2000], 0);
+}
+
+#[test]
+fn test_machine() {
+    let files: &[(&str, &[u8], u8)] = &[
+        ("CART.ROM", &[0x44; 0x2000], 0),
+        ("README.TXT", b"hello", 0),
+        ("GAME.CAS", &[0x3c; 100], 0),
+        ("disks/NOS9.DSK", &[0x55; 512], 0),
+        ("big.rom", &[0; 0x4000], 0),
+        ("Another.dsk", &[0x66; 512], 0),
+    ];
+    let dev = Formatter::new(FatKind::Fat16, false).format(files);
+    let volume = Rc::new(RefCell::new(FatVolume::mount(dev).unwrap()));
+    let server = Rc::new(RefCell::new(Server::new()));
+    let mut core = create_core();
+    let mut loaded = Loaded::default();
+    let mut flash = MemFlash::new(FLASH_SECTORS);
+    let mut machine = Machine {
+        core: &mut core,
+        volume: Some(volume),
+        server: Some(server.clone()),
+        loaded: &mut loaded,
+        flash: &mut flash,
+    };
+    assert_eq!(
+        machine.files(Media::Disk(0)),
+        ["Another.dsk", "disks/NOS9.DSK"]
+    );
+    assert_eq!(machine.files(Media::Cart), ["big.rom", "CART.ROM"]);
+    assert_eq!(machine.files(Media::Tape), ["GAME.CAS"]);
+
+    machine
+        .perform(&Action::Mount(Media::Cart, "CART.ROM".into()))
+        .unwrap();
+    assert_eq!(machine.core.raw_ram[0xc000], 0x44);
+    assert_eq!(machine.core.raw_ram[0xe000], 0xff);
+    assert!(machine.core.cart_pending);
+    assert!(machine
+        .perform(&Action::Mount(Media::Cart, "big.rom".into()))
+        .is_err());
+    assert_eq!(machine.mounted(Media::Cart).as_deref(), Some("CART.ROM"));
+    machine.perform(&Action::Eject(Media::Cart)).unwrap();
+    assert_eq!(machine.core.raw_ram[0xc000], 0xff);
+    assert!(!machine.core.cart_pending);
+
+    machine
+        .perform(&Action::Mount(Media::Disk(2), "disks/NOS9.DSK".into()))
+        .unwrap();
+    assert!(server.borrow().is_mounted(2));
+    assert_eq!(
+        machine.mounted(Media::Disk(2)).as_deref(),
+        Some("disks/NOS9.DSK")
+    );
+    machine.perform(&Action::Eject(Media::Disk(2))).unwrap();
+    assert!(!server.borrow().is_mounted(2));
+    assert!(machine
+        .perform(&Action::Mount(Media::Disk(0), "NONE.DSK".into()))
+        .is_err());
+    machine
+        .perform(&Action::Mount(Media::Tape, "GAME.CAS".into()))
+        .unwrap();
+    assert_eq!(machine.loaded.tape.as_ref().unwrap().1.len(), 100);
+
+    // states are kept in flash
+    machine.core.raw_ram[0x100] = 0xaa;
+    machine.perform(&Action::SaveState(1)).unwrap();
+    machine.core.raw_ram[0x100] = 0;
+    assert!(machine.perform(&Action::LoadState(0)).is_err());
+    machine.perform(&Action::LoadState(1)).unwrap();
+    assert_eq!(machine.core.raw_ram[0x100], 0xaa);
+    machine.perform(&Action::ColdBoot).unwrap();
+    assert_eq!(machine.core.raw_ram[0x100], 0);
+
+    let settings = Settings {
+        artifact: Artifact::BlueRed,
+        keys: vec![(KEY_F1, KEY_ESC)],
+    };
+    machine
+        .perform(&Action::ApplySettings(settings.clone()))
+        .unwrap();
+    assert_eq!(machine.core._vdg.lock().get_artifact(), Artifact::BlueRed);
+    assert_eq!(Settings::load(&mut flash).unwrap(), settings);
+
+    // without a card nothing can be mounted
+    let mut machine: Machine<MemBlockDevice> = Machine {
+        core: &mut core,
+        volume: None,
+        server: None,
+        loaded: &mut loaded,
+        flash: &mut flash,
+    };
+    assert!(machine.files(Media::Cart).is_empty());
+    assert!(machine
+        .perform(&Action::Mount(Media::Tape, "GAME.CAS".into()))
+        .is_err());
+    machine.perform(&Action::LoadState(1)).unwrap();
+}
@@ -1,7 +1,7 @@
 #![allow(unused)]
 use super::instructions::AddressingMode;
 #[cfg(not(target_os = "none"))]
-use super::test::{AddrOrVal, RegOrAddr, TestCriterion};
+use super::test::{self, AddrOrVal, Counter, RegOrAddr, TestCriterion};
 
 use super::*;
 
@@ -12,7 +12,7 @@ use core::str::Chars;
 
 type TokenIter = Peekable<IntoIter<Token>>;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenType {
     Number,
     Label,
@@ -22,15 +22,62 @@ pub enum TokenType {
     Star, // used for both multiplication and location reference
     Div,
     Mod,
-    Pow,
+    Pow, // "**"
     Hash,
     Comma,
     LBracket,
     RBracket,
     LParen,
     RParen,
-    LAngle,
-    RAngle,
+    LAngle, // used for both direct mode and "less than"
+    RAngle, // used for both extended mode and "greater than"
+    Amp,    // bitwise AND
+    Bar,    // bitwise OR
+    Caret,  // bitwise XOR
+    Tilde,  // bitwise NOT (ones' complement)
+    Bang,   // logical NOT
+    Shl,
+    Shr,
+    Eq, // "=" or "=="
+    Ne, // "!=" or "<>"
+    Le,
+    Ge,
+    AndAnd,
+    OrOr,
+    High, // the high byte of a value (".HIGH(value)" or "#>value")
+    Low,  // the low byte of a value (".LOW(value)" or "#<value")
+}
+impl TokenType {
+    fn is_binary_op(&self) -> bool {
+        use TokenType::*;
+        matches!(
+            self,
+            Add | Sub
+                | Star
+                | Div
+                | Mod
+                | Pow
+                | LAngle
+                | RAngle
+                | Amp
+                | Bar
+                | Caret
+                | Shl
+                | Shr
+                | Eq
+                | Ne
+                | Le
+                | Ge
+                | AndAnd
+                | OrOr
+        )
+    }
+    fn is_unary_op(&self) -> bool {
+        matches!(
+            self,
+            TokenType::Tilde | TokenType::Bang | TokenType::High | TokenType::Low
+        )
+    }
 }
 impl From<Token> for TokenType {
     fn from(item: Token) -> Self {
@@ -80,6 +127,11 @@ impl Display for Token {
 }
 pub trait LabelResolver {
     fn resolve(&self, label: &str) -> Option<u8u16>;
+    /// The byte at `addr`, for a "[addr]" in an expression (see Parser::with_registers_as_labels).
+    /// Only the debugger's expressions can read memory.
+    fn read_u8(&self, _addr: u16) -> Option<u8> {
+        None
+    }
 }
 
 /// Each value expression is parsed and converted into a tree of ValueNode objects.
@@ -140,31 +192,40 @@ impl ValueNode {
                     ))
                 }
             }
-            TokenType::Add
-            | TokenType::Sub
-            | TokenType::Star
-            | TokenType::Div
-            | TokenType::Mod
-            | TokenType::Pow => {
-                if let Some(left) = &self.left {
-                    if let Some(right) = &self.right {
-                        return self._eval_binary(lr, addr, signed, left, right);
+            // the byte of memory at an address (the child node)
+            TokenType::LBracket => match &self.left {
+                Some(operand) => {
+                    let at = operand.eval(lr, addr, false)?.u16();
+                    match lr.read_u8(at) {
+                        Some(byte) => Ok(self.negated(u8u16::u8(byte))),
+                        None => Err(syntax_err!("memory can't be read in this expression")),
                     }
-                } else if self.token.ttype == TokenType::Star {
-                    // this is a location reference; it has no child nodes
-                    // use the supplied addr as the value for the special label "*"
-                    return Ok(u8u16::u16(addr));
                 }
-                Err(Error::new(
-                    ErrorKind::Syntax,
-                    None,
-                    format!(
-                        "missing operand(s) for binary operation \"{}\"",
-                        self.token.clean()
-                    )
-                    .as_str(),
-                ))
+                None => Err(syntax_err!("missing address")),
+            },
+            TokenType::Star if self.left.is_none() && self.right.is_none() => {
+                // this is a location reference; it has no child nodes
+                // use the supplied addr as the value for the special label "*"
+                Ok(u8u16::u16(addr))
             }
+            t if t.is_binary_op() => match (&self.left, &self.right) {
+                (Some(left), Some(right)) => self
+                    ._eval_binary(lr, addr, signed, left, right)
+                    .map(|v| self.negated(v)),
+                _ => Err(syntax_err!(
+                    "missing operand(s) for binary operation \"{}\"",
+                    self.token.clean()
+                )),
+            },
+            t if t.is_unary_op() => match &self.left {
+                Some(operand) => self
+                    ._eval_unary(lr, addr, signed, operand)
+                    .map(|v| self.negated(v)),
+                None => Err(syntax_err!(
+                    "missing operand for \"{}\"",
+                    self.token.clean()
+                )),
+            },
             _ => Err(Error::new(
                 ErrorKind::Syntax,
                 None,
@@ -172,6 +233,35 @@ impl ValueNode {
             )),
         }
     }
+    /// Returns true if the expression refers to the current location ("*")
+    pub fn uses_location(&self) -> bool {
+        (self.token.ttype == TokenType::Star && self.left.is_none() && self.right.is_none())
+            || self.left.as_ref().is_some_and(|n| n.uses_location())
+            || self.right.as_ref().is_some_and(|n| n.uses_location())
+    }
+    /// Returns true if the node is a negative expression (e.g. "-1" or "~MASK").
+    /// Operators that combine an 8-bit negative value with a 16-bit value sign-extend it.
+    pub fn is_negative(&self) -> bool {
+        xor!(self.negate, self.token.ttype == TokenType::Tilde)
+    }
+    /// Returns the 16-bit value of this node given the result of evaluating it.
+    /// 8-bit values are sign-extended if the expression is negative.
+    pub fn widen(&self, val: u8u16) -> u16 {
+        if self.is_negative() {
+            val.sign_extended().u16()
+        } else {
+            val.u16()
+        }
+    }
+    /// Applies the sign of the node to the value of an operation
+    fn negated(&self, val: u8u16) -> u8u16 {
+        if self.negate {
+            let (u, _) = val.force_signed(true);
+            u
+        } else {
+            val
+        }
+    }
     fn _eval_binary(
         &self,
         lr: &dyn LabelResolver,
@@ -181,9 +271,59 @@ impl ValueNode {
         right: &ValueNode,
     ) -> Result<u8u16, Error> {
         let lhs = left.eval(lr, addr, signed)?;
+        // logical operators don't evaluate their right side if they don't need it
+        match self.token.ttype {
+            TokenType::AndAnd if lhs.u16() == 0 => return Ok(u8u16::u8(0)),
+            TokenType::OrOr if lhs.u16() != 0 => return Ok(u8u16::u8(1)),
+            _ => {}
+        }
         let rhs = right.eval(lr, addr, signed)?;
+        // the operands of the bitwise, shift and comparison operators are 16-bit values
+        // unless they're both 8-bit
+        let (l16, r16) = (left.widen(lhs), right.widen(rhs));
+        let bitwise = |op: fn(u16, u16) -> u16| {
+            if lhs.is_u8() && rhs.is_u8() {
+                u8u16::u8(op(l16, r16) as u8)
+            } else {
+                u8u16::u16(op(l16, r16))
+            }
+        };
+        // comparisons are signed if either operand is negative
+        let signed_cmp = left.is_negative() || right.is_negative();
+        let compare = |op: fn(i32, i32) -> bool| {
+            let (l, r) = if signed_cmp {
+                (l16 as i16 as i32, r16 as i16 as i32)
+            } else {
+                (l16 as i32, r16 as i32)
+            };
+            u8u16::u8(op(l, r) as u8)
+        };
         // TODO: warn about overflow in these operations
         match self.token.ttype {
+            TokenType::Add | TokenType::Sub if left.is_negative() || right.is_negative() => {
+                // signed arithmetic (only the negative operands are sign-extended)
+                let signed = |node: &ValueNode, v: u16| {
+                    if node.is_negative() {
+                        v as i16 as i32
+                    } else {
+                        v as i32
+                    }
+                };
+                let (l, r) = (signed(left, l16), signed(right, r16));
+                let n = if self.token.ttype == TokenType::Add {
+                    l + r
+                } else {
+                    l - r
+                };
+                if !(-0x8000..=0xffff).contains(&n) {
+                    Err(syntax_err!("signed arithmetic overflow"))
+                } else if lhs.is_u8() && rhs.is_u8() && (-128..0).contains(&n) {
+                    // like a negative 8-bit number
+                    Ok(u8u16::u8(n as u8))
+                } else {
+                    Ok(u8u16::from_u16_shrink(n as u16))
+                }
+            }
             TokenType::Add => {
                 let (u, f) = lhs.u16().overflowing_add(rhs.u16());
                 if f {
@@ -208,6 +348,9 @@ impl ValueNode {
                     Ok(u8u16::from_u16_shrink(u))
                 }
             }
+            TokenType::Div | TokenType::Mod if rhs.u16() == 0 => {
+                Err(syntax_err!("division by zero"))
+            }
             TokenType::Div => Ok(lhs.div(rhs)),
             TokenType::Mod => Ok(lhs.modulo(rhs)),
             TokenType::Pow => {
@@ -218,9 +361,60 @@ impl ValueNode {
                     Ok(u8u16::from_u16_shrink(u))
                 }
             }
+            TokenType::Amp => Ok(bitwise(|l, r| l & r)),
+            TokenType::Bar => Ok(bitwise(|l, r| l | r)),
+            TokenType::Caret => Ok(bitwise(|l, r| l ^ r)),
+            TokenType::Shl | TokenType::Shr => {
+                let n = rhs.u16().min(16) as u32;
+                let val = if self.token.ttype == TokenType::Shl {
+                    l16.checked_shl(n).unwrap_or(0)
+                } else if left.is_negative() {
+                    // an arithmetic shift keeps the sign of a negative value
+                    ((l16 as i16) >> n.min(15)) as u16
+                } else {
+                    l16.checked_shr(n).unwrap_or(0)
+                };
+                // an 8-bit value stays 8-bit if the result fits
+                let fits = if left.is_negative() {
+                    (-128..=127).contains(&(val as i16))
+                } else {
+                    val <= 0xff
+                };
+                if lhs.is_u8() && fits {
+                    Ok(u8u16::u8(val as u8))
+                } else {
+                    Ok(u8u16::u16(val))
+                }
+            }
+            TokenType::Eq => Ok(compare(|l, r| l == r)),
+            TokenType::Ne => Ok(compare(|l, r| l != r)),
+            TokenType::LAngle => Ok(compare(|l, r| l < r)),
+            TokenType::Le => Ok(compare(|l, r| l <= r)),
+            TokenType::RAngle => Ok(compare(|l, r| l > r)),
+            TokenType::Ge => Ok(compare(|l, r| l >= r)),
+            TokenType::AndAnd | TokenType::OrOr => Ok(u8u16::u8((rhs.u16() != 0) as u8)),
             _ => unreachable!(),
         }
     }
+    fn _eval_unary(
+        &self,
+        lr: &dyn LabelResolver,
+        addr: u16,
+        signed: bool,
+        operand: &ValueNode,
+    ) -> Result<u8u16, Error> {
+        let val = operand.eval(lr, addr, signed)?;
+        Ok(match self.token.ttype {
+            TokenType::Tilde => match val {
+                u8u16::u8(b) => u8u16::u8(!b),
+                u8u16::u16(w) => u8u16::u16(!w),
+            },
+            TokenType::Bang => u8u16::u8((val.u16() == 0) as u8),
+            TokenType::High => u8u16::u8((operand.widen(val) >> 8) as u8),
+            TokenType::Low => u8u16::u8(val.lsb()),
+            _ => unreachable!(),
+        })
+    }
 }
 impl Display for ValueNode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -234,13 +428,14 @@ impl Display for ValueNode {
                 self.token.clean(),
                 self.right.as_ref().unwrap()
             );
-        } else if self.left.is_some() {
-            r = write!(
-                f,
-                "ERROR? {} LEFT: {} ",
-                self.token.clean(),
-                self.left.as_ref().unwrap()
-            );
+        } else if let Some(operand) = self.left.as_ref() {
+            let sign = if self.negate { "-" } else { "" };
+            r = match self.token.ttype {
+                TokenType::High => write!(f, "{}.HIGH({})", sign, operand),
+                TokenType::Low => write!(f, "{}.LOW({})", sign, operand),
+                TokenType::LBracket => write!(f, "{}[{}]", sign, operand),
+                _ => write!(f, "{}{}{}", sign, self.token.clean(), operand),
+            };
         } else if self.negate {
             r = write!(f, "-{}", self.token.clean());
         } else {
@@ -336,13 +531,48 @@ impl Display for OperandDescriptor {
         Ok(())
     }
 }
+/// The binary operators from the lowest to the highest precedence (the mulops and
+/// exponentiation are handled by parse_mulexpr and parse_powexpr)
+const BINARY_OPS: [&[TokenType]; 9] = [
+    &[TokenType::OrOr],
+    &[TokenType::AndAnd],
+    &[TokenType::Bar],
+    &[TokenType::Caret],
+    &[TokenType::Amp],
+    &[TokenType::Eq, TokenType::Ne],
+    &[
+        TokenType::LAngle,
+        TokenType::Le,
+        TokenType::RAngle,
+        TokenType::Ge,
+    ],
+    &[TokenType::Shl, TokenType::Shr],
+    &[TokenType::Add, TokenType::Sub],
+];
+
 /// The container for parsing methods.
-pub struct Parser {}
+pub struct Parser {
+    /// true if register names are labels (so a LabelResolver can supply their values)
+    registers_as_labels: bool,
+}
 impl Parser {
     pub fn new() -> Self {
-        Parser {}
+        Parser {
+            registers_as_labels: false,
+        }
+    }
+    /// Returns a parser for expressions that refer to the contents of registers and memory
+    /// (e.g. the debugger's breakpoint conditions). Register names are parsed as labels and
+    /// "[addr]" is the byte at addr.
+    pub fn with_registers_as_labels() -> Self {
+        Parser {
+            registers_as_labels: true,
+        }
     }
     fn is_register(&self, s: &str) -> bool {
+        if self.registers_as_labels {
+            return false;
+        }
         match s.to_uppercase().as_str() {
             "A" | "B" | "CC" | "DP" | "D" | "PC" | "PCR" | "S" | "U" | "X" | "Y" => true,
             _ => false,
@@ -352,13 +582,21 @@ impl Parser {
     ///
     /// Grammar for operands:
     /// ```text
-    ///  operand = "#" valexpr | "<" valexpr | "[" opexpr "]" | opexpr
+    ///  operand = "#" ["<" | ">"] valexpr | "<" valexpr | ">" valexpr | "[" opexpr "]" | opexpr
     ///  opexpr = valexpr | valexpr, reg | reg, reg | ,incdec
-    ///  valexpr (pemdas) = mulexpr [addop mulexpr]
-    ///  valexpr (l-to-r) = powexpr [<addop|mulop> powexpr]
+    ///  valexpr = andexpr ["||" andexpr]
+    ///  andexpr = bitorexpr ["&&" bitorexpr]
+    ///  bitorexpr = xorexpr ["|" xorexpr]
+    ///  xorexpr = bitandexpr ["^" bitandexpr]
+    ///  bitandexpr = eqexpr ["&" eqexpr]
+    ///  eqexpr = relexpr [("=" | "==" | "!=" | "<>") relexpr]
+    ///  relexpr = shiftexpr [("<" | "<=" | ">" | ">=") shiftexpr]
+    ///  shiftexpr = addexpr [("<<" | ">>") addexpr]
+    ///  addexpr (pemdas) = mulexpr [addop mulexpr]
+    ///  addexpr (l-to-r) = powexpr [<addop|mulop> powexpr]
     ///  mulexpr = powexpr [mulop powexpr]
-    ///  powexpr = "-" powexpr | "+" powexpr | atom ["^" powexpr]
-    ///  atom = label | number | "*" | "(" valexpr ")"
+    ///  powexpr = ("-" | "+" | "~" | "!") powexpr | atom ["**" powexpr]
+    ///  atom = label | number | "*" | "(" valexpr ")" | (".HIGH" | ".LOW") "(" valexpr ")"
     ///  number = /\d{1,5}/ | /$\x{1,4}/ | /%{1,16}/
     ///  label = /[a-zA-Z][$_a-zA-Z0-9]+/
     ///  incdec = reg "+" | reg "++" | "-" reg | "--" reg
@@ -369,6 +607,9 @@ impl Parser {
     /// until labels are resolved. Thus, the assembler may require 2 or more passes. First all label
     /// definitions are found and then all label references are resolved.
     ///
+    /// Comparisons and logical operators evaluate to 1 (true) or 0 (false). "#<" and "#>"
+    /// select the low and high bytes of an immediate value.
+    ///
     pub fn parse_operand(&self, val_str: &str) -> Result<OperandDescriptor, Error> {
         let tokens = self.tokenize(val_str)?;
         let mut token_iter = tokens.into_iter().peekable();
@@ -381,8 +622,19 @@ impl Parser {
         match token.unwrap().ttype {
             TokenType::Hash => {
                 token_iter.next();
-                // this is immediate mode
-                let value = self.parse_valexpr(&mut token_iter)?;
+                // this is immediate mode (possibly of the low or high byte of the value)
+                let select = token_iter
+                    .next_if(|t| t.ttype == TokenType::LAngle || t.ttype == TokenType::RAngle);
+                let mut value = self.parse_valexpr(&mut token_iter)?;
+                if let Some(token) = select {
+                    let ttype = if token.ttype == TokenType::LAngle {
+                        TokenType::Low
+                    } else {
+                        TokenType::High
+                    };
+                    let token = Token::new(ttype, token.raw, None);
+                    value = ValueNode::new(token, false, Some(value), None);
+                }
                 od.mode = AddressingMode::Immediate;
                 od.value = Some(value);
             }
@@ -452,6 +704,9 @@ impl Parser {
                     || t.ttype == TokenType::Number
                     || t.ttype == TokenType::Sub
                     || t.ttype == TokenType::Star
+                    || t.ttype == TokenType::Tilde
+                    || t.ttype == TokenType::Bang
+                    || t.ttype == TokenType::LParen
             })
             .is_some()
         {
@@ -589,9 +844,10 @@ impl Parser {
         }
         Err(Error::new(ErrorKind::Syntax, None, "cannot parse operand"))
     }
-    /// Parse a valexpr. If order of operations is PEMDAS then:
+    /// Parse a valexpr. The binary operators are parsed in order of precedence
+    /// (see BINARY_OPS) down to the mulops, e.g. if order of operations is PEMDAS then:
     /// ```text
-    ///      valexpr ::= mulexpr [addop mulexpr]
+    ///      addexpr ::= mulexpr [addop mulexpr]
     /// ```
     /// See parse_valexpr_lr for handling of left-to-right operation ordering.
     fn parse_valexpr(&self, token_iter: &mut TokenIter) -> Result<ValueNode, Error> {
@@ -601,22 +857,23 @@ impl Parser {
             return self.parse_valexpr_lr(token_iter);
         }
         */
-        let mut node = self.parse_mulexpr(token_iter)?;
-        while token_iter.peek().is_some() {
-            match token_iter.peek().unwrap().ttype {
-                TokenType::Add | TokenType::Sub => {
-                    // consume the operation token and get the next mulexpr
-                    let op_token = token_iter.next().unwrap();
-                    let add_node = self.parse_mulexpr(token_iter)?;
-                    // make a parent node for both our new nodes
-                    node = ValueNode::new(op_token, false, Some(node), Some(add_node));
-                }
-                _ => {
-                    // this function doesn't understand this token
-                    // so leave it alone and return the node(s) we have
-                    return Ok(node);
-                }
+        self.parse_binexpr(token_iter, 0)
+    }
+    /// Parse the binary operators of one level of precedence and those above it
+    fn parse_binexpr(&self, token_iter: &mut TokenIter, level: usize) -> Result<ValueNode, Error> {
+        let operand = |token_iter: &mut TokenIter| {
+            if level + 1 < BINARY_OPS.len() {
+                self.parse_binexpr(token_iter, level + 1)
+            } else {
+                self.parse_mulexpr(token_iter)
             }
+        };
+        let mut node = operand(token_iter)?;
+        // operators of the same precedence are left-associative
+        while let Some(op_token) = token_iter.next_if(|t| BINARY_OPS[level].contains(&t.ttype)) {
+            let right = operand(token_iter)?;
+            // make a parent node for both our new nodes
+            node = ValueNode::new(op_token, false, Some(node), Some(right));
         }
         Ok(node)
     }
@@ -680,7 +937,7 @@ impl Parser {
     }
     /// Parse a powexpr.  
     /// ```text
-    ///     powexpr ::= "-" powexpr | "+" powexpr | atom ["^" powexpr]
+    ///     powexpr ::= ("-" | "+" | "~" | "!") powexpr | atom ["**" powexpr]
     /// ```
     fn parse_powexpr(&self, token_iter: &mut TokenIter) -> Result<ValueNode, Error> {
         // make sure we have tokens to parse
@@ -713,6 +970,13 @@ impl Parser {
             node.negate = xor!(node.negate, negate);
             return Ok(node);
         }
+        // bitwise and logical NOT
+        if let Some(op_token) =
+            token_iter.next_if(|t| t.ttype == TokenType::Tilde || t.ttype == TokenType::Bang)
+        {
+            let operand = self.parse_powexpr(token_iter)?;
+            return Ok(ValueNode::new(op_token, false, Some(operand), None));
+        }
         // if we get here then we expect to parse an atom
         let left = self.parse_atom(token_iter)?;
         // does it have an exponent?
@@ -733,10 +997,28 @@ impl Parser {
     }
     /// Parse an atom.
     /// ```text
-    ///     atom ::= label | number | '*' | '(' valexpr ')'
+    ///     atom ::= label | number | '*' | '(' valexpr ')' | ('.HIGH' | '.LOW') '(' valexpr ')'
+    ///              | '[' valexpr ']'
     /// ```
+    /// The '[' valexpr ']' form (the byte at an address) is only for the debugger's expressions.
     fn parse_atom(&self, token_iter: &mut TokenIter) -> Result<ValueNode, Error> {
         if let Some(token) = token_iter.next() {
+            // byte selection functions
+            let select = match token.raw.to_ascii_uppercase().as_str() {
+                ".HIGH" => Some(TokenType::High),
+                ".LOW" => Some(TokenType::Low),
+                _ => None,
+            };
+            if let Some(ttype) = select.filter(|_| {
+                token.ttype == TokenType::Label
+                    && token_iter
+                        .peek()
+                        .is_some_and(|t| t.ttype == TokenType::LParen)
+            }) {
+                let operand = self.parse_atom(token_iter)?;
+                let token = Token::new(ttype, token.raw, None);
+                return Ok(ValueNode::new(token, false, Some(operand), None));
+            }
             match token.ttype {
                 TokenType::Number | TokenType::Label | TokenType::Star => {
                     return Ok(ValueNode::new(token, false, None, None));
@@ -750,6 +1032,15 @@ impl Parser {
                     }
                     return Err(syntax_err!("missing closing parenthesis"));
                 }
+                TokenType::LBracket if self.registers_as_labels => {
+                    let node = self.parse_valexpr(token_iter)?;
+                    if let Some(rbracket) = token_iter.next() {
+                        if rbracket.ttype == TokenType::RBracket {
+                            return Ok(ValueNode::new(token, false, Some(node), None));
+                        }
+                    }
+                    return Err(syntax_err!("missing closing bracket"));
+                }
                 _ => {}
             }
         }
@@ -772,6 +1063,76 @@ impl Parser {
         tc: &mut TestCriterion,
         lr: &dyn LabelResolver,
     ) -> Result<(), Error> {
+        // counters (e.g. "cycles" or "cycles(START,DONE)") take a plain count on the RHS
+        if let Some((counter, args)) = Counter::from_lhs(&tc.lhs_src) {
+            let span = match args {
+                Some(args) => {
+                    let (start, end) = self.parse_arg_pair(args, lr)?;
+                    Some((start, end))
+                }
+                None => None,
+            };
+            tc.lhs = Some(RegOrAddr::Counter(counter, span));
+            tc.rhs = Some(AddrOrVal::Count(self.parse_count(&tc.rhs_src, lr)?));
+            return Ok(());
+        }
+        // condition code bits (e.g. "cc.z" or "cc.carry") take 0 or 1 on the RHS
+        if let Some(name) = tc
+            .lhs_src
+            .get(..3)
+            .filter(|cc| cc.eq_ignore_ascii_case("cc."))
+            .map(|_| &tc.lhs_src[3..])
+        {
+            let bit = registers::CCBit::from_name(name)
+                .ok_or_else(|| syntax_err!("Invalid CC bit \"{}\" in test criterion", name))?;
+            let rhs = tc.rhs_src.strip_prefix('#').unwrap_or(&tc.rhs_src);
+            let val = self.str_to_value_node(rhs)?.eval(lr, 0, true)?;
+            if val.u16() > 1 {
+                return Err(syntax_err!("CC bit can only be compared with 0 or 1"));
+            }
+            tc.lhs = Some(RegOrAddr::CC(bit));
+            tc.rhs = Some(AddrOrVal::Val(u8u16::u8(val.u8())));
+            return Ok(());
+        }
+        // memory ranges: "mem(START,LEN)" or "screen(COL,ROW)"
+        if let Some((name, args)) = test::split_call(&tc.lhs_src) {
+            let (a, b) = self.parse_arg_pair(args, lr)?;
+            let len = if name == "mem" { Some(b) } else { None };
+            let rhs = self.parse_range_rhs(&tc.rhs_src, len, lr)?;
+            let (lhs, rhs) = match name.as_str() {
+                "mem" => (RegOrAddr::Range(a, b), rhs),
+                "screen" => {
+                    let bytes = match rhs {
+                        AddrOrVal::Bytes(bytes) => bytes,
+                        _ => return Err(syntax_err!("screen criteria require a string or bytes")),
+                    };
+                    if a >= test::SCREEN_COLUMNS || b >= test::SCREEN_ROWS {
+                        return Err(syntax_err!("screen position is out of bounds"));
+                    }
+                    if a as usize + bytes.len() > test::SCREEN_COLUMNS as usize {
+                        return Err(syntax_err!("screen text runs past the end of the row"));
+                    }
+                    let codes = if test::string_literal(&tc.rhs_src).is_some() {
+                        bytes.into_iter().map(test::ascii_to_screen_code).collect()
+                    } else {
+                        bytes
+                    };
+                    (RegOrAddr::Screen(a as u8, b as u8), AddrOrVal::Bytes(codes))
+                }
+                _ => {
+                    return Err(syntax_err!(
+                        "Invalid LHS \"{}\" in test criterion",
+                        &tc.lhs_src
+                    ))
+                }
+            };
+            if tc.op != test::CmpOp::Eq && tc.op != test::CmpOp::Ne {
+                return Err(syntax_err!("only = and != can be used with memory ranges"));
+            }
+            tc.lhs = Some(lhs);
+            tc.rhs = Some(rhs);
+            return Ok(());
+        }
         let mut tokens = self.tokenize(&tc.lhs_src)?;
         let mut token_iter = tokens.into_iter().peekable();
         // try to get the lhs; start by looking for a register
@@ -789,11 +1150,20 @@ impl Parser {
             let addr = node.eval(lr, 0, true)?;
             tc.lhs = Some(RegOrAddr::Addr(addr.u16()));
         } else {
-            return Err(syntax_err!(format!(
+            return Err(syntax_err!(
                 "Invalid LHS \"{}\" in test criterion",
                 &tc.lhs_src
-            )
-            .as_str()));
+            ));
+        }
+        // an address can also be compared with a string or a list of bytes
+        if matches!(tc.lhs, Some(RegOrAddr::Addr(_)))
+            && (tc.rhs_src.contains(',') || test::string_literal(&tc.rhs_src).is_some())
+        {
+            if tc.op != test::CmpOp::Eq && tc.op != test::CmpOp::Ne {
+                return Err(syntax_err!("only = and != can be used with memory ranges"));
+            }
+            tc.rhs = Some(self.parse_range_rhs(&tc.rhs_src, None, lr)?);
+            return Ok(());
         }
         let mut rhs_is_value = false;
         tokens = self.tokenize(&tc.rhs_src)?;
@@ -819,6 +1189,88 @@ impl Parser {
         Ok(())
     }
 
+    /// Parse a pair of comma-separated expressions, e.g. the args of "mem(START,LEN)"
+    #[cfg(not(target_os = "none"))]
+    fn parse_arg_pair(&self, args: &str, lr: &dyn LabelResolver) -> Result<(u16, u16), Error> {
+        let (a, b) = args
+            .split_once(',')
+            .ok_or_else(|| syntax_err!("Expected two arguments but found \"{}\"", args))?;
+        let a = self.str_to_value_node(a)?.eval(lr, 0, true)?;
+        let b = self.str_to_value_node(b)?.eval(lr, 0, true)?;
+        Ok((a.u16(), b.u16()))
+    }
+
+    /// Parse the RHS of a memory range criterion. This can be a string literal, a list of
+    /// values ("#1,2,3"), another range ("mem(START,LEN)") or an address (the start of the
+    /// range to compare with). Values above $FF in a list are stored as 16-bit (big endian) words.
+    /// If the length of the LHS range is given then the RHS must be the same length.
+    #[cfg(not(target_os = "none"))]
+    fn parse_range_rhs(
+        &self,
+        src: &str,
+        len: Option<u16>,
+        lr: &dyn LabelResolver,
+    ) -> Result<AddrOrVal, Error> {
+        let check_len = |rhs_len: usize| match len {
+            Some(len) if len as usize != rhs_len => Err(syntax_err!(
+                "Range length ({}) doesn't match the length of the RHS ({})",
+                len,
+                rhs_len
+            )),
+            _ => Ok(()),
+        };
+        if let Some(s) = test::string_literal(src) {
+            check_len(s.len())?;
+            return Ok(AddrOrVal::Bytes(s.bytes().collect()));
+        }
+        if let Some((name, args)) = test::split_call(src) {
+            if name != "mem" {
+                return Err(syntax_err!("Invalid RHS \"{}\" in test criterion", src));
+            }
+            let (start, rhs_len) = self.parse_arg_pair(args, lr)?;
+            check_len(rhs_len as usize)?;
+            return Ok(AddrOrVal::Addr(start));
+        }
+        let mut token_iter = self.tokenize(src)?.into_iter().peekable();
+        if token_iter.next_if(|t| t.ttype == TokenType::Hash).is_none() {
+            // not a list of values so it must be the address of the range to compare with
+            let addr = self.str_to_value_node(src)?.eval(lr, 0, true)?;
+            return Ok(AddrOrVal::Addr(addr.u16()));
+        }
+        let mut bytes = Vec::new();
+        loop {
+            let val = self.parse_valexpr(&mut token_iter)?.eval(lr, 0, true)?;
+            if val.u16() > 0xff {
+                bytes.push((val.u16() >> 8) as u8);
+            }
+            bytes.push(val.u8());
+            match token_iter.next() {
+                None => break,
+                Some(t) if t.ttype == TokenType::Comma => continue,
+                Some(_) => return Err(syntax_err!("Expected ',' between values")),
+            }
+        }
+        check_len(bytes.len())?;
+        Ok(AddrOrVal::Bytes(bytes))
+    }
+
+    /// Parse the RHS of a counter test criterion. Plain decimal, hex ($) and binary (%)
+    /// numbers may exceed 16 bits (up to 64); anything else is evaluated as an expression.
+    #[cfg(not(target_os = "none"))]
+    fn parse_count(&self, src: &str, lr: &dyn LabelResolver) -> Result<u64, Error> {
+        let src = src.strip_prefix('#').unwrap_or(src);
+        let (digits, radix) = match (src.strip_prefix('$'), src.strip_prefix('%')) {
+            (Some(hex), _) => (hex, 16),
+            (_, Some(bin)) => (bin, 2),
+            _ => (src, 10),
+        };
+        if !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix)) {
+            return u64::from_str_radix(digits, radix)
+                .map_err(|_| syntax_err!("count is too large"));
+        }
+        Ok(self.str_to_value_node(src)?.eval(lr, 0, false)?.u16() as u64)
+    }
+
     /// Tokenize the given string and return a Vec<Token>.
     fn tokenize(&self, input: &str) -> Result<Vec<Token>, Error> {
         let mut chars = input.chars();
@@ -886,6 +1338,11 @@ impl Parser {
                     }
                 }
                 '0'..='9' => {
+                    // digits followed by 'f' or 'b' are a reference to a temporary label (e.g. "1f")
+                    if let Some(token) = self.get_temp_label_ref(&mut current, &mut chars) {
+                        output.push(token);
+                        continue;
+                    }
                     let r = self.get_number_from_decimal(&mut current, &mut chars);
                     if r.is_err() {
                         err_msg = r.err();
@@ -893,7 +1350,7 @@ impl Parser {
                     }
                     output.push(r.unwrap());
                 }
-                'a'..='z' => {
+                'a'..='z' | '_' | '@' | '.' => {
                     let r = self.get_label_or_register(&mut current, &mut chars);
                     if r.is_err() {
                         err_msg = r.err();
@@ -910,17 +1367,56 @@ impl Parser {
                     current = chars.next();
                 }
                 '*' => {
-                    output.push(Token::new(TokenType::Star, "*".to_string(), None));
-                    current = chars.next();
+                    // "**" is exponentiation if it follows a value (otherwise "*" is a location)
+                    let follows_value = output.last().is_some_and(|t: &Token| {
+                        matches!(
+                            t.ttype,
+                            TokenType::Number | TokenType::Label | TokenType::RParen
+                        )
+                    });
+                    let token = if follows_value {
+                        self.get_operator(&mut current, &mut chars, &[("**", TokenType::Pow)])
+                    } else {
+                        None
+                    };
+                    output.push(token.unwrap_or_else(|| {
+                        current = chars.next();
+                        Token::new(TokenType::Star, "*".to_string(), None)
+                    }));
                 }
                 '/' => {
                     output.push(Token::new(TokenType::Div, "/".to_string(), None));
                     current = chars.next();
                 }
                 '^' => {
-                    output.push(Token::new(TokenType::Pow, "^".to_string(), None));
+                    output.push(Token::new(TokenType::Caret, "^".to_string(), None));
                     current = chars.next();
                 }
+                '~' => {
+                    output.push(Token::new(TokenType::Tilde, "~".to_string(), None));
+                    current = chars.next();
+                }
+                '&' | '|' | '=' | '!' | '<' | '>' => {
+                    let ops: &[(&str, TokenType)] = match ch {
+                        '&' => &[("&&", TokenType::AndAnd), ("&", TokenType::Amp)],
+                        '|' => &[("||", TokenType::OrOr), ("|", TokenType::Bar)],
+                        '=' => &[("==", TokenType::Eq), ("=", TokenType::Eq)],
+                        '!' => &[("!=", TokenType::Ne), ("!", TokenType::Bang)],
+                        '<' => &[
+                            ("<<", TokenType::Shl),
+                            ("<=", TokenType::Le),
+                            ("<>", TokenType::Ne),
+                            ("<", TokenType::LAngle),
+                        ],
+                        _ => &[
+                            (">>", TokenType::Shr),
+                            (">=", TokenType::Ge),
+                            (">", TokenType::RAngle),
+                        ],
+                    };
+                    // one of the operators is always a single char so this can't fail
+                    output.extend(self.get_operator(&mut current, &mut chars, ops));
+                }
                 '#' => {
                     output.push(Token::new(TokenType::Hash, "#".to_string(), None));
                     current = chars.next();
@@ -937,14 +1433,6 @@ impl Parser {
                     output.push(Token::new(TokenType::RBracket, "]".to_string(), None));
                     current = chars.next();
                 }
-                '<' => {
-                    output.push(Token::new(TokenType::LAngle, "<".to_string(), None));
-                    current = chars.next();
-                }
-                '>' => {
-                    output.push(Token::new(TokenType::RAngle, ">".to_string(), None));
-                    current = chars.next();
-                }
                 '(' => {
                     output.push(Token::new(TokenType::LParen, "(".to_string(), None));
                     current = chars.next();
@@ -965,6 +1453,23 @@ impl Parser {
             Ok(output)
         }
     }
+    /// Returns the token for the first of the operators that matches the input at
+    /// `current` and consumes it. Longer operators must come first (e.g. "<<" before "<").
+    fn get_operator(
+        &self,
+        current: &mut Option<char>,
+        chars: &mut Chars,
+        ops: &[(&str, TokenType)],
+    ) -> Option<Token> {
+        let mut input = String::new();
+        input.push((*current)?);
+        input.extend(chars.clone().next());
+        let (op, ttype) = ops.iter().find(|(op, _)| input.starts_with(op))?;
+        for _ in 0..op.len() {
+            *current = chars.next();
+        }
+        Some(Token::new(*ttype, op.to_string(), None))
+    }
     fn get_number_from_binary(
         &self,
         current: &mut Option<char>,
@@ -1073,6 +1578,28 @@ impl Parser {
         };
         Ok(Token::new(TokenType::Number, raw, Some(u)))
     }
+    /// If the digits at `current` are followed by 'f' or 'b' (and nothing else that could be
+    /// part of a label) they are consumed and returned as a Label token, e.g. "1b".
+    /// Otherwise nothing is consumed and None is returned.
+    fn get_temp_label_ref(&self, current: &mut Option<char>, chars: &mut Chars) -> Option<Token> {
+        let mut raw = String::new();
+        raw.push((*current)?);
+        let mut ahead = chars.clone();
+        let mut next = ahead.next();
+        while let Some(c) = next.filter(|c| c.is_ascii_digit()) {
+            raw.push(c);
+            next = ahead.next();
+        }
+        let direction = next.filter(|c| matches!(c.to_ascii_lowercase(), 'f' | 'b'))?;
+        let after = ahead.next();
+        if after.is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '$') {
+            return None;
+        }
+        raw.push(direction);
+        *chars = ahead;
+        *current = after;
+        Some(Token::new(TokenType::Label, raw, None))
+    }
     fn get_number_from_char(
         &self,
         current: &mut Option<char>,
@@ -1091,7 +1618,12 @@ impl Parser {
         current: &mut Option<char>,
         chars: &mut Chars,
     ) -> Result<Token, String> {
+        // the first char has already been checked (it may also be the '@' or '.' of a local label)
         let mut raw = String::new();
+        if let Some(ch) = current.take() {
+            raw.push(ch);
+            *current = chars.next();
+        }
         while let Some(ref ch) = current {
             if !ch.is_ascii_digit() && !ch.is_alphabetic() && !ch.eq(&'_') && !ch.eq(&'$') {
                 break;
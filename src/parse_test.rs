@@ -0,0 +1,301 @@
+use crate::instructions::AddressingMode;
+use crate::parse::{LabelResolver, Parser};
+use crate::test::{CmpOp, TestCriterion};
+use crate::*;
+use alloc::collections::BTreeMap;
+
+struct Labels(BTreeMap<&'static str, u8u16>);
+impl LabelResolver for Labels {
+    fn resolve(&self, label: &str) -> Option<u8u16> {
+        self.0.get(label).copied()
+    }
+}
+fn labels() -> Labels {
+    Labels(BTreeMap::from([
+        ("TABLE", u8u16::u16(0x1234)),
+        ("FLAGS", u8u16::u8(0xa5)),
+        ("MASK", u8u16::u8(0x0f)),
+        ("ZERO", u8u16::u8(0)),
+    ]))
+}
+
+fn eval_at(expr: &str, addr: u16) -> Result<u8u16, Error> {
+    Parser::new()
+        .str_to_value_node(expr)?
+        .eval(&labels(), addr, false)
+}
+fn eval(expr: &str) -> u8u16 {
+    eval_at(expr, 0).unwrap_or_else(|e| panic!("{}: {}", expr, e))
+}
+/// Checks the value of each expression regardless of whether it's 8 or 16-bit
+fn check_values(cases: &[(&str, u16)]) {
+    for (expr, val) in cases {
+        assert_eq!(eval(expr).u16(), *val, "{}", expr);
+    }
+}
+
+#[test]
+fn test_precedence() {
+    check_values(&[
+        ("1+2*3", 7),
+        ("(1+2)*3", 9),
+        ("10-2-3", 5),
+        ("64/4/2", 8),
+        ("2**3**2", 512),
+        ("2*3**2", 18),
+        ("1<<2+1", 8),
+        ("2*3<<1", 12),
+        ("64>>2>>1", 8),
+        ("$F0>>4|1", 0x0f),
+        ("1|2&3", 3),
+        ("1^3&1", 0),
+        ("1|3^1", 3),
+        ("6&3==3", 0),
+        ("1<2==1", 1),
+        ("1+1<<1<3", 0),
+        ("1||0&&0", 1),
+        ("(1||0)&&0", 0),
+        ("1==1&&2==2", 1),
+        ("~0&MASK", 0x0f),
+        ("-2+5", 3),
+        ("!0+1", 2),
+        ("-(2+3)+6", 1),
+    ]);
+}
+
+#[test]
+fn test_bitwise() {
+    assert_eq!(eval("$F0&$3C"), u8u16::u8(0x30));
+    assert_eq!(eval("$F0|$0F"), u8u16::u8(0xff));
+    assert_eq!(eval("$FF^$0F"), u8u16::u8(0xf0));
+    assert_eq!(eval("FLAGS&$7F"), u8u16::u8(0x25));
+    assert_eq!(eval("MASK|%10000000"), u8u16::u8(0x8f));
+    assert_eq!(eval("~MASK"), u8u16::u8(0xf0));
+    assert_eq!(eval("~$000F"), u8u16::u16(0xfff0));
+    assert_eq!(eval("~~$55"), u8u16::u8(0x55));
+    assert_eq!(eval("TABLE&$FF"), u8u16::u16(0x0034));
+    assert_eq!(eval("TABLE|$8000"), u8u16::u16(0x9234));
+    assert_eq!(eval("TABLE^TABLE"), u8u16::u16(0));
+    // negative 8-bit values are sign-extended when combined with 16-bit values
+    assert_eq!(eval("TABLE&~MASK"), u8u16::u16(0x1230));
+    assert_eq!(eval("TABLE&-1"), u8u16::u16(0x1234));
+    assert_eq!(eval("TABLE&-2"), u8u16::u16(0x1234));
+    assert_eq!(eval("-1&MASK"), u8u16::u8(0x0f));
+}
+
+#[test]
+fn test_shifts() {
+    assert_eq!(eval("3<<1"), u8u16::u8(6));
+    assert_eq!(eval("1<<8"), u8u16::u16(0x100));
+    assert_eq!(eval("$80<<1"), u8u16::u16(0x100));
+    assert_eq!(eval("FLAGS<<8"), u8u16::u16(0xa500));
+    assert_eq!(eval("$8000<<1"), u8u16::u16(0));
+    assert_eq!(eval("1<<16"), u8u16::u8(0));
+    assert_eq!(eval("TABLE>>8"), u8u16::u16(0x12));
+    assert_eq!(eval("$80>>7"), u8u16::u8(1));
+    assert_eq!(eval("$8000>>15"), u8u16::u16(1));
+    assert_eq!(eval("$8000>>16"), u8u16::u16(0));
+    // negative values are shifted arithmetically
+    assert_eq!(eval("-16>>2"), u8u16::u8(0xfc));
+    assert_eq!(eval("-1>>20"), u8u16::u8(0xff));
+    assert_eq!(eval("-$1000>>4"), u8u16::u16(0xff00));
+    assert_eq!(eval("-1<<4"), u8u16::u8(0xf0));
+    assert_eq!(eval("-128<<1"), u8u16::u16(0xff00));
+}
+
+#[test]
+fn test_signed_arithmetic() {
+    assert_eq!(eval("-2+5"), u8u16::u8(3));
+    assert_eq!(eval("5+-2"), u8u16::u8(3));
+    assert_eq!(eval("2+-5+1"), u8u16::u8(0xfe));
+    assert_eq!(eval("-1-1"), u8u16::u8(0xfe));
+    assert_eq!(eval("TABLE+-1"), u8u16::u16(0x1233));
+    assert_eq!(eval("TABLE-(-1)"), u8u16::u16(0x1235));
+    assert_eq!(eval("$8000+-1"), u8u16::u16(0x7fff));
+    assert_eq!(eval("-100-100"), u8u16::u16(0xff38));
+    assert_eq!(eval("-(2+3)"), u8u16::u8(0xfb));
+    // unsigned arithmetic still can't go below zero
+    assert!(eval_at("2-5", 0).is_err());
+    assert!(eval_at("-$8000-1", 0).is_err());
+}
+
+#[test]
+fn test_comparisons() {
+    check_values(&[
+        ("3=3", 1),
+        ("3==4", 0),
+        ("3!=4", 1),
+        ("3<>3", 0),
+        ("2<3", 1),
+        ("3<3", 0),
+        ("3<=3", 1),
+        ("4>3", 1),
+        ("3>3", 0),
+        ("3>=4", 0),
+        ("TABLE=$1234", 1),
+        ("TABLE>FLAGS", 1),
+        // unsigned unless an operand is negative
+        ("$FF>$7F", 1),
+        ("$FFFF>0", 1),
+        ("-1<0", 1),
+        ("-1>-2", 1),
+        ("-2>=-1", 0),
+        ("-1=$FFFF", 1),
+        ("~0<1", 1),
+    ]);
+    // the result is always an 8-bit 0 or 1
+    assert_eq!(eval("TABLE==TABLE"), u8u16::u8(1));
+}
+
+#[test]
+fn test_logical() {
+    check_values(&[
+        ("!0", 1),
+        ("!5", 0),
+        ("!!5", 1),
+        ("!TABLE", 0),
+        ("1&&2", 1),
+        ("1&&ZERO", 0),
+        ("0||0", 0),
+        ("0||TABLE", 1),
+        // the right side isn't evaluated if the left side decides the result
+        ("0&&UNDEFINED", 0),
+        ("1||UNDEFINED", 1),
+    ]);
+    let e = eval_at("1&&UNDEFINED", 0).unwrap_err();
+    assert_eq!(e.kind, ErrorKind::Reference);
+}
+
+#[test]
+fn test_byte_select() {
+    assert_eq!(eval(".HIGH(TABLE)"), u8u16::u8(0x12));
+    assert_eq!(eval(".low(TABLE)"), u8u16::u8(0x34));
+    assert_eq!(eval(".HIGH(TABLE+$100)"), u8u16::u8(0x13));
+    assert_eq!(eval(".HIGH(FLAGS)"), u8u16::u8(0));
+    assert_eq!(eval(".HIGH(-2)"), u8u16::u8(0xff));
+    assert_eq!(eval(".LOW(-2)"), u8u16::u8(0xfe));
+    assert_eq!(eval(".HIGH(TABLE)<<8|.LOW(TABLE)"), u8u16::u16(0x1234));
+
+    let parser = Parser::new();
+    let od = parser.parse_operand("#>TABLE").unwrap();
+    assert_eq!(od.mode, AddressingMode::Immediate);
+    let value = od.value.unwrap();
+    assert_eq!(value.eval(&labels(), 0, false).unwrap(), u8u16::u8(0x12));
+    assert_eq!(value.to_string(), ".HIGH(TABLE)");
+    let od = parser.parse_operand("#<TABLE+1").unwrap();
+    let value = od.value.unwrap();
+    assert_eq!(value.eval(&labels(), 0, false).unwrap(), u8u16::u8(0x35));
+    // "<" and ">" still force direct and extended mode at the start of an operand
+    assert_eq!(
+        parser.parse_operand("<$10").unwrap().mode,
+        AddressingMode::Direct
+    );
+    let od = parser.parse_operand(">$10").unwrap();
+    assert_eq!(od.mode, AddressingMode::Extended);
+    assert!(od.force_mode);
+    let od = parser.parse_operand("<$10<<1").unwrap();
+    assert_eq!(od.mode, AddressingMode::Direct);
+    assert_eq!(
+        od.value.unwrap().eval(&labels(), 0, false).unwrap().u16(),
+        0x20
+    );
+}
+
+#[test]
+fn test_unary_operands() {
+    let parser = Parser::new();
+    for operand in ["~MASK", "!ZERO", "(MASK)", "~MASK,x"] {
+        assert!(parser.parse_operand(operand).is_ok(), "{}", operand);
+    }
+    assert_eq!(
+        parser.parse_operand("~MASK,x").unwrap().mode,
+        AddressingMode::Offset
+    );
+}
+
+#[test]
+fn test_location_and_power() {
+    // "*" is the location unless it follows a value
+    assert_eq!(eval_at("*", 0x100).unwrap(), u8u16::u16(0x100));
+    assert_eq!(eval_at("**2", 0x100).unwrap(), u8u16::u16(0x200));
+    assert_eq!(eval_at("*+2*3", 0x100).unwrap(), u8u16::u16(0x106));
+    assert_eq!(eval("2**10"), u8u16::u16(1024));
+    assert_eq!(eval("(1+1)**4"), u8u16::u8(16));
+    // "^" is XOR (it was the power operator before "**")
+    assert_eq!(eval("2**3"), u8u16::u8(8));
+    assert_eq!(eval("6^3"), u8u16::u8(5));
+}
+
+#[test]
+fn test_expression_errors() {
+    for expr in [
+        "1/0", "5%ZERO", "1&", "&1", "(1", "~", "!", ".HIGH(", ".LOW()", "1<<", "1==", "1||",
+        "2**20",
+    ] {
+        let result = Parser::new()
+            .str_to_value_node(expr)
+            .and_then(|node| node.eval(&labels(), 0, false));
+        assert!(result.is_err(), "{}", expr);
+    }
+    // (str_to_value_node leaves a trailing ")" for the caller to reject)
+    assert!(Parser::new().parse_operand("#1)").is_err());
+    let e = eval_at("1/0", 0).unwrap_err();
+    assert!(e.msg.contains("division by zero"));
+}
+
+#[test]
+fn test_criterion_operators() {
+    for (line, lhs, op, rhs) in [
+        (";! cycles<=1200", "cycles", CmpOp::Le, "1200"),
+        (";! cycles <= 1200", "cycles", CmpOp::Le, "1200"),
+        (";! instructions>=$10", "instructions", CmpOp::Ge, "$10"),
+        (";! a!=#1", "a", CmpOp::Ne, "#1"),
+        (";! TABLE<<1 > #2", "TABLE<<1", CmpOp::Gt, "#2"),
+        (
+            ";! cycles(TABLE,TABLE+2)<5",
+            "cycles(TABLE,TABLE+2)",
+            CmpOp::Lt,
+            "5",
+        ),
+        (";! buf = \"a<b\"", "buf", CmpOp::Eq, "\"a<b\""),
+    ] {
+        let tc = TestCriterion::from_line(1, line).unwrap().unwrap();
+        assert_eq!(
+            (tc.lhs_src.as_str(), tc.op, tc.rhs_src.as_str()),
+            (lhs, op, rhs)
+        );
+    }
+    assert!(TestCriterion::from_line(1, ";! cycles 1200").is_err());
+}
+
+#[test]
+fn test_criterion_counts() {
+    let count = |line: &str| {
+        let mut tc = TestCriterion::from_line(1, line).unwrap().unwrap();
+        Parser::new()
+            .parse_test_criterion(&mut tc, &labels())
+            .map(|_| tc.rhs.unwrap().to_string())
+    };
+    assert_eq!(count(";! cycles<=100000").unwrap(), "100000");
+    assert_eq!(count(";! cycles = $123456789").unwrap(), "4886718345");
+    assert_eq!(count(";! cycles = %100000000000000000").unwrap(), "131072");
+    assert_eq!(count(";! instructions = TABLE+1").unwrap(), "4661");
+    assert!(count(";! cycles = 99999999999999999999").is_err());
+    assert!(count(";! cycles = $10000000000000000").is_err());
+}
+
+#[test]
+fn test_screen_criteria() {
+    let parse = |line: &str| {
+        let mut tc = TestCriterion::from_line(1, line).unwrap().unwrap();
+        Parser::new()
+            .parse_test_criterion(&mut tc, &labels())
+            .map(|_| tc.rhs.unwrap().to_string())
+    };
+    // $41,$71,$60,$41,$40 (shown as text by Display)
+    assert_eq!(parse(";! screen(0,0) = \"A1 a@\"").unwrap(), "\"Aq`A@\"");
+    assert!(parse(";! screen(29,15) = \"ABC\"").is_ok());
+    assert!(parse(";! screen(30,15) = \"ABC\"").is_err());
+    assert!(parse(";! screen(0,16) = \"A\"").is_err());
+    assert!(parse(";! screen(32,0) = #1").is_err());
+}
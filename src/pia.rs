@@ -1,4 +1,5 @@
 use super::*;
+use crate::input::{KEY_DOWN, KEY_ESC, KEY_HOME, KEY_LEFT, KEY_RIGHT, KEY_UP};
 use spin::Mutex;
 
 pub trait Pia {
@@ -23,6 +24,18 @@ struct PiaSide {
 }
 
 impl PiaSide {
+    fn state(&self) -> [u8; 6] {
+        let lines = self.c1 as u8 | (self.c2 as u8) << 1;
+        [self.cr, self.ir, self.or, self.ddr, lines, 0]
+    }
+    fn set_state(&mut self, state: &[u8]) {
+        self.cr = state[0];
+        self.ir = state[1];
+        self.or = state[2];
+        self.ddr = state[3];
+        self.c1 = state[4] & 1 != 0;
+        self.c2 = state[4] & 2 != 0;
+    }
     fn manual_c2_trigger(&self) -> bool {
         self.cr & 0x30 == 0x30
     }
@@ -119,6 +132,14 @@ impl PiaSide {
     }
 }
 
+/// The keyboard matrices the PIA can be wired to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    #[default]
+    CoCo,
+    Dragon,
+}
+
 #[derive(Debug)]
 pub struct Pia0 {
     ab: [PiaSide; 2],
@@ -127,6 +148,7 @@ pub struct Pia0 {
     joy_y: u8,
     joy_sw_1: bool,
     joy_sw_2: bool,
+    layout: Layout,
     // Deadlock risk! but Pia0 needs to read Pia1.
     // In real life, they are wired together.
     // I'm sure there's a better way to do this
@@ -186,9 +208,17 @@ impl Pia0 {
             joy_y: 0x1f,
             joy_sw_1: false,
             joy_sw_2: false,
+            layout: Layout::default(),
             pia1,
         }
     }
+    /// Sets the machine whose keyboard matrix keys are pressed in
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+    pub fn get_layout(&self) -> Layout {
+        self.layout
+    }
     // Helper to map ASCII to CoCo matrix (Row, Col)
     fn ascii_to_matrix(c: u8) -> Option<(usize, usize)> {
         match c.to_ascii_uppercase() {
@@ -238,12 +268,22 @@ impl Pia0 {
             b'/' => Some((5, 7)),
             b'\r' | b'\n' => Some((6, 0)), // Enter
             0x08 => Some((3, 5)),          // Backspace -> Left Arrow as Backspace behavior
+            KEY_UP => Some((3, 3)),
+            KEY_DOWN => Some((3, 4)),
+            KEY_LEFT => Some((3, 5)),
+            KEY_RIGHT => Some((3, 6)),
+            KEY_HOME => Some((6, 1)), // CLEAR
+            KEY_ESC => Some((6, 2)),  // BREAK
             _ => None,
         }
     }
 
     pub fn set_key(&mut self, key: u8, pressed: bool) {
-        if let Some((row, col)) = Self::ascii_to_matrix(key) {
+        if let Some((mut row, col)) = Self::ascii_to_matrix(key) {
+            if self.layout == Layout::Dragon && row < 6 {
+                // the Dragon's rows are the CoCo's moved down by two, so digits come first
+                row = (row + 2) % 6;
+            }
             if pressed {
                 self.col[col] |= 1 << row;
             } else {
@@ -252,6 +292,22 @@ impl Pia0 {
             self.strobe_keyboard();
         }
     }
+    /// Lets go of every key, e.g. when the keyboard is taken over by the on-screen menu
+    pub fn release_keys(&mut self) {
+        self.col = [0; 8];
+        self.strobe_keyboard();
+    }
+    /// The registers of both sides, for saving the machine's state
+    pub fn state(&self) -> [u8; 12] {
+        let mut state = [0u8; 12];
+        state[..6].copy_from_slice(&self.ab[0].state());
+        state[6..].copy_from_slice(&self.ab[1].state());
+        state
+    }
+    pub fn set_state(&mut self, state: &[u8; 12]) {
+        self.ab[0].set_state(&state[..6]);
+        self.ab[1].set_state(&state[6..]);
+    }
     pub fn strobe_keyboard(&mut self) {
         // strobe the keyboard based on side B output
         let mut com = 0u8;
@@ -340,12 +396,46 @@ impl Pia1 {
     pub fn get_vdg_bits(&self) -> u8 {
         (self.ab[1].read_data() >> 3) & 0x1f
     }
-    /// Lets PIA1 know that a cartridge was inserted.
+    /// Lets PIA1 know that the cartridge port's CART line (CB1) was raised, either by an
+    /// inserted cartridge or by the RS-232 Pak's IRQ output.
     /// Returns true if FIRQ is signalled
     pub fn cart_firq(&mut self) -> bool {
         self.ab[1].set_c1(true);
         self.ab[1].consume_interrupt()
     }
+    /// The level of the RS-232 output (side A bit 1)
+    pub fn rs232_out(&self) -> bool {
+        self.ab[0].read_output() & 2 == 2
+    }
+    /// True if reading register `reg_num` returns the RS-232 input, i.e. it's side B's
+    /// data register ($FF22 or a mirror) and CRB bit 2 selects that rather than the DDR
+    pub fn reads_rs232_in(&self, reg_num: usize) -> bool {
+        reg_num % 4 == 2 && self.ab[1].pr_selected()
+    }
+    /// Sets the level of the RS-232 input (side B bit 0)
+    pub fn set_rs232_in(&mut self, level: bool) {
+        self.ab[1].ir = (self.ab[1].ir & !1) | level as u8;
+    }
+    /// The registers of both sides and the sound and DAC settings, for saving the
+    /// machine's state
+    pub fn state(&self) -> [u8; 13] {
+        let mut state = [0u8; 13];
+        state[..6].copy_from_slice(&self.ab[0].state());
+        state[6..12].copy_from_slice(&self.ab[1].state());
+        state[12] = self.sound_enabled as u8
+            | (self.dac_sel_a as u8) << 1
+            | (self.dac_sel_b as u8) << 2
+            | (self.last_bit_sound as u8) << 3;
+        state
+    }
+    pub fn set_state(&mut self, state: &[u8; 13]) {
+        self.ab[0].set_state(&state[..6]);
+        self.ab[1].set_state(&state[6..12]);
+        self.sound_enabled = state[12] & 1 != 0;
+        self.dac_sel_a = state[12] & 2 != 0;
+        self.dac_sel_b = state[12] & 4 != 0;
+        self.last_bit_sound = state[12] & 8 != 0;
+    }
     pub fn set_dac_mux(&mut self, a: bool, b: bool) {
         self.dac_sel_a = a;
         self.dac_sel_b = b;
@@ -47,6 +47,22 @@ mod embedded {
     use coco::input::{ps2::Ps2Keyboard, usb::UsbKeyboard, InputDevice, InputEvent};
     use hal::pio::PIOExt;
 
+    // Serial Support
+    use coco::serial::uart::Uart;
+    use hal::fugit::RateExtU32;
+    use hal::Clock;
+
+    // Storage Support
+    use coco::storage::{
+        boot, fat::FatVolume, flash::SECTOR_SIZE, pico_flash::PicoFlash, sdcard::SdCard,
+    };
+
+    // On-Screen Menu Support
+    use coco::osd::{self, Loaded, Machine, Osd, Settings};
+
+    /// The Pico 2's flash; settings and saved states are kept at the end of it
+    const FLASH_SIZE: usize = 4 * 1024 * 1024;
+
     #[rp235x_hal::entry]
     fn main() -> ! {
         // ... (setup skipped) ...
@@ -59,7 +75,7 @@ mod embedded {
         // Initialise the clocks (Custom DVI Clock Setup)
         let timing = VGA_TIMING;
         let width = timing.h_active_pixels; // Capture width before move
-        let _clocks = init_clocks(
+        let clocks = init_clocks(
             pac.XOSC,
             pac.ROSC,
             pac.CLOCKS,
@@ -146,6 +162,24 @@ mod embedded {
         // Initialize USB Keyboard Driver (Placeholder)
         let mut usb_kb = UsbKeyboard::new();
 
+        // --- Serial Initialization ---
+        // UART0 (GPIO 0 = TX, GPIO 1 = RX) is the RS-232 Pak's port
+        let uart_pins = (
+            pins.gpio0.into_function::<hal::gpio::FunctionUart>(),
+            pins.gpio1.into_function::<hal::gpio::FunctionUart>(),
+        );
+        let uart = hal::uart::UartPeripheral::new(pac.UART0, uart_pins, &mut pac.RESETS)
+            .enable(
+                hal::uart::UartConfig::new(
+                    115_200.Hz(),
+                    hal::uart::DataBits::Eight,
+                    None,
+                    hal::uart::StopBits::One,
+                ),
+                clocks.peripheral_clock.freq(),
+            )
+            .unwrap();
+
         // --- Emulator Core Initialization ---
         let mut dm = DeviceManager::new();
         let mut core = Core::new(
@@ -155,19 +189,83 @@ mod embedded {
             dm.pia0.clone(),
             dm.pia1.clone(),
             0x8000,
-            None,
+            Some(acia::RS232_PAK_ADDR),
+        );
+        core.attach_serial(Box::new(Uart::new(uart))).unwrap();
+
+        // --- SD Card Initialization ---
+        // SPI0 (GPIO 2 = SCK, GPIO 3 = MOSI, GPIO 4 = MISO, GPIO 5 = CS)
+        let spi_pins = (
+            pins.gpio3.into_function::<hal::gpio::FunctionSpi>(),
+            pins.gpio4.into_function::<hal::gpio::FunctionSpi>(),
+            pins.gpio2.into_function::<hal::gpio::FunctionSpi>(),
+        );
+        let sd_cs = pins
+            .gpio5
+            .into_push_pull_output_in_state(hal::gpio::PinState::High);
+        // cards are initialized at 400kHz at most
+        let spi = hal::spi::Spi::<_, _, _, 8>::new(pac.SPI0, spi_pins).init(
+            &mut pac.RESETS,
+            clocks.peripheral_clock.freq(),
+            400.kHz(),
+            embedded_hal::spi::MODE_0,
         );
 
-        // Load a placeholder ROM
-        let dummy_rom = [0x12, 0x12, 0x12, 0xFE];
-        core.load_bytes(&dummy_rom, 0xA000).unwrap();
-        core.force_reset_vector(0xA000).unwrap();
+        // Load the ROMs, programs and disks listed in coco.yaml on the card
+        let volume = match SdCard::new(spi, sd_cs).and_then(|mut sd| {
+            sd.spi()
+                .set_baudrate(clocks.peripheral_clock.freq(), 16.MHz());
+            FatVolume::mount(sd)
+        }) {
+            Ok(fs) => Some(Rc::new(core::cell::RefCell::new(fs))),
+            Err(e) => {
+                defmt::warn!("No SD card: {}", defmt::Display2Format(&e));
+                None
+            }
+        };
+        // what's in the machine and where disks are mounted, for the menu
+        let mut loaded = Loaded::default();
+        let mut server = None;
+        match volume.clone().map(|fs| boot::boot(fs, &mut core)) {
+            Some(Ok(media)) => {
+                // a cartridge starts itself with a FIRQ once BASIC is up
+                core.cart_pending = media.cart.is_some();
+                loaded.cart = media.cart;
+                for (drive, path) in media.config.disks.iter().enumerate() {
+                    loaded.disks.insert(drive as u8, path.clone());
+                }
+                // disks are served over DriveWire through the Becker port
+                let shared = Rc::new(core::cell::RefCell::new(media.server));
+                core.attach_becker(Box::new(shared.clone()));
+                server = Some(shared);
+            }
+            booted => {
+                if let Some(Err(e)) = booted {
+                    defmt::warn!("No boot media: {}", defmt::Display2Format(&e));
+                }
+                // Load a placeholder ROM
+                let dummy_rom = [0x12, 0x12, 0x12, 0xFE];
+                core.load_bytes(&dummy_rom, 0xA000).unwrap();
+                core.force_reset_vector(0xA000).unwrap();
+            }
+        }
         core.reset().unwrap();
 
+        // --- On-Screen Menu Initialization ---
+        let flash_region = osd::FLASH_SECTORS * SECTOR_SIZE;
+        let mut flash = PicoFlash::new(FLASH_SIZE - flash_region, flash_region);
+        let settings = Settings::load(&mut flash).unwrap_or_else(|e| {
+            defmt::warn!("Settings not loaded: {}", defmt::Display2Format(&e));
+            Settings::default()
+        });
+        dm.vdg.lock().set_artifact(settings.artifact);
+        let mut osd = Osd::new(settings);
+
         // Main Emulator Loop
         loop {
-            // Run core for a slice of cycles
-            for _ in 0..10000 {
+            // Run core for a slice of cycles (the CoCo is paused while the menu is open)
+            let slice = if osd.is_open() { 0 } else { 10000 };
+            for _ in 0..slice {
                 // Increased slice
                 if let Err(_e) = core.exec_one() {
                     break;
@@ -176,20 +274,34 @@ mod embedded {
             // Update devices
 
             // Poll Input Devices
-            if let Some(event) = ps2_kb.poll() {
-                match event {
-                    InputEvent::Press(k) => dm.pia0.lock().set_key(k, true),
-                    InputEvent::Release(k) => dm.pia0.lock().set_key(k, false),
+            for event in [ps2_kb.poll(), usb_kb.poll()].into_iter().flatten() {
+                let was_open = osd.is_open();
+                let mut machine = Machine {
+                    core: &mut core,
+                    volume: volume.clone(),
+                    server: server.clone(),
+                    loaded: &mut loaded,
+                    flash: &mut flash,
+                };
+                if osd.handle(event, &mut machine) {
+                    match (was_open, osd.is_open()) {
+                        // the menu has the keyboard now
+                        (false, true) => dm.pia0.lock().release_keys(),
+                        // redraw what the menu covered
+                        (true, false) => dm.vdg.lock().set_dirty(),
+                        _ => {}
+                    }
+                    continue;
                 }
-            }
-            if let Some(event) = usb_kb.poll() {
+                let settings = osd.settings();
                 match event {
-                    InputEvent::Press(k) => dm.pia0.lock().set_key(k, true),
-                    InputEvent::Release(k) => dm.pia0.lock().set_key(k, false),
+                    InputEvent::Press(k) => dm.pia0.lock().set_key(settings.map_key(k), true),
+                    InputEvent::Release(k) => dm.pia0.lock().set_key(settings.map_key(k), false),
                 }
             }
 
             dm.update();
+            osd.render(&mut dm.display);
 
             // Build DVI Display List
             // VGA 640x480 @ 60Hz. VERTICAL_REPEAT=1, so we must produce all 480 scanlines.
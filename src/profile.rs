@@ -0,0 +1,329 @@
+//! Profiling of guest code: where the cycles go.
+//!
+//! A [Profiler] attached to a [Core] (see [Core::attach_profiler]) counts the cycles
+//! spent at each address and follows subroutine calls (JSR, BSR and LBSR) and
+//! interrupts to build a call graph. A call returns when the stack pointer rises above
+//! its return address, which catches RTS and RTI as well as PULS PC and code that
+//! unwinds the stack itself.
+//!
+//! Functions are known by their entry address and named with the symbols in
+//! [Core::addr_to_sym]. Results come as a flat text report or as folded stacks (one line
+//! per call stack with the cycles spent in it) for flame graph tools.
+use crate::registers::{CCBit, Set};
+use crate::{format, Core, Map, String, Vec};
+use core::fmt::Write;
+
+/// The deepest call stack followed. Deeper calls (which usually mean the stack has been
+/// switched rather than a real call chain) lose their oldest callers.
+const MAX_DEPTH: usize = 256;
+
+/// Cycles spent at an address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PcStats {
+    pub cycles: u64,
+    /// the number of instructions executed there
+    pub count: u64,
+}
+
+/// Cycles spent in a function
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FnStats {
+    pub calls: u64,
+    /// cycles in the function and everything it called
+    pub inclusive: u64,
+    /// cycles in the function itself
+    pub exclusive: u64,
+}
+
+/// Calls from one function to another
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EdgeStats {
+    pub calls: u64,
+    /// cycles in the callee and everything it called, when called from the caller
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Frame {
+    /// the function's entry address
+    func: u16,
+    /// the stack pointer the frame ends at: the call returns when S reaches it
+    end: u32,
+    /// the cycle count when the function was entered
+    entered: u64,
+    /// cycles spent in the function itself not yet added to its folded stack
+    pending: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    pcs: Map<u16, PcStats>,
+    functions: Map<u16, FnStats>,
+    edges: Map<(u16, u16), EdgeStats>,
+    /// exclusive cycles by call stack (entry addresses, outermost first)
+    folded: Map<Vec<u16>, u64>,
+    stack: Vec<Frame>,
+    /// cycles executed while profiling
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+/// A function's name: its symbol, or its address
+fn name(addr: u16, symbols: &Map<u16, Vec<String>>) -> String {
+    match symbols.get(&addr).and_then(|s| s.last()) {
+        Some(sym) => sym.clone(),
+        None => format!("${:04X}", addr),
+    }
+}
+
+/// The symbol at or before `addr`, with the offset from it
+fn location(addr: u16, symbols: &Map<u16, Vec<String>>) -> String {
+    match symbols.range(..=addr).next_back() {
+        Some((&at, syms)) if at == addr => syms[syms.len() - 1].clone(),
+        Some((&at, syms)) => format!("{}+{}", syms[syms.len() - 1], addr - at),
+        None => String::new(),
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+    /// Cycles and instruction counts by address
+    pub fn pcs(&self) -> &Map<u16, PcStats> {
+        &self.pcs
+    }
+    /// Calls by (caller, callee) entry address
+    pub fn edges(&self) -> &Map<(u16, u16), EdgeStats> {
+        &self.edges
+    }
+    /// Functions by entry address. Functions still running count up to now.
+    pub fn functions(&self) -> Map<u16, FnStats> {
+        let mut functions = self.functions.clone();
+        for (i, frame) in self.stack.iter().enumerate() {
+            if !self.stack[..i].iter().any(|f| f.func == frame.func) {
+                functions.entry(frame.func).or_default().inclusive += self.cycles - frame.entered;
+            }
+        }
+        functions
+    }
+    /// Exclusive cycles by call stack, outermost function first
+    pub fn stacks(&self) -> Map<Vec<u16>, u64> {
+        let mut folded = self.folded.clone();
+        if let Some(top) = self.stack.last().filter(|f| f.pending > 0) {
+            let path = self.stack.iter().map(|f| f.func).collect();
+            *folded.entry(path).or_default() += top.pending;
+        }
+        folded
+    }
+    /// Cycles by symbol: each address counts towards the symbol at or before it
+    pub fn symbols(&self, symbols: &Map<u16, Vec<String>>) -> Map<String, PcStats> {
+        let mut by_symbol: Map<String, PcStats> = Map::new();
+        for (&pc, stats) in &self.pcs {
+            let sym = match symbols.range(..=pc).next_back() {
+                Some((_, syms)) => syms[syms.len() - 1].clone(),
+                None => String::from("(no symbol)"),
+            };
+            let entry = by_symbol.entry(sym).or_default();
+            entry.cycles += stats.cycles;
+            entry.count += stats.count;
+        }
+        by_symbol
+    }
+    /// Adds the top frame's pending cycles to its folded stack
+    fn flush(&mut self) {
+        if let Some(top) = self.stack.last_mut() {
+            if top.pending > 0 {
+                let pending = core::mem::take(&mut top.pending);
+                let path = self.stack.iter().map(|f| f.func).collect();
+                *self.folded.entry(path).or_default() += pending;
+            }
+        }
+    }
+    fn push(&mut self, func: u16, end: u32) {
+        self.flush();
+        if let Some(caller) = self.stack.last() {
+            self.edges.entry((caller.func, func)).or_default().calls += 1;
+        }
+        self.functions.entry(func).or_default().calls += 1;
+        if self.stack.len() == MAX_DEPTH {
+            self.stack.remove(1);
+        }
+        self.stack.push(Frame {
+            func,
+            end,
+            entered: self.cycles,
+            pending: 0,
+        });
+    }
+    fn pop(&mut self) {
+        self.flush();
+        let Some(frame) = self.stack.pop() else {
+            return;
+        };
+        let elapsed = self.cycles - frame.entered;
+        // recursive calls are already counted by the outermost one
+        if !self.stack.iter().any(|f| f.func == frame.func) {
+            self.functions.entry(frame.func).or_default().inclusive += elapsed;
+        }
+        if let Some(caller) = self.stack.last() {
+            self.edges
+                .entry((caller.func, frame.func))
+                .or_default()
+                .cycles += elapsed;
+        }
+    }
+    /// Counts an instruction at `pc` that took `cycles`, leaving the registers `reg`
+    pub(crate) fn instruction(&mut self, pc: u16, cycles: u64, name: &str, reg: &Set) {
+        if self.stack.is_empty() {
+            // whatever was running when profiling started
+            self.push(pc, u32::MAX);
+        }
+        let stats = self.pcs.entry(pc).or_default();
+        stats.cycles += cycles;
+        stats.count += 1;
+        self.cycles += cycles;
+        self.instructions += 1;
+        if let Some(top) = self.stack.last_mut() {
+            top.pending += cycles;
+            self.functions.entry(top.func).or_default().exclusive += cycles;
+        }
+        // a call has returned once its return address is off the stack
+        while self.stack.len() > 1 && self.stack.last().is_some_and(|f| f.end <= reg.s as u32) {
+            self.pop();
+        }
+        if matches!(name, "JSR" | "BSR" | "LBSR") {
+            self.push(reg.pc, reg.s as u32 + 2);
+        }
+    }
+    /// Notes the start of an interrupt handler, with the registers stacked
+    pub(crate) fn interrupt(&mut self, reg: &Set) {
+        let stacked = if reg.cc.is_set(CCBit::E) { 12 } else { 3 };
+        self.push(reg.pc, reg.s as u32 + stacked);
+    }
+    /// A flat report: the total, then the top `top` addresses, symbols and functions by
+    /// cycles, and the calls between functions
+    pub fn report(&self, symbols: &Map<u16, Vec<String>>, top: usize) -> String {
+        let total = self.cycles;
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} cycles in {} instructions",
+            total, self.instructions
+        );
+
+        let mut pcs: Vec<_> = self.pcs.iter().collect();
+        pcs.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        let _ = writeln!(out, "\nAddresses:\n    cycles      %     count  address");
+        for (&pc, stats) in pcs.iter().take(top) {
+            let _ = writeln!(
+                out,
+                "{:>10} {:>5.1}% {:>9}  {:04X} {}",
+                stats.cycles,
+                percent(stats.cycles, total),
+                stats.count,
+                pc,
+                location(pc, symbols)
+            );
+        }
+
+        let by_symbol = self.symbols(symbols);
+        let mut syms: Vec<_> = by_symbol.iter().collect();
+        syms.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        let _ = writeln!(out, "\nSymbols:\n    cycles      %     count  symbol");
+        for (sym, stats) in syms.iter().take(top) {
+            let _ = writeln!(
+                out,
+                "{:>10} {:>5.1}% {:>9}  {}",
+                stats.cycles,
+                percent(stats.cycles, total),
+                stats.count,
+                sym
+            );
+        }
+
+        let functions = self.functions();
+        let mut funcs: Vec<_> = functions.iter().collect();
+        funcs.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        let _ = writeln!(
+            out,
+            "\nFunctions:\n inclusive      %  exclusive      %     calls  function"
+        );
+        for (&addr, stats) in funcs.iter().take(top) {
+            let _ = writeln!(
+                out,
+                "{:>10} {:>5.1}% {:>10} {:>5.1}% {:>9}  {}",
+                stats.inclusive,
+                percent(stats.inclusive, total),
+                stats.exclusive,
+                percent(stats.exclusive, total),
+                stats.calls,
+                name(addr, symbols)
+            );
+        }
+
+        let mut edges: Vec<_> = self.edges.iter().collect();
+        edges.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        let _ = writeln!(out, "\nCalls:\n    cycles     calls  caller -> callee");
+        for (&(caller, callee), stats) in edges.iter().take(top) {
+            let _ = writeln!(
+                out,
+                "{:>10} {:>9}  {} -> {}",
+                stats.cycles,
+                stats.calls,
+                name(caller, symbols),
+                name(callee, symbols)
+            );
+        }
+        out
+    }
+    /// The call stacks in the folded format flame graph tools read: the functions from
+    /// the outermost in, separated by semicolons, then the cycles spent there
+    pub fn folded(&self, symbols: &Map<u16, Vec<String>>) -> String {
+        let mut out = String::new();
+        for (path, cycles) in self.stacks() {
+            let names: Vec<String> = path.iter().map(|&f| name(f, symbols)).collect();
+            let _ = writeln!(out, "{} {}", names.join(";"), cycles);
+        }
+        out
+    }
+    /// Writes the report (see [Profiler::report]) to a file
+    #[cfg(not(target_os = "none"))]
+    pub fn save_report(
+        &self,
+        path: &str,
+        symbols: &Map<u16, Vec<String>>,
+        top: usize,
+    ) -> Result<(), crate::Error> {
+        std::fs::write(path, self.report(symbols, top))
+            .map_err(|e| err!(crate::ErrorKind::IO, None, "profile {}: {}", path, e))
+    }
+    /// Writes the folded stacks (see [Profiler::folded]) to a file
+    #[cfg(not(target_os = "none"))]
+    pub fn save_folded(
+        &self,
+        path: &str,
+        symbols: &Map<u16, Vec<String>>,
+    ) -> Result<(), crate::Error> {
+        std::fs::write(path, self.folded(symbols))
+            .map_err(|e| err!(crate::ErrorKind::IO, None, "profile {}: {}", path, e))
+    }
+}
+
+impl Core {
+    /// Starts profiling with `profiler`, replacing any previous one
+    pub fn attach_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+    pub fn detach_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+}
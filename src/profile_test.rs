@@ -0,0 +1,166 @@
+use crate::cpu_test::create_core;
+use crate::profile::*;
+use crate::*;
+use alloc::vec;
+
+/// Assembles `src`, adds `symbols` and runs the program with a profiler
+fn profile(src: &str, symbols: &[(&str, u16)]) -> (Core, Profiler) {
+    let mut core = create_core();
+    let program = assembler::Assembler::new(&instructions::Instance::new(0, None))
+        .assemble(src.lines())
+        .unwrap_or_else(|e| panic!("{}\n{}", e, src));
+    core.load_image(&program.to_image()).unwrap();
+    for &(name, addr) in symbols {
+        core.sym_to_addr.insert(name.to_string(), addr);
+        core.addr_to_sym.insert(addr, vec![name.to_string()]);
+    }
+    core.attach_profiler(Profiler::new());
+    core.reg.pc = 0x1000;
+    core.reg.s = 0x8000;
+    core.exec().unwrap();
+    let profiler = core.detach_profiler().unwrap();
+    (core, profiler)
+}
+
+const START: u16 = 0x1000;
+const LOOP: u16 = 0x1002;
+const OUTER: u16 = 0x1100;
+const INNER: u16 = 0x1200;
+
+#[test]
+fn test_profile() {
+    let src = "
+    org $1000
+    ldb #3
+loop bsr outer
+    decb
+    bne loop
+    exit
+    org $1100
+outer jsr inner
+    nop
+    rts
+    org $1200
+inner pshs a
+    lda #1
+    puls a,pc
+";
+    let symbols = [
+        ("START", START),
+        ("LOOP", LOOP),
+        ("OUTER", OUTER),
+        ("INNER", INNER),
+    ];
+    let (core, profiler) = profile(src, &symbols);
+    let total = profiler.cycles;
+    assert_eq!(total, core.clock_cycles);
+    assert_eq!(profiler.instructions, core.instruction_count);
+
+    // cycles by address (the forward BSR is assembled as an LBSR)
+    let decb = profiler.pcs()[&0x1005];
+    assert_eq!(decb.count, 3);
+    let clk = instructions::opcode_to_flavor(0x5a).unwrap().detail.clk;
+    assert_eq!(decb.cycles, 3 * clk as u64);
+    assert_eq!(
+        profiler.pcs().values().map(|s| s.cycles).sum::<u64>(),
+        total
+    );
+
+    // by symbol
+    let by_symbol = profiler.symbols(&core.addr_to_sym);
+    assert_eq!(by_symbol["START"].count, 1);
+    assert_eq!(by_symbol["INNER"].count, 9);
+    assert_eq!(by_symbol.values().map(|s| s.cycles).sum::<u64>(), total);
+
+    // the call graph
+    let functions = profiler.functions();
+    let (start, outer, inner) = (functions[&START], functions[&OUTER], functions[&INNER]);
+    assert_eq!((start.calls, outer.calls, inner.calls), (1, 3, 3));
+    assert_eq!(start.inclusive, total);
+    assert_eq!(inner.inclusive, inner.exclusive);
+    assert_eq!(outer.inclusive, outer.exclusive + inner.inclusive);
+    assert_eq!(start.exclusive + outer.exclusive + inner.exclusive, total);
+    let edges = profiler.edges();
+    assert_eq!(edges[&(START, OUTER)].calls, 3);
+    assert_eq!(edges[&(START, OUTER)].cycles, outer.inclusive);
+    assert_eq!(edges[&(OUTER, INNER)].calls, 3);
+    assert_eq!(edges[&(OUTER, INNER)].cycles, inner.inclusive);
+    assert_eq!(edges.len(), 2);
+
+    // folded stacks
+    let folded = profiler.folded(&core.addr_to_sym);
+    let lines: Vec<&str> = folded.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            format!("START {}", start.exclusive),
+            format!("START;OUTER {}", outer.exclusive),
+            format!("START;OUTER;INNER {}", inner.exclusive),
+        ]
+    );
+
+    let report = profiler.report(&core.addr_to_sym, 10);
+    assert!(report.starts_with(&format!("{} cycles in", total)));
+    assert!(report.contains("  1005 LOOP+3\n"), "{}", report);
+    assert!(report.contains("START -> OUTER"), "{}", report);
+    // only the top entries
+    let short = profiler.report(&core.addr_to_sym, 1);
+    assert!(!short.contains("OUTER -> INNER"), "{}", short);
+}
+
+#[test]
+fn test_profile_interrupts_and_recursion() {
+    let src = "
+    org $1000
+    ldb #4
+    bsr rec
+    swi
+    exit
+    org $1100
+rec decb
+    beq done
+    bsr rec
+done rts
+    org $1300
+handler inc $2000
+    rti
+";
+    let mut core = create_core();
+    // the SWI vector at $FFFA is in RAM at $BFFA
+    core.raw_ram[0xbffa..0xbffc].copy_from_slice(&[0x13, 0x00]);
+    let program = assembler::Assembler::new(&instructions::Instance::new(0, None))
+        .assemble(src.lines())
+        .unwrap();
+    core.load_image(&program.to_image()).unwrap();
+    core.attach_profiler(Profiler::new());
+    core.reg.pc = 0x1000;
+    core.reg.s = 0x8000;
+    core.exec().unwrap();
+    assert_eq!(core.raw_ram[0x2000], 1);
+    let profiler = core.detach_profiler().unwrap();
+    let functions = profiler.functions();
+    let (rec, handler) = (functions[&0x1100], functions[&0x1300]);
+    assert_eq!(rec.calls, 4);
+    // the recursion is only counted once
+    assert_eq!(rec.inclusive, rec.exclusive);
+    assert_eq!(handler.calls, 1);
+    assert_eq!(handler.inclusive, handler.exclusive);
+    assert_eq!(profiler.edges()[&(0x1000, 0x1300)].calls, 1);
+    assert_eq!(profiler.edges()[&(0x1100, 0x1100)].calls, 3);
+    // the handler returned, so the program carried on in its caller
+    let folded = profiler.folded(&Map::new());
+    assert!(folded.contains("$1000;$1300 "), "{}", folded);
+    assert!(
+        folded.contains("$1000;$1100;$1100;$1100;$1100 "),
+        "{}",
+        folded
+    );
+    assert_eq!(
+        folded
+            .lines()
+            .map(|l| l.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap())
+            .sum::<u64>(),
+        profiler.cycles
+    );
+    assert_eq!(functions[&0x1000].inclusive, profiler.cycles);
+}
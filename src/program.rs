@@ -6,6 +6,10 @@ use super::parse::{LabelResolver, ValueNode};
 #[cfg(not(target_os = "none"))]
 use super::test::TestCriterion;
 use super::*;
+use crate::hex::HexRecordCollection;
+use crate::image::Image;
+use alloc::borrow::Cow;
+use core::cell::Cell;
 
 // use lazy_static::lazy_static;
 // use regex::Regex;
@@ -20,45 +24,43 @@ lazy_static! {
 */
 
 #[derive(Debug)]
-struct MacroLineSegment {
-    pub s: String,        // the text fragment
-    pub n: Option<usize>, // the index of the arg that should be prepended to this fragment
+enum MacroLineSegment {
+    Text(String), // a fragment of the line's text
+    Arg(usize), // the index of the arg that should be substituted here (written as "@n" or "\name")
+    ArgCount,   // the number of args supplied to the macro (written as "\0")
+    Unique,     // a suffix that's unique to each expansion of the macro (written as "\@")
 }
 impl MacroLineSegment {
-    pub fn new(raw_fragment: &str) -> Result<Self, Error> {
-        let mut n = 0usize;
-        let mut i = 0usize;
-        for c in raw_fragment.chars() {
-            match c {
-                '0'..='9' => n = n * 10 + c.to_digit(10).unwrap() as usize,
-                c if c.is_whitespace() => break,
-                _ => return Err(syntax_err!("invalid macro parameter")),
-            }
-            i += 1;
-        }
-        let (_, r) = raw_fragment.split_at(i);
-        Ok(MacroLineSegment {
-            s: r.to_string(),
-            n: if i > 0 { Some(n) } else { None },
-        })
-    }
-    pub fn hydrate(&self, args: &[&str]) -> Result<String, Error> {
-        match self.n {
-            Some(n) => {
-                if n > args.len() {
-                    Err(syntax_err!("macro arg index out of bounds"))
-                } else {
-                    Ok(format!("{}{}", args[n], self.s))
-                }
-            }
-            None => Ok(self.s.to_string()),
+    pub fn hydrate(
+        &self,
+        args: &[&str],
+        supplied: usize,
+        unique_id: usize,
+        out: &mut String,
+    ) -> Result<(), Error> {
+        match self {
+            MacroLineSegment::Text(s) => out.push_str(s),
+            MacroLineSegment::Arg(n) => out.push_str(
+                args.get(*n)
+                    .ok_or_else(|| syntax_err!("macro arg index out of bounds"))?,
+            ),
+            MacroLineSegment::ArgCount => out.push_str(&format!("{}", supplied)),
+            MacroLineSegment::Unique => out.push_str(&format!("_{}", unique_id)),
         }
+        Ok(())
     }
 }
+/// A named macro parameter along with its default value (if it has one)
+#[derive(Debug)]
+pub struct MacroParam {
+    pub name: String,
+    pub default: Option<String>,
+}
 #[derive(Debug)]
 pub struct Macro {
     pub name: String,                  // name assigned to macro by programmer
-    pub arg_count: usize,              // number of args required by macro
+    pub arg_count: usize,              // number of args required by the macro's "@n" references
+    pub params: Vec<MacroParam>,       // named parameters (if any)
     lines: Vec<Vec<MacroLineSegment>>, // the non-empty lines of the macro (excluding .macro and .endm lines)
 }
 impl Macro {
@@ -66,52 +68,194 @@ impl Macro {
         Macro {
             name: name.to_string(),
             arg_count: 0,
+            params: Vec::new(),
             lines: Vec::new(),
         }
     }
+    /// Adds a named parameter. A parameter with a default value may be omitted when the
+    /// macro is invoked (an empty default, e.g. "b=", makes the parameter optional).
+    pub fn add_param(&mut self, name: &str, default: Option<&str>) -> Result<(), Error> {
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(syntax_err!("invalid macro parameter name \"{}\"", name));
+        }
+        if self.params.iter().any(|p| p.name == name) {
+            return Err(syntax_err!("duplicate macro parameter \"{}\"", name));
+        }
+        self.params.push(MacroParam {
+            name: name.to_string(),
+            default: default.map(String::from),
+        });
+        Ok(())
+    }
+    /// Adds a line to the body of the macro. Within the line:
+    /// - "@n" (where n is a number) refers to the macro's nth arg (starting at 0)
+    /// - "\name" refers to the arg for the named parameter
+    /// - "\0" is replaced with the number of args the macro was invoked with
+    /// - "\@" is replaced with a suffix that's unique to each expansion (which allows macros
+    ///   to define labels)
+    ///
+    /// Anything else (including an "@" or "\" that isn't part of one of the above) is just text.
     pub fn add_line(&mut self, line: &str) -> Result<(), Error> {
-        let s = line.split('@');
         let mut v: Vec<MacroLineSegment> = Vec::new();
-        for (i, raw) in s.enumerate() {
-            let f = MacroLineSegment::new(raw)?;
-            if let Some(n) = f.n {
-                if n >= self.arg_count {
-                    self.arg_count = n + 1
+        let mut text = String::new();
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let segment = match c {
+                '\\' if chars.next_if(|(_, c)| *c == '@').is_some() => MacroLineSegment::Unique,
+                '\\' if chars.next_if(|(_, c)| *c == '0').is_some() => MacroLineSegment::ArgCount,
+                '\\' => {
+                    // is it a reference to a named parameter? (the longest name wins)
+                    let rest = &line[i + 1..];
+                    let len = rest
+                        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                        .unwrap_or(rest.len());
+                    match self.params.iter().position(|p| p.name == rest[..len]) {
+                        Some(n) if len > 0 => {
+                            rest[..len].chars().for_each(|_| {
+                                chars.next();
+                            });
+                            MacroLineSegment::Arg(n)
+                        }
+                        _ => {
+                            text.push(c);
+                            continue;
+                        }
+                    }
+                }
+                '@' if chars.peek().is_some_and(|(_, c)| c.is_ascii_digit()) => {
+                    let mut n = 0usize;
+                    while let Some(d) = chars.peek().and_then(|(_, c)| c.to_digit(10)) {
+                        n = n
+                            .checked_mul(10)
+                            .and_then(|n| n.checked_add(d as usize))
+                            .ok_or_else(|| syntax_err!("invalid macro parameter"))?;
+                        chars.next();
+                    }
+                    if n >= self.arg_count {
+                        self.arg_count = n + 1
+                    }
+                    MacroLineSegment::Arg(n)
                 }
-            } else if i > 0 {
-                return Err(syntax_err!("missing macro parameter number"));
+                _ => {
+                    text.push(c);
+                    continue;
+                }
+            };
+            if !text.is_empty() {
+                v.push(MacroLineSegment::Text(core::mem::take(&mut text)));
             }
-            v.push(f);
+            v.push(segment);
+        }
+        if !text.is_empty() {
+            v.push(MacroLineSegment::Text(text));
         }
         self.lines.push(v);
         Ok(())
     }
-    pub fn hydrate_instance(&self, _args: Vec<&str>) -> Result<Vec<String>, Error> {
-        /*
-        let mut m = Vec::new();
-        if args.len() != self.arg_count {
-            return Err(syntax_err!(format!(
-                "wrong number of args for macro \"{}\"",
-                self.name
-            )));
+    /// Returns the lines of the macro with the given args substituted for its parameters.
+    /// Omitted (or empty) args for named parameters take their default values.
+    /// `unique_id` distinguishes this expansion from any other (see add_line).
+    pub fn hydrate_instance(&self, args: &[&str], unique_id: usize) -> Result<Vec<String>, Error> {
+        if !self.params.is_empty() && args.len() > self.params.len() {
+            return Err(syntax_err!(
+                "too many args for macro \"{}\" (expected at most {} but found {})",
+                self.name,
+                self.params.len(),
+                args.len()
+            ));
         }
+        let mut values: Vec<&str> = args.to_vec();
+        for (n, param) in self.params.iter().enumerate() {
+            let value = values.get(n).copied().filter(|v| !v.is_empty());
+            let value = match (value, param.default.as_deref()) {
+                (Some(v), _) | (None, Some(v)) => v,
+                (None, None) => {
+                    return Err(syntax_err!(
+                        "missing arg for parameter \"{}\" of macro \"{}\"",
+                        param.name,
+                        self.name
+                    ))
+                }
+            };
+            match values.get_mut(n) {
+                Some(v) => *v = value,
+                None => values.push(value),
+            }
+        }
+        if values.len() < self.arg_count {
+            return Err(syntax_err!(
+                "wrong number of args for macro \"{}\" (expected {} but found {})",
+                self.name,
+                self.arg_count,
+                args.len()
+            ));
+        }
+        let mut m = Vec::with_capacity(self.lines.len());
         for lsv in self.lines.iter() {
-            m.push(
-                lsv.iter()
-                    .map(|lf| lf.hydrate(&args))
-                    .collect::<Result<Vec<String>, Error>>()?
-                    .concat(),
-            );
+            let mut line = String::new();
+            for segment in lsv {
+                segment.hydrate(&values, args.len(), unique_id, &mut line)?;
+            }
+            m.push(line);
         }
         Ok(m)
-        */
-        Ok(Vec::new())
+    }
+}
+
+/// A macro (or REPT block) expansion that produced some of a program's lines.
+/// Expansions within expansions refer to the expansion they're nested in.
+#[derive(Debug)]
+pub struct Expansion {
+    pub name: Option<String>, // the name of the macro (None for a REPT block)
+    pub parent: Option<Rc<Expansion>>,
+}
+impl Expansion {
+    /// The number of expansions (including this one) that are nested
+    pub fn depth(&self) -> usize {
+        1 + self.parent.as_ref().map_or(0, |p| p.depth())
+    }
+    /// Returns true if this is `other` or is nested (at any depth) within `other`
+    pub fn is_within(self: &Rc<Self>, other: &Rc<Expansion>) -> bool {
+        Rc::ptr_eq(self, other) || self.parent.as_ref().is_some_and(|p| p.is_within(other))
+    }
+    /// The innermost macro expansion (skipping REPT blocks)
+    pub fn innermost_macro(self: &Rc<Self>) -> Option<&Rc<Expansion>> {
+        if self.name.is_some() {
+            Some(self)
+        } else {
+            self.parent.as_ref().and_then(|p| p.innermost_macro())
+        }
+    }
+}
+impl fmt::Display for Expansion {
+    /// Describes the chain of expansions, e.g. `in macro "INNER", from macro "OUTER"`
+    /// (long chains are abbreviated)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const SHOWN: usize = 4;
+        let mut e = Some(self);
+        let mut n = 0;
+        while let Some(exp) = e {
+            if n == SHOWN {
+                return write!(f, ", ... ({} more)", exp.depth());
+            }
+            f.write_str(if n == 0 { "in " } else { ", from " })?;
+            match &exp.name {
+                Some(name) => write!(f, "macro \"{}\"", name)?,
+                None => write!(f, "REPT block")?,
+            }
+            e = exp.parent.as_deref();
+            n += 1;
+        }
+        Ok(())
     }
 }
 
 #[derive(Debug)]
 pub struct ProgramLine {
     pub src_line_num: usize,       // corresponding line number in source
+    pub file: Option<Rc<str>>,     // the source file the line came from (if known)
     pub src: String,               // verbatim line from source
     pub label: Option<String>,     // label defined on this line
     pub operation: Option<String>, // operation (mnemonic or directive) used on this line
@@ -119,6 +263,8 @@ pub struct ProgramLine {
     pub obj: Option<Box<dyn ObjectProducer>>,
     pub obj_size: u16, // keep track of object size between passes
     pub addr: u16, // the program address corresponding to this line (whether the line produces an object or not)
+    pub dp: Option<u8>, // the direct page assumed for this line (None if unknown)
+    pub expansion: Option<Rc<Expansion>>, // the macro/REPT expansion that produced this line (if any)
 }
 impl ProgramLine {
     pub fn get_label(&self) -> &str {
@@ -133,6 +279,27 @@ impl ProgramLine {
     pub fn is_inert(&self) -> bool {
         self.label.is_none() && self.operation.is_none()
     }
+    /// Returns the (1-based) columns of the operation and the operand in the source line.
+    /// Columns that can't be found are reported as 0.
+    pub fn columns(&self) -> (usize, usize) {
+        let upper = self.src.to_ascii_uppercase();
+        // finds a field in the source (starting at byte offset `start`) and returns its byte range
+        let find = |start: usize, s: &str| {
+            let word = s.split_whitespace().next()?.to_ascii_uppercase();
+            upper[start..]
+                .find(&word)
+                .map(|i| (i + start, i + start + word.len()))
+        };
+        let column = |pos: usize| self.src[..pos].chars().count() + 1;
+        let label_end = find(0, self.get_label()).map_or(0, |(_, end)| end);
+        match find(label_end, self.get_operation()) {
+            Some((op_pos, op_end)) => (
+                column(op_pos),
+                find(op_end, self.get_operand()).map_or(0, |(pos, _)| column(pos)),
+            ),
+            None => (0, 0),
+        }
+    }
 }
 impl fmt::Display for ProgramLine {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -153,6 +320,7 @@ impl fmt::Display for ProgramLine {
 pub struct Label {
     pub name: String,         // name of label; Note: these are case sensitive!
     pub line: usize,          // line number where the label is defined
+    index: usize,             // index of the line where the label is defined
     addr: u16,                // address (location) of this label
     node: Option<ValueNode>,  // if this label is defined by EQU then it has a ValueNode
     val_cache: Option<u8u16>, // cache of last value received from node.eval()
@@ -171,9 +339,84 @@ impl fmt::Display for Label {
     }
 }
 
+/// Returns true if `name` is a local label (i.e. it starts with '@' or '.')
+pub(crate) fn is_local_label(name: &str) -> bool {
+    name.starts_with(['@', '.'])
+}
+/// Returns true if `name` is a temporary label (i.e. it's just digits)
+pub(crate) fn is_temp_label(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Returns the words in an operand that could refer to labels. The operand ends at the
+/// first whitespace and quoted text (including character constants) is skipped.
+fn operand_words(operand: &str) -> Vec<&str> {
+    let operand = operand.split_whitespace().next().unwrap_or("");
+    let is_word_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '@' | '.');
+    let mut words = Vec::new();
+    let mut chars = operand.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                // skip a quoted string
+                for (_, c) in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                }
+            }
+            '\'' => {
+                // skip a character constant
+                chars.next();
+            }
+            c if is_word_char(c) => {
+                let mut end = i + c.len_utf8();
+                while let Some((j, c)) = chars.next_if(|(_, c)| is_word_char(*c)) {
+                    end = j + c.len_utf8();
+                }
+                let word = &operand[i..end];
+                // numbers aren't labels (except for references to temporary labels, e.g. "1f")
+                let is_temp_ref = word.len() > 1
+                    && word.ends_with(['f', 'F', 'b', 'B'])
+                    && is_temp_label(&word[..word.len() - 1]);
+                if is_temp_ref || !(c.is_ascii_digit() || c == '$') {
+                    words.push(word);
+                }
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+/// A label (or SET symbol) as shown in the symbol table of a listing
+#[derive(Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub value: Option<u8u16>,   // None if the value couldn't be determined
+    pub line: usize,            // the line number where the symbol is defined (0 for SET symbols)
+    pub is_address: bool,       // false if the value was given by EQU or SET
+    pub references: Vec<usize>, // the line numbers of the lines that refer to the symbol
+}
+
+/// All of the labels (and SET symbols) in a program.
+///
+/// Besides ordinary (global) labels there are:
+/// - local labels, which start with '@' or '.' and are scoped to the global label that
+///   precedes them (so "@loop" can be reused after every global label)
+/// - temporary labels, which are just a number (e.g. "1") and are referenced with a 'b' or
+///   'f' suffix meaning the nearest definition backward or forward (e.g. "bne 1b")
+///
+/// Internally a local label is keyed by its scope plus its name and a temporary label by its
+/// name plus the index of its line. Which definition a reference resolves to depends on the
+/// line being assembled (see [ProgramLabels::set_line]).
 #[derive(Debug)]
 pub struct ProgramLabels {
     map: Map<String, Label>,
+    sets: Map<String, Vec<(usize, u8u16)>>, // values of SET symbols along with the (index of) lines that set them
+    scopes: Vec<(usize, String)>, // global labels along with the (index of) lines that define them
+    temps: Map<String, Vec<usize>>, // the (indexes of) lines that define each temporary label
+    line: Cell<usize>, // index of the line being assembled (determines which symbols are visible)
 }
 impl LabelResolver for ProgramLabels {
     fn resolve(&self, label: &str) -> Option<u8u16> {
@@ -182,7 +425,13 @@ impl LabelResolver for ProgramLabels {
 }
 impl ProgramLabels {
     pub fn new() -> ProgramLabels {
-        ProgramLabels { map: Map::new() }
+        ProgramLabels {
+            map: Map::new(),
+            sets: Map::new(),
+            scopes: Vec::new(),
+            temps: Map::new(),
+            line: Cell::new(usize::MAX),
+        }
     }
     pub fn dump(&self) {
         if self.map.is_empty() {
@@ -207,16 +456,28 @@ impl ProgramLabels {
         addr: u16,
         node: Option<ValueNode>,
     ) -> Result<(), Error> {
-        if self.map.contains_key(name) {
-            return Err(Error::new(
-                ErrorKind::Syntax,
-                None,
-                format!("Duplicate label \"{}\"", name).as_str(),
+        let key = self.definition_key(name);
+        if let Some(existing) = self.map.get(key.as_ref()) {
+            return Err(syntax_err!(
+                "Duplicate label \"{}\" (first defined on line {})",
+                name,
+                existing.line
             ));
         }
+        if self.sets.contains_key(key.as_ref()) {
+            return Err(syntax_err!("Duplicate label \"{}\" (defined by SET)", name));
+        }
+        let index = self.line.get();
+        if is_temp_label(name) {
+            self.temps.entry(name.to_string()).or_default().push(index);
+        } else if !is_local_label(name) {
+            // a global label starts a new scope for local labels
+            self.scopes.push((index, name.to_string()));
+        }
         let label = Label {
-            name: name.to_string(),
+            name: key.into_owned(),
             line,
+            index,
             addr,
             node,
             val_cache: None,
@@ -226,7 +487,8 @@ impl ProgramLabels {
     }
 
     pub fn set_address(&mut self, name: &str, new_addr: u16) -> Result<u16, Error> {
-        if let Some(label) = self.map.get_mut(name) {
+        let key = self.definition_key(name);
+        if let Some(label) = self.map.get_mut(key.as_ref()) {
             // Note: NOT checking for overwriting an existing value!
             let old_addr = label.addr;
             label.addr = new_addr;
@@ -239,12 +501,74 @@ impl ProgramLabels {
             ))
         }
     }
+    /// Sets the (index of the) line being assembled. The value of a SET symbol is the one
+    /// most recently set at or before this line.
+    pub fn set_line(&mut self, line: usize) {
+        self.line.set(line);
+    }
+    /// The global label that local labels on the current line belong to ("" if there isn't one)
+    fn scope(&self) -> &str {
+        let line = self.line.get();
+        let n = self.scopes.partition_point(|(index, _)| *index <= line);
+        n.checked_sub(1).map_or("", |n| self.scopes[n].1.as_str())
+    }
+    /// The key for a label defined on the current line
+    fn definition_key<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if is_local_label(name) {
+            Cow::Owned(format!("{}{}", self.scope(), name))
+        } else if is_temp_label(name) {
+            Cow::Owned(format!("{}~{}", name, self.line.get()))
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+    /// The key for a label referenced on the current line
+    /// (None if it's a reference to a temporary label that doesn't exist)
+    fn reference_key<'a>(&self, name: &'a str) -> Option<Cow<'a, str>> {
+        let temp = name
+            .strip_suffix(['b', 'B', 'f', 'F'])
+            .filter(|n| is_temp_label(n));
+        let Some(temp) = temp else {
+            return Some(self.definition_key(name));
+        };
+        let indexes = self.temps.get(temp)?;
+        // a definition on the current line counts as backward
+        let line = self.line.get();
+        let n = indexes.partition_point(|index| *index <= line);
+        let index = if name.ends_with(['b', 'B']) {
+            indexes[..n].last()
+        } else {
+            indexes.get(n)
+        }?;
+        Some(Cow::Owned(format!("{}~{}", temp, index)))
+    }
+    /// Defines (or redefines) a SET symbol on the line with the given index
+    pub fn set_symbol(&mut self, name: &str, line: usize, val: u8u16) -> Result<(), Error> {
+        let name = self.definition_key(name).into_owned();
+        if let Some(existing) = self.map.get(&name) {
+            return Err(syntax_err!(
+                "Can't SET \"{}\" (it's a label defined on line {})",
+                name,
+                existing.line
+            ));
+        }
+        self.sets.entry(name).or_default().push((line, val));
+        Ok(())
+    }
     pub fn get_value(&self, name: &str) -> Option<u8u16> {
+        let key = self.reference_key(name)?;
+        if let Some(values) = self.sets.get(key.as_ref()) {
+            return values
+                .iter()
+                .rev()
+                .find(|(line, _)| *line <= self.line.get())
+                .map(|(_, val)| *val);
+        }
         // if a label has a ValueNode then its value is defined as the .eval of that node
         // otherwise, the value of the label is its address
-        if let Some(label) = self.map.get(name) {
+        if let Some(label) = self.map.get(key.as_ref()) {
             if let Some(node) = label.node.as_ref() {
-                node.eval(self, label.addr, true).ok()
+                self.eval_at(label, node)
             } else {
                 Some(u8u16::u16(label.addr))
             }
@@ -252,8 +576,78 @@ impl ProgramLabels {
             None
         }
     }
+    /// The value of the label (or SET symbol) with the given name as seen from the line
+    /// with the given index
+    pub fn value_at(&self, name: &str, index: usize) -> Option<u8u16> {
+        let line = self.line.replace(index);
+        let val = self.get_value(name);
+        self.line.set(line);
+        val
+    }
+    /// Returns the program's symbols (sorted by name) along with the lines that refer to
+    /// them. Temporary labels are left out.
+    pub fn symbols(&self, lines: &[ProgramLine]) -> Vec<Symbol> {
+        let line = self.line.get();
+        // find the lines that refer to each symbol
+        let mut refs: Map<String, Vec<usize>> = Map::new();
+        for (index, pl) in lines.iter().enumerate() {
+            if pl.operation.is_none() {
+                continue;
+            }
+            self.line.set(index);
+            for word in operand_words(pl.get_operand()) {
+                if let Some(key) = self.reference_key(word) {
+                    let refs = refs.entry(key.into_owned()).or_default();
+                    if refs.last() != Some(&pl.src_line_num) {
+                        refs.push(pl.src_line_num);
+                    }
+                }
+            }
+        }
+        self.line.set(line);
+        let labels = self
+            .map
+            .values()
+            .filter(|label| !label.name.contains('~'))
+            .map(|label| Symbol {
+                name: label.name.clone(),
+                value: match label.node.as_ref() {
+                    Some(node) => self.eval_at(label, node),
+                    None => Some(u8u16::u16(label.addr)),
+                },
+                line: label.line,
+                is_address: label.node.is_none(),
+                references: refs.remove(&label.name).unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        let sets = self.sets.iter().map(|(name, values)| Symbol {
+            name: name.clone(),
+            value: values.last().map(|(_, val)| *val),
+            line: 0,
+            is_address: false,
+            references: refs.remove(name).unwrap_or_default(),
+        });
+        let mut symbols: Vec<Symbol> = labels.into_iter().chain(sets).collect();
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+        symbols
+    }
+    /// Returns true if a label with the given name has been defined (so far)
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.reference_key(name).is_some_and(|key| {
+            self.map.contains_key(key.as_ref()) || self.sets.contains_key(key.as_ref())
+        })
+    }
+    /// Evaluates a label's node as of the line that defines the label
+    /// (so that any local labels it refers to are the ones in its scope)
+    fn eval_at(&self, label: &Label, node: &ValueNode) -> Option<u8u16> {
+        let line = self.line.replace(label.index);
+        let val = node.eval(self, label.addr, true).ok();
+        self.line.set(line);
+        val
+    }
     pub fn set_node(&mut self, name: &str, node: ValueNode) -> Result<(), Error> {
-        if let Some(label) = self.map.get_mut(name) {
+        let key = self.definition_key(name);
+        if let Some(label) = self.map.get_mut(key.as_ref()) {
             // Note: NOT checking for overwriting an existing node!
             label.node = Some(node);
             Ok(())
@@ -295,7 +689,10 @@ impl ProgramLabels {
         }
         for label in self.map.values_mut() {
             if let Some(node) = label.node.as_ref() {
-                if let Ok(val) = node.eval(lr, label.addr, true) {
+                let line = self.line.replace(label.index);
+                let val = node.eval(lr, label.addr, true);
+                self.line.set(line);
+                if let Ok(val) = val {
                     changes += label.val_cache.map_or(1, |v| if v == val { 0 } else { 1 });
                     label.val_cache = Some(val);
                 } else {
@@ -367,8 +764,21 @@ pub struct Program {
     #[cfg(not(target_os = "none"))]
     pub results: Vec<TestCriterion>, // expected results for test criteria
     pub segs: ProgramSegments,   // program segments (defined by ORG directive)
-    pub dp_dirty: bool,          // true if DP register has been written to
+    pub dp: Option<u8>,          // direct page assumed at the current line (None if unknown)
+    pub exec: Option<u16>,       // entry point given by the operand of END (if any)
+}
+/// Formats bytes as hex separated by spaces
+fn hex_bytes(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    for b in bytes {
+        if !s.is_empty() {
+            s.push(' ');
+        }
+        s.push_str(&format!("{:02X}", b));
+    }
+    s
 }
+
 impl LabelResolver for Program {
     fn resolve(&self, label: &str) -> Option<u8u16> {
         self.labels.get_value(label)
@@ -395,38 +805,176 @@ impl Program {
             #[cfg(not(target_os = "none"))]
             results: Vec::new(),
             segs: ProgramSegments::new(),
-            dp_dirty: false,
+            dp: Some(0),
+            exec: None,
         }
     }
-    pub fn write_listing(&self, _f: &mut dyn core::fmt::Write) -> Result<(), Error> {
-        /*
-        for line in &self.lines {
-            if config::ARGS.code_only && line.is_inert() {
-                continue;
+    /// Returns the address of the first instruction in the program (if there is one).
+    /// This serves as the entry point for programs that don't set the reset vector.
+    pub fn first_instruction_addr(&self) -> Option<u16> {
+        self.lines
+            .iter()
+            .find(|line| {
+                line.obj.is_some()
+                    && instructions::name_to_descriptor(line.get_operation()).is_some()
+            })
+            .map(|line| line.addr)
+    }
+    /// Writes a listing of the program: the line number, address, bytes and (minimum)
+    /// cycle count of each line followed by its source. Lines produced by a macro (or REPT)
+    /// expansion are marked with a '+' after the line number. The listing ends with the
+    /// symbol table (see [Program::write_symbol_table]).
+    pub fn write_listing(&self, f: &mut dyn fmt::Write) -> Result<(), Error> {
+        const BYTES_PER_ROW: usize = 4;
+        writeln!(
+            f,
+            "{:6} {:4}  {:12} {:>3}  SOURCE",
+            "LINE", "ADDR", "BYTES", "CYC"
+        )?;
+        for (index, line) in self.lines.iter().enumerate() {
+            let marker = if line.expansion.is_some() { '+' } else { ' ' };
+            let bytes = line
+                .obj
+                .as_ref()
+                .and_then(|op| op.bob_ref())
+                .map_or(Vec::new(), |bob| {
+                    let mut bytes = vec![0u8; bob.size as usize];
+                    let n = bob.to_bytes(&mut bytes) as usize;
+                    bytes.truncate(n);
+                    bytes
+                });
+            // EQU and SET lines show their value instead of an address
+            let addr = match line.get_operation() {
+                "EQU" | "SET" => self
+                    .labels
+                    .value_at(line.get_label(), index)
+                    .map_or("=????".to_string(), |v| format!("={:04X}", v.u16())),
+                _ if line.is_inert() => String::new(),
+                _ => format!("{:04X}", line.addr),
+            };
+            let cycles = line
+                .obj
+                .as_ref()
+                .and_then(|op| op.cycles())
+                .map_or(String::new(), |c| c.to_string());
+            let mut rows = bytes.chunks(BYTES_PER_ROW);
+            let first = rows.next().map_or(String::new(), hex_bytes);
+            writeln!(
+                f,
+                "{:5}{} {:5} {:12} {:>3}  {}",
+                line.src_line_num, marker, addr, first, cycles, line.src
+            )?;
+            // any remaining bytes go on rows of their own
+            let mut addr = line.addr;
+            for row in rows {
+                addr = addr.wrapping_add(BYTES_PER_ROW as u16);
+                writeln!(f, "{:6} {:04X}  {}", "", addr, hex_bytes(row))?;
             }
-            write!(f, "{:4} ", line.src_line_num)?;
-            if let Some(bob) = line.obj.as_ref().and_then(|op| op.bob_ref()) {
-                write!(f, "{:28} ", bob)?;
-            } else if let Some(op) = line.obj.as_ref() {
-                write!(f, "{:28} ", op)?;
-            } else {
-                write!(f, "{:28} ", format!("{:04X}", line.addr),)?;
+        }
+        writeln!(f)?;
+        self.write_symbol_table(f)
+    }
+    /// Writes a table of the program's symbols (sorted by name) that shows the value of
+    /// each symbol, the line that defines it and the lines that refer to it.
+    pub fn write_symbol_table(&self, f: &mut dyn fmt::Write) -> Result<(), Error> {
+        let symbols = self.labels.symbols(&self.lines);
+        if symbols.is_empty() {
+            writeln!(f, "No symbols.")?;
+            return Ok(());
+        }
+        let width = symbols
+            .iter()
+            .map(|s| s.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(6);
+        writeln!(f, "{:width$}  VALUE  DEFINED  REFERENCES", "SYMBOL")?;
+        for symbol in symbols {
+            let value = symbol
+                .value
+                .map_or("????".to_string(), |v| format!("{:04X}", v.u16()));
+            let defined = match symbol.line {
+                0 => "SET".to_string(),
+                line => line.to_string(),
+            };
+            write!(f, "{:width$}  {}  {:>7}", symbol.name, value, defined)?;
+            if !symbol.references.is_empty() {
+                write!(f, " ")?;
             }
-            writeln!(f, " {line}")?;
+            for line in symbol.references {
+                write!(f, " {}", line)?;
+            }
+            writeln!(f)?;
         }
-        */
         Ok(())
     }
-    pub fn write_output_files(&self, _path: &core::ffi::CStr) -> Result<(), Error> {
-        /*
-        let basename = path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .ok_or_else(|| general_err!("bad filename"))?;
-        let mut pb = path.to_path_buf();
-        pb.set_file_name(basename);
-        // ... (rest of the body)
-        */
+    /// Writes a symbol file: one "ADDR,NAME" line (with the address in hex) for each label
+    /// that names an address. This is the format that the debugger loads.
+    pub fn write_symbols(&self, f: &mut dyn fmt::Write) -> Result<(), Error> {
+        let mut symbols: Vec<_> = self
+            .labels
+            .symbols(&self.lines)
+            .into_iter()
+            .filter(|s| s.is_address)
+            .filter_map(|s| s.value.map(|v| (v.u16(), s.name)))
+            .collect();
+        symbols.sort();
+        for (addr, name) in symbols {
+            writeln!(f, "{:04X},{}", addr, name)?;
+        }
         Ok(())
     }
+    /// Returns the program's binary (every byte of every object) along with its entry point
+    pub fn to_image(&self) -> Image {
+        let mut image = Image::new();
+        for bob in self.lines.iter().filter_map(|l| l.obj.as_ref()?.bob_ref()) {
+            let mut bytes = vec![0u8; bob.size as usize];
+            let n = bob.to_bytes(&mut bytes) as usize;
+            // can't fail; objects that extend past $FFFF don't build
+            let _ = image.add(bob.addr, &bytes[..n]);
+        }
+        image.exec = self.entry_point();
+        image
+    }
+    /// The address where execution of the program starts: the operand of END if it has
+    /// one and otherwise the first instruction
+    pub fn entry_point(&self) -> Option<u16> {
+        self.exec.or_else(|| self.first_instruction_addr())
+    }
+    /// Returns the program's binary as Intel hex records (which Core::load_hex can load)
+    pub fn to_hex(&self) -> HexRecordCollection {
+        self.to_image().to_hex()
+    }
+    /// Writes the listing (.lst), symbol file (.sym) and binary (as both .hex and DECB .bin)
+    /// for the program into `out_dir` (which is created if needed), each named after the
+    /// source file (whose path is given).
+    #[cfg(not(target_os = "none"))]
+    pub fn write_output_files(&self, path: &str, out_dir: &std::path::Path) -> Result<(), Error> {
+        let io_err = |path: &std::path::Path, e: std::io::Error| {
+            err!(
+                ErrorKind::IO,
+                None,
+                "failed to write {}: {}",
+                path.display(),
+                e
+            )
+        };
+        std::fs::create_dir_all(out_dir).map_err(|e| io_err(out_dir, e))?;
+        let stem = std::path::Path::new(path)
+            .file_stem()
+            .unwrap_or_else(|| std::ffi::OsStr::new("program"));
+        let write = |ext: &str, contents: Vec<u8>| {
+            let out = out_dir.join(stem).with_extension(ext);
+            std::fs::write(&out, contents).map_err(|e| io_err(&out, e))
+        };
+        let mut listing = String::new();
+        self.write_listing(&mut listing)?;
+        write("lst", listing.into_bytes())?;
+        let mut symbols = String::new();
+        self.write_symbols(&mut symbols)?;
+        write("sym", symbols.into_bytes())?;
+        let hex: String = self.to_hex().iter().map(|r| r.to_string()).collect();
+        write("hex", hex.into_bytes())?;
+        write("bin", crate::decb::write(&self.to_image()))
+    }
 }
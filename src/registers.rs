@@ -2,7 +2,7 @@
 use super::*;
 
 /// Enumeration of the condition code register bits
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum CCBit {
     C = 0,
     V = 1,
@@ -75,6 +75,24 @@ impl CCBit {
     pub fn info(&self) -> &CCInfo {
         &CC_TABLE[*self as usize]
     }
+    /// Looks up a bit by its letter (e.g. "Z") or its name (e.g. "zero" or "halfcarry").
+    /// Case and whitespace are ignored.
+    pub fn from_name(s: &str) -> Option<CCBit> {
+        let normalize = |name: &str| -> String {
+            name.chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| c.to_ascii_lowercase())
+                .collect()
+        };
+        let wanted = normalize(s);
+        CC_TABLE
+            .iter()
+            .find(|info| {
+                wanted == normalize(info.name)
+                    || (wanted.len() == 1 && wanted.starts_with(info.short.to_ascii_lowercase()))
+            })
+            .map(|info| info.bit)
+    }
 }
 
 impl CCBits {
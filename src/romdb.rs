@@ -0,0 +1,447 @@
+//! Identifies ROM images so they can be put where they belong and a machine picked to
+//! match them.
+//!
+//! Known dumps are looked up by CRC32 and confirmed by SHA-1. A ROM that isn't in the
+//! database can often still be recognized from its contents (Extended BASIC starts with
+//! "EX", Disk BASIC with "DK", and so on), which is taken as a sign of a bad dump or a
+//! version that isn't listed.
+use crate::pia::Layout;
+use crate::{format, Core, String, Vec};
+
+/// The parts of a ROM set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomKind {
+    ColorBasic,
+    ExtendedBasic,
+    DiskBasic,
+    /// Color and Extended BASIC 2.0 in one 32K ROM
+    CoCo3Basic,
+    DragonBasic,
+}
+
+impl RomKind {
+    /// Where the ROM is mapped
+    pub fn addr(self) -> u16 {
+        match self {
+            RomKind::ColorBasic => 0xa000,
+            RomKind::DiskBasic => 0xc000,
+            RomKind::ExtendedBasic | RomKind::CoCo3Basic | RomKind::DragonBasic => 0x8000,
+        }
+    }
+    pub fn size(self) -> usize {
+        match self {
+            RomKind::ColorBasic | RomKind::ExtendedBasic | RomKind::DiskBasic => 0x2000,
+            RomKind::CoCo3Basic => 0x8000,
+            RomKind::DragonBasic => 0x4000,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            RomKind::ColorBasic => "Color BASIC",
+            RomKind::ExtendedBasic => "Extended BASIC",
+            RomKind::DiskBasic => "Disk BASIC",
+            RomKind::CoCo3Basic => "CoCo 3 BASIC",
+            RomKind::DragonBasic => "Dragon BASIC",
+        }
+    }
+    fn is_coco(self) -> bool {
+        matches!(
+            self,
+            RomKind::ColorBasic | RomKind::ExtendedBasic | RomKind::DiskBasic
+        )
+    }
+}
+
+/// The machines a ROM set can be for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    CoCo1,
+    CoCo2,
+    CoCo3,
+    Dragon32,
+    Dragon64,
+}
+
+impl Profile {
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::CoCo1 => "CoCo 1",
+            Profile::CoCo2 => "CoCo 2",
+            Profile::CoCo3 => "CoCo 3",
+            Profile::Dragon32 => "Dragon 32",
+            Profile::Dragon64 => "Dragon 64",
+        }
+    }
+    /// Parses a name such as "coco2" or "Dragon 32"
+    pub fn parse(text: &str) -> Option<Self> {
+        let name: String = text
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '_'))
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        match name.as_str() {
+            "coco" | "coco1" => Some(Profile::CoCo1),
+            "coco2" => Some(Profile::CoCo2),
+            "coco3" => Some(Profile::CoCo3),
+            "dragon" | "dragon32" => Some(Profile::Dragon32),
+            "dragon64" => Some(Profile::Dragon64),
+            _ => None,
+        }
+    }
+    pub fn is_dragon(self) -> bool {
+        matches!(self, Profile::Dragon32 | Profile::Dragon64)
+    }
+    pub fn layout(self) -> Layout {
+        if self.is_dragon() {
+            Layout::Dragon
+        } else {
+            Layout::CoCo
+        }
+    }
+    /// Sets up the hardware that differs between machines
+    pub fn apply(self, core: &mut Core) {
+        core.pia0.lock().set_layout(self.layout());
+    }
+    /// Whether software for `other` runs on this machine as it is emulated
+    fn runs(self, other: Profile) -> bool {
+        let coco = |p| matches!(p, Profile::CoCo1 | Profile::CoCo2);
+        self == other || (coco(self) && coco(other))
+    }
+}
+
+/// A dump in the database
+#[derive(Debug, PartialEq, Eq)]
+pub struct KnownRom {
+    pub name: &'static str,
+    pub kind: RomKind,
+    pub crc32: u32,
+    /// the SHA-1 in lower case hex
+    pub sha1: &'static str,
+    /// the machine the ROM came in, if it decides one
+    pub profile: Option<Profile>,
+}
+
+const fn rom(
+    name: &'static str,
+    kind: RomKind,
+    crc32: u32,
+    sha1: &'static str,
+    profile: Option<Profile>,
+) -> KnownRom {
+    KnownRom {
+        name,
+        kind,
+        crc32,
+        sha1,
+        profile,
+    }
+}
+
+/// The commonly circulated dumps of the ROMs
+pub static KNOWN_ROMS: &[KnownRom] = &[
+    rom(
+        "Color BASIC 1.0",
+        RomKind::ColorBasic,
+        0x00b50aaa,
+        "1f08455cd48ce6a06132aea15c4778f264e19539",
+        Some(Profile::CoCo1),
+    ),
+    rom(
+        "Color BASIC 1.1",
+        RomKind::ColorBasic,
+        0x6270955a,
+        "cecb7c24ff1e0ab5836e4a7a8eb1b8e01f1fded3",
+        Some(Profile::CoCo1),
+    ),
+    rom(
+        "Color BASIC 1.2",
+        RomKind::ColorBasic,
+        0x54368805,
+        "0f14dc46c647510eb0b7bd3f53e33da07907d04f",
+        Some(Profile::CoCo2),
+    ),
+    rom(
+        "Color BASIC 1.3",
+        RomKind::ColorBasic,
+        0xd8f4d15e,
+        "28b92bebe35fa4f026a084416d6ea3b1552b63d3",
+        Some(Profile::CoCo2),
+    ),
+    rom(
+        "Extended BASIC 1.0",
+        RomKind::ExtendedBasic,
+        0x6111a086,
+        "8aa58f2eb3e8bcfd5470e3e35e2b359e9a72848e",
+        None,
+    ),
+    rom(
+        "Extended BASIC 1.1",
+        RomKind::ExtendedBasic,
+        0xa82a6254,
+        "ad927fb4f30746d820cb8b860ebb585e7f095dea",
+        None,
+    ),
+    rom(
+        "Disk BASIC 1.0",
+        RomKind::DiskBasic,
+        0xb4f9968e,
+        "04115be3f97952b9d9310b52f806d04f80b40d03",
+        None,
+    ),
+    rom(
+        "Disk BASIC 1.1",
+        RomKind::DiskBasic,
+        0x0b9c5415,
+        "10bdc5aa2d7d7f205f67b47b19003a4bd89defd1",
+        None,
+    ),
+    rom(
+        "CoCo 3 BASIC",
+        RomKind::CoCo3Basic,
+        0xb4c88d6c,
+        "e0d82953fb6fd03768604933df1ce8bc51fc427d",
+        Some(Profile::CoCo3),
+    ),
+    rom(
+        "Dragon 32 BASIC",
+        RomKind::DragonBasic,
+        0xe3879310,
+        "f2dab125673e653995a83bf6b793e3390ec7f65a",
+        Some(Profile::Dragon32),
+    ),
+    rom(
+        "Dragon 64 BASIC",
+        RomKind::DragonBasic,
+        0x60a4634c,
+        "f119506eaa3b4b70b9aa0dd83761d8cb1b5e5b32",
+        Some(Profile::Dragon64),
+    ),
+];
+
+/// The CRC-32 used by zip and most ROM databases
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = Vec::from(data);
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut out = [0u8; 20];
+    for (i, v) in h.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes());
+    }
+    out
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Recognizes a ROM from its contents: the signatures BASIC looks for when it starts
+/// and the sign-on messages
+fn recognize(data: &[u8]) -> Option<RomKind> {
+    let contains = |text: &[u8]| data.windows(text.len()).any(|w| w == text);
+    match data.len() {
+        0x8000 if data.starts_with(b"EX") => Some(RomKind::CoCo3Basic),
+        0x4000 if contains(b"DRAGON DATA") => Some(RomKind::DragonBasic),
+        len if len > 0x2000 => None,
+        _ if data.starts_with(b"EX") => Some(RomKind::ExtendedBasic),
+        _ if data.starts_with(b"DK") => Some(RomKind::DiskBasic),
+        _ if contains(b"COLOR BASIC") => Some(RomKind::ColorBasic),
+        _ => None,
+    }
+}
+
+/// What a ROM image turned out to be
+#[derive(Debug, Clone)]
+pub struct RomId {
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+    /// the database entry the image matched
+    pub known: Option<&'static KnownRom>,
+    /// what the ROM is, from the database or failing that its contents
+    pub kind: Option<RomKind>,
+    /// the length of the ROM, which is less than the image's if it's an overdump (the
+    /// ROM repeated to fill a bigger chip)
+    pub len: usize,
+    /// signs of a bad dump
+    pub warnings: Vec<String>,
+}
+
+impl RomId {
+    /// What to call the ROM in messages
+    pub fn name(&self) -> &'static str {
+        match (self.known, self.kind) {
+            (Some(known), _) => known.name,
+            (None, Some(kind)) => kind.name(),
+            (None, None) => "an unknown ROM",
+        }
+    }
+}
+
+/// Identifies `data` using the dumps in `roms`
+pub(crate) fn identify_in(data: &[u8], roms: &'static [KnownRom]) -> RomId {
+    let mut warnings = Vec::new();
+    let mut len = data.len();
+    while len > 0x2000 && len.is_multiple_of(2) && data[..len / 2] == data[len / 2..len] {
+        len /= 2;
+    }
+    if len < data.len() {
+        warnings.push(format!(
+            "is {} copies of a {} byte ROM",
+            data.len() / len,
+            len
+        ));
+    }
+    let data = &data[..len];
+    let crc32 = crc32(data);
+    let sha1 = sha1(data);
+    let mut known = None;
+    let kind = match roms.iter().find(|rom| rom.crc32 == crc32) {
+        Some(rom) if rom.sha1 == to_hex(&sha1) => {
+            known = Some(rom);
+            Some(rom.kind)
+        }
+        Some(rom) => {
+            warnings.push(format!(
+                "has the CRC32 of {} but not its SHA-1, so it may be a bad dump",
+                rom.name
+            ));
+            Some(rom.kind)
+        }
+        None => {
+            let kind = recognize(data);
+            if let Some(kind) = kind {
+                warnings.push(format!(
+                    "looks like {} but isn't a known dump (CRC32 {:08x}), so it may be a bad dump",
+                    kind.name(),
+                    crc32
+                ));
+            }
+            kind
+        }
+    };
+    if let Some(kind) = kind.filter(|k| k.size() != len) {
+        warnings.push(format!(
+            "is {} bytes, but {} is {}",
+            len,
+            kind.name(),
+            kind.size()
+        ));
+    }
+    RomId {
+        crc32,
+        sha1,
+        known,
+        kind,
+        len,
+        warnings,
+    }
+}
+
+/// Identifies `data` as one of the [KNOWN_ROMS] or by its contents
+pub fn identify(data: &[u8]) -> RomId {
+    identify_in(data, KNOWN_ROMS)
+}
+
+/// Picks the machine for a set of ROMs, or checks them against `machine` if it's
+/// given, and describes anything missing or out of place
+pub fn check_set(roms: &[RomId], machine: Option<Profile>) -> (Profile, Vec<String>) {
+    let mut warnings = Vec::new();
+    let has = |kind| roms.iter().any(|r| r.kind == Some(kind));
+    let detected = roms
+        .iter()
+        .find_map(|r| r.known.and_then(|k| k.profile))
+        .or_else(|| has(RomKind::CoCo3Basic).then_some(Profile::CoCo3))
+        .or_else(|| has(RomKind::DragonBasic).then_some(Profile::Dragon32));
+    let profile = match (machine, detected) {
+        (Some(machine), Some(detected)) => {
+            if !machine.runs(detected) {
+                warnings.push(format!(
+                    "the machine is a {}, but the ROMs are for a {}",
+                    machine.name(),
+                    detected.name()
+                ));
+            }
+            machine
+        }
+        (Some(profile), None) | (None, Some(profile)) => profile,
+        (None, None) => Profile::CoCo2,
+    };
+    for (i, rom) in roms.iter().enumerate() {
+        if rom.kind.is_some() && roms[..i].iter().any(|r| r.kind == rom.kind) {
+            warnings.push(format!(
+                "there are two {} ROMs, and the second replaces the first",
+                rom.kind.map_or("", |k| k.name())
+            ));
+        }
+    }
+    let mut missing = |kind: RomKind, why: &str| {
+        if !has(kind) {
+            warnings.push(format!("there's no {} ROM, {}", kind.name(), why));
+        }
+    };
+    match profile {
+        Profile::CoCo1 | Profile::CoCo2 => {
+            missing(RomKind::ColorBasic, "which the CoCo needs to start");
+            if has(RomKind::DiskBasic) {
+                missing(RomKind::ExtendedBasic, "which Disk BASIC needs");
+            }
+        }
+        Profile::CoCo3 => {
+            missing(RomKind::CoCo3Basic, "which the CoCo 3 needs to start");
+            warnings.push("the CoCo 3's GIME isn't emulated, so its ROM won't start".into());
+        }
+        Profile::Dragon32 | Profile::Dragon64 => {
+            missing(RomKind::DragonBasic, "which the Dragon needs to start");
+            if let Some(rom) = roms.iter().find(|r| r.kind.is_some_and(RomKind::is_coco)) {
+                warnings.push(format!("{} is for the CoCo, not the Dragon", rom.name()));
+            }
+        }
+    }
+    (profile, warnings)
+}
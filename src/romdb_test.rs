@@ -0,0 +1,169 @@
+use crate::romdb::*;
+use crate::*;
+use alloc::vec;
+
+#[test]
+fn test_hashes() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    assert_eq!(
+        to_hex(&sha1(b"abc")),
+        "a9993e364706816aba3e25717850c26c9cd0d89d"
+    );
+    assert_eq!(
+        to_hex(&sha1(b"")),
+        "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+    );
+    // more than one block
+    assert_eq!(
+        to_hex(&sha1(
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+        )),
+        "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+    );
+}
+
+#[test]
+fn test_identify() {
+    // the database is keyed by CRC32 and confirmed by SHA-1
+    let rom = vec![0x5a; 0x2000];
+    let crc = crc32(&rom);
+    let db: &'static [KnownRom] = Box::leak(Box::new([KnownRom {
+        name: "Test BASIC",
+        kind: RomKind::ExtendedBasic,
+        crc32: crc,
+        sha1: Box::leak(to_hex(&sha1(&rom)).into_boxed_str()),
+        profile: None,
+    }]));
+    let id = identify_in(&rom, db);
+    assert_eq!(id.known, Some(&db[0]));
+    assert_eq!(id.kind, Some(RomKind::ExtendedBasic));
+    assert_eq!(id.name(), "Test BASIC");
+    assert!(id.warnings.is_empty());
+
+    // an overdump is the ROM repeated
+    let mut overdump = rom.clone();
+    overdump.extend_from_slice(&rom);
+    let id = identify_in(&overdump, db);
+    assert_eq!((id.known, id.len), (Some(&db[0]), 0x2000));
+    assert_eq!(id.warnings, ["is 2 copies of a 8192 byte ROM"]);
+
+    // a CRC32 collision isn't the ROM
+    let other: &'static [KnownRom] = Box::leak(Box::new([KnownRom {
+        sha1: "0000000000000000000000000000000000000000",
+        ..db[0]
+    }]));
+    let id = identify_in(&rom, other);
+    assert_eq!((id.known, id.kind), (None, Some(RomKind::ExtendedBasic)));
+    assert!(id.warnings[0].contains("may be a bad dump"));
+
+    // otherwise ROMs are recognized by their contents
+    let mut rom = vec![0u8; 0x2000];
+    assert_eq!(identify(&rom).kind, None);
+    assert!(identify(&rom).warnings.is_empty());
+    rom[..2].copy_from_slice(b"DK");
+    assert_eq!(identify(&rom).kind, Some(RomKind::DiskBasic));
+    rom[..2].copy_from_slice(b"EX");
+    assert_eq!(identify(&rom).kind, Some(RomKind::ExtendedBasic));
+    let id = identify(&rom[..0x1800]);
+    assert_eq!(id.kind, Some(RomKind::ExtendedBasic));
+    assert_eq!(id.warnings[1], "is 6144 bytes, but Extended BASIC is 8192");
+    rom.resize(0x8000, 1);
+    assert_eq!(identify(&rom).kind, Some(RomKind::CoCo3Basic));
+    let mut rom = vec![0u8; 0x4000];
+    rom[0x3000..0x3018].copy_from_slice(b"(C) 1982 DRAGON DATA LTD");
+    assert_eq!(identify(&rom).kind, Some(RomKind::DragonBasic));
+    rom[0x3000..0x300b].copy_from_slice(b"COLOR BASIC");
+    assert_eq!(identify(&rom[..0x2000]).kind, None);
+    assert_eq!(identify(&rom[0x2000..]).kind, Some(RomKind::ColorBasic));
+}
+
+#[test]
+fn test_known_roms() {
+    for (i, rom) in KNOWN_ROMS.iter().enumerate() {
+        assert_eq!(rom.sha1.len(), 40, "{}", rom.name);
+        assert!(rom
+            .sha1
+            .bytes()
+            .all(|c| c.is_ascii_digit() || (b'a'..=b'f').contains(&c)));
+        assert!(KNOWN_ROMS[..i].iter().all(|r| r.crc32 != rom.crc32));
+    }
+}
+
+#[test]
+fn test_check_set() {
+    let kinds = |kinds: &[RomKind]| -> Vec<RomId> {
+        kinds
+            .iter()
+            .map(|&kind| {
+                let mut id = identify(&[]);
+                id.kind = Some(kind);
+                id
+            })
+            .collect()
+    };
+    let known = |name: &str| {
+        let mut id = identify(&[]);
+        id.known = KNOWN_ROMS.iter().find(|r| r.name == name);
+        id.kind = id.known.map(|r| r.kind);
+        id
+    };
+
+    let (profile, warnings) = check_set(
+        &kinds(&[
+            RomKind::ColorBasic,
+            RomKind::ExtendedBasic,
+            RomKind::DiskBasic,
+        ]),
+        None,
+    );
+    assert_eq!(profile, Profile::CoCo2);
+    assert!(warnings.is_empty());
+
+    // the Color BASIC version decides the CoCo
+    let (profile, warnings) = check_set(&[known("Color BASIC 1.1")], None);
+    assert_eq!(profile, Profile::CoCo1);
+    assert!(warnings.is_empty());
+    let (profile, warnings) = check_set(&[known("Color BASIC 1.3")], Some(Profile::CoCo1));
+    assert_eq!(profile, Profile::CoCo1);
+    assert!(warnings.is_empty());
+    let (profile, warnings) = check_set(&[known("Dragon 64 BASIC")], None);
+    assert_eq!(profile, Profile::Dragon64);
+    assert!(warnings.is_empty());
+
+    // missing pieces
+    let (_, warnings) = check_set(&kinds(&[RomKind::ExtendedBasic]), None);
+    assert_eq!(
+        warnings,
+        ["there's no Color BASIC ROM, which the CoCo needs to start"]
+    );
+    let (_, warnings) = check_set(&kinds(&[RomKind::ColorBasic, RomKind::DiskBasic]), None);
+    assert_eq!(
+        warnings,
+        ["there's no Extended BASIC ROM, which Disk BASIC needs"]
+    );
+    let (_, warnings) = check_set(&kinds(&[RomKind::ColorBasic, RomKind::ColorBasic]), None);
+    assert_eq!(
+        warnings,
+        ["there are two Color BASIC ROMs, and the second replaces the first"]
+    );
+
+    // the wrong machine
+    let (profile, warnings) = check_set(&[known("Color BASIC 1.2")], Some(Profile::Dragon32));
+    assert_eq!(profile, Profile::Dragon32);
+    assert_eq!(
+        warnings,
+        [
+            "the machine is a Dragon 32, but the ROMs are for a CoCo 2",
+            "there's no Dragon BASIC ROM, which the Dragon needs to start",
+            "Color BASIC 1.2 is for the CoCo, not the Dragon",
+        ]
+    );
+    let (profile, warnings) = check_set(&kinds(&[RomKind::CoCo3Basic]), None);
+    assert_eq!(profile, Profile::CoCo3);
+    assert_eq!(warnings.len(), 1);
+
+    assert_eq!(Profile::parse("Dragon-64"), Some(Profile::Dragon64));
+    assert_eq!(Profile::parse("CoCo 2"), Some(Profile::CoCo2));
+    assert_eq!(Profile::parse("coco4"), None);
+}
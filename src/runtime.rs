@@ -9,6 +9,7 @@ use crate::{
 use super::*;
 use memory::AccessType;
 
+pub const CPU_HZ: u64 = 894_886; // the CoCo's CPU clock
 pub const HSYNC_PERIOD_CYCLES: u64 = 64; // Approx for 1MHz
 pub const VSYNC_PERIOD_CYCLES: u64 = 16667; // Approx for 1MHz
 
@@ -28,11 +29,19 @@ impl Core {
         self.faulted = false;
         Ok(())
     }
+    /// Resets as if the machine had been switched off and on: RAM below the ROMs is
+    /// cleared so BASIC starts from scratch, and an inserted cartridge starts again.
+    pub fn cold_boot(&mut self, cart_inserted: bool) -> Result<(), Error> {
+        let end = self.raw_ram.len().min(0x8000);
+        self.raw_ram[..end].fill(0);
+        self.cart_pending = cart_inserted;
+        self.in_cwai = false;
+        self.in_sync = false;
+        self.reset()
+    }
     pub fn force_reset_vector(&mut self, addr: u16) -> Result<(), Error> {
         self._write_u8u16(memory::AccessType::System, 0xfffe, u8u16::u16(addr))
     }
-    /// Displays current perf information to stdout
-
     /// Starts executing instructions at the current program counter.  
     /// Does not set or read any registers before attempting to execute.  
     /// Will attempt to execute until an EXIT psuedo-instruction or an
@@ -42,6 +51,10 @@ impl Core {
         loop {
             let temp_pc = self.reg.pc;
             if let Err(e) = self.exec_one() {
+                // the trace leading up to an exit or a crash is worth keeping
+                if let Some(tracer) = self.tracer.as_ref() {
+                    tracer.borrow_mut().flush()?;
+                }
                 if e.kind == ErrorKind::Exit {
                     // this is a normal exit
                     break;
@@ -65,15 +78,40 @@ impl Core {
             self.debug_cli()?;
         }
         let temp_pc = self.reg.pc;
+        if !self.spans.is_empty() {
+            let (cycles, instructions) = (self.clock_cycles, self.instruction_count);
+            self.spans
+                .iter_mut()
+                .for_each(|span| span.check(temp_pc, cycles, instructions));
+        }
         if !self.in_cwai && !self.in_sync {
+            let traced = self
+                .tracer
+                .as_ref()
+                .is_some_and(|t| t.borrow_mut().begin(temp_pc));
+            let (before, cycle) = (self.reg, self.clock_cycles);
             let outcome = self.exec_next(self.list_mode.is_none())?;
+            if let Some(profiler) = self.profiler.as_mut() {
+                let cycles = self.clock_cycles - cycle;
+                profiler.instruction(temp_pc, cycles, outcome.inst.flavor.desc.name, &self.reg);
+            }
+            if traced {
+                // the instance only holds the opcode, so get the operand from memory too
+                let mut bytes = [0u8; 8];
+                for (i, b) in bytes.iter_mut().take(outcome.inst.size as usize).enumerate() {
+                    *b = self._read_u8(AccessType::Program, temp_pc.wrapping_add(i as u16), None)?;
+                }
+                if let Some(tracer) = self.tracer.as_ref() {
+                    tracer.borrow_mut().finish(cycle, before, self.reg, &outcome, bytes)?;
+                }
+            }
 
             // check for meta instructions (interrupts, SYNC, CWAI, EXIT)
             if let Some(meta) = outcome.meta.as_ref() {
                 let it = meta.to_interrupt_type();
                 match meta {
                     instructions::Meta::EXIT => {
-                        info!("EXIT instruction at PC={:0x}", self.reg.pc);
+                        info!(target: Cpu, "EXIT instruction at PC={:0x}", self.reg.pc);
                         return Err(Error::new(
                             ErrorKind::Exit,
                             None,
@@ -83,11 +121,11 @@ impl Core {
                     instructions::Meta::CWAI => {
                         self.stack_for_interrupt(true)?;
                         self.in_cwai = true;
-                        verbose_println!("CWAI at PC={:0x}: waiting for interrupt...", self.reg.pc);
+                        verbose_println!(target: Cpu, "CWAI at PC={:0x}: waiting for interrupt...", self.reg.pc);
                     }
                     instructions::Meta::SYNC => {
                         self.in_sync = true;
-                        verbose_println!("SYNC at PC={:0x}: waiting for interrupt...", self.reg.pc);
+                        verbose_println!(target: Cpu, "SYNC at PC={:0x}: waiting for interrupt...", self.reg.pc);
                     }
                     _ if it.is_some() => {
                         self.start_interrupt(it.unwrap())?;
@@ -121,6 +159,16 @@ impl Core {
                 let mut pia0 = self.pia0.lock();
                 irq = pia0.hsync_irq();
             }
+            if let Some(bb) = self.bitbanger.as_ref() {
+                bb.borrow_mut().update(self.clock_cycles);
+            }
+            // the RS-232 Pak's IRQ output drives the cartridge port's CART line, like a
+            // cartridge does, so it reaches the CPU as a FIRQ through PIA1
+            if let Some(acia) = self.acia.as_ref() {
+                if acia.borrow_mut().update(self.clock_cycles) {
+                    firq = self.pia1.lock().cart_firq() || firq;
+                }
+            }
             // if it's vsync time, then also check for vsync irq
             if self.clock_cycles - self.vsync_prev >= VSYNC_PERIOD_CYCLES {
                 self.vsync_prev = self.clock_cycles;
@@ -227,6 +275,9 @@ impl Core {
         self.reg.set_register(registers::Name::PC, u8u16::u16(addr));
         // we're no longer waiting for an interrupt
         self.in_cwai = false;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.interrupt(&self.reg);
+        }
         Ok(())
     }
     /// Attempt to execute the next instruction at PC.  
@@ -295,7 +346,7 @@ impl Core {
         &self,
         pc: u16,
         rhs: u16,
-        _inst: &instructions::Instance,
+        inst: &instructions::Instance,
     ) -> Result<u16, Error> {
         // avoiding ok_or and ok_or_else to increase performance
         // ok_or would invoke the runtime_err! macro every time (regardless of result)
@@ -421,14 +472,11 @@ impl Core {
                     0b10000000 => {
                         // ,R+
                         if indirect {
-                            return Err(Error::new(
+                            return Err(err!(
                                 ErrorKind::Syntax,
                                 Some(self.reg),
-                                format!(
-                                    "Illegal indirect indexed addressing mode [,R+] at {:04X}",
-                                    self.reg.pc
-                                )
-                                .as_str(),
+                                "Illegal indirect indexed addressing mode [,R+] at {:04X}",
+                                self.reg.pc
                             ));
                         }
                         inst.ea = ir_val;
@@ -450,14 +498,11 @@ impl Core {
                     0b10000010 => {
                         // ,-R
                         if indirect {
-                            return Err(Error::new(
+                            return Err(err!(
                                 ErrorKind::Syntax,
                                 Some(self.reg),
-                                format!(
-                                    "Illegal indirect indexed addressing mode [,-R] at {:04X}",
-                                    self.reg.pc
-                                )
-                                .as_str(),
+                                "Illegal indirect indexed addressing mode [,-R] at {:04X}",
+                                self.reg.pc
                             ));
                         }
                         let (r, _) = (ir_val).overflowing_sub(1);
@@ -571,14 +616,11 @@ impl Core {
                         inst.size += 2;
                     }
                     _ => {
-                        return Err(Error::new(
+                        return Err(err!(
                             ErrorKind::Syntax,
                             Some(self.reg),
-                            format!(
-                                "Invalid indexed addressing post-byte {:02X} in instruction at {:04X}",
-                                pb, self.reg.pc
-                            )
-                            .as_str(),
+                            "Invalid indexed addressing post-byte {:02X} in instruction at {:04X}",
+                            pb, self.reg.pc
                         ));
                     }
                 }
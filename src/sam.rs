@@ -29,6 +29,10 @@ impl Sam {
     pub fn get_raw_config(&self) -> u16 {
         self.config
     }
+    /// Restores the config, e.g. from a saved state
+    pub fn set_raw_config(&mut self, config: u16) {
+        self.config = config;
+    }
     pub fn get_vdg_bits(&self) -> u8 {
         VDG_MODE.extract(self.config) as u8
     }
@@ -54,7 +58,7 @@ impl Sam {
         } else {
             self.config |= val;
         }
-        verbose_println!("SAM config={:016b}", self.config);
+        verbose_println!(target: Sam, "SAM config={:016b}", self.config);
     }
 }
 
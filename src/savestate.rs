@@ -0,0 +1,122 @@
+//! Saving and restoring the state of the machine: the CPU, SAM, PIAs and RAM.
+//!
+//! Serial devices (the ACIA, bit banger and Becker port) aren't saved; whatever they
+//! were in the middle of is lost when a state is loaded.
+use crate::registers::CCBits;
+use crate::{Core, Error, ErrorKind, Vec};
+
+const MAGIC: &[u8; 4] = b"CCST";
+const VERSION: u8 = 1;
+
+// bits in the flags byte
+const CART_PENDING: u8 = 1;
+const IN_CWAI: u8 = 2;
+const IN_SYNC: u8 = 4;
+
+fn state_err(msg: &str) -> Error {
+    err!(ErrorKind::General, None, "saved state: {}", msg)
+}
+
+/// Takes fields from the front of a saved state
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(state_err("too short"));
+        }
+        let (field, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(field)
+    }
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+}
+
+impl Core {
+    /// The state of the machine as bytes that [Core::load_state] can restore
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.raw_ram.len() + 128);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        let r = &self.reg;
+        out.extend_from_slice(&[r.a, r.b, r.dp, r.cc.reg]);
+        for word in [r.x, r.y, r.u, r.s, r.pc] {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+        let mut flags = 0;
+        if self.cart_pending {
+            flags |= CART_PENDING;
+        }
+        if self.in_cwai {
+            flags |= IN_CWAI;
+        }
+        if self.in_sync {
+            flags |= IN_SYNC;
+        }
+        out.push(flags);
+        for count in [self.clock_cycles, self.hsync_prev, self.vsync_prev] {
+            out.extend_from_slice(&count.to_be_bytes());
+        }
+        out.extend_from_slice(&self.sam.lock().get_raw_config().to_be_bytes());
+        out.extend_from_slice(&self.pia0.lock().state());
+        out.extend_from_slice(&self.pia1.lock().state());
+        out.extend_from_slice(&(self.raw_ram.len() as u32).to_be_bytes());
+        out.extend_from_slice(self.raw_ram);
+        out
+    }
+    /// Puts the machine back in a state saved by [Core::save_state]
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut rd = Reader { data };
+        if rd.take(4)? != MAGIC {
+            return Err(state_err("not a saved state"));
+        }
+        if rd.u8()? != VERSION {
+            return Err(state_err("saved by a different version"));
+        }
+        let [a, b, dp, cc] = rd.array()?;
+        let (x, y, u, s, pc) = (rd.u16()?, rd.u16()?, rd.u16()?, rd.u16()?, rd.u16()?);
+        let flags = rd.u8()?;
+        let (clock_cycles, hsync_prev, vsync_prev) = (rd.u64()?, rd.u64()?, rd.u64()?);
+        let sam = rd.u16()?;
+        let pia0 = rd.array()?;
+        let pia1 = rd.array()?;
+        let ram_len = u32::from_be_bytes(rd.array()?) as usize;
+        if ram_len != self.raw_ram.len() {
+            return Err(state_err("saved with a different amount of RAM"));
+        }
+        let ram = rd.take(ram_len)?;
+        // nothing changes unless the whole state could be read
+        self.reg.a = a;
+        self.reg.b = b;
+        self.reg.d = ((a as u16) << 8) | b as u16;
+        self.reg.dp = dp;
+        self.reg.cc = CCBits { reg: cc };
+        (self.reg.x, self.reg.y, self.reg.u, self.reg.s, self.reg.pc) = (x, y, u, s, pc);
+        self.cart_pending = flags & CART_PENDING != 0;
+        self.in_cwai = flags & IN_CWAI != 0;
+        self.in_sync = flags & IN_SYNC != 0;
+        self.clock_cycles = clock_cycles;
+        self.hsync_prev = hsync_prev;
+        self.vsync_prev = vsync_prev;
+        self.sam.lock().set_raw_config(sam);
+        self.pia1.lock().set_state(&pia1);
+        self.pia0.lock().set_state(&pia0);
+        self.raw_ram.copy_from_slice(ram);
+        self._vdg.lock().set_dirty();
+        self.faulted = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+//! Host-side ends of the emulated serial ports.
+//!
+//! A [SerialBackend] is where the bytes sent by the guest go and where the bytes it
+//! receives come from. Backends never block; the device that owns one (e.g. the
+//! [Acia](crate::acia::Acia)) polls it at the rate the guest's baud rate allows.
+pub mod printer;
+#[cfg(unix)]
+pub mod pty;
+#[cfg(not(target_os = "none"))]
+pub mod stdio;
+#[cfg(not(target_os = "none"))]
+pub mod tcp;
+#[cfg(target_os = "none")]
+pub mod uart;
+
+use crate::{Arc, Rc, Vec, VecDeque};
+use core::cell::RefCell;
+use spin::Mutex;
+
+/// Trait for serial connections to implement.
+pub trait SerialBackend {
+    /// Returns the next received byte, if one is waiting.
+    fn read_byte(&mut self) -> Option<u8>;
+    /// Sends one byte.
+    fn write_byte(&mut self, byte: u8);
+    /// True if something is connected at the far end (reported to the guest as
+    /// carrier detect and data set ready).
+    fn connected(&mut self) -> bool {
+        true
+    }
+}
+
+/// A backend shared with its owner, who can still get at it (e.g. to mount disks on a
+/// DriveWire server) once it's attached to a device.
+impl<T: SerialBackend> SerialBackend for Rc<RefCell<T>> {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.borrow_mut().read_byte()
+    }
+    fn write_byte(&mut self, byte: u8) {
+        self.borrow_mut().write_byte(byte)
+    }
+    fn connected(&mut self) -> bool {
+        self.borrow_mut().connected()
+    }
+}
+
+/// An in-memory connection: bytes pushed with [Pipe::send] are received by the
+/// guest and bytes sent by the guest are collected for [Pipe::take_output].
+/// Clones share the same buffers so one can be handed to a device while the
+/// other is kept to talk to it.
+#[derive(Debug, Clone, Default)]
+pub struct Pipe {
+    input: Arc<Mutex<VecDeque<u8>>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Pipe {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Queues bytes for the guest to receive.
+    pub fn send(&self, bytes: &[u8]) {
+        self.input.lock().extend(bytes);
+    }
+    /// Returns (and clears) everything the guest has sent so far.
+    pub fn take_output(&self) -> Vec<u8> {
+        core::mem::take(&mut *self.output.lock())
+    }
+    /// The number of bytes still waiting to be received by the guest.
+    pub fn pending(&self) -> usize {
+        self.input.lock().len()
+    }
+}
+
+impl SerialBackend for Pipe {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.input.lock().pop_front()
+    }
+    fn write_byte(&mut self, byte: u8) {
+        self.output.lock().push(byte);
+    }
+}
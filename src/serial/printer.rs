@@ -0,0 +1,93 @@
+//! Captures what the CoCo prints.
+use super::SerialBackend;
+use crate::Box;
+
+// control codes understood by the TRS-80 printers the CoCo was sold with
+const LF: u8 = 0x0a;
+const FF: u8 = 0x0c;
+const CR: u8 = 0x0d;
+const TAB: u8 = 0x09;
+const ESC: u8 = 0x1b;
+
+/// A printer that passes everything it's sent to another backend (e.g. a file).
+///
+/// By default the bytes are captured exactly as sent. With control codes translated,
+/// the capture is plain text:
+/// - BASIC ends lines with a CR; CR, LF and CR LF all become a single newline
+/// - tabs and form feeds are kept
+/// - ESC and the byte after it (printer commands such as pitch changes) are dropped
+/// - other control codes and non-ASCII bytes (e.g. semigraphics) are dropped
+pub struct Printer {
+    output: Box<dyn SerialBackend>,
+    translate: bool,
+    after_cr: bool,
+    after_esc: bool,
+}
+
+impl Printer {
+    pub fn new(output: Box<dyn SerialBackend>, translate: bool) -> Self {
+        Printer {
+            output,
+            translate,
+            after_cr: false,
+            after_esc: false,
+        }
+    }
+    /// Creates a printer that writes to the file at `path` (replacing an existing file).
+    #[cfg(not(target_os = "none"))]
+    pub fn to_file(path: &str, translate: bool) -> Result<Self, crate::Error> {
+        let file = std::fs::File::create(path).map_err(|e| {
+            err!(
+                crate::ErrorKind::IO,
+                None,
+                "printer capture {}: {}",
+                path,
+                e
+            )
+        })?;
+        Ok(Printer::new(Box::new(FileOutput(file)), translate))
+    }
+    fn print(&mut self, byte: u8) {
+        if !self.translate {
+            return self.output.write_byte(byte);
+        }
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == CR);
+        if core::mem::replace(&mut self.after_esc, false) {
+            return;
+        }
+        match byte {
+            CR => self.output.write_byte(b'\n'),
+            LF if after_cr => {}
+            LF => self.output.write_byte(b'\n'),
+            ESC => self.after_esc = true,
+            TAB | FF | 0x20..=0x7e => self.output.write_byte(byte),
+            _ => {}
+        }
+    }
+}
+
+impl SerialBackend for Printer {
+    fn read_byte(&mut self) -> Option<u8> {
+        None
+    }
+    fn write_byte(&mut self, byte: u8) {
+        self.print(byte);
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+struct FileOutput(std::fs::File);
+
+#[cfg(not(target_os = "none"))]
+impl SerialBackend for FileOutput {
+    fn read_byte(&mut self) -> Option<u8> {
+        None
+    }
+    fn write_byte(&mut self, byte: u8) {
+        use std::io::Write;
+        // written as it arrives so the capture is complete even if the emulator is killed
+        if let Err(e) = self.0.write_all(&[byte]) {
+            warn!(target: Pia, "printer capture failed: {}", e);
+        }
+    }
+}
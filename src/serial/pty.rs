@@ -0,0 +1,100 @@
+//! Connects a serial port to a host pseudo-terminal.
+use super::SerialBackend;
+use crate::{Error, ErrorKind, String, ToString};
+use core::ffi::CStr;
+
+/// The master side of a pseudo-terminal. Terminal programs (`screen`, `minicom`, ...)
+/// open the slave device named by [Pty::path] to talk to the guest.
+/// The guest sees carrier detect only while the slave is open.
+pub struct Pty {
+    fd: libc::c_int,
+    path: String,
+    slave_open: bool,
+}
+
+impl Pty {
+    pub fn open() -> Result<Self, Error> {
+        let os_err = |what: &str| {
+            err!(
+                ErrorKind::IO,
+                None,
+                "pseudo-terminal {}: {}",
+                what,
+                std::io::Error::last_os_error()
+            )
+        };
+        // Safety: plain libc calls on a descriptor we own; ptsname's result is copied
+        // before anything else can overwrite it
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(os_err("open"));
+            }
+            let pty = |path: String| Pty {
+                fd,
+                path,
+                slave_open: false,
+            };
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                drop(pty(String::new()));
+                return Err(os_err("unlock"));
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                drop(pty(String::new()));
+                return Err(os_err("name"));
+            }
+            let pty = pty(CStr::from_ptr(name).to_string_lossy().to_string());
+            // raw bytes in both directions and never block
+            let mut tio: libc::termios = core::mem::zeroed();
+            if libc::tcgetattr(fd, &mut tio) == 0 {
+                libc::cfmakeraw(&mut tio);
+                libc::tcsetattr(fd, libc::TCSANOW, &tio);
+            }
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                return Err(os_err("configure"));
+            }
+            info!(target: Acia, "serial port is on {}", pty.path);
+            Ok(pty)
+        }
+    }
+    /// The path of the slave device, e.g. /dev/pts/3
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl SerialBackend for Pty {
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut b = 0u8;
+        // Safety: reads one byte into b
+        let n = unsafe { libc::read(self.fd, &mut b as *mut u8 as *mut libc::c_void, 1) };
+        if n == 1 {
+            self.slave_open = true;
+            return Some(b);
+        }
+        // the master reads EIO while nothing has the slave open
+        let would_block = std::io::Error::last_os_error().kind() == std::io::ErrorKind::WouldBlock;
+        self.slave_open = n < 0 && would_block;
+        None
+    }
+    fn write_byte(&mut self, byte: u8) {
+        // Safety: writes one byte from byte
+        unsafe {
+            libc::write(self.fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+    fn connected(&mut self) -> bool {
+        self.slave_open
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        // Safety: the descriptor is owned by this Pty
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
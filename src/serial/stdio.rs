@@ -0,0 +1,42 @@
+//! Connects a serial port to the console.
+use super::SerialBackend;
+use std::io::{Read, Write};
+use std::sync::mpsc::{channel, Receiver};
+
+/// Sends the guest's output to stdout and feeds it what is read from stdin.
+/// Stdin is read on a separate thread so polling never blocks.
+pub struct Stdio {
+    input: Receiver<u8>,
+}
+
+impl Stdio {
+    pub fn new() -> Self {
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            for byte in std::io::stdin().lock().bytes() {
+                match byte {
+                    Ok(b) if tx.send(b).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        Stdio { input: rx }
+    }
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SerialBackend for Stdio {
+    fn read_byte(&mut self) -> Option<u8> {
+        // once stdin is closed there is nothing more to receive but output still works
+        self.input.try_recv().ok()
+    }
+    fn write_byte(&mut self, byte: u8) {
+        let mut out = std::io::stdout().lock();
+        let _ = out.write_all(&[byte]).and_then(|_| out.flush());
+    }
+}
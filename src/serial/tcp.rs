@@ -0,0 +1,147 @@
+//! Connects a serial port to a TCP socket.
+use super::SerialBackend;
+use crate::{Error, ErrorKind, String};
+use std::io::{ErrorKind as IoErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+/// Listens on a localhost port and connects the guest to one client at a time
+/// (e.g. `telnet localhost 6551`). A new client can connect once the previous one
+/// disconnects. The guest sees carrier detect only while a client is connected.
+pub struct TcpSerial {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+}
+
+impl TcpSerial {
+    /// Starts listening on 127.0.0.1:`port`. Port 0 picks a free port; see [TcpSerial::port].
+    pub fn listen(port: u16) -> Result<Self, Error> {
+        let io_err = |e: std::io::Error| err!(ErrorKind::IO, None, "serial port {}: {}", port, e);
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).map_err(io_err)?;
+        listener.set_nonblocking(true).map_err(io_err)?;
+        Ok(TcpSerial {
+            listener,
+            stream: None,
+        })
+    }
+    /// The port being listened on
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().map(|a| a.port()).unwrap_or(0)
+    }
+    /// Accepts a waiting client if there isn't one already.
+    fn check_for_client(&mut self) {
+        if self.stream.is_some() {
+            return;
+        }
+        if let Ok((stream, peer)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                let _ = stream.set_nodelay(true);
+                info!(target: Acia, "serial client connected from {}", peer);
+                self.stream = Some(stream);
+            }
+        }
+    }
+}
+
+impl SerialBackend for TcpSerial {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.check_for_client();
+        read_stream(&mut self.stream)
+    }
+    fn write_byte(&mut self, byte: u8) {
+        self.check_for_client();
+        write_stream(&mut self.stream, byte);
+    }
+    fn connected(&mut self) -> bool {
+        self.check_for_client();
+        self.stream.is_some()
+    }
+}
+
+/// Connects to a server such as a DriveWire server (e.g. `localhost:65504`).
+/// If the connection drops, reconnecting is tried again (at most once a second)
+/// the next time the guest sends something.
+pub struct TcpClient {
+    addr: String,
+    stream: Option<TcpStream>,
+    last_attempt: Option<std::time::Instant>,
+}
+
+impl TcpClient {
+    /// Connects to `addr` (host:port)
+    pub fn connect(addr: &str) -> Result<Self, Error> {
+        let mut client = TcpClient {
+            addr: addr.into(),
+            stream: None,
+            last_attempt: None,
+        };
+        client.stream = Some(client.open()?);
+        Ok(client)
+    }
+    fn open(&mut self) -> Result<TcpStream, Error> {
+        self.last_attempt = Some(std::time::Instant::now());
+        let io_err =
+            |e: std::io::Error| err!(ErrorKind::IO, None, "connecting to {}: {}", self.addr, e);
+        let stream = TcpStream::connect(&self.addr).map_err(io_err)?;
+        stream.set_nonblocking(true).map_err(io_err)?;
+        let _ = stream.set_nodelay(true);
+        info!(target: Acia, "connected to {}", self.addr);
+        Ok(stream)
+    }
+    fn reconnect(&mut self) {
+        let due = self
+            .last_attempt
+            .is_none_or(|t| t.elapsed() >= std::time::Duration::from_secs(1));
+        if self.stream.is_none() && due {
+            match self.open() {
+                Ok(stream) => self.stream = Some(stream),
+                Err(e) => warn!(target: Acia, "{}", e),
+            }
+        }
+    }
+}
+
+impl SerialBackend for TcpClient {
+    fn read_byte(&mut self) -> Option<u8> {
+        read_stream(&mut self.stream)
+    }
+    fn write_byte(&mut self, byte: u8) {
+        self.reconnect();
+        write_stream(&mut self.stream, byte);
+    }
+    fn connected(&mut self) -> bool {
+        self.stream.is_some()
+    }
+}
+
+/// Reads a byte without blocking, dropping the stream if it's been closed
+fn read_stream(stream: &mut Option<TcpStream>) -> Option<u8> {
+    let mut buf = [0u8; 1];
+    match stream.as_mut()?.read(&mut buf) {
+        Ok(1) => Some(buf[0]),
+        Err(e) if e.kind() == IoErrorKind::WouldBlock => None,
+        // zero bytes read means the other end closed the connection
+        _ => {
+            disconnect(stream);
+            None
+        }
+    }
+}
+
+/// Writes a byte, dropping the stream if it's been closed.
+/// With nobody connected the byte is lost, as it would be on a real wire.
+fn write_stream(stream: &mut Option<TcpStream>, byte: u8) {
+    if let Some(s) = stream.as_mut() {
+        match s.write(&[byte]) {
+            Ok(1) => {}
+            // the other end isn't keeping up
+            Err(e) if e.kind() == IoErrorKind::WouldBlock => {}
+            _ => disconnect(stream),
+        }
+    }
+}
+
+fn disconnect(stream: &mut Option<TcpStream>) {
+    if stream.take().is_some() {
+        info!(target: Acia, "serial connection closed");
+    }
+}
@@ -0,0 +1,37 @@
+//! Connects a serial port to one of the RP2350's hardware UARTs.
+use super::SerialBackend;
+use rp235x_hal::uart::{Enabled, UartDevice, UartPeripheral, ValidUartPinout};
+
+/// Passes bytes straight through an enabled UART. The UART's own baud rate is
+/// whatever it was enabled with; the guest's baud rate only paces the emulated ACIA.
+pub struct Uart<D: UartDevice, P: ValidUartPinout<D>> {
+    uart: UartPeripheral<Enabled, D, P>,
+}
+
+impl<D: UartDevice, P: ValidUartPinout<D>> Uart<D, P> {
+    pub fn new(uart: UartPeripheral<Enabled, D, P>) -> Self {
+        Uart { uart }
+    }
+    /// Gives the UART back (e.g. to reconfigure it)
+    pub fn free(self) -> UartPeripheral<Enabled, D, P> {
+        self.uart
+    }
+}
+
+impl<D: UartDevice, P: ValidUartPinout<D>> SerialBackend for Uart<D, P> {
+    fn read_byte(&mut self) -> Option<u8> {
+        if !self.uart.uart_is_readable() {
+            return None;
+        }
+        let mut buf = [0u8; 1];
+        // bytes with framing or parity errors are dropped
+        match self.uart.read_raw(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+    fn write_byte(&mut self, byte: u8) {
+        // the TX FIFO drains much faster than the guest can fill it, so this rarely waits
+        self.uart.write_full_blocking(&[byte]);
+    }
+}
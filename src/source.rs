@@ -0,0 +1,115 @@
+//! File-system abstraction used by the assembler to read source files (the main
+//! program as well as anything it pulls in with INCLUDE/USE).
+//!
+//! On the host, [StdFs] reads files with std::fs. On the Pico (or anywhere else that
+//! doesn't have a file system) sources can be embedded in the binary, e.g. with
+//! `include_bytes!`, and served from an [EmbeddedFs].
+use super::*;
+
+/// Something the assembler can read source files from.
+/// Paths use '/' as the separator and have already been normalized (see [normalize]).
+pub trait SourceFs {
+    /// Returns true if there is a readable file at `path`
+    fn exists(&self, path: &str) -> bool;
+    /// Reads the entire file at `path` as text
+    fn read(&self, path: &str) -> Result<String, Error>;
+}
+
+/// Reads source files from the host's file system
+#[cfg(not(target_os = "none"))]
+#[derive(Debug, Default)]
+pub struct StdFs;
+#[cfg(not(target_os = "none"))]
+impl SourceFs for StdFs {
+    fn exists(&self, path: &str) -> bool {
+        std::path::Path::new(path).is_file()
+    }
+    fn read(&self, path: &str) -> Result<String, Error> {
+        std::fs::read_to_string(path)
+            .map_err(|e| err!(ErrorKind::IO, None, "failed to read {}: {}", path, e))
+    }
+}
+
+/// Serves source files from byte slices that are embedded in the program
+#[derive(Debug, Default)]
+pub struct EmbeddedFs {
+    files: Map<String, &'static [u8]>,
+}
+impl EmbeddedFs {
+    pub fn new() -> Self {
+        EmbeddedFs { files: Map::new() }
+    }
+    /// Adds (or replaces) the file with the given path
+    pub fn add(&mut self, path: &str, contents: &'static [u8]) {
+        self.files.insert(normalize(path), contents);
+    }
+    pub fn with_file(mut self, path: &str, contents: &'static [u8]) -> Self {
+        self.add(path, contents);
+        self
+    }
+}
+impl SourceFs for EmbeddedFs {
+    fn exists(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+    fn read(&self, path: &str) -> Result<String, Error> {
+        let bytes = self
+            .files
+            .get(path)
+            .ok_or_else(|| err!(ErrorKind::IO, None, "no such file: {}", path))?;
+        core::str::from_utf8(bytes)
+            .map(String::from)
+            .map_err(|_| err!(ErrorKind::IO, None, "{} is not valid UTF-8", path))
+    }
+}
+
+/// The file system the assembler uses unless it's given another one
+pub fn default_fs() -> Box<dyn SourceFs> {
+    #[cfg(not(target_os = "none"))]
+    return Box::new(StdFs);
+    #[cfg(target_os = "none")]
+    return Box::new(EmbeddedFs::new());
+}
+
+/// Normalizes a path so that the same file is always referred to by the same string
+/// (this is what makes include cycle detection work). Backslashes become '/',
+/// and "." and "dir/.." components are removed.
+pub fn normalize(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let absolute = path.starts_with('/');
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.last().is_some_and(|p| *p != "..") => {
+                parts.pop();
+            }
+            ".." if absolute => {}
+            _ => parts.push(part),
+        }
+    }
+    let joined = parts.join("/");
+    if absolute {
+        format!("/{}", joined)
+    } else {
+        joined
+    }
+}
+
+/// Returns the directory portion of a (normalized) path or "" if there isn't one
+pub fn parent(path: &str) -> &str {
+    path.rfind('/')
+        .map_or("", |i| if i == 0 { "/" } else { &path[..i] })
+}
+
+/// Joins `name` to the directory `dir` unless `name` is already absolute
+pub fn join(dir: &str, name: &str) -> String {
+    // a drive letter (e.g. "C:") also makes a path absolute
+    let is_absolute =
+        name.starts_with('/') || name.starts_with('\\') || name.as_bytes().get(1) == Some(&b':');
+    if dir.is_empty() || is_absolute {
+        normalize(name)
+    } else {
+        normalize(&format!("{}/{}", dir, name))
+    }
+}
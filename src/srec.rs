@@ -0,0 +1,125 @@
+//! Motorola S-records (S19 and S28 files).
+//!
+//! Each record is a line of the form `S<type><count><address><data><checksum>` in hex
+//! where _count_ is the number of bytes that follow it and _checksum_ is the ones'
+//! complement of the low byte of the sum of the count, address and data bytes.
+//! - S0 is a header (its data is usually a name)
+//! - S1/S2/S3 hold data at a 16/24/32-bit address
+//! - S5/S6 hold the number of data records
+//! - S9/S8/S7 end the file and hold the entry point as a 16/24/32-bit address
+use crate::image::Image;
+use crate::{format, Error, ErrorKind, String, Vec};
+
+/// The size of the addresses in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SrecFormat {
+    /// 16-bit addresses (S1 data records ending with S9)
+    S19,
+    /// 24-bit addresses (S2 data records ending with S8)
+    S28,
+}
+impl SrecFormat {
+    fn addr_len(&self) -> usize {
+        match self {
+            SrecFormat::S19 => 2,
+            SrecFormat::S28 => 3,
+        }
+    }
+}
+
+const BYTES_PER_RECORD: usize = 16;
+
+/// Formats one record
+fn record(rtype: u8, addr: u32, addr_len: usize, data: &[u8]) -> String {
+    let addr_bytes = &addr.to_be_bytes()[4 - addr_len..];
+    let count = (addr_len + data.len() + 1) as u8;
+    let sum = addr_bytes
+        .iter()
+        .chain(data)
+        .fold(count, |sum, &b| sum.wrapping_add(b));
+    let mut s = format!("S{}{:02X}", rtype, count);
+    addr_bytes
+        .iter()
+        .chain(data)
+        .for_each(|b| s.push_str(&format!("{:02X}", b)));
+    s.push_str(&format!("{:02X}\n", !sum));
+    s
+}
+
+/// Returns the image as S-records with a header record holding `name`.
+/// The entry point is the image's exec address (or 0 if it doesn't have one).
+pub fn write(image: &Image, format: SrecFormat, name: &str) -> String {
+    let addr_len = format.addr_len();
+    let mut s = record(0, 0, 2, name.as_bytes());
+    let mut count = 0u32;
+    for seg in &image.segments {
+        let mut addr = seg.addr as u32;
+        for chunk in seg.data.chunks(BYTES_PER_RECORD) {
+            s.push_str(&record(addr_len as u8 - 1, addr, addr_len, chunk));
+            addr += chunk.len() as u32;
+            count += 1;
+        }
+    }
+    // the record count is optional; it's only included when it fits in S5
+    if count <= 0xffff {
+        s.push_str(&record(5, count, 2, &[]));
+    }
+    let exec = image.exec.unwrap_or(0) as u32;
+    s.push_str(&record(11 - addr_len as u8, exec, addr_len, &[]));
+    s
+}
+
+/// Reads S-records. Header and count records are ignored, as is anything after the
+/// termination record. Addresses must fit in 16 bits.
+pub fn read(text: &str) -> Result<Image, Error> {
+    let mut image = Image::new();
+    for (n, line) in text.lines().enumerate().map(|(n, l)| (n + 1, l.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let bad = |what: &str| err!(ErrorKind::IO, None, "{} in S-record", what).at_line(n);
+        let rtype = line
+            .strip_prefix(['S', 's'])
+            .and_then(|s| s.chars().next())
+            .and_then(|c| c.to_digit(10))
+            .ok_or_else(|| bad("invalid record type"))?;
+        let bytes = (2..line.len())
+            .step_by(2)
+            .map(|i| {
+                line.get(i..i + 2)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| bad("invalid hex"))?;
+        if bytes.is_empty() || bytes[0] as usize != bytes.len() - 1 {
+            return Err(bad("wrong byte count"));
+        }
+        let sum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        if sum != 0xff {
+            return Err(bad("bad checksum"));
+        }
+        let addr_len = match rtype {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(bad("invalid record type")),
+        };
+        let fields = &bytes[1..bytes.len() - 1];
+        if fields.len() < addr_len {
+            return Err(bad("missing address"));
+        }
+        let (addr, data) = fields.split_at(addr_len);
+        let addr = addr.iter().fold(0u32, |a, &b| (a << 8) | b as u32);
+        let addr =
+            u16::try_from(addr).map_err(|_| bad(&format!("address ${:X} out of range", addr)))?;
+        match rtype {
+            1..=3 => image.add(addr, data).map_err(|e| e.at_line(n))?,
+            7..=9 => {
+                image.exec = Some(addr);
+                break;
+            }
+            _ => {}
+        }
+    }
+    Ok(image)
+}
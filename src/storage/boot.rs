@@ -0,0 +1,472 @@
+//! Loads ROMs, programs and disks at boot as listed in coco.yaml:
+//! ```yaml
+//! load_rom:
+//!   - path: "BASIC.ROM"
+//!     addr: 0xa000
+//!   - path: "EXTBASIC.ROM"  # known ROMs can leave out addr
+//!   - path: "GAME.CCC"      # a ROM loaded at $C000 that isn't BASIC is a cartridge
+//!     addr: 0xc000
+//! load_code:                # assembly source, DECB .bin or S-records
+//!   - path: "hello.asm"
+//! machine: coco2            # or coco1, coco3, dragon32, dragon64; picked from the ROMs if left out
+//! disks:                    # DriveWire drives 0, 1, ...
+//!   - "disks/NOS9.DSK"
+//! log:
+//!   level: warn
+//! ```
+//! The settings are a [BootConfig], deserialized with serde. serde_yaml needs std, so the
+//! file is read by a small reader for the block and list forms of YAML shown above, which
+//! is all the config needs.
+//!
+//! ROMs are checked against the [romdb](crate::romdb): known ROMs are loaded where they
+//! belong whatever addr says, and bad dumps and missing pieces are warned about.
+use super::fat::{FatDisk, FatStore, FatVolume};
+use super::BlockDevice;
+use crate::drivewire::Server;
+use crate::logging::LogConfig;
+use crate::romdb::{self, Profile};
+use crate::{format, Box, Core, Error, ErrorKind, Rc, String, ToString, Vec};
+use core::cell::RefCell;
+use core::fmt;
+use serde::de::{self, IntoDeserializer, Visitor};
+use serde::Deserialize;
+
+/// The config file's name in the root directory of the card
+pub const CONFIG_FILE: &str = "coco.yaml";
+
+/// Where a cartridge ROM is mapped
+pub const CART_ADDR: u16 = 0xc000;
+/// The most a cartridge can hold ($C000-$FEFF)
+pub const CART_SIZE: usize = 0x3f00;
+
+/// A ROM image and where it goes
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomEntry {
+    pub path: String,
+    /// where to load the ROM if it isn't one the [romdb] knows the place of
+    #[serde(default, deserialize_with = "addr")]
+    pub addr: Option<u16>,
+}
+
+/// A program to load into memory
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CodeEntry {
+    pub path: String,
+}
+
+/// What to load at boot
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BootConfig {
+    pub load_rom: Vec<RomEntry>,
+    /// programs loaded after the ROMs; the machine starts at the entry point of the
+    /// last one that has one
+    pub load_code: Vec<CodeEntry>,
+    /// the machine to emulate, if not the one the ROMs are for
+    #[serde(deserialize_with = "machine")]
+    pub machine: Option<Profile>,
+    /// disk images for DriveWire drives 0, 1, ...
+    pub disks: Vec<String>,
+    pub log: LogConfig,
+}
+
+/// What was loaded besides the ROMs and programs, which go straight into memory
+pub struct BootMedia {
+    pub config: BootConfig,
+    /// the ROM in load_rom that's a cartridge, if there is one
+    pub cart: Option<String>,
+    /// a DriveWire server with the disks mounted, which can also mount other disk
+    /// images in the root directory by name
+    pub server: Server,
+    /// the machine being emulated
+    pub profile: Profile,
+    /// problems found with the ROMs, which have also been logged
+    pub warnings: Vec<String>,
+}
+
+/// A value in the config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Scalar(String),
+    List(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+fn config_err(line: usize, msg: &str) -> Error {
+    err!(
+        ErrorKind::Syntax,
+        None,
+        "{} line {}: {}",
+        CONFIG_FILE,
+        line,
+        msg
+    )
+}
+
+/// Removes a comment, unless the # is quoted
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('#', None) if i == 0 || line[..i].ends_with(' ') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn scalar(text: &str) -> Value {
+    let text = text.trim();
+    let unquoted = ['"', '\'']
+        .iter()
+        .find_map(|&q| text.strip_prefix(q).and_then(|t| t.strip_suffix(q)));
+    Value::Scalar(unquoted.unwrap_or(text).to_string())
+}
+
+/// Parses the lines at `indent` starting at `lines[*pos]` as a list or a map
+fn parse_block(lines: &mut [Line], pos: &mut usize, indent: usize) -> Result<Value, Error> {
+    let is_item = |l: &Line| l.text == "-" || l.text.starts_with("- ");
+    if is_item(&lines[*pos]) {
+        let mut items = Vec::new();
+        while *pos < lines.len() && lines[*pos].indent == indent && is_item(&lines[*pos]) {
+            let line = lines[*pos];
+            let rest = line.text[1..].trim_start();
+            if rest.is_empty() {
+                *pos += 1;
+                items.push(parse_nested(lines, pos, indent)?);
+            } else if rest.contains(": ") || rest.ends_with(':') {
+                // "- key: value" starts a map whose other keys line up with `key`
+                let indent = indent + (line.text.len() - rest.len());
+                lines[*pos] = Line {
+                    indent,
+                    text: rest,
+                    ..line
+                };
+                items.push(parse_block(lines, pos, indent)?);
+            } else {
+                items.push(scalar(rest));
+                *pos += 1;
+            }
+        }
+        return Ok(Value::List(items));
+    }
+    let mut map = Vec::new();
+    while *pos < lines.len() && lines[*pos].indent == indent {
+        let line = lines[*pos];
+        let (key, value) = match line.text.split_once(": ") {
+            Some((key, value)) => (key, value),
+            None => match line.text.strip_suffix(':') {
+                Some(key) => (key, ""),
+                None => return Err(config_err(line.number, "expected key: value")),
+            },
+        };
+        *pos += 1;
+        let value = if value.trim().is_empty() {
+            parse_nested(lines, pos, indent)?
+        } else {
+            scalar(value)
+        };
+        map.push((key.trim().to_string(), value));
+    }
+    Ok(Value::Map(map))
+}
+
+/// Parses the block indented under a key or list item, or an empty value if there isn't one
+fn parse_nested(lines: &mut [Line], pos: &mut usize, indent: usize) -> Result<Value, Error> {
+    match lines.get(*pos) {
+        Some(next) if next.indent > indent => parse_block(lines, pos, next.indent),
+        // YAML allows a list under a key at the key's own indentation
+        Some(next) if next.indent == indent && next.text.starts_with('-') => {
+            parse_block(lines, pos, indent)
+        }
+        _ => Ok(Value::Scalar(String::new())),
+    }
+}
+
+fn parse(text: &str) -> Result<Value, Error> {
+    let mut lines = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        if raw.contains('\t') {
+            return Err(config_err(i + 1, "tabs can't be used for indentation"));
+        }
+        let line = strip_comment(raw).trim_end();
+        let text = line.trim_start();
+        if !text.is_empty() && text != "---" {
+            lines.push(Line {
+                number: i + 1,
+                indent: line.len() - text.len(),
+                text,
+            });
+        }
+    }
+    if lines.is_empty() {
+        return Ok(Value::Map(Vec::new()));
+    }
+    let (mut pos, indent) = (0, lines[0].indent);
+    let value = parse_block(&mut lines, &mut pos, indent)?;
+    match lines.get(pos) {
+        Some(line) => Err(config_err(line.number, "unexpected indentation")),
+        None => Ok(value),
+    }
+}
+
+/// Parses an address such as 0xA000, $A000 or 40960
+fn parse_addr(text: &str) -> Option<u16> {
+    let text = text.trim();
+    match text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// An error found while deserializing the config
+#[derive(Debug)]
+struct DeError(String);
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+impl de::StdError for DeError {}
+impl de::Error for DeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DeError(msg.to_string())
+    }
+}
+
+impl Value {
+    /// A key with nothing after it, which stands for an empty list or map or no value
+    fn is_empty(&self) -> bool {
+        matches!(self, Value::Scalar(s) if s.is_empty())
+    }
+}
+
+impl<'de> IntoDeserializer<'de, DeError> for Value {
+    type Deserializer = Value;
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self {
+            Value::Scalar(s) => visitor.visit_string(s),
+            Value::List(items) => {
+                visitor.visit_seq(de::value::SeqDeserializer::new(items.into_iter()))
+            }
+            Value::Map(entries) => {
+                visitor.visit_map(de::value::MapDeserializer::new(entries.into_iter()))
+            }
+        }
+    }
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        if self.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self {
+            v if v.is_empty() => visitor.visit_seq(de::value::SeqDeserializer::new(
+                Vec::<Value>::new().into_iter(),
+            )),
+            v => v.deserialize_any(visitor),
+        }
+    }
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self {
+            v if v.is_empty() => visitor.visit_map(de::value::MapDeserializer::new(
+                Vec::<(String, Value)>::new().into_iter(),
+            )),
+            v => v.deserialize_any(visitor),
+        }
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.deserialize_map(visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct tuple tuple_struct enum
+        identifier ignored_any
+    }
+}
+
+/// Deserializes an address such as 0xA000, $A000 or 40960
+fn addr<'de, D: de::Deserializer<'de>>(d: D) -> Result<Option<u16>, D::Error> {
+    let text = String::deserialize(d)?;
+    parse_addr(&text)
+        .map(Some)
+        .ok_or_else(|| de::Error::custom(format!("bad address {}", text)))
+}
+
+/// Deserializes a machine name (see [Profile::parse])
+fn machine<'de, D: de::Deserializer<'de>>(d: D) -> Result<Option<Profile>, D::Error> {
+    let name = String::deserialize(d)?;
+    Profile::parse(&name)
+        .map(Some)
+        .ok_or_else(|| de::Error::custom(format!("unknown machine {}", name)))
+}
+
+/// Loads a program, choosing the format by the file's extension, and returns its entry
+/// point if it has one
+fn load_code(core: &mut Core, path: &str, data: &[u8]) -> Result<Option<u16>, Error> {
+    let text =
+        || core::str::from_utf8(data).map_err(|_| general_err!("{} isn't a text file", path));
+    let ext = path.rsplit_once('.').map_or("", |(_, ext)| ext);
+    match ext.to_ascii_lowercase().as_str() {
+        "asm" => {
+            let instructions = crate::instructions::Instance::new(0, None);
+            let program =
+                crate::assembler::Assembler::new(&instructions).assemble(text()?.lines())?;
+            // (not load_program, which clears the reset vector in the ROM)
+            core.load_image(&program.to_image())?;
+            Ok(program.entry_point())
+        }
+        "bin" => core.load_decb(data).map(Some),
+        "s19" | "s28" | "s37" | "srec" => core.load_srec(text()?),
+        _ => Err(general_err!(
+            "{}: {} isn't a program (.asm, .bin or S-records)",
+            CONFIG_FILE,
+            path
+        )),
+    }
+}
+
+impl BootConfig {
+    /// Parses the text of a config file
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let value = parse(text)?;
+        if !matches!(value, Value::Map(_)) {
+            return Err(general_err!("{} must be a map of settings", CONFIG_FILE));
+        }
+        BootConfig::deserialize(value).map_err(|e| general_err!("{}: {}", CONFIG_FILE, e))
+    }
+    /// Loads the ROMs and programs into `core` and opens the disks on `volume`
+    pub fn load<D: BlockDevice + 'static>(
+        self,
+        volume: Rc<RefCell<FatVolume<D>>>,
+        core: &mut Core,
+    ) -> Result<BootMedia, Error> {
+        self.log.apply()?;
+        let mut warnings = Vec::new();
+        let mut ids = Vec::new();
+        let mut cart = None;
+        for rom in &self.load_rom {
+            let data = volume.borrow_mut().read_file(&rom.path)?;
+            let id = romdb::identify(&data);
+            for w in &id.warnings {
+                warnings.push(format!("{} {}", rom.path, w));
+            }
+            let addr = match (id.kind, rom.addr) {
+                (Some(kind), Some(addr)) if addr != kind.addr() => {
+                    warnings.push(format!(
+                        "{} is {}, which goes at {:04X}, not {:04X}",
+                        rom.path,
+                        id.name(),
+                        kind.addr(),
+                        addr
+                    ));
+                    kind.addr()
+                }
+                (Some(kind), _) => kind.addr(),
+                (None, Some(addr)) => addr,
+                (None, None) => {
+                    return Err(general_err!(
+                        "{}: {} isn't a known ROM, so it needs an address (addr)",
+                        CONFIG_FILE,
+                        rom.path
+                    ))
+                }
+            };
+            if addr == CART_ADDR && id.kind.is_none() {
+                if data.len() > CART_SIZE {
+                    return Err(general_err!(
+                        "cartridge {} is bigger than {} bytes",
+                        rom.path,
+                        CART_SIZE
+                    ));
+                }
+                cart = Some(rom.path.clone());
+            }
+            let loaded = core.load_bytes(&data[..id.len], addr)?;
+            info!(
+                "loaded {} ({}, {} bytes) at {:04X}",
+                rom.path,
+                id.name(),
+                loaded,
+                addr
+            );
+            ids.push(id);
+        }
+        // without a BASIC ROM, whatever's run is taken not to need one
+        let profile = if ids.iter().all(|id| id.kind.is_none()) {
+            self.machine.unwrap_or(Profile::CoCo2)
+        } else {
+            let (profile, problems) = romdb::check_set(&ids, self.machine);
+            warnings.extend(problems);
+            profile
+        };
+        profile.apply(core);
+        info!("machine: {}", profile.name());
+        for w in &warnings {
+            warn!("{}", w);
+        }
+        let mut server = Server::new();
+        server.set_object_store(Box::new(FatStore::new(volume.clone(), "")));
+        for (drive, path) in self.disks.iter().enumerate() {
+            let drive = u8::try_from(drive).map_err(|_| general_err!("too many disks"))?;
+            server.mount(drive, Box::new(FatDisk::open(volume.clone(), path)?));
+            info!("DriveWire drive {}: {}", drive, path);
+        }
+        for code in &self.load_code {
+            let data = volume.borrow_mut().read_file(&code.path)?;
+            if let Some(entry) = load_code(core, &code.path, &data)? {
+                core.reset_vector = Some(entry);
+            }
+            info!("loaded {}", code.path);
+        }
+        Ok(BootMedia {
+            config: self,
+            cart,
+            server,
+            profile,
+            warnings,
+        })
+    }
+}
+
+/// Reads the config file from the root directory of `volume` and loads what it lists.
+pub fn boot<D: BlockDevice + 'static>(
+    volume: Rc<RefCell<FatVolume<D>>>,
+    core: &mut Core,
+) -> Result<BootMedia, Error> {
+    let text = volume.borrow_mut().read_file(CONFIG_FILE)?;
+    let text = core::str::from_utf8(&text)
+        .map_err(|_| general_err!("{} isn't valid UTF-8", CONFIG_FILE))?;
+    BootConfig::parse(text)?.load(volume, core)
+}
@@ -0,0 +1,514 @@
+//! Reads FAT12, FAT16 and FAT32 file systems, such as the one on an SD card.
+//!
+//! Files can be read whole or in part and rewritten in place, which is all that
+//! loading ROMs and serving disk images needs. Files can't be created or grown.
+//! Long file names are matched as well as 8.3 names, ignoring case, and paths use
+//! `/` between directories.
+use super::{Block, BlockDevice, BLOCK_SIZE};
+use crate::drivewire::disk::{DiskImage, ObjectStore};
+use crate::drivewire::SECTOR_SIZE;
+use crate::{Box, Error, ErrorKind, Rc, String, ToString, Vec};
+use core::cell::RefCell;
+
+// directory entry attributes
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+const DIR_ENTRY_SIZE: usize = 32;
+
+// MBR partition types that hold a FAT file system
+const FAT_PARTITION_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0b, 0x0c, 0x0e];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatKind {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// A file or directory found in a directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// the long name if there is one, otherwise the 8.3 name
+    pub name: String,
+    pub is_dir: bool,
+    pub read_only: bool,
+    pub size: u32,
+    first_cluster: u32,
+}
+
+/// An open file: where its data is and how big it is
+#[derive(Debug, Clone)]
+pub struct FatFile {
+    clusters: Vec<u32>,
+    size: u32,
+    read_only: bool,
+}
+
+impl FatFile {
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+/// Where a directory's entries are
+#[derive(Debug, Clone, Copy)]
+enum Dir {
+    /// the fixed-size root directory of FAT12 and FAT16
+    FixedRoot,
+    Cluster(u32),
+}
+
+/// A mounted FAT file system
+pub struct FatVolume<D: BlockDevice> {
+    dev: D,
+    kind: FatKind,
+    fat_start: u32,
+    fat_blocks: u32,
+    root_start: u32,
+    root_blocks: u32,
+    root_cluster: u32,
+    data_start: u32,
+    cluster_blocks: u32,
+    clusters: u32,
+    // the last block of the FAT that was read
+    cache: Block,
+    cache_lba: Option<u32>,
+}
+
+fn u16_at(b: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([b[i], b[i + 1]])
+}
+
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+}
+
+fn not_fat(why: &str) -> Error {
+    err!(ErrorKind::IO, None, "no FAT file system found: {}", why)
+}
+
+impl<D: BlockDevice> FatVolume<D> {
+    /// Mounts the file system on `dev`, which can be either the whole device or the
+    /// first FAT partition in its partition table.
+    pub fn mount(mut dev: D) -> Result<Self, Error> {
+        let mut block = [0u8; BLOCK_SIZE];
+        dev.read_block(0, &mut block)?;
+        if block[510..] != [0x55, 0xaa] {
+            return Err(not_fat("missing boot signature"));
+        }
+        let mut start = 0;
+        // a boot sector starts with a jump; a partition table doesn't
+        if !matches!(block[0], 0xeb | 0xe9) {
+            start = (0..4)
+                .map(|i| &block[446 + i * 16..462 + i * 16])
+                .find(|p| FAT_PARTITION_TYPES.contains(&p[4]))
+                .map(|p| u32_at(p, 8))
+                .ok_or_else(|| not_fat("no FAT partition"))?;
+            dev.read_block(start, &mut block)?;
+        }
+        if u16_at(&block, 11) as usize != BLOCK_SIZE {
+            return Err(not_fat("sectors aren't 512 bytes"));
+        }
+        let cluster_blocks = block[13] as u32;
+        let reserved = u16_at(&block, 14) as u32;
+        let fats = block[16] as u32;
+        let root_entries = u16_at(&block, 17) as u32;
+        let total = match u16_at(&block, 19) {
+            0 => u32_at(&block, 32),
+            n => n as u32,
+        };
+        let fat_blocks = match u16_at(&block, 22) {
+            0 => u32_at(&block, 36),
+            n => n as u32,
+        };
+        if cluster_blocks == 0 || fats == 0 || fat_blocks == 0 {
+            return Err(not_fat("invalid BIOS parameter block"));
+        }
+        let root_blocks = (root_entries * DIR_ENTRY_SIZE as u32).div_ceil(BLOCK_SIZE as u32);
+        let fat_start = start + reserved;
+        let root_start = fat_start + fats * fat_blocks;
+        let data_start = root_start + root_blocks;
+        let clusters = total
+            .checked_sub(data_start - start)
+            .ok_or_else(|| not_fat("invalid size"))?
+            / cluster_blocks;
+        let kind = match clusters {
+            0..4085 => FatKind::Fat12,
+            4085..65525 => FatKind::Fat16,
+            _ => FatKind::Fat32,
+        };
+        Ok(FatVolume {
+            dev,
+            kind,
+            fat_start,
+            fat_blocks,
+            root_start,
+            root_blocks,
+            root_cluster: if kind == FatKind::Fat32 {
+                u32_at(&block, 44)
+            } else {
+                0
+            },
+            data_start,
+            cluster_blocks,
+            clusters,
+            cache: [0; BLOCK_SIZE],
+            cache_lba: None,
+        })
+    }
+    pub fn kind(&self) -> FatKind {
+        self.kind
+    }
+    /// The size of a cluster in bytes
+    pub fn cluster_size(&self) -> usize {
+        self.cluster_blocks as usize * BLOCK_SIZE
+    }
+    /// Gives back the block device
+    pub fn into_inner(self) -> D {
+        self.dev
+    }
+    /// Reads the byte at `offset` in the (first) FAT
+    fn fat_byte(&mut self, offset: u32) -> Result<u8, Error> {
+        let lba = self.fat_start + offset / BLOCK_SIZE as u32;
+        if lba >= self.fat_start + self.fat_blocks {
+            return Err(err!(ErrorKind::IO, None, "FAT entry out of range"));
+        }
+        if self.cache_lba != Some(lba) {
+            self.cache_lba = None;
+            self.dev.read_block(lba, &mut self.cache)?;
+            self.cache_lba = Some(lba);
+        }
+        Ok(self.cache[offset as usize % BLOCK_SIZE])
+    }
+    /// The FAT entry for `cluster`: the next cluster in its chain, or None at the end
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, Error> {
+        let (next, end) = match self.kind {
+            FatKind::Fat12 => {
+                let offset = cluster + cluster / 2;
+                let pair = u16::from_le_bytes([self.fat_byte(offset)?, self.fat_byte(offset + 1)?]);
+                let entry = if cluster & 1 == 1 {
+                    pair >> 4
+                } else {
+                    pair & 0xfff
+                };
+                (entry as u32, 0xff8)
+            }
+            FatKind::Fat16 => {
+                let offset = cluster * 2;
+                let entry =
+                    u16::from_le_bytes([self.fat_byte(offset)?, self.fat_byte(offset + 1)?]);
+                (entry as u32, 0xfff8)
+            }
+            FatKind::Fat32 => {
+                let offset = cluster * 4;
+                let mut entry = [0u8; 4];
+                for (i, b) in entry.iter_mut().enumerate() {
+                    *b = self.fat_byte(offset + i as u32)?;
+                }
+                (u32::from_le_bytes(entry) & 0x0fff_ffff, 0x0fff_fff8)
+            }
+        };
+        Ok(if next >= end || next < 2 {
+            None
+        } else {
+            Some(next)
+        })
+    }
+    /// The clusters in the chain starting at `first`
+    fn chain(&mut self, first: u32) -> Result<Vec<u32>, Error> {
+        let mut chain = Vec::new();
+        let mut cluster = Some(first).filter(|&c| c >= 2);
+        while let Some(c) = cluster {
+            // a chain longer than the volume has clusters must loop
+            if c >= self.clusters + 2 || chain.len() > self.clusters as usize {
+                return Err(err!(ErrorKind::IO, None, "corrupt FAT chain at {}", c));
+            }
+            chain.push(c);
+            cluster = self.next_cluster(c)?;
+        }
+        Ok(chain)
+    }
+    fn cluster_lba(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.cluster_blocks
+    }
+    /// The blocks a directory is stored in
+    fn dir_blocks(&mut self, dir: Dir) -> Result<Vec<u32>, Error> {
+        Ok(match dir {
+            Dir::FixedRoot => (self.root_start..self.root_start + self.root_blocks).collect(),
+            Dir::Cluster(first) => self
+                .chain(first)?
+                .into_iter()
+                .flat_map(|c| {
+                    let lba = self.cluster_lba(c);
+                    lba..lba + self.cluster_blocks
+                })
+                .collect(),
+        })
+    }
+    fn root(&self) -> Dir {
+        match self.kind {
+            FatKind::Fat32 => Dir::Cluster(self.root_cluster),
+            _ => Dir::FixedRoot,
+        }
+    }
+    /// The entries in a directory, without `.`, `..`, deleted files and volume labels
+    fn entries(&mut self, dir: Dir) -> Result<Vec<DirEntry>, Error> {
+        let mut entries = Vec::new();
+        // the parts of the long name for the next entry, which come last part first
+        let mut long_name: Vec<[u16; 13]> = Vec::new();
+        let mut block = [0u8; BLOCK_SIZE];
+        for lba in self.dir_blocks(dir)? {
+            self.dev.read_block(lba, &mut block)?;
+            for e in block.chunks(DIR_ENTRY_SIZE) {
+                match e[0] {
+                    // no more entries
+                    0 => return Ok(entries),
+                    0xe5 => {
+                        long_name.clear();
+                        continue;
+                    }
+                    _ => {}
+                }
+                let attr = e[11];
+                if attr & 0x3f == ATTR_LONG_NAME {
+                    let order = (e[0] & 0x1f) as usize;
+                    if e[0] & 0x40 != 0 {
+                        long_name = vec![[0xffff; 13]; order];
+                    }
+                    if let Some(part) = order.checked_sub(1).and_then(|i| long_name.get_mut(i)) {
+                        let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+                        for (c, &o) in part.iter_mut().zip(offsets.iter()) {
+                            *c = u16_at(e, o);
+                        }
+                    }
+                    continue;
+                }
+                let parts = core::mem::take(&mut long_name);
+                if attr & ATTR_VOLUME_ID != 0 || e[0] == b'.' {
+                    continue;
+                }
+                let name = if parts.is_empty() {
+                    short_name(e)
+                } else {
+                    let units = parts.iter().flatten().copied();
+                    char::decode_utf16(units.take_while(|&c| c != 0 && c != 0xffff))
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect()
+                };
+                entries.push(DirEntry {
+                    name,
+                    is_dir: attr & ATTR_DIRECTORY != 0,
+                    read_only: attr & ATTR_READ_ONLY != 0,
+                    size: u32_at(e, 28),
+                    first_cluster: (u16_at(e, 20) as u32) << 16 | u16_at(e, 26) as u32,
+                });
+            }
+        }
+        Ok(entries)
+    }
+    /// Finds the entry at `path`, or None for the root directory
+    fn find(&mut self, path: &str) -> Result<Option<DirEntry>, Error> {
+        let mut dir = self.root();
+        let mut found = None;
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            if let Some(DirEntry { is_dir: false, .. }) = found {
+                return Err(not_found(path));
+            }
+            let entry = self
+                .entries(dir)?
+                .into_iter()
+                .find(|e| e.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| not_found(path))?;
+            // `..` pointing at the root has cluster 0, but those aren't listed
+            dir = Dir::Cluster(entry.first_cluster);
+            found = Some(entry);
+        }
+        Ok(found)
+    }
+    /// Lists the directory at `path` ("" or "/" for the root)
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>, Error> {
+        match self.find(path)? {
+            None => self.entries(self.root()),
+            Some(e) if e.is_dir => self.entries(Dir::Cluster(e.first_cluster)),
+            Some(_) => Err(err!(ErrorKind::IO, None, "{} is not a directory", path)),
+        }
+    }
+    /// True if there's a file or directory at `path`
+    pub fn exists(&mut self, path: &str) -> bool {
+        self.find(path).is_ok()
+    }
+    /// Opens the file at `path`
+    pub fn open(&mut self, path: &str) -> Result<FatFile, Error> {
+        match self.find(path)? {
+            Some(e) if !e.is_dir => {
+                let clusters = self.chain(e.first_cluster)?;
+                if (clusters.len() * self.cluster_size()) < e.size as usize {
+                    return Err(err!(ErrorKind::IO, None, "{} is truncated", path));
+                }
+                Ok(FatFile {
+                    clusters,
+                    size: e.size,
+                    read_only: e.read_only,
+                })
+            }
+            _ => Err(err!(ErrorKind::IO, None, "{} is not a file", path)),
+        }
+    }
+    /// Calls `f` with each block of `file` that overlaps `len` bytes at `offset`,
+    /// along with the range within the block and within the data
+    fn for_blocks(
+        &mut self,
+        file: &FatFile,
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(
+            &mut D,
+            u32,
+            core::ops::Range<usize>,
+            core::ops::Range<usize>,
+        ) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let cluster = file.clusters[pos / self.cluster_size()];
+            let lba = self.cluster_lba(cluster) + (pos % self.cluster_size() / BLOCK_SIZE) as u32;
+            let start = pos % BLOCK_SIZE;
+            let n = (BLOCK_SIZE - start).min(len - done);
+            f(&mut self.dev, lba, start..start + n, done..done + n)?;
+            done += n;
+        }
+        Ok(())
+    }
+    /// Reads from `file` at `offset` into `buf` and returns the number of bytes read,
+    /// which is less than asked for at the end of the file.
+    pub fn read(&mut self, file: &FatFile, offset: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = buf.len().min((file.size as usize).saturating_sub(offset));
+        let mut block = [0u8; BLOCK_SIZE];
+        self.for_blocks(file, offset, len, |dev, lba, in_block, in_buf| {
+            dev.read_block(lba, &mut block)?;
+            buf[in_buf].copy_from_slice(&block[in_block]);
+            Ok(())
+        })?;
+        Ok(len)
+    }
+    /// Overwrites part of `file` at `offset`. Writing past the end of the file fails.
+    pub fn write(&mut self, file: &FatFile, offset: usize, data: &[u8]) -> Result<(), Error> {
+        if offset + data.len() > file.size as usize {
+            return Err(err!(
+                ErrorKind::IO,
+                None,
+                "can't write past the end of the file"
+            ));
+        }
+        let mut block = [0u8; BLOCK_SIZE];
+        self.for_blocks(file, offset, data.len(), |dev, lba, in_block, in_data| {
+            if in_block.len() < BLOCK_SIZE {
+                dev.read_block(lba, &mut block)?;
+            }
+            block[in_block].copy_from_slice(&data[in_data]);
+            dev.write_block(lba, &block)
+        })
+    }
+    /// Reads the whole file at `path`
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, Error> {
+        let file = self.open(path)?;
+        let mut data = vec![0u8; file.size as usize];
+        self.read(&file, 0, &mut data)?;
+        Ok(data)
+    }
+}
+
+fn not_found(path: &str) -> Error {
+    err!(ErrorKind::IO, None, "{} not found", path)
+}
+
+/// The 8.3 name of a directory entry, lowercased where Windows NT flagged it
+fn short_name(e: &[u8]) -> String {
+    let part = |bytes: &[u8], lower: bool| {
+        let mut s: String = bytes.iter().map(|&b| b as char).collect();
+        s.truncate(s.trim_end().len());
+        if lower {
+            s.make_ascii_lowercase();
+        }
+        s
+    };
+    let mut name = part(&e[..8], e[12] & 0x08 != 0);
+    // 0x05 stands for a name that really starts with 0xe5
+    if e[0] == 0x05 {
+        name.replace_range(..1, "\u{e5}");
+    }
+    let ext = part(&e[8..11], e[12] & 0x10 != 0);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+    name
+}
+
+/// A disk image file on a FAT volume, served by DriveWire. It can be rewritten but
+/// not grown.
+pub struct FatDisk<D: BlockDevice> {
+    volume: Rc<RefCell<FatVolume<D>>>,
+    file: FatFile,
+}
+
+impl<D: BlockDevice> FatDisk<D> {
+    pub fn open(volume: Rc<RefCell<FatVolume<D>>>, path: &str) -> Result<Self, Error> {
+        let file = volume.borrow_mut().open(path)?;
+        Ok(FatDisk { volume, file })
+    }
+}
+
+impl<D: BlockDevice> DiskImage for FatDisk<D> {
+    fn read_sector(&mut self, lsn: u32, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), Error> {
+        buf.fill(0);
+        self.volume
+            .borrow_mut()
+            .read(&self.file, lsn as usize * SECTOR_SIZE, buf)?;
+        Ok(())
+    }
+    fn write_sector(&mut self, lsn: u32, buf: &[u8; SECTOR_SIZE]) -> Result<(), Error> {
+        self.volume
+            .borrow_mut()
+            .write(&self.file, lsn as usize * SECTOR_SIZE, buf)
+    }
+    fn write_protected(&self) -> bool {
+        self.file.read_only
+    }
+}
+
+/// Serves the disk images in a directory on a FAT volume by name
+pub struct FatStore<D: BlockDevice> {
+    volume: Rc<RefCell<FatVolume<D>>>,
+    dir: String,
+}
+
+impl<D: BlockDevice> FatStore<D> {
+    /// Serves the files in `dir` ("" for the root directory)
+    pub fn new(volume: Rc<RefCell<FatVolume<D>>>, dir: &str) -> Self {
+        FatStore {
+            volume,
+            dir: dir.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl<D: BlockDevice + 'static> ObjectStore for FatStore<D> {
+    fn open(&mut self, name: &str, create: bool) -> Result<Box<dyn DiskImage>, Error> {
+        if name.contains('/') {
+            return Err(general_err!("invalid disk name {:?}", name));
+        }
+        if create {
+            return Err(general_err!("can't create {} on a FAT volume", name));
+        }
+        let path = format!("{}/{}", self.dir, name);
+        Ok(Box::new(FatDisk::open(self.volume.clone(), &path)?))
+    }
+}
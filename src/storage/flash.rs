@@ -0,0 +1,82 @@
+//! Flash memory for things that have to survive a power cycle, such as settings and
+//! saved states.
+//!
+//! Flash is erased a sector at a time (to 0xff) before it can be written, so a write
+//! replaces whole sectors.
+use crate::{Error, ErrorKind, Vec};
+
+/// The smallest amount of flash that can be erased
+pub const SECTOR_SIZE: usize = 4096;
+
+/// A region of flash, addressed from its start
+pub trait Flash {
+    /// The size of the region in bytes (a multiple of SECTOR_SIZE)
+    fn size(&self) -> usize;
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error>;
+    /// Erases the sectors covering `offset..offset + data.len()` and writes `data` at
+    /// `offset`, which must be the start of a sector. The rest of the last sector is
+    /// left erased.
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error>;
+}
+
+/// Checks that `len` bytes at `offset` are in a region of `size` bytes, and for
+/// writes that they start a sector
+pub(crate) fn check_range(
+    size: usize,
+    offset: usize,
+    len: usize,
+    write: bool,
+) -> Result<(), Error> {
+    if offset.checked_add(len).is_none_or(|end| end > size) {
+        return Err(err!(
+            ErrorKind::Memory,
+            None,
+            "flash: {} bytes at {:#x} is outside the {:#x} byte region",
+            len,
+            offset,
+            size
+        ));
+    }
+    if write && !offset.is_multiple_of(SECTOR_SIZE) {
+        return Err(err!(
+            ErrorKind::Memory,
+            None,
+            "flash: writes must start at a sector, not {:#x}",
+            offset
+        ));
+    }
+    Ok(())
+}
+
+/// Flash in memory, which starts out erased
+#[derive(Debug, Clone)]
+pub struct MemFlash {
+    data: Vec<u8>,
+}
+
+impl MemFlash {
+    /// A region of `sectors` sectors
+    pub fn new(sectors: usize) -> Self {
+        MemFlash {
+            data: vec![0xff; sectors * SECTOR_SIZE],
+        }
+    }
+}
+
+impl Flash for MemFlash {
+    fn size(&self) -> usize {
+        self.data.len()
+    }
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        check_range(self.data.len(), offset, buf.len(), false)?;
+        buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+        Ok(())
+    }
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        check_range(self.data.len(), offset, data.len(), true)?;
+        let erase = data.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+        self.data[offset..offset + erase].fill(0xff);
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
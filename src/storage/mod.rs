@@ -0,0 +1,78 @@
+//! Storage for ROMs and media: block devices (an SD card on the Pico), the FAT
+//! file system on them, and loading what the boot config asks for. Settings and
+//! saved states go in [flash].
+//!
+//! [MemBlockDevice] stands in for an SD card on the host so the file system and
+//! loader can be tested without hardware.
+pub mod boot;
+pub mod fat;
+pub mod flash;
+#[cfg(target_os = "none")]
+pub mod pico_flash;
+#[cfg(target_os = "none")]
+pub mod sdcard;
+
+use crate::{Error, Map};
+
+/// The size of a block on an SD card (and of a FAT sector)
+pub const BLOCK_SIZE: usize = 512;
+
+pub type Block = [u8; BLOCK_SIZE];
+
+/// A device made of fixed-size blocks addressed by logical block address (LBA)
+pub trait BlockDevice {
+    fn read_block(&mut self, lba: u32, buf: &mut Block) -> Result<(), Error>;
+    fn write_block(&mut self, lba: u32, buf: &Block) -> Result<(), Error>;
+}
+
+/// A block device in memory. Only blocks that have been written are stored so large
+/// cards cost nothing; the rest read as zeros.
+#[derive(Debug, Clone, Default)]
+pub struct MemBlockDevice {
+    blocks: Map<u32, Block>,
+    read_only: bool,
+}
+
+impl MemBlockDevice {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Creates a device holding an image of a card, e.g. one made with `dd`
+    pub fn from_image(image: &[u8]) -> Self {
+        let mut dev = Self::new();
+        for (lba, chunk) in image.chunks(BLOCK_SIZE).enumerate() {
+            let mut block = [0u8; BLOCK_SIZE];
+            block[..chunk.len()].copy_from_slice(chunk);
+            if block.iter().any(|&b| b != 0) {
+                dev.blocks.insert(lba as u32, block);
+            }
+        }
+        dev
+    }
+    /// Makes writes fail, like a card with its lock switch on
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+}
+
+impl BlockDevice for MemBlockDevice {
+    fn read_block(&mut self, lba: u32, buf: &mut Block) -> Result<(), Error> {
+        match self.blocks.get(&lba) {
+            Some(block) => buf.copy_from_slice(block),
+            None => buf.fill(0),
+        }
+        Ok(())
+    }
+    fn write_block(&mut self, lba: u32, buf: &Block) -> Result<(), Error> {
+        if self.read_only {
+            return Err(err!(
+                crate::ErrorKind::IO,
+                None,
+                "block {} is read only",
+                lba
+            ));
+        }
+        self.blocks.insert(lba, *buf);
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+//! The RP2350's own flash, the chip the firmware runs from.
+//!
+//! Reads go through the XIP window. Writes use the boot ROM's flash routines, which
+//! take the flash out of XIP mode, so they run from RAM with interrupts off. Core 1's
+//! DVI code runs from RAM (pico-dvi-rs links it there) and keeps going meanwhile.
+use super::flash::{check_range, Flash, SECTOR_SIZE};
+use crate::{Error, Vec};
+use rp235x_hal::{pac, rom_data};
+
+/// Where flash appears in the address space
+const XIP_BASE: usize = 0x1000_0000;
+/// Flash is programmed a page at a time
+const PAGE_SIZE: usize = 256;
+/// The 64K block erase command and its size, used where a write covers whole blocks
+const BLOCK_ERASE_CMD: u8 = 0xd8;
+const BLOCK_SIZE: u32 = 0x10000;
+
+pub struct PicoFlash {
+    /// the region's start, from the start of flash
+    start: usize,
+    size: usize,
+}
+
+impl PicoFlash {
+    /// The `size` bytes of flash at `start` bytes from its beginning. Both must be
+    /// multiples of SECTOR_SIZE, and the region must be clear of the firmware.
+    pub fn new(start: usize, size: usize) -> Self {
+        assert!(start.is_multiple_of(SECTOR_SIZE) && size.is_multiple_of(SECTOR_SIZE));
+        PicoFlash { start, size }
+    }
+}
+
+impl Flash for PicoFlash {
+    fn size(&self) -> usize {
+        self.size
+    }
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        check_range(self.size, offset, buf.len(), false)?;
+        let src = (XIP_BASE + self.start + offset) as *const u8;
+        // SAFETY: the range was checked to be inside the region, which is mapped
+        unsafe { core::ptr::copy_nonoverlapping(src, buf.as_mut_ptr(), buf.len()) };
+        Ok(())
+    }
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        check_range(self.size, offset, data.len(), true)?;
+        // pages are programmed whole, so pad the data with erased bytes
+        let mut pages = Vec::with_capacity(data.len().div_ceil(PAGE_SIZE) * PAGE_SIZE);
+        pages.extend_from_slice(data);
+        pages.resize(pages.capacity(), 0xff);
+        let erase = data.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+        let addr = (self.start + offset) as u32;
+        // the ROM routines are looked up now, while flash can still be read
+        let routines = Routines {
+            connect: rom_data::connect_internal_flash::ptr(),
+            exit_xip: rom_data::flash_exit_xip::ptr(),
+            erase: rom_data::flash_range_erase::ptr(),
+            program: rom_data::flash_range_program::ptr(),
+            flush: rom_data::flash_flush_cache::ptr(),
+        };
+        // SAFETY: the registers are only read, and written back as they were
+        let qmi = unsafe { &*pac::QMI::ptr() };
+        let xip_regs = [
+            qmi.m0_timing().as_ptr(),
+            qmi.m0_rfmt().as_ptr(),
+            qmi.m0_rcmd().as_ptr(),
+        ];
+        critical_section::with(|_| unsafe {
+            write_flash(&routines, &xip_regs, addr, erase, &pages);
+        });
+        Ok(())
+    }
+}
+
+struct Routines {
+    connect: unsafe extern "C" fn(),
+    exit_xip: unsafe extern "C" fn(),
+    erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    program: unsafe extern "C" fn(u32, *const u8, usize),
+    flush: unsafe extern "C" fn(),
+}
+
+/// Erases and programs flash. Nothing in here may touch flash (including this code,
+/// hence `.data`) until XIP is set up again at the end.
+#[inline(never)]
+#[link_section = ".data"]
+unsafe fn write_flash(
+    routines: &Routines,
+    xip_regs: &[*mut u32; 3],
+    addr: u32,
+    erase: usize,
+    pages: &[u8],
+) {
+    // the boot ROM leaves XIP in its slow mode afterwards, so put back how it was
+    // (without loops or iterators, which might not be inlined)
+    let timing = core::ptr::read_volatile(xip_regs[0]);
+    let rfmt = core::ptr::read_volatile(xip_regs[1]);
+    let rcmd = core::ptr::read_volatile(xip_regs[2]);
+    (routines.connect)();
+    (routines.exit_xip)();
+    (routines.erase)(addr, erase, BLOCK_SIZE, BLOCK_ERASE_CMD);
+    (routines.program)(addr, pages.as_ptr(), pages.len());
+    (routines.flush)();
+    core::ptr::write_volatile(xip_regs[0], timing);
+    core::ptr::write_volatile(xip_regs[1], rfmt);
+    core::ptr::write_volatile(xip_regs[2], rcmd);
+}
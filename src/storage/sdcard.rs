@@ -0,0 +1,233 @@
+//! An SD card in SPI mode.
+//!
+//! The card has to be initialized with the SPI clock at 400kHz or less; it can be
+//! raised (to 25MHz at most) once [SdCard::new] returns, through [SdCard::spi].
+use super::{Block, BlockDevice, BLOCK_SIZE};
+use crate::{Error, ErrorKind};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::SpiBus;
+
+// commands
+const CMD0: u8 = 0; // GO_IDLE_STATE
+const CMD8: u8 = 8; // SEND_IF_COND
+const CMD16: u8 = 16; // SET_BLOCKLEN
+const CMD17: u8 = 17; // READ_SINGLE_BLOCK
+const CMD24: u8 = 24; // WRITE_BLOCK
+const CMD55: u8 = 55; // APP_CMD
+const CMD58: u8 = 58; // READ_OCR
+const ACMD41: u8 = 41; // SD_SEND_OP_COND
+
+// R1 response bits
+const R1_IDLE: u8 = 0x01;
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+
+// data tokens
+const DATA_START: u8 = 0xfe;
+const DATA_ACCEPTED: u8 = 0x05;
+
+/// How many bytes to wait for a response or for the card to be ready
+const TIMEOUT: usize = 100_000;
+
+fn sd_err(msg: &str) -> Error {
+    err!(ErrorKind::IO, None, "SD card: {}", msg)
+}
+
+pub struct SdCard<SPI: SpiBus, CS: OutputPin> {
+    spi: SPI,
+    cs: CS,
+    /// SDHC and SDXC cards are addressed by block, older cards by byte
+    block_addressing: bool,
+}
+
+impl<SPI: SpiBus, CS: OutputPin> SdCard<SPI, CS> {
+    /// Initializes the card on `spi`, selected by `cs`
+    pub fn new(spi: SPI, cs: CS) -> Result<Self, Error> {
+        let mut card = SdCard {
+            spi,
+            cs,
+            block_addressing: false,
+        };
+        card.init()?;
+        Ok(card)
+    }
+    /// The SPI bus, e.g. to raise its clock after initialization
+    pub fn spi(&mut self) -> &mut SPI {
+        &mut self.spi
+    }
+    fn transfer(&mut self, byte: u8) -> Result<u8, Error> {
+        let mut buf = [byte];
+        self.spi
+            .transfer_in_place(&mut buf)
+            .map_err(|_| sd_err("SPI error"))?;
+        Ok(buf[0])
+    }
+    fn select(&mut self, selected: bool) -> Result<(), Error> {
+        let result = if selected {
+            self.cs.set_low()
+        } else {
+            self.cs.set_high()
+        };
+        result.map_err(|_| sd_err("chip select error"))?;
+        // the card needs clocks to release the bus after it's deselected
+        if !selected {
+            self.transfer(0xff)?;
+        }
+        Ok(())
+    }
+    fn wait_ready(&mut self) -> Result<(), Error> {
+        for _ in 0..TIMEOUT {
+            if self.transfer(0xff)? == 0xff {
+                return Ok(());
+            }
+        }
+        Err(sd_err("timed out waiting for the card"))
+    }
+    /// Sends a command and returns its R1 response
+    fn command(&mut self, cmd: u8, arg: u32) -> Result<u8, Error> {
+        if cmd != CMD0 {
+            self.wait_ready()?;
+        }
+        // only CMD0 and CMD8 are checked in SPI mode
+        let crc = match cmd {
+            CMD0 => 0x95,
+            CMD8 => 0x87,
+            _ => 0x01,
+        };
+        let [a, b, c, d] = arg.to_be_bytes();
+        for byte in [0x40 | cmd, a, b, c, d, crc] {
+            self.transfer(byte)?;
+        }
+        for _ in 0..10 {
+            let r1 = self.transfer(0xff)?;
+            if r1 & 0x80 == 0 {
+                return Ok(r1);
+            }
+        }
+        Err(sd_err("no response"))
+    }
+    fn init(&mut self) -> Result<(), Error> {
+        // at least 74 clocks with the card deselected to put it in SPI mode
+        self.cs
+            .set_high()
+            .map_err(|_| sd_err("chip select error"))?;
+        for _ in 0..10 {
+            self.transfer(0xff)?;
+        }
+        self.select(true)?;
+        let result = self.init_selected();
+        self.select(false)?;
+        result
+    }
+    fn init_selected(&mut self) -> Result<(), Error> {
+        if self.command(CMD0, 0)? != R1_IDLE {
+            return Err(sd_err("no card"));
+        }
+        let v2 = self.command(CMD8, 0x1aa)? & R1_ILLEGAL_COMMAND == 0;
+        if v2 {
+            let mut r7 = [0u8; 4];
+            for b in r7.iter_mut() {
+                *b = self.transfer(0xff)?;
+            }
+            if r7[3] != 0xaa {
+                return Err(sd_err("unusable card"));
+            }
+        }
+        // ask high capacity cards to say so
+        let hcs = if v2 { 0x4000_0000 } else { 0 };
+        let mut ready = false;
+        for _ in 0..TIMEOUT / 10 {
+            self.command(CMD55, 0)?;
+            if self.command(ACMD41, hcs)? == 0 {
+                ready = true;
+                break;
+            }
+        }
+        if !ready {
+            return Err(sd_err("timed out initializing"));
+        }
+        if v2 {
+            if self.command(CMD58, 0)? != 0 {
+                return Err(sd_err("can't read OCR"));
+            }
+            let ocr = self.transfer(0xff)?;
+            for _ in 0..3 {
+                self.transfer(0xff)?;
+            }
+            self.block_addressing = ocr & 0x40 != 0;
+        }
+        if !self.block_addressing && self.command(CMD16, BLOCK_SIZE as u32)? != 0 {
+            return Err(sd_err("can't set the block size"));
+        }
+        Ok(())
+    }
+    fn address(&self, lba: u32) -> u32 {
+        if self.block_addressing {
+            lba
+        } else {
+            lba * BLOCK_SIZE as u32
+        }
+    }
+    fn read_selected(&mut self, lba: u32, buf: &mut Block) -> Result<(), Error> {
+        if self.command(CMD17, self.address(lba))? != 0 {
+            return Err(sd_err("read refused"));
+        }
+        let mut token = 0xff;
+        for _ in 0..TIMEOUT {
+            token = self.transfer(0xff)?;
+            if token != 0xff {
+                break;
+            }
+        }
+        if token != DATA_START {
+            return Err(err!(
+                ErrorKind::IO,
+                None,
+                "SD card: can't read block {}",
+                lba
+            ));
+        }
+        buf.fill(0xff);
+        self.spi
+            .transfer_in_place(buf)
+            .map_err(|_| sd_err("SPI error"))?;
+        // CRC
+        self.transfer(0xff)?;
+        self.transfer(0xff)?;
+        Ok(())
+    }
+    fn write_selected(&mut self, lba: u32, buf: &Block) -> Result<(), Error> {
+        if self.command(CMD24, self.address(lba))? != 0 {
+            return Err(sd_err("write refused"));
+        }
+        self.transfer(0xff)?;
+        self.transfer(DATA_START)?;
+        self.spi.write(buf).map_err(|_| sd_err("SPI error"))?;
+        // CRC (not checked)
+        self.transfer(0xff)?;
+        self.transfer(0xff)?;
+        if self.transfer(0xff)? & 0x1f != DATA_ACCEPTED {
+            return Err(err!(
+                ErrorKind::IO,
+                None,
+                "SD card: can't write block {}",
+                lba
+            ));
+        }
+        self.wait_ready()
+    }
+}
+
+impl<SPI: SpiBus, CS: OutputPin> BlockDevice for SdCard<SPI, CS> {
+    fn read_block(&mut self, lba: u32, buf: &mut Block) -> Result<(), Error> {
+        self.select(true)?;
+        let result = self.read_selected(lba, buf);
+        self.select(false)?;
+        result
+    }
+    fn write_block(&mut self, lba: u32, buf: &Block) -> Result<(), Error> {
+        self.select(true)?;
+        let result = self.write_selected(lba, buf);
+        self.select(false)?;
+        result
+    }
+}
@@ -0,0 +1,585 @@
+use crate::cpu_test::create_core;
+use crate::drivewire::{self, Server, SECTOR_SIZE};
+use crate::serial::SerialBackend;
+use crate::storage::boot::{self, BootConfig, CodeEntry, RomEntry};
+use crate::storage::fat::{FatDisk, FatKind, FatStore, FatVolume};
+use crate::storage::{Block, BlockDevice, MemBlockDevice, BLOCK_SIZE};
+use crate::*;
+use alloc::vec;
+use core::cell::RefCell;
+
+/// Writes `data` at byte `offset` of `dev`
+fn put(dev: &mut MemBlockDevice, offset: usize, data: &[u8]) {
+    let mut block: Block = [0; BLOCK_SIZE];
+    for (i, &b) in data.iter().enumerate() {
+        let (lba, at) = ((offset + i) / BLOCK_SIZE, (offset + i) % BLOCK_SIZE);
+        dev.read_block(lba as u32, &mut block).unwrap();
+        block[at] = b;
+        dev.write_block(lba as u32, &block).unwrap();
+    }
+}
+
+/// Lays out a FAT volume the way a formatter would, with files stored one after the
+/// other. Files can be in one level of directories ("dir/file").
+pub(crate) struct Formatter {
+    dev: MemBlockDevice,
+    kind: FatKind,
+    // byte offsets
+    fat: usize,
+    root: usize,
+    data: usize,
+    cluster_size: usize,
+    next_cluster: u32,
+}
+
+impl Formatter {
+    pub(crate) fn new(kind: FatKind, partitioned: bool) -> Self {
+        // (total blocks, blocks per cluster, reserved blocks, root entries, blocks per FAT)
+        let (total, spc, reserved, root_entries, fat_blocks) = match kind {
+            FatKind::Fat12 => (2048u32, 1u8, 1u16, 64u16, 6u32),
+            FatKind::Fat16 => (16384, 2, 1, 512, 32),
+            FatKind::Fat32 => (70000, 1, 32, 0, 548),
+        };
+        let mut dev = MemBlockDevice::new();
+        let start_lba = if partitioned { 63 } else { 0 };
+        if partitioned {
+            let mut entry = [0u8; 16];
+            entry[4] = if kind == FatKind::Fat32 { 0x0c } else { 0x06 };
+            entry[8..12].copy_from_slice(&(start_lba as u32).to_le_bytes());
+            entry[12..16].copy_from_slice(&total.to_le_bytes());
+            put(&mut dev, 446 + 16, &entry);
+            put(&mut dev, 510, &[0x55, 0xaa]);
+        }
+        let start = start_lba * BLOCK_SIZE;
+        let mut bpb = vec![0u8; BLOCK_SIZE];
+        bpb[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        bpb[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+        bpb[13] = spc;
+        bpb[14..16].copy_from_slice(&reserved.to_le_bytes());
+        bpb[16] = 2;
+        bpb[17..19].copy_from_slice(&root_entries.to_le_bytes());
+        bpb[32..36].copy_from_slice(&total.to_le_bytes());
+        if kind == FatKind::Fat32 {
+            bpb[36..40].copy_from_slice(&fat_blocks.to_le_bytes());
+            bpb[44..48].copy_from_slice(&2u32.to_le_bytes());
+        } else {
+            bpb[22..24].copy_from_slice(&(fat_blocks as u16).to_le_bytes());
+        }
+        bpb[510..].copy_from_slice(&[0x55, 0xaa]);
+        put(&mut dev, start, &bpb);
+        let fat = start + reserved as usize * BLOCK_SIZE;
+        let root = fat + 2 * fat_blocks as usize * BLOCK_SIZE;
+        let data = root + root_entries as usize * 32;
+        let mut f = Formatter {
+            dev,
+            kind,
+            fat,
+            root,
+            data,
+            cluster_size: spc as usize * BLOCK_SIZE,
+            next_cluster: 2,
+        };
+        if kind == FatKind::Fat32 {
+            // the root directory's cluster
+            f.allocate(f.cluster_size);
+        }
+        f
+    }
+    fn set_fat(&mut self, cluster: u32, value: u32) {
+        match self.kind {
+            FatKind::Fat12 => {
+                let offset = self.fat + (cluster + cluster / 2) as usize;
+                let mut pair = [0u8; 2];
+                let mut block: Block = [0; BLOCK_SIZE];
+                for (i, b) in pair.iter_mut().enumerate() {
+                    let at = offset + i;
+                    self.dev
+                        .read_block((at / BLOCK_SIZE) as u32, &mut block)
+                        .unwrap();
+                    *b = block[at % BLOCK_SIZE];
+                }
+                let old = u16::from_le_bytes(pair);
+                let new = if cluster & 1 == 1 {
+                    (old & 0x000f) | (value as u16) << 4
+                } else {
+                    (old & 0xf000) | value as u16 & 0xfff
+                };
+                put(&mut self.dev, offset, &new.to_le_bytes());
+            }
+            FatKind::Fat16 => {
+                let offset = self.fat + cluster as usize * 2;
+                put(&mut self.dev, offset, &(value as u16).to_le_bytes());
+            }
+            FatKind::Fat32 => {
+                let offset = self.fat + cluster as usize * 4;
+                put(&mut self.dev, offset, &value.to_le_bytes());
+            }
+        }
+    }
+    /// Allocates a chain of clusters for `len` bytes and returns the first
+    fn allocate(&mut self, len: usize) -> u32 {
+        let count = len.div_ceil(self.cluster_size).max(1) as u32;
+        let first = self.next_cluster;
+        for c in first..first + count {
+            let last = c == first + count - 1;
+            self.set_fat(c, if last { 0x0fff_ffff } else { c + 1 });
+        }
+        self.next_cluster += count;
+        first
+    }
+    fn cluster_offset(&self, cluster: u32) -> usize {
+        self.data + (cluster as usize - 2) * self.cluster_size
+    }
+    /// Writes directory entries for `name` at `at` and returns the offset after them
+    fn dir_entry(&mut self, at: usize, name: &str, attr: u8, cluster: u32, size: u32) -> usize {
+        let (stem, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+        let fits = stem.len() <= 8
+            && ext.len() <= 3
+            && !stem.contains(' ')
+            && name.to_ascii_uppercase() == name;
+        let mut short = [b' '; 11];
+        let stem = stem.to_ascii_uppercase().replace(' ', "");
+        if name.starts_with('.') {
+            // . and ..
+            short[..name.len()].copy_from_slice(name.as_bytes());
+        } else if fits {
+            short[..stem.len()].copy_from_slice(stem.as_bytes());
+        } else {
+            let alias = format!("{}~1", &stem[..stem.len().min(6)]);
+            short[..alias.len()].copy_from_slice(alias.as_bytes());
+        }
+        let ext = ext.to_ascii_uppercase();
+        short[8..8 + ext.len().min(3)].copy_from_slice(&ext.as_bytes()[..ext.len().min(3)]);
+        let mut at = at;
+        if !fits {
+            let sum = short
+                .iter()
+                .fold(0u8, |s, &b| s.rotate_right(1).wrapping_add(b));
+            let mut units: Vec<u16> = name.encode_utf16().collect();
+            units.push(0);
+            let parts = units.chunks(13).collect::<Vec<_>>();
+            for (i, part) in parts.iter().enumerate().rev() {
+                let mut e = [0u8; 32];
+                e[0] = (i + 1) as u8 | if i == parts.len() - 1 { 0x40 } else { 0 };
+                e[11] = 0x0f;
+                e[13] = sum;
+                let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+                for (j, &o) in offsets.iter().enumerate() {
+                    let c = part.get(j).copied().unwrap_or(0xffff);
+                    e[o..o + 2].copy_from_slice(&c.to_le_bytes());
+                }
+                put(&mut self.dev, at, &e);
+                at += 32;
+            }
+        }
+        let mut e = [0u8; 32];
+        e[..11].copy_from_slice(&short);
+        e[11] = attr;
+        e[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        e[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        e[28..32].copy_from_slice(&size.to_le_bytes());
+        put(&mut self.dev, at, &e);
+        at + 32
+    }
+    /// Adds files, each with its attributes
+    pub(crate) fn format(mut self, files: &[(&str, &[u8], u8)]) -> MemBlockDevice {
+        let mut root_at = match self.kind {
+            FatKind::Fat32 => self.cluster_offset(2),
+            _ => self.root,
+        };
+        let mut dirs: Vec<(String, usize)> = Vec::new();
+        for &(path, data, attr) in files {
+            let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+            let cluster = self.allocate(data.len());
+            let offset = self.cluster_offset(cluster);
+            put(&mut self.dev, offset, data);
+            let at = if dir.is_empty() {
+                &mut root_at
+            } else {
+                if !dirs.iter().any(|(d, _)| d == dir) {
+                    let dir_cluster = self.allocate(self.cluster_size);
+                    root_at = self.dir_entry(root_at, dir, 0x10, dir_cluster, 0);
+                    let mut at = self.cluster_offset(dir_cluster);
+                    at = self.dir_entry(at, ".", 0x10, dir_cluster, 0);
+                    at = self.dir_entry(at, "..", 0x10, 0, 0);
+                    dirs.push((dir.into(), at));
+                }
+                &mut dirs.iter_mut().find(|(d, _)| d == dir).unwrap().1
+            };
+            *at = self.dir_entry(*at, name, attr, cluster, data.len() as u32);
+        }
+        self.dev
+    }
+}
+
+/// `len` bytes counting up from `seed`
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_add(seed)).collect()
+}
+
+#[test]
+fn test_mem_block_device() {
+    let mut image = vec![0u8; 3 * BLOCK_SIZE];
+    image[BLOCK_SIZE + 1] = 0x42;
+    let mut dev = MemBlockDevice::from_image(&image);
+    let mut block: Block = [0xff; BLOCK_SIZE];
+    dev.read_block(1, &mut block).unwrap();
+    assert_eq!(block[1], 0x42);
+    // far past the image
+    dev.read_block(1_000_000, &mut block).unwrap();
+    assert_eq!(block, [0; BLOCK_SIZE]);
+    dev.set_read_only(true);
+    assert!(dev.write_block(0, &block).is_err());
+}
+
+#[test]
+fn test_fat_volumes() {
+    let big = pattern(5000, 7);
+    let files: &[(&str, &[u8], u8)] = &[
+        ("BASIC.ROM", &[0x12; 8192], 0),
+        ("Extended Color Basic.rom", &big, 0),
+        ("disks/NOS9.DSK", &[0xaa; 600], 0),
+        ("empty.txt", b"", 0),
+    ];
+    for kind in [FatKind::Fat12, FatKind::Fat16, FatKind::Fat32] {
+        for partitioned in [false, true] {
+            let what = format!("{:?} partitioned={}", kind, partitioned);
+            let dev = Formatter::new(kind, partitioned).format(files);
+            let mut fs = FatVolume::mount(dev).unwrap();
+            assert_eq!(fs.kind(), kind, "{}", what);
+
+            let names = fs
+                .read_dir("/")
+                .unwrap()
+                .into_iter()
+                .map(|e| (e.name, e.is_dir))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                names,
+                [
+                    ("BASIC.ROM".into(), false),
+                    ("Extended Color Basic.rom".into(), false),
+                    ("disks".into(), true),
+                    ("empty.txt".into(), false)
+                ],
+                "{}",
+                what
+            );
+            assert_eq!(fs.read_dir("disks").unwrap()[0].size, 600);
+
+            assert_eq!(fs.read_file("basic.rom").unwrap(), [0x12; 8192]);
+            assert_eq!(fs.read_file("EXTENDED COLOR BASIC.ROM").unwrap(), big);
+            assert_eq!(fs.read_file("/disks/nos9.dsk").unwrap(), [0xaa; 600]);
+            assert!(fs.read_file("empty.txt").unwrap().is_empty());
+            assert!(fs.exists("disks"));
+            assert!(!fs.exists("missing.rom"));
+            assert!(fs.read_file("missing.rom").is_err());
+            assert!(fs.read_file("disks").is_err());
+            assert!(fs.read_file("BASIC.ROM/x").is_err());
+
+            // partial reads and writes across block and cluster boundaries
+            let file = fs.open("Extended Color Basic.rom").unwrap();
+            let mut buf = [0u8; 1000];
+            assert_eq!(fs.read(&file, 300, &mut buf).unwrap(), 1000);
+            assert_eq!(buf[..], big[300..1300]);
+            assert_eq!(fs.read(&file, 4500, &mut buf).unwrap(), 500);
+            fs.write(&file, 1000, &[0x5a; 1200]).unwrap();
+            assert!(fs.write(&file, 4900, &[0; 101]).is_err());
+            let mut expected = big.clone();
+            expected[1000..2200].fill(0x5a);
+            assert_eq!(fs.read_file("Extended Color Basic.rom").unwrap(), expected);
+        }
+    }
+    assert!(FatVolume::mount(MemBlockDevice::new()).is_err());
+}
+
+#[test]
+fn test_fat_disks() {
+    let files: &[(&str, &[u8], u8)] = &[
+        ("disks/NOS9.DSK", &pattern(4 * SECTOR_SIZE, 0), 0),
+        ("disks/GAMES.DSK", &[0x11; SECTOR_SIZE], 0x01),
+    ];
+    let dev = Formatter::new(FatKind::Fat16, true).format(files);
+    let fs = Rc::new(RefCell::new(FatVolume::mount(dev).unwrap()));
+    let mut server = Server::new();
+    server.mount(
+        0,
+        Box::new(FatDisk::open(fs.clone(), "disks/nos9.dsk").unwrap()),
+    );
+    server.set_object_store(Box::new(FatStore::new(fs.clone(), "disks/")));
+    let send = |server: &mut Server, bytes: &[u8]| {
+        bytes.iter().for_each(|&b| server.write_byte(b));
+        core::iter::from_fn(|| server.read_byte()).collect::<Vec<u8>>()
+    };
+
+    let sector = send(&mut server, &[drivewire::OP_READEX, 0, 0, 0, 1]);
+    assert_eq!(sector, pattern(SECTOR_SIZE, 0));
+    assert_eq!(
+        send(&mut server, &drivewire::checksum(&sector).to_be_bytes()),
+        [drivewire::E_OK]
+    );
+    let write = |drive: u8, lsn: u8| {
+        let mut req = vec![drivewire::OP_WRITE, drive, 0, 0, lsn];
+        req.extend([0x77; SECTOR_SIZE]);
+        req.extend(drivewire::checksum(&[0x77; SECTOR_SIZE]).to_be_bytes());
+        req
+    };
+    assert_eq!(send(&mut server, &write(0, 2)), [drivewire::E_OK]);
+    // disk images on the card can't grow
+    assert_eq!(send(&mut server, &write(0, 4)), [drivewire::E_WRITE]);
+
+    // mounted by name from disks/, write protected by its read-only attribute
+    let mut mount = vec![drivewire::OP_NAMEOBJ_MOUNT, 9];
+    mount.extend(b"GAMES.DSK");
+    assert_eq!(send(&mut server, &mount), [1]);
+    assert_eq!(send(&mut server, &write(1, 0)), [drivewire::E_WP]);
+    mount[0] = drivewire::OP_NAMEOBJ_CREATE;
+    mount[2] = b'N';
+    assert_eq!(send(&mut server, &mount), [0]);
+
+    drop(server);
+    let data = fs.borrow_mut().read_file("disks/NOS9.DSK").unwrap();
+    assert_eq!(data[2 * SECTOR_SIZE..3 * SECTOR_SIZE], [0x77; SECTOR_SIZE]);
+}
+
+#[test]
+fn test_boot_config() {
+    let config = BootConfig::parse(
+        "---
+# ROMs
+load_rom:
+  - path: \"BASIC.ROM\"   # Color BASIC
+    addr: 0xa000
+  - path: 'EXT #2.ROM'
+    addr: $8000
+  -
+    path: ROM3
+    addr: 49152
+  - path: EXTBASIC.ROM
+machine: Dragon 32
+disks:
+- nos9.dsk
+- \"games/GAMES.DSK\"
+load_code:
+  - path: hello.asm
+  - path: GAME.BIN
+log:
+  level: warn
+  acia: debug
+",
+    )
+    .unwrap();
+    assert_eq!(
+        config.load_rom,
+        [
+            RomEntry {
+                path: "BASIC.ROM".into(),
+                addr: Some(0xa000)
+            },
+            RomEntry {
+                path: "EXT #2.ROM".into(),
+                addr: Some(0x8000)
+            },
+            RomEntry {
+                path: "ROM3".into(),
+                addr: Some(0xc000)
+            },
+            RomEntry {
+                path: "EXTBASIC.ROM".into(),
+                addr: None
+            }
+        ]
+    );
+    assert_eq!(config.machine, Some(romdb::Profile::Dragon32));
+    assert_eq!(config.disks, ["nos9.dsk", "games/GAMES.DSK"]);
+    assert_eq!(
+        config.load_code,
+        [
+            CodeEntry {
+                path: "hello.asm".into()
+            },
+            CodeEntry {
+                path: "GAME.BIN".into()
+            }
+        ]
+    );
+    assert_eq!(config.log.level.as_deref(), Some("warn"));
+    assert_eq!(config.log.acia.as_deref(), Some("debug"));
+
+    // the coco.yaml in the repo, with the ROMs commented out
+    let config = BootConfig::parse(include_str!("../coco.yaml")).unwrap();
+    assert!(config.load_rom.is_empty());
+    assert!(config.machine.is_none());
+    assert_eq!(config.load_code.len(), 1);
+
+    for bad in [
+        "load_rom:\n  - path: BASIC.ROM\n    addr: 0x1ffff\n",
+        "load_rom:\n  - path: BASIC.ROM\n    adr: 0xa000\n",
+        "load_rom:\n  - BASIC.ROM\n",
+        "machine: coco4\n",
+        "disks: nos9.dsk\n",
+        // keys that aren't part of the config
+        "cart: A.ROM\n",
+        "log:\n  level: warn\ncassette: GAME.CAS\n",
+        "load_code A.ASM\n",
+        "log:\n\tlevel: warn\n",
+        "- A.ROM\n",
+    ] {
+        assert!(BootConfig::parse(bad).is_err(), "{:?}", bad);
+    }
+}
+
+#[test]
+fn test_boot() {
+    let mut basic = vec![0x39; 0x2000];
+    // reset vector at $BFFE
+    basic[0x1ffe..].copy_from_slice(&[0xa0, 0x27]);
+    let config = b"load_rom:
+  - path: roms/BASIC.ROM
+    addr: 0xa000
+  - path: roms/GAME.CCC
+    addr: 0xc000
+disks:
+  - NOS9.DSK
+";
+    let files: &[(&str, &[u8], u8)] = &[
+        ("coco.yaml", config, 0),
+        ("roms/BASIC.ROM", &basic, 0),
+        ("roms/GAME.CCC", &[0x44; 0x2000], 0),
+        ("NOS9.DSK", &[0x55; 2 * SECTOR_SIZE], 0),
+    ];
+    let dev = Formatter::new(FatKind::Fat32, true).format(files);
+    let fs = Rc::new(RefCell::new(FatVolume::mount(dev).unwrap()));
+    let mut core = create_core();
+    let mut media = boot::boot(fs.clone(), &mut core).unwrap();
+    assert_eq!(media.config.load_rom.len(), 2);
+    // an unknown ROM at $C000 is a cartridge
+    assert_eq!(media.cart.as_deref(), Some("roms/GAME.CCC"));
+    assert!(media.server.is_mounted(0) && !media.server.is_mounted(1));
+    media.server.write_byte(drivewire::OP_READEX);
+    for _ in 0..4 {
+        media.server.write_byte(0);
+    }
+    assert_eq!(media.server.read_byte(), Some(0x55));
+
+    core.reset().unwrap();
+    assert_eq!(core.reg.pc, 0xa027);
+    let peek = |core: &mut Core, addr| {
+        core._read_u8(memory::AccessType::System, addr, None)
+            .unwrap()
+    };
+    assert_eq!(peek(&mut core, 0xa000), 0x39);
+    assert_eq!(peek(&mut core, 0xc000), 0x44);
+    assert_eq!(peek(&mut core, 0xdfff), 0x44);
+
+    // everything listed has to be there
+    let dev = Formatter::new(FatKind::Fat16, false).format(&files[..2]);
+    let fs = Rc::new(RefCell::new(FatVolume::mount(dev).unwrap()));
+    assert!(boot::boot(fs, &mut create_core()).is_err());
+    let dev = Formatter::new(FatKind::Fat16, false).format(&files[1..]);
+    let fs = Rc::new(RefCell::new(FatVolume::mount(dev).unwrap()));
+    assert!(boot::boot(fs, &mut create_core()).is_err());
+}
+
+#[test]
+fn test_boot_code() {
+    let mut image = image::Image::new();
+    image.add(0x4000, &[0x12, 0x39]).unwrap();
+    image.exec = Some(0x4001);
+    let bin = decb::write(&image);
+    let files: &[(&str, &[u8], u8)] = &[
+        (
+            "coco.yaml",
+            b"load_code:\n  - path: hello.asm\n  - path: GAME.BIN\n",
+            0,
+        ),
+        (
+            "hello.asm",
+            b"    org $3000\ndata fcb 7\nstart lda data\n    rts\n    end start\n",
+            0,
+        ),
+        ("GAME.BIN", &bin, 0),
+    ];
+    let dev = Formatter::new(FatKind::Fat16, false).format(files);
+    let fs = Rc::new(RefCell::new(FatVolume::mount(dev).unwrap()));
+    let mut core = create_core();
+    let media = boot::boot(fs, &mut core).unwrap();
+    assert!(media.cart.is_none());
+    assert_eq!(core.raw_ram[0x3000..0x3002], [7, 0xb6]);
+    assert_eq!(core.raw_ram[0x4000..0x4002], [0x12, 0x39]);
+    // the last program with an entry point is where the machine starts
+    core.reset().unwrap();
+    assert_eq!(core.reg.pc, 0x4001);
+
+    let dev = Formatter::new(FatKind::Fat16, false).format(&[
+        ("coco.yaml", b"load_code:\n  - path: GAME.CAS\n", 0),
+        ("GAME.CAS", &[0x55; 16], 0),
+    ]);
+    let fs = Rc::new(RefCell::new(FatVolume::mount(dev).unwrap()));
+    assert!(boot::boot(fs, &mut create_core()).is_err());
+}
+
+#[test]
+fn test_boot_rom_set() {
+    // ROMs that look like BASIC but aren't known dumps
+    let mut basic = vec![0x39; 0x2000];
+    basic[0x100..0x10b].copy_from_slice(b"COLOR BASIC");
+    let mut ext = vec![0x12; 0x2000];
+    ext[..2].copy_from_slice(b"EX");
+    let files: &[(&str, &[u8], u8)] = &[
+        (
+            "coco.yaml",
+            b"load_rom:\n  - path: BASIC.ROM\n  - path: EXT.ROM\n    addr: 0xa000\n",
+            0,
+        ),
+        ("BASIC.ROM", &basic, 0),
+        ("EXT.ROM", &ext, 0),
+    ];
+    let dev = Formatter::new(FatKind::Fat16, false).format(files);
+    let fs = Rc::new(RefCell::new(FatVolume::mount(dev).unwrap()));
+    let mut core = create_core();
+    let media = boot::boot(fs, &mut core).unwrap();
+    // both go where they belong
+    assert_eq!(&core.raw_ram[0xa100..0xa10b], b"COLOR BASIC");
+    assert_eq!(&core.raw_ram[0x8000..0x8003], b"EX\x12");
+    assert_eq!(media.profile, romdb::Profile::CoCo2);
+    assert_eq!(media.warnings.len(), 3, "{:?}", media.warnings);
+    assert!(media.warnings[0].starts_with("BASIC.ROM looks like Color BASIC"));
+    assert!(media.warnings[1].starts_with("EXT.ROM looks like Extended BASIC"));
+    assert_eq!(
+        media.warnings[2],
+        "EXT.ROM is Extended BASIC, which goes at 8000, not A000"
+    );
+
+    // a Dragon needs its own ROM, and its keyboard is wired differently
+    let files: &[(&str, &[u8], u8)] = &[
+        (
+            "coco.yaml",
+            b"machine: dragon\nload_rom:\n  - path: BASIC.ROM\n",
+            0,
+        ),
+        ("BASIC.ROM", &basic, 0),
+    ];
+    let dev = Formatter::new(FatKind::Fat16, false).format(files);
+    let fs = Rc::new(RefCell::new(FatVolume::mount(dev).unwrap()));
+    let mut core = create_core();
+    let media = boot::boot(fs, &mut core).unwrap();
+    assert_eq!(media.profile, romdb::Profile::Dragon32);
+    assert_eq!(core.pia0.lock().get_layout(), pia::Layout::Dragon);
+    assert!(media
+        .warnings
+        .iter()
+        .any(|w| w.starts_with("there's no Dragon BASIC ROM")));
+    assert!(media
+        .warnings
+        .iter()
+        .any(|w| w == "Color BASIC is for the CoCo, not the Dragon"));
+
+    // an unknown ROM needs an address
+    let dev = Formatter::new(FatKind::Fat16, false).format(&[
+        ("coco.yaml", b"load_rom:\n  - path: A.ROM\n", 0),
+        ("A.ROM", &[0u8; 0x2000], 0),
+    ]);
+    let fs = Rc::new(RefCell::new(FatVolume::mount(dev).unwrap()));
+    assert!(boot::boot(fs, &mut create_core()).is_err());
+}
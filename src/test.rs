@@ -32,36 +32,226 @@
 //! - `;! label = a` Passes if byte at address _label_ equals value of register A
 //! - `;! b = #'C` Passes if register B holds the value of ascii char 'C' (0x43)
 //!
-use crate::registers;
+//! Criteria may also use one of the comparison operators `!=`, `<`, `<=`, `>` or `>=`
+//! in place of `=`.
+//!
+//! Execution counters can be checked with `cycles` or `instructions` on the LHS and a
+//! plain count on the RHS. Counts exclude the final EXIT instruction.
+//! - `;! cycles <= 1200` Passes if the whole program took no more than 1200 clock cycles
+//! - `;! instructions = 42` Passes if the program executed exactly 42 instructions
+//! - `;! cycles(START,DONE) = 345` Passes if 345 cycles elapsed between the first time the
+//!   instruction at _START_ was about to execute and the next time the instruction at _DONE_
+//!   was about to execute
+//!
+//! Memory ranges can be compared with inline bytes, an FCC-style string (delimited by `"` or `/`)
+//! or another range of the same length. Only `=` and `!=` may be used with ranges.
+//! - `;! msg = "HELLO"` Passes if the 5 bytes at _msg_ hold the ascii string HELLO
+//! - `;! buf = #1,2,$ff` Passes if the 3 bytes at _buf_ are 1, 2 and $FF (values above $FF are 16-bit words)
+//! - `;! mem(buf,16) = copy` Passes if the 16 bytes at _buf_ match the 16 bytes at _copy_
+//! - `;! mem(buf,3) = #0,0,0` Passes if the 3 bytes at _buf_ are all zero
+//! - `;! screen(0,2) = "HELLO"` Passes if the text screen shows HELLO at column 0 of row 2
+//!   (the expected text is converted to VDG character codes and must fit on the row; the screen
+//!   starts wherever the SAM points)
+//!
+//! Individual condition code bits can be checked by letter or by name:
+//! - `;! cc.z = 1` Passes if the zero flag is set
+//! - `;! cc.carry = 0` Passes if the carry flag is clear
+//!
+use crate::{memory, registers, u8u16, Core, Error, ErrorKind, Program};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use core::fmt;
-use alloc::string::String;
+
+/// The comparison made by a test criterion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+impl CmpOp {
+    pub fn from_symbol(s: &str) -> Option<CmpOp> {
+        match s {
+            "=" | "==" => Some(CmpOp::Eq),
+            "!=" | "<>" => Some(CmpOp::Ne),
+            "<" => Some(CmpOp::Lt),
+            "<=" => Some(CmpOp::Le),
+            ">" => Some(CmpOp::Gt),
+            ">=" => Some(CmpOp::Ge),
+            _ => None,
+        }
+    }
+    pub fn holds<T: Ord>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            CmpOp::Eq => lhs == rhs,
+            CmpOp::Ne => lhs != rhs,
+            CmpOp::Lt => lhs < rhs,
+            CmpOp::Le => lhs <= rhs,
+            CmpOp::Gt => lhs > rhs,
+            CmpOp::Ge => lhs >= rhs,
+        }
+    }
+}
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            CmpOp::Eq => "=",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Execution counters that can be checked by a test criterion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    Cycles,
+    Instructions,
+}
+impl Counter {
+    /// Splits an LHS of the form `cycles` or `cycles(START,DONE)` into the counter
+    /// and the (unparsed) span arguments. Returns None if the LHS isn't a counter.
+    pub fn from_lhs(lhs: &str) -> Option<(Counter, Option<&str>)> {
+        let (name, args) = match lhs.split_once('(') {
+            Some((name, rest)) => (name, Some(rest.strip_suffix(')')?)),
+            None => (lhs, None),
+        };
+        if name.eq_ignore_ascii_case("cycles") {
+            Some((Counter::Cycles, args))
+        } else if name.eq_ignore_ascii_case("instructions") {
+            Some((Counter::Instructions, args))
+        } else {
+            None
+        }
+    }
+}
+impl fmt::Display for Counter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Counter::Cycles => f.write_str("cycles"),
+            Counter::Instructions => f.write_str("instructions"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum RegOrAddr {
     Reg(registers::Name),
     Addr(u16),
+    Counter(Counter, Option<(u16, u16)>), // counter for the whole program or for a START..DONE span
+    Range(u16, u16),                      // memory starting at an address (start, length)
+    Screen(u8, u8),                       // text screen memory starting at (column, row)
+    CC(registers::CCBit),                 // a single condition code bit
 }
 impl fmt::Display for RegOrAddr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             RegOrAddr::Reg(r) => write!(f, "{:?}", r),
             RegOrAddr::Addr(a) => write!(f, "${:04X}", a),
+            RegOrAddr::Counter(c, None) => write!(f, "{}", c),
+            RegOrAddr::Counter(c, Some((start, end))) => {
+                write!(f, "{}(${:04X},${:04X})", c, start, end)
+            }
+            RegOrAddr::Range(start, len) => write!(f, "mem(${:04X},{})", start, len),
+            RegOrAddr::Screen(col, row) => write!(f, "screen({},{})", col, row),
+            RegOrAddr::CC(bit) => write!(f, "CC.{:?}", bit),
         }
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AddrOrVal {
     Addr(u16),
     Val(u8u16),
+    Count(u64),
+    Bytes(Vec<u8>), // inline bytes or a string (already converted to screen codes for screen criteria)
 }
 impl fmt::Display for AddrOrVal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             AddrOrVal::Addr(a) => write!(f, "${:04X}", a),
             AddrOrVal::Val(u) => write!(f, "#${}", u),
+            AddrOrVal::Count(n) => write!(f, "{}", n),
+            AddrOrVal::Bytes(bytes) => {
+                if bytes.iter().all(|b| (0x20..0x7f).contains(b)) {
+                    write!(f, "\"{}\"", String::from_utf8_lossy(bytes))
+                } else {
+                    write!(f, "#")?;
+                    for (i, b) in bytes.iter().enumerate() {
+                        write!(f, "{}${:02X}", if i == 0 { "" } else { "," }, b)?;
+                    }
+                    Ok(())
+                }
+            }
         }
     }
 }
 
+/// Returns the contents of an FCC-style string literal (delimited by `"` or `/`)
+pub fn string_literal(s: &str) -> Option<&str> {
+    let delim = s.chars().next().filter(|c| *c == '"' || *c == '/')?;
+    s[1..]
+        .strip_suffix(delim)
+        .filter(|inner| !inner.contains(delim))
+}
+
+/// Splits a criterion LHS of the form `name(args)` into the lowercase name and the args
+pub fn split_call(s: &str) -> Option<(String, &str)> {
+    let (name, rest) = s.split_once('(')?;
+    Some((name.to_ascii_lowercase(), rest.strip_suffix(')')?))
+}
+
+/// Splits a criterion body at its comparison operator, which needn't be surrounded by
+/// whitespace (e.g. `cycles<=1200`). Shifts (`<<` and `>>`) and anything in parentheses
+/// belong to the LHS expression.
+fn split_criterion(body: &str) -> Option<(&str, CmpOp, &str)> {
+    let bytes = body.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        let pair = body.get(i..i + 2).unwrap_or("");
+        match bytes[i] {
+            b'(' => depth += 1,
+            b')' => depth = depth.saturating_sub(1),
+            b'<' | b'>' if pair == "<<" || pair == ">>" => i += 1,
+            b'=' | b'!' | b'<' | b'>' if depth == 0 => {
+                let single = CmpOp::from_symbol(&body[i..i + 1]);
+                if let Some((op, len)) = CmpOp::from_symbol(pair)
+                    .map(|op| (op, 2))
+                    .or(single.map(|op| (op, 1)))
+                {
+                    return Some((body[..i].trim(), op, body[i + len..].trim()));
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Converts an ascii char to the code that displays it on the text screen, the same way
+/// the ROM's output routine does: $40-$5F are stored as they are, codes below $40 have
+/// bit 6 flipped (so a space is $60) and lower case letters are folded to upper case
+pub fn ascii_to_screen_code(c: u8) -> u8 {
+    match c {
+        0x00..=0x3f => c ^ 0x40,
+        0x60..=0x7f => c & !0x20,
+        _ => c,
+    }
+}
+
+/// The number of character columns on the text screen
+pub const SCREEN_COLUMNS: u16 = 32;
+/// The number of character rows on the text screen
+pub const SCREEN_ROWS: u16 = 16;
+
 #[derive(Debug)]
 pub struct TestCriterion {
     pub line_number: usize,
@@ -70,7 +260,8 @@ pub struct TestCriterion {
     // or a memory location, e.g. $0100 or a label
     pub rhs_src: String,
     pub rhs: Option<AddrOrVal>, // A constant, e.g. #$ff, or #0 or #%0110
-                                // or an address, e.g. $0100 or a label
+    // or an address, e.g. $0100 or a label
+    pub op: CmpOp,
 }
 impl TestCriterion {
     pub fn new(line_number: usize, lhs_src: &str, rhs_src: &str) -> Self {
@@ -80,6 +271,36 @@ impl TestCriterion {
             lhs: None,
             rhs_src: rhs_src.to_string(),
             rhs: None,
+            op: CmpOp::Eq,
+        }
+    }
+    /// Creates a TestCriterion from a source line of the form `;! <lhs> <op> <rhs>`.
+    /// Returns Ok(None) if the line is not a test criterion at all.
+    /// The LHS and RHS are left unresolved (see Parser::parse_test_criterion).
+    pub fn from_line(line_number: usize, src: &str) -> Result<Option<Self>, Error> {
+        let body = match src.strip_prefix(";!") {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        let (lhs, op, rhs) =
+            split_criterion(body.trim()).ok_or_else(|| syntax_err!("malformed test criterion"))?;
+        // only string literals may contain whitespace
+        if lhs.is_empty()
+            || rhs.is_empty()
+            || lhs.contains(char::is_whitespace)
+            || (rhs.contains(char::is_whitespace) && string_literal(rhs).is_none())
+        {
+            return Err(syntax_err!("malformed test criterion"));
+        }
+        let mut tc = TestCriterion::new(line_number, lhs, rhs);
+        tc.op = op;
+        Ok(Some(tc))
+    }
+    /// Returns the (START,DONE) addresses if this criterion checks a counter over a span.
+    pub fn span(&self) -> Option<(u16, u16)> {
+        match self.lhs {
+            Some(RegOrAddr::Counter(_, span)) => span,
+            _ => None,
         }
     }
     pub fn eval(&self, core: &Core) -> Result<(), Error> {
@@ -92,20 +313,45 @@ impl TestCriterion {
             .rhs
             .as_ref()
             .ok_or_else(|| general_err!("TestCriterion missing RHS"))?;
-        let lhs_val = match lhs {
-            RegOrAddr::Reg(reg) => {
+        let lhs_val = match (lhs, rhs) {
+            (RegOrAddr::Addr(addr), AddrOrVal::Bytes(bytes)) => {
+                return self.eval_range(core, *addr, AddrOrVal::Bytes(bytes.clone()), bytes.len())
+            }
+            (RegOrAddr::Range(start, len), _) => {
+                return self.eval_range(core, *start, rhs.clone(), *len as usize)
+            }
+            (RegOrAddr::Screen(col, row), AddrOrVal::Bytes(bytes)) => {
+                let vram = core.sam.lock().get_vram_start();
+                let offset = *row as u16 * SCREEN_COLUMNS + *col as u16;
+                return self.eval_range(
+                    core,
+                    vram.wrapping_add(offset),
+                    AddrOrVal::Bytes(bytes.clone()),
+                    bytes.len(),
+                );
+            }
+            (RegOrAddr::CC(bit), _) => u8u16::u8(core.reg.cc.is_set(*bit) as u8),
+            (RegOrAddr::Reg(reg), _) => {
                 lhs_size = registers::reg_size(*reg);
                 core.reg.get_register(*reg)
             }
-            RegOrAddr::Addr(addr) => {
+            (RegOrAddr::Addr(addr), _) => {
                 if let AddrOrVal::Val(val) = rhs {
                     lhs_size = val.size();
                 }
                 core._read_u8u16(memory::AccessType::Generic, *addr, lhs_size)?
             }
+            (RegOrAddr::Counter(counter, span), _) => {
+                return self.eval_counter(core, *counter, *span)
+            }
+            (RegOrAddr::Screen(..), _) => {
+                return Err(general_err!("TestCriterion has invalid RHS"))
+            }
         };
         let rhs_val = match rhs {
-            AddrOrVal::Addr(addr) => core._read_u8u16(memory::AccessType::Generic, *addr, lhs_size)?,
+            AddrOrVal::Addr(addr) => {
+                core._read_u8u16(memory::AccessType::Generic, *addr, lhs_size)?
+            }
             AddrOrVal::Val(val) => {
                 if lhs_size == 2 && val.size() == 1 {
                     u8u16::new(val.u8(), Some(0))
@@ -113,15 +359,131 @@ impl TestCriterion {
                     *val
                 }
             }
+            AddrOrVal::Count(_) | AddrOrVal::Bytes(_) => {
+                return Err(general_err!("TestCriterion has invalid RHS"))
+            }
         };
-        if lhs_val == rhs_val {
+        if self.op.holds(lhs_val.u16(), rhs_val.u16()) {
             Ok(())
-        } else {
+        } else if self.op == CmpOp::Eq {
             Err(Error::new(
                 ErrorKind::Test,
                 Some(core.reg),
                 format!("{} ({}) != {} ({})", lhs, lhs_val, rhs, rhs_val).as_str(),
             ))
+        } else {
+            Err(Error::new(
+                ErrorKind::Test,
+                Some(core.reg),
+                format!(
+                    "expected {} ({}) {} {} ({})",
+                    lhs, lhs_val, self.op, rhs, rhs_val
+                )
+                .as_str(),
+            ))
+        }
+    }
+    /// Compares `len` bytes of memory starting at `start` with the expected bytes
+    /// (or with the same number of bytes at another address).
+    fn eval_range(&self, core: &Core, start: u16, rhs: AddrOrVal, len: usize) -> Result<(), Error> {
+        let read = |addr: u16, i: usize| {
+            core._read_u8(
+                memory::AccessType::Generic,
+                addr.wrapping_add(i as u16),
+                None,
+            )
+        };
+        let mut mismatch = None;
+        for i in 0..len {
+            let actual = read(start, i)?;
+            let expected = match &rhs {
+                AddrOrVal::Bytes(bytes) => bytes[i],
+                AddrOrVal::Addr(addr) => read(*addr, i)?,
+                _ => return Err(general_err!("TestCriterion has invalid RHS")),
+            };
+            if actual != expected {
+                mismatch = Some((i, actual, expected));
+                break;
+            }
+        }
+        match (mismatch, self.op) {
+            (None, CmpOp::Eq) | (Some(_), CmpOp::Ne) => Ok(()),
+            (Some((i, actual, expected)), _) => Err(Error::new(
+                ErrorKind::Test,
+                Some(core.reg),
+                format!(
+                    "{} != {}: byte {} (${:04X}) is ${:02X} but expected ${:02X}",
+                    self.lhs.as_ref().unwrap(),
+                    rhs,
+                    i,
+                    start.wrapping_add(i as u16),
+                    actual,
+                    expected
+                )
+                .as_str(),
+            )),
+            (None, _) => Err(Error::new(
+                ErrorKind::Test,
+                Some(core.reg),
+                format!(
+                    "{} unexpectedly matches {}",
+                    self.lhs.as_ref().unwrap(),
+                    rhs
+                )
+                .as_str(),
+            )),
+        }
+    }
+    fn eval_counter(
+        &self,
+        core: &Core,
+        counter: Counter,
+        span: Option<(u16, u16)>,
+    ) -> Result<(), Error> {
+        let expected = match self.rhs {
+            Some(AddrOrVal::Count(n)) => n,
+            _ => return Err(general_err!("TestCriterion has invalid RHS")),
+        };
+        let actual = match span {
+            None => match counter {
+                Counter::Cycles => core.clock_cycles,
+                Counter::Instructions => core.instruction_count,
+            },
+            Some((start, end)) => {
+                let measured = core
+                    .spans
+                    .iter()
+                    .find(|s| s.start == start && s.end == end)
+                    .and_then(|s| s.measured());
+                match (measured, counter) {
+                    (Some((cycles, _)), Counter::Cycles) => cycles,
+                    (Some((_, instructions)), Counter::Instructions) => instructions,
+                    (None, _) => {
+                        return Err(Error::new(
+                            ErrorKind::Test,
+                            Some(core.reg),
+                            format!("span ${:04X}..${:04X} was never completed", start, end)
+                                .as_str(),
+                        ))
+                    }
+                }
+            }
+        };
+        if self.op.holds(actual, expected) {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::Test,
+                Some(core.reg),
+                format!(
+                    "expected {} {} {} but was {}",
+                    self.lhs.as_ref().unwrap(),
+                    self.op,
+                    expected,
+                    actual
+                )
+                .as_str(),
+            ))
         }
     }
 }
@@ -129,9 +491,373 @@ impl fmt::Display for TestCriterion {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if let Some(lhs) = &self.lhs {
             if let Some(rhs) = &self.rhs {
-                return write!(f, "{} = {}", lhs, rhs);
+                return write!(f, "{} {} {}", lhs, self.op, rhs);
+            }
+        }
+        write!(f, "<{} {} {}>?", self.lhs_src, self.op, self.rhs_src)
+    }
+}
+
+/// Default location of the system stack for test programs that don't set their own.
+pub const DEFAULT_STACK_TOP: u16 = 0x8000;
+
+/// Limits applied to each test program so that a runaway program can't hang the runner.
+#[derive(Debug, Clone, Copy)]
+pub struct RunLimits {
+    pub max_cycles: u64,
+    pub max_time: Option<std::time::Duration>,
+}
+impl Default for RunLimits {
+    fn default() -> Self {
+        RunLimits {
+            max_cycles: 100_000_000,
+            max_time: Some(std::time::Duration::from_secs(10)),
+        }
+    }
+}
+
+/// A single failed test criterion
+#[derive(Debug, Clone)]
+pub struct CriterionFailure {
+    pub line_number: usize,
+    pub criterion: String,
+    pub msg: String,
+}
+
+/// The outcome of running one test program
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    pub criteria: usize,                    // number of criteria evaluated
+    pub failures: Vec<CriterionFailure>,    // criteria that did not pass
+    pub error: Option<(ErrorKind, String)>, // set if the program failed to assemble or run to completion
+    pub cycles: u64,
+    pub instructions: u64,
+    pub elapsed: std::time::Duration,
+}
+impl TestResult {
+    fn new(name: &str) -> Self {
+        TestResult {
+            name: name.to_string(),
+            criteria: 0,
+            failures: Vec::new(),
+            error: None,
+            cycles: 0,
+            instructions: 0,
+            elapsed: std::time::Duration::ZERO,
+        }
+    }
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.failures.is_empty()
+    }
+}
+impl fmt::Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} {} ({} criteria, {} cycles, {} instructions, {:.3}s)",
+            if self.passed() { "PASS" } else { "FAIL" },
+            self.name,
+            self.criteria,
+            self.cycles,
+            self.instructions,
+            self.elapsed.as_secs_f64()
+        )?;
+        if let Some((kind, msg)) = &self.error {
+            writeln!(f, "    error ({:?}): {}", kind, msg.replace('\n', "\n    "))?;
+        }
+        for fail in &self.failures {
+            writeln!(
+                f,
+                "    line {}: {} -> {}",
+                fail.line_number, fail.criterion, fail.msg
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The collected outcomes of a test run
+#[derive(Debug, Default)]
+pub struct TestReport {
+    pub results: Vec<TestResult>,
+}
+impl TestReport {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|r| r.passed()).count()
+    }
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+    pub fn all_passed(&self) -> bool {
+        self.failed() == 0
+    }
+    /// Renders the report as JUnit-style XML (one testsuite, one testcase per program)
+    pub fn to_junit_xml(&self) -> String {
+        let mut xml = String::new();
+        let total: f64 = self.results.iter().map(|r| r.elapsed.as_secs_f64()).sum();
+        let errors = self.results.iter().filter(|r| r.error.is_some()).count();
+        let failures = self
+            .results
+            .iter()
+            .filter(|r| r.error.is_none() && !r.failures.is_empty())
+            .count();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuite name=\"dm-test\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+            self.results.len(),
+            failures,
+            errors,
+            total
+        ));
+        for r in &self.results {
+            xml.push_str(&format!(
+                "  <testcase name=\"{}\" classname=\"dm-test\" time=\"{:.3}\">\n",
+                xml_escape(&r.name),
+                r.elapsed.as_secs_f64()
+            ));
+            if let Some((kind, msg)) = &r.error {
+                xml.push_str(&format!(
+                    "    <error type=\"{:?}\" message=\"{}\"/>\n",
+                    kind,
+                    xml_escape(msg)
+                ));
+            }
+            for fail in &r.failures {
+                xml.push_str(&format!(
+                    "    <failure message=\"line {}: {}\">{}</failure>\n",
+                    fail.line_number,
+                    xml_escape(&fail.criterion),
+                    xml_escape(&fail.msg)
+                ));
+            }
+            xml.push_str(&format!(
+                "    <system-out>cycles={} instructions={}</system-out>\n",
+                r.cycles, r.instructions
+            ));
+            xml.push_str("  </testcase>\n");
+        }
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+}
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for r in &self.results {
+            write!(f, "{}", r)?;
+        }
+        writeln!(
+            f,
+            "test result: {}. {} passed; {} failed",
+            if self.all_passed() { "ok" } else { "FAILED" },
+            self.passed(),
+            self.failed()
+        )
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Assembles, runs and checks test programs without any UI.
+///
+/// Each program runs from its reset vector (or its first instruction if it doesn't set one)
+/// until it executes EXIT, at which point all of its `;!` criteria are evaluated.
+pub struct TestRunner {
+    pub core: core::mem::ManuallyDrop<Core>,
+    pub limits: RunLimits,
+    /// if set, write the listing, symbol file and binary of each program run from a file
+    /// into this directory (see Program::write_output_files)
+    pub output_dir: Option<std::path::PathBuf>,
+    assembler: crate::assembler::Assembler,
+    // the 64K of RAM lent to core (freed when the runner is dropped)
+    ram: *mut [u8],
+}
+impl TestRunner {
+    pub fn new(limits: RunLimits) -> Self {
+        let ram = std::boxed::Box::into_raw(vec![0u8; 0x10000].into_boxed_slice());
+        // SAFETY: the RAM isn't freed until the runner is dropped, after core is (see drop)
+        let core_ram = unsafe { &mut *ram };
+        let sam = crate::Arc::new(crate::Mutex::new(crate::Sam::new()));
+        let vdg = crate::Arc::new(crate::Mutex::new(crate::Vdg::with_ram(0)));
+        let pia1 = crate::Arc::new(crate::Mutex::new(crate::Pia1::new()));
+        let pia0 = crate::Arc::new(crate::Mutex::new(crate::Pia0::new(pia1.clone())));
+        let core = Core::new(core_ram, sam, vdg, pia0, pia1, 0xFFFF, None);
+        let assembler =
+            crate::assembler::Assembler::new(&crate::instructions::Instance::new(0, None));
+        TestRunner {
+            core: core::mem::ManuallyDrop::new(core),
+            limits,
+            output_dir: None,
+            assembler,
+            ram,
+        }
+    }
+
+    /// Assembles and runs the program in the given source lines.
+    pub fn run_source<I, T>(&mut self, name: &str, src: I) -> TestResult
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let start = std::time::Instant::now();
+        let assembled = self.assembler.assemble(src);
+        self.run_assembled(name, assembled, start)
+    }
+
+    /// Assembles and runs the program in the file at `path`.
+    /// Files that it includes are found relative to its directory.
+    pub fn run_file(&mut self, path: &std::path::Path) -> TestResult {
+        let name = path.display().to_string();
+        let start = std::time::Instant::now();
+        let mut assembled = self.assembler.assemble_file(&name);
+        if let Some(dir) = &self.output_dir {
+            if let Ok(program) = &assembled {
+                if let Err(e) = program.write_output_files(&name, dir) {
+                    assembled = Err(e);
+                }
+            }
+        }
+        self.run_assembled(&name, assembled, start)
+    }
+
+    fn run_assembled(
+        &mut self,
+        name: &str,
+        assembled: Result<Program, Error>,
+        start: std::time::Instant,
+    ) -> TestResult {
+        let mut result = TestResult::new(name);
+        match assembled {
+            Ok(program) => self.run_program(&program, &mut result),
+            Err(e) => {
+                // report every problem the assembler found, not just the first
+                let diagnostics: Vec<String> = self
+                    .assembler
+                    .take_diagnostics()
+                    .iter()
+                    .map(|d| d.to_string())
+                    .collect();
+                let msg = if diagnostics.is_empty() {
+                    e.to_string()
+                } else {
+                    diagnostics.join("\n")
+                };
+                result.error = Some((e.kind, msg));
             }
         }
-        write!(f, "<{} = {}>?", self.lhs_src, self.rhs_src)
+        result.elapsed = start.elapsed();
+        result
+    }
+
+    /// Runs every file in `paths` and collects the results in a TestReport.
+    pub fn run_files<P: AsRef<std::path::Path>>(&mut self, paths: &[P]) -> TestReport {
+        TestReport {
+            results: paths.iter().map(|p| self.run_file(p.as_ref())).collect(),
+        }
+    }
+
+    fn run_program(&mut self, program: &Program, result: &mut TestResult) {
+        result.criteria = program.results.len();
+        if let Err(e) = self.exec_program(program) {
+            result.cycles = self.core.clock_cycles;
+            result.instructions = self.core.instruction_count;
+            result.error = Some((e.kind, e.to_string()));
+            return;
+        }
+        result.cycles = self.core.clock_cycles;
+        result.instructions = self.core.instruction_count;
+        for tc in &program.results {
+            if let Err(mut e) = tc.eval(&self.core) {
+                result.failures.push(CriterionFailure {
+                    line_number: tc.line_number,
+                    criterion: tc.to_string(),
+                    msg: core::mem::take(&mut e.msg),
+                });
+            }
+        }
+    }
+
+    fn exec_program(&mut self, program: &Program) -> Result<(), Error> {
+        let core = &mut *self.core;
+        core.raw_ram.fill(0);
+        // the SAM and PIAs start from their power-on state too, so that the modes one
+        // program sets don't carry over to the next
+        *core.sam.lock() = crate::Sam::new();
+        *core.pia1.lock() = crate::Pia1::new();
+        *core.pia0.lock() = crate::Pia0::new(core.pia1.clone());
+        core.load_program(program)?;
+        core.reset()?;
+        if core.reg.pc == 0 {
+            core.reg.pc = program.entry_point().ok_or_else(|| {
+                Error::new(ErrorKind::Test, None, "program contains no instructions")
+            })?;
+        }
+        core.reg.s = DEFAULT_STACK_TOP;
+        core.clock_cycles = 0;
+        core.instruction_count = 0;
+        core.hsync_prev = 0;
+        core.vsync_prev = 0;
+        core.in_cwai = false;
+        core.in_sync = false;
+        core.faulted = false;
+        core.spans = Vec::new();
+        for (start, end) in program.results.iter().filter_map(|tc| tc.span()) {
+            if !core.spans.iter().any(|s| s.start == start && s.end == end) {
+                core.spans.push(crate::cpu::CycleSpan::new(start, end));
+            }
+        }
+        let start = std::time::Instant::now();
+        loop {
+            // counts reported to the criteria exclude the EXIT instruction itself
+            let counts = (core.clock_cycles, core.instruction_count);
+            match core.exec_one() {
+                Ok(()) => {}
+                Err(e) if e.kind == ErrorKind::Exit => {
+                    (core.clock_cycles, core.instruction_count) = counts;
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+            if core.clock_cycles > self.limits.max_cycles {
+                return Err(Error::new(
+                    ErrorKind::Test,
+                    Some(core.reg),
+                    format!("cycle limit ({}) exceeded", self.limits.max_cycles).as_str(),
+                ));
+            }
+            if core.instruction_count.is_multiple_of(1024) {
+                if let Some(max_time) = self.limits.max_time {
+                    if start.elapsed() > max_time {
+                        return Err(Error::new(
+                            ErrorKind::Test,
+                            Some(core.reg),
+                            format!("time limit ({:?}) exceeded", max_time).as_str(),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+impl Drop for TestRunner {
+    fn drop(&mut self) {
+        // SAFETY: core is the only user of the RAM and it's dropped first
+        unsafe {
+            core::mem::ManuallyDrop::drop(&mut self.core);
+            drop(std::boxed::Box::from_raw(self.ram));
+        }
     }
 }
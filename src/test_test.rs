@@ -0,0 +1,30 @@
+use crate::test::{RunLimits, TestRunner};
+
+#[test]
+fn test_programs_start_clean() {
+    let mut runner = TestRunner::new(RunLimits::default());
+    // the first program selects the data register of PIA0 side B and all-RAM mode
+    let setup = [
+        "    org $1000",
+        "start:",
+        "    lda #$34",
+        "    sta $ff03",
+        "    sta $ffdf",
+        "    exit",
+    ];
+    assert!(runner.run_source("setup", setup).passed());
+    assert!(runner.core.sam.lock().get_map_type());
+    // the next one sees the power-on state again
+    let check = [
+        "    org $1000",
+        "start:",
+        "    lda $ff03",
+        "    sta result",
+        "    exit",
+        "result: rmb 1",
+        ";! result = #0",
+    ];
+    let result = runner.run_source("check", check);
+    assert!(result.passed(), "{:?}", result.failures);
+    assert!(!runner.core.sam.lock().get_map_type());
+}
@@ -0,0 +1,530 @@
+//! Structured tracing of executed instructions.
+//!
+//! A [Tracer] attached to a [Core] (see [Core::attach_tracer]) makes a [Record] of each
+//! instruction executed: the cycle it started on, its address and bytes, the registers
+//! before and after, its effective address and the memory it read and wrote. Records
+//! can be limited to address ranges (or symbols) and to between start and stop
+//! addresses. The last N records are kept in a ring buffer, so they're there to look at
+//! after a crash, and every record can also be written to a [TraceSink] as it's made.
+//!
+//! Records are written as text, one line per instruction, or in a compact binary format
+//! that [read_binary] reads back.
+use crate::instructions::{self, AddressingMode};
+use crate::registers::{CCBits, Set};
+use crate::{Box, Core, Error, ErrorKind, Map, String, Vec, VecDeque};
+use core::fmt::Write;
+use core::ops::Bound;
+
+const MAGIC: &[u8; 4] = b"CCTR";
+const VERSION: u8 = 1;
+
+/// A memory access made by an instruction (other than fetching the instruction itself)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub addr: u16,
+    pub data: u8,
+    pub write: bool,
+}
+
+/// One executed instruction
+#[derive(Clone)]
+pub struct Record {
+    /// the clock cycle the instruction started on
+    pub cycle: u64,
+    pub pc: u16,
+    bytes: [u8; 8],
+    size: u8,
+    pub name: &'static str,
+    /// the effective address, for instructions that have one
+    pub ea: Option<u16>,
+    pub before: Set,
+    pub after: Set,
+    pub accesses: Vec<Access>,
+}
+
+fn trace_err(msg: &str) -> Error {
+    err!(ErrorKind::General, None, "trace: {}", msg)
+}
+
+fn put_regs(out: &mut Vec<u8>, r: &Set) {
+    out.extend_from_slice(&[r.a, r.b, r.dp, r.cc.reg]);
+    for word in [r.x, r.y, r.u, r.s, r.pc] {
+        out.extend_from_slice(&word.to_be_bytes());
+    }
+}
+
+impl Record {
+    /// The instruction's bytes: opcode and operand
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.size as usize]
+    }
+    /// The record as a line of text (without a newline). A symbol at the instruction's
+    /// address in `symbols` labels the line.
+    pub fn to_text(&self, symbols: &Map<u16, Vec<String>>) -> String {
+        let mut line = String::new();
+        let label = symbols
+            .get(&self.pc)
+            .and_then(|s| s.last())
+            .map_or("", |s| s.as_str());
+        let mut bytes = String::new();
+        for b in self.bytes() {
+            let _ = write!(bytes, "{:02X}", b);
+        }
+        let _ = write!(
+            line,
+            "{:>10} {:04X} {:10} {:10} {:6}",
+            self.cycle, self.pc, label, bytes, self.name
+        );
+        match self.ea {
+            Some(ea) => {
+                let _ = write!(line, " ea={:04X}", ea);
+            }
+            None => line.push_str("        "),
+        }
+        let (b, a) = (&self.before, &self.after);
+        let _ = write!(
+            line,
+            " A={:02X} B={:02X} X={:04X} Y={:04X} U={:04X} S={:04X} DP={:02X} CC={:02X} ->",
+            b.a, b.b, b.x, b.y, b.u, b.s, b.dp, b.cc.reg
+        );
+        // after the arrow, only what changed
+        let byte_regs = [("A", b.a, a.a), ("B", b.b, a.b), ("DP", b.dp, a.dp)];
+        let word_regs = [
+            ("X", b.x, a.x),
+            ("Y", b.y, a.y),
+            ("U", b.u, a.u),
+            ("S", b.s, a.s),
+        ];
+        let mut changed = false;
+        for (name, old, new) in byte_regs {
+            if old != new {
+                let _ = write!(line, " {}={:02X}", name, new);
+                changed = true;
+            }
+        }
+        for (name, old, new) in word_regs {
+            if old != new {
+                let _ = write!(line, " {}={:04X}", name, new);
+                changed = true;
+            }
+        }
+        if b.cc.reg != a.cc.reg {
+            let _ = write!(line, " CC={:02X}", a.cc.reg);
+            changed = true;
+        }
+        if !changed {
+            line.push_str(" -");
+        }
+        for access in &self.accesses {
+            let rw = if access.write { 'W' } else { 'R' };
+            let _ = write!(line, " {}:{:04X}={:02X}", rw, access.addr, access.data);
+        }
+        line
+    }
+    /// Appends the record in the binary format
+    pub fn to_binary(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.cycle.to_be_bytes());
+        out.extend_from_slice(&self.pc.to_be_bytes());
+        out.push(self.size);
+        out.extend_from_slice(self.bytes());
+        out.push(self.ea.is_some() as u8);
+        out.extend_from_slice(&self.ea.unwrap_or(0).to_be_bytes());
+        put_regs(out, &self.before);
+        put_regs(out, &self.after);
+        out.push(self.accesses.len() as u8);
+        for access in &self.accesses {
+            out.extend_from_slice(&access.addr.to_be_bytes());
+            out.extend_from_slice(&[access.data, access.write as u8]);
+        }
+    }
+}
+
+/// Takes fields from the front of a binary trace
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(trace_err("truncated record"));
+        }
+        let (field, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(field)
+    }
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    fn regs(&mut self) -> Result<Set, Error> {
+        let [a, b, dp, cc]: [u8; 4] = self.take(4)?.try_into().unwrap();
+        let mut r = Set {
+            a,
+            b,
+            d: ((a as u16) << 8) | b as u16,
+            dp,
+            cc: CCBits { reg: cc },
+            ..Default::default()
+        };
+        (r.x, r.y, r.u, r.s, r.pc) = (
+            self.u16()?,
+            self.u16()?,
+            self.u16()?,
+            self.u16()?,
+            self.u16()?,
+        );
+        Ok(r)
+    }
+}
+
+/// Reads a trace written in the binary format
+pub fn read_binary(data: &[u8]) -> Result<Vec<Record>, Error> {
+    let mut rd = Reader { data };
+    if rd.take(4)? != MAGIC {
+        return Err(trace_err("not a binary trace"));
+    }
+    if rd.u8()? != VERSION {
+        return Err(trace_err("written by a different version"));
+    }
+    let mut records = Vec::new();
+    while !rd.data.is_empty() {
+        let cycle = u64::from_be_bytes(rd.take(8)?.try_into().unwrap());
+        let pc = rd.u16()?;
+        let size = rd.u8()?;
+        if size == 0 || size > 8 {
+            return Err(trace_err("bad instruction size"));
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size as usize].copy_from_slice(rd.take(size as usize)?);
+        let has_ea = rd.u8()? != 0;
+        let ea = rd.u16()?;
+        let (before, after) = (rd.regs()?, rd.regs()?);
+        let mut accesses = Vec::new();
+        for _ in 0..rd.u8()? {
+            let addr = rd.u16()?;
+            let (data, write) = (rd.u8()?, rd.u8()? != 0);
+            accesses.push(Access { addr, data, write });
+        }
+        let op = match bytes[0] {
+            0x10 | 0x11 => ((bytes[0] as u16) << 8) | bytes[1] as u16,
+            op => op as u16,
+        };
+        records.push(Record {
+            cycle,
+            pc,
+            bytes,
+            size,
+            name: instructions::opcode_to_flavor(op).map_or("???", |f| f.desc.name),
+            ea: has_ea.then_some(ea),
+            before,
+            after,
+            accesses,
+        });
+    }
+    Ok(records)
+}
+
+/// How records are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Binary,
+}
+
+/// Somewhere records are written as they're made
+pub trait TraceSink {
+    fn write(&mut self, data: &[u8]) -> Result<(), Error>;
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl TraceSink for Vec<u8> {
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+/// A file records are written to
+#[cfg(not(target_os = "none"))]
+pub struct FileSink {
+    file: std::io::BufWriter<std::fs::File>,
+}
+
+#[cfg(not(target_os = "none"))]
+impl FileSink {
+    pub fn create(path: &str) -> Result<Self, Error> {
+        let file = std::fs::File::create(path)
+            .map_err(|e| err!(ErrorKind::IO, None, "trace file {}: {}", path, e))?;
+        Ok(FileSink {
+            file: std::io::BufWriter::new(file),
+        })
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl TraceSink for FileSink {
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        use std::io::Write;
+        self.file
+            .write_all(data)
+            .map_err(|e| err!(ErrorKind::IO, None, "writing trace: {}", e))
+    }
+    fn flush(&mut self) -> Result<(), Error> {
+        use std::io::Write;
+        self.file
+            .flush()
+            .map_err(|e| err!(ErrorKind::IO, None, "writing trace: {}", e))
+    }
+}
+
+/// Where a tracer is with its start and stop addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceState {
+    /// waiting for the start address
+    Waiting,
+    Recording,
+    /// the stop address was reached
+    Stopped,
+}
+
+/// A sink and how to write to it
+struct Sink {
+    out: Box<dyn TraceSink>,
+    format: Format,
+    /// the symbols text lines are labelled with
+    symbols: Map<u16, Vec<String>>,
+}
+
+pub struct Tracer {
+    /// the most records kept in the ring
+    capacity: usize,
+    ring: VecDeque<Record>,
+    /// inclusive address ranges to record; everything if there are none
+    ranges: Vec<(u16, u16)>,
+    start: Option<u16>,
+    stop: Option<u16>,
+    state: TraceState,
+    sink: Option<Sink>,
+    /// whether the instruction executing is being recorded
+    capturing: bool,
+    accesses: Vec<Access>,
+    /// the number of records made
+    pub recorded: u64,
+}
+
+impl Tracer {
+    /// A tracer that keeps the last `capacity` records, recording everything
+    pub fn new(capacity: usize) -> Self {
+        Tracer {
+            capacity,
+            ring: VecDeque::with_capacity(capacity.min(4096)),
+            ranges: Vec::new(),
+            start: None,
+            stop: None,
+            state: TraceState::Recording,
+            sink: None,
+            capturing: false,
+            accesses: Vec::new(),
+            recorded: 0,
+        }
+    }
+    /// Records instructions from `start` to `end` (inclusive) as well as any other ranges
+    /// added. With no ranges, every instruction is recorded.
+    pub fn add_range(&mut self, start: u16, end: u16) {
+        self.ranges.push((start.min(end), start.max(end)));
+    }
+    /// Waits for the instruction at `addr` before recording, or records straight away
+    /// with None
+    pub fn set_start(&mut self, addr: Option<u16>) {
+        self.start = addr;
+        self.state = match addr {
+            Some(_) => TraceState::Waiting,
+            None => TraceState::Recording,
+        };
+    }
+    /// Stops recording when the instruction at `addr` is reached (without recording it)
+    pub fn set_stop(&mut self, addr: Option<u16>) {
+        self.stop = addr;
+    }
+    pub fn state(&self) -> TraceState {
+        self.state
+    }
+    /// Writes each record to `sink` as it's made as well as keeping it in the ring. A
+    /// text sink labels lines with `symbols`.
+    pub fn set_sink(
+        &mut self,
+        mut sink: Box<dyn TraceSink>,
+        format: Format,
+        symbols: Map<u16, Vec<String>>,
+    ) -> Result<(), Error> {
+        if format == Format::Binary {
+            sink.write(MAGIC)?;
+            sink.write(&[VERSION])?;
+        }
+        self.sink = Some(Sink {
+            out: sink,
+            format,
+            symbols,
+        });
+        Ok(())
+    }
+    /// Flushes the sink and stops writing to it
+    pub fn take_sink(&mut self) -> Result<Option<Box<dyn TraceSink>>, Error> {
+        match self.sink.take() {
+            Some(mut sink) => {
+                sink.out.flush()?;
+                Ok(Some(sink.out))
+            }
+            None => Ok(None),
+        }
+    }
+    pub fn flush(&mut self) -> Result<(), Error> {
+        match self.sink.as_mut() {
+            Some(sink) => sink.out.flush(),
+            None => Ok(()),
+        }
+    }
+    /// The records in the ring, oldest first
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.ring.iter()
+    }
+    pub fn clear(&mut self) {
+        self.ring.clear();
+    }
+    /// The records in the ring in `format`
+    pub fn export(&self, format: Format, symbols: &Map<u16, Vec<String>>) -> Vec<u8> {
+        let mut out = Vec::new();
+        if format == Format::Binary {
+            out.extend_from_slice(MAGIC);
+            out.push(VERSION);
+        }
+        for record in &self.ring {
+            encode(record, format, symbols, &mut out);
+        }
+        out
+    }
+    /// Writes the records in the ring to a file
+    #[cfg(not(target_os = "none"))]
+    pub fn save(
+        &self,
+        path: &str,
+        format: Format,
+        symbols: &Map<u16, Vec<String>>,
+    ) -> Result<(), Error> {
+        std::fs::write(path, self.export(format, symbols))
+            .map_err(|e| err!(ErrorKind::IO, None, "trace file {}: {}", path, e))
+    }
+    /// Decides whether the instruction at `pc` is recorded, following the start and stop
+    /// addresses, and if it is starts gathering its memory accesses
+    pub(crate) fn begin(&mut self, pc: u16) -> bool {
+        match self.state {
+            TraceState::Waiting if self.start == Some(pc) => self.state = TraceState::Recording,
+            TraceState::Recording if self.stop == Some(pc) => self.state = TraceState::Stopped,
+            _ => {}
+        }
+        self.capturing = self.state == TraceState::Recording
+            && (self.ranges.is_empty() || self.ranges.iter().any(|&(s, e)| s <= pc && pc <= e));
+        self.accesses.clear();
+        self.capturing
+    }
+    /// Notes a memory access by the instruction being recorded
+    #[inline(always)]
+    pub(crate) fn access(&mut self, addr: u16, data: u8, write: bool) {
+        if self.capturing {
+            self.accesses.push(Access { addr, data, write });
+        }
+    }
+    /// Makes the record of the instruction started with [Tracer::begin], whose bytes
+    /// are `bytes`
+    pub(crate) fn finish(
+        &mut self,
+        cycle: u64,
+        before: Set,
+        after: Set,
+        outcome: &instructions::Outcome,
+        bytes: [u8; 8],
+    ) -> Result<(), Error> {
+        self.capturing = false;
+        let inst = &outcome.inst;
+        let record = Record {
+            cycle,
+            pc: inst.pc,
+            bytes,
+            size: inst.size as u8,
+            name: inst.flavor.desc.name,
+            ea: (inst.flavor.mode != AddressingMode::Inherent).then_some(inst.ea),
+            before,
+            after,
+            accesses: core::mem::take(&mut self.accesses),
+        };
+        self.recorded += 1;
+        if let Some(sink) = self.sink.as_mut() {
+            let mut out = Vec::new();
+            encode(&record, sink.format, &sink.symbols, &mut out);
+            sink.out.write(&out)?;
+        }
+        if self.capacity > 0 {
+            if self.ring.len() == self.capacity {
+                self.ring.pop_front();
+            }
+            self.ring.push_back(record);
+        }
+        Ok(())
+    }
+}
+
+fn encode(record: &Record, format: Format, symbols: &Map<u16, Vec<String>>, out: &mut Vec<u8>) {
+    match format {
+        Format::Text => {
+            out.extend_from_slice(record.to_text(symbols).as_bytes());
+            out.push(b'\n');
+        }
+        Format::Binary => record.to_binary(out),
+    }
+}
+
+impl Core {
+    /// Starts tracing with `tracer`, replacing any previous one
+    pub fn attach_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(core::cell::RefCell::new(tracer));
+    }
+    /// Stops tracing, flushing the tracer's sink
+    pub fn detach_tracer(&mut self) -> Result<Option<Tracer>, Error> {
+        match self.tracer.take() {
+            Some(tracer) => {
+                let mut tracer = tracer.into_inner();
+                tracer.flush()?;
+                Ok(Some(tracer))
+            }
+            None => Ok(None),
+        }
+    }
+    /// The addresses of the routine at symbol `name`: from the symbol up to the next one
+    pub fn symbol_range(&self, name: &str) -> Option<(u16, u16)> {
+        let start = *self.sym_to_addr.get(name)?;
+        let end = self
+            .addr_to_sym
+            .range((Bound::Excluded(start), Bound::Unbounded))
+            .next()
+            .map_or(0xffff, |(&addr, _)| addr - 1);
+        Some((start, end))
+    }
+    /// Limits the trace to the routine at symbol `name` (see [Core::symbol_range])
+    pub fn trace_symbol(&mut self, name: &str) -> Result<(), Error> {
+        let (start, end) = self
+            .symbol_range(name)
+            .ok_or_else(|| trace_err("no such symbol"))?;
+        match self.tracer.as_ref() {
+            Some(tracer) => {
+                tracer.borrow_mut().add_range(start, end);
+                Ok(())
+            }
+            None => Err(trace_err("no tracer attached")),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::cpu_test::create_core;
+use crate::trace::*;
+use crate::*;
+use alloc::vec;
+
+const PROGRAM: &str = "
+    org $1000
+    lda #$12
+    sta $2000
+    ldx #$2000
+    ldb ,x
+    bsr sub
+    exit
+sub inca
+    rts
+";
+
+/// Assembles `src` and runs it with `tracer`, returning the tracer and how the run ended
+fn run(src: &str, tracer: Tracer, symbols: &[(&str, u16)]) -> (Core, Result<(), Error>) {
+    let mut core = create_core();
+    let program = assembler::Assembler::new(&instructions::Instance::new(0, None))
+        .assemble(src.lines())
+        .unwrap_or_else(|e| panic!("{}\n{}", e, src));
+    core.load_image(&program.to_image()).unwrap();
+    for &(name, addr) in symbols {
+        core.sym_to_addr.insert(name.to_string(), addr);
+        core.addr_to_sym.insert(addr, vec![name.to_string()]);
+    }
+    core.attach_tracer(tracer);
+    core.reg.pc = 0x1000;
+    core.reg.s = 0x8000;
+    let result = core.exec();
+    (core, result)
+}
+
+fn pcs(core: &Core) -> Vec<u16> {
+    let tracer = core.tracer.as_ref().unwrap().borrow();
+    tracer.records().map(|r| r.pc).collect()
+}
+
+#[test]
+fn test_trace_records() {
+    let (mut core, result) = run(PROGRAM, Tracer::new(100), &[]);
+    result.unwrap();
+    let tracer = core.detach_tracer().unwrap().unwrap();
+    let records: Vec<&Record> = tracer.records().collect();
+    // the EXIT instruction is recorded too
+    assert_eq!(records.len(), 8);
+    assert_eq!(tracer.recorded, 8);
+    assert_eq!(records[0].pc, 0x1000);
+    assert_eq!(records[0].bytes(), [0x86, 0x12]);
+    assert_eq!(records[0].ea, Some(0x1001));
+    assert_eq!((records[0].before.a, records[0].after.a), (0, 0x12));
+    assert_eq!(records[1].cycle, records[0].cycle + 2);
+
+    let sta = records[1];
+    assert_eq!(sta.bytes(), [0xb7, 0x20, 0x00]);
+    assert_eq!(sta.ea, Some(0x2000));
+    assert_eq!(
+        sta.accesses,
+        [Access {
+            addr: 0x2000,
+            data: 0x12,
+            write: true
+        }]
+    );
+    let ldb = records[3];
+    assert_eq!(ldb.after.b, 0x12);
+    assert_eq!(
+        ldb.accesses,
+        [Access {
+            addr: 0x2000,
+            data: 0x12,
+            write: false
+        }]
+    );
+    // BSR pushes the return address
+    let bsr = records[4];
+    assert_eq!(bsr.after.s, 0x7ffe);
+    assert_eq!(bsr.accesses.len(), 2);
+    assert!(bsr.accesses.iter().all(|a| a.write && a.addr >= 0x7ffe));
+    let inca = records[5];
+    assert_eq!(inca.ea, None);
+    assert_eq!(inca.after.a, 0x13);
+
+    // text, labelled with symbols
+    let mut symbols = Map::new();
+    symbols.insert(0x1002, vec!["STORE".to_string()]);
+    let text = String::from_utf8(tracer.export(Format::Text, &symbols)).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 8);
+    assert!(lines[1].contains("1002 STORE"), "{}", lines[1]);
+    assert!(lines[1].contains("B72000"), "{}", lines[1]);
+    assert!(lines[1].contains("ea=2000"), "{}", lines[1]);
+    assert!(lines[1].ends_with("W:2000=12"), "{}", lines[1]);
+    assert!(lines[0].contains("-> A=12"), "{}", lines[0]);
+
+    // binary reads back as it was
+    let binary = tracer.export(Format::Binary, &symbols);
+    let read = read_binary(&binary).unwrap();
+    assert_eq!(read.len(), 8);
+    for (a, b) in read.iter().zip(&records) {
+        assert_eq!(a.to_text(&symbols), b.to_text(&symbols));
+        assert_eq!(a.name, b.name);
+    }
+    assert!(read_binary(&binary[..binary.len() - 1]).is_err());
+    assert!(read_binary(b"CCST\x01").is_err());
+}
+
+#[test]
+fn test_trace_ring() {
+    // only the last few are kept
+    let (core, result) = run(PROGRAM, Tracer::new(3), &[]);
+    result.unwrap();
+    let all = pcs(&run(PROGRAM, Tracer::new(100), &[]).0);
+    assert_eq!(pcs(&core), all[5..]);
+    assert_eq!(core.tracer.as_ref().unwrap().borrow().recorded, 8);
+
+    // the last instructions before a crash
+    let src = "
+    org $1000
+    lda #1
+    ldb #2
+    fcb $01
+";
+    let path = std::env::temp_dir().join(format!("coco-trace-{}.txt", std::process::id()));
+    let path = path.to_str().unwrap();
+    let mut tracer = Tracer::new(2);
+    tracer
+        .set_sink(
+            Box::new(FileSink::create(path).unwrap()),
+            Format::Text,
+            Map::new(),
+        )
+        .unwrap();
+    let (core, result) = run(src, tracer, &[]);
+    assert!(result.is_err());
+    assert_eq!(pcs(&core), [0x1000, 0x1002]);
+    // the file was flushed when the CPU stopped
+    let text = std::fs::read_to_string(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(text.lines().count(), 2);
+}
+
+#[test]
+fn test_trace_filters() {
+    let all = pcs(&run(PROGRAM, Tracer::new(100), &[]).0);
+    let sub = all[5];
+
+    // an address range
+    let mut tracer = Tracer::new(100);
+    tracer.add_range(0x1002, 0x1008);
+    let (core, _) = run(PROGRAM, tracer, &[]);
+    assert_eq!(pcs(&core), all[1..4]);
+
+    // a symbol runs up to the next symbol
+    let mut core = run(
+        PROGRAM,
+        Tracer::new(100),
+        &[("START", 0x1000), ("SUB", sub)],
+    )
+    .0;
+    assert_eq!(core.symbol_range("START"), Some((0x1000, sub - 1)));
+    assert_eq!(core.symbol_range("SUB"), Some((sub, 0xffff)));
+    assert!(core.trace_symbol("NOWHERE").is_err());
+    core.trace_symbol("SUB").unwrap();
+    core.tracer.as_ref().unwrap().borrow_mut().clear();
+    core.reg.pc = 0x1000;
+    core.reg.s = 0x8000;
+    core.exec().unwrap();
+    assert_eq!(pcs(&core), all[5..7]);
+
+    // start and stop addresses
+    let mut tracer = Tracer::new(100);
+    tracer.set_start(Some(0x1005));
+    tracer.set_stop(Some(sub));
+    assert_eq!(tracer.state(), TraceState::Waiting);
+    let (core, _) = run(PROGRAM, tracer, &[]);
+    assert_eq!(pcs(&core), all[2..5]);
+    let state = core.tracer.as_ref().unwrap().borrow().state();
+    assert_eq!(state, TraceState::Stopped);
+}
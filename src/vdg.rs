@@ -170,6 +170,37 @@ impl Color {
         Color::from_code(1 + (bits | if css { 4 } else { 0 }))
     }
 }
+/// How the 256-pixel-wide two colour mode (PMODE 4) is shown. On a TV, pixels that
+/// alternate with their neighbours blur into red and blue; which is which depends on
+/// the phase the machine happened to start up in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Artifact {
+    /// show the pixels as they are
+    #[default]
+    Off,
+    BlueRed,
+    RedBlue,
+}
+impl Artifact {
+    /// The colour of a pair of pixels (the left one is bit 1)
+    fn color(self, pair: u8, fg_color: Color, bg_color: Color) -> Color {
+        match (self, pair) {
+            (_, 0) => bg_color,
+            (_, 3) => fg_color,
+            (Artifact::BlueRed, 2) | (Artifact::RedBlue, 1) => Blue,
+            _ => Red,
+        }
+    }
+}
+/// The rows of the built-in font's glyph for an ASCII character (BLOCK_DIM_Y of them,
+/// the top bit of each is the leftmost pixel). Lower case is shown as upper case.
+pub fn font_glyph(c: u8) -> &'static [u8] {
+    let ch = Char::try_from_ascii(c.to_ascii_uppercase()).unwrap_or(Char {
+        font_index: 0x20 * BLOCK_DIM_Y,
+        inverted: false,
+    });
+    &FONT_MAP[ch.font_index..ch.font_index + BLOCK_DIM_Y]
+}
 // Setting refresh rate to roughly 30 Hz (emulating NTSC)
 pub const SCREEN_REFRESH_PERIOD: Duration = Duration::from_micros(33333);
 pub const SCREEN_DIM_X: usize = 256;
@@ -219,6 +250,7 @@ pub struct Vdg {
     dirty: bool,
     vram_offset: usize,
     ascii: bool,
+    artifact: Artifact,
 }
 unsafe impl Send for Vdg {}
 
@@ -231,12 +263,22 @@ impl Vdg {
             dirty: true,
             vram_offset,
             ascii: false,
+            artifact: Artifact::Off,
+        }
+    }
+    pub fn set_artifact(&mut self, artifact: Artifact) {
+        if self.artifact != artifact {
+            self.artifact = artifact;
+            self.dirty = true;
         }
     }
+    pub fn get_artifact(&self) -> Artifact {
+        self.artifact
+    }
 
     pub fn set_mode(&mut self, mode: VdgMode) {
         if self.mode != mode {
-            info!("VDG VdgMode changed from {:?} to {:?}", self.mode, mode);
+            info!(target: Vdg, "VDG VdgMode changed from {:?} to {:?}", self.mode, mode);
             self.dirty = true;
             self.mode = mode;
         }
@@ -256,6 +298,7 @@ impl Vdg {
         }
         if vram_offset != self.vram_offset {
             info!(
+                target: Vdg,
                 "VDG vram_offset changed from {:4x} to {:4x}",
                 self.vram_offset, vram_offset
             );
@@ -323,6 +366,18 @@ impl Vdg {
         let src_bytes_per_row = cells_per_row / cells_per_src_byte;
         let mut dst_index = 0usize;
         let (fg_color, bg_color) = (Color::Green, Color::Black);
+        if self.mode == RG6 && self.artifact != Artifact::Off {
+            // green (or buff with CSS) on black, with alternating pixels in red or blue
+            let fg_color = if css { Color::Buff } else { fg_color };
+            for (i, pixels) in display.chunks_exact_mut(8).enumerate() {
+                let src_data = unsafe { RAM_DISK[self.vram_offset + i] };
+                for (j, pair) in pixels.chunks_exact_mut(2).enumerate() {
+                    let bits = (src_data >> (6 - 2 * j)) & 3;
+                    pair.fill(self.artifact.color(bits, fg_color, bg_color).to_rgb555());
+                }
+            }
+            return;
+        }
         for src_row in 0..cells_per_col {
             for _ in 0..md.cell_y {
                 // repeat for each row in each cell
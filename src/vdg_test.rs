@@ -1,4 +1,4 @@
-use crate::vdg::{Color, Vdg, VdgMode, SCREEN_DIM_X, SCREEN_DIM_Y};
+use crate::vdg::{Artifact, Color, Vdg, VdgMode, SCREEN_DIM_X, SCREEN_DIM_Y};
 use crate::RAM_DISK;
 
 /// Verify all Color::to_rgb555() values are correct RGB555 bit patterns.
@@ -206,3 +206,33 @@ fn test_render_sg4_text_char() {
         "Character 'A' should have at least some black pixels (background)"
     );
 }
+
+/// Verify PMODE 4 artifact colours: pairs of pixels become black, white, blue or red.
+#[test]
+fn test_render_artifact_colors() {
+    let offset = 0x6000;
+    let mut vdg = Vdg::with_ram(offset);
+    vdg.set_mode(VdgMode::RG6);
+    unsafe {
+        // pairs: 00, 11, 10, 01
+        RAM_DISK[offset] = 0b0011_1001;
+    }
+    let mut display = [0u16; SCREEN_DIM_X * SCREEN_DIM_Y];
+    vdg.set_dirty();
+    vdg.render(&mut display, true);
+    let green = Color::Green.to_rgb555();
+    let black = Color::Black.to_rgb555();
+    assert_eq!(display[..8], [black, black, green, green, green, black, black, green]);
+
+    vdg.set_artifact(Artifact::BlueRed);
+    vdg.render(&mut display, true);
+    let (buff, blue, red) = (
+        Color::Buff.to_rgb555(),
+        Color::Blue.to_rgb555(),
+        Color::Red.to_rgb555(),
+    );
+    assert_eq!(display[..8], [black, black, buff, buff, blue, blue, red, red]);
+    vdg.set_artifact(Artifact::RedBlue);
+    vdg.render(&mut display, true);
+    assert_eq!(display[4..8], [red, red, blue, blue]);
+}
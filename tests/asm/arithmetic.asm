@@ -0,0 +1,18 @@
+* Basic 8 and 16-bit arithmetic
+    org $1000
+start:
+    lda #$12
+    adda #$34
+    ldb #$10
+    subb #$01
+    ldx #$1234
+    leax -4,x
+    ldd #$0100
+    addd #$00ff
+    std result
+    exit
+result: rmb 2
+;! a = #$01
+;! b = #$ff
+;! x = #$1230
+;! result = #$01ff
@@ -0,0 +1,44 @@
+* Conditional assembly (nested blocks, ELSE and IFDEF/IFNDEF)
+DEBUG equ 1
+LEVEL equ 2
+    org $1000
+start:
+    clra
+    ifne DEBUG
+    inca
+    ifeq LEVEL-2
+    inca
+    else
+    lda #$ff
+    endc
+    ifgt LEVEL
+    inca
+    endc
+    iflt -3
+    inca
+    endc
+    ifle LEVEL
+    lda #$ff
+    endc
+    else
+    lda #$ff
+    ifeq 0
+    lda #$ee
+    endc
+    endc
+    ifdef MISSING
+    lda #$ff
+    endc
+    ifndef MISSING
+    inca
+    endc
+    ifdef DEBUG
+    inca
+    endc
+    sta result
+    exit
+result: rmb 1
+    ifeq 1
+;! result = #$ff
+    endc
+;! result = #6
@@ -0,0 +1,46 @@
+* Data and listing-control directives
+    nam directives
+    ttl Directive byte output
+    opt list
+COUNT set 1
+COUNT set COUNT+1
+    org $1000
+start:
+    lda #COUNT
+    sta result
+    exit
+result: rmb 1
+    pag
+fcs:    fcs /ABC/
+fcn:    fcn "hi"
+fcz:    fcz 'z'
+fill:   fill $AA,3      ; value, count
+zmb:    zmb 2
+bsz:    bsz 1
+    fcb $EE
+align:  align 4,$55     ; $1017 -> $1018
+aligned:
+    fcb COUNT
+COUNT set COUNT*3
+    fcb COUNT
+sizes:
+    fcb rmd_end-rmd,align_end-align_start
+rmd:    rmd 3
+rmd_end:
+align_start:
+    align 16
+align_end:
+    fcb 1
+    align 4 ; pad, to, word
+    fcb 2
+
+;! result = #2
+;! mem(fcs,3) = #$41,$42,$C3
+;! mem(fcn,3) = #'h,'i,0
+;! mem(fcz,2) = #'z,0
+;! mem(fill,3) = #$AA,$AA,$AA
+;! mem(zmb,4) = #0,0,0,$EE
+;! mem(align,2) = #$55,2
+;! mem(aligned,2) = #2,6
+;! mem(sizes,2) = #6,14
+;! mem(align_end,5) = #1,0,0,0,2
@@ -0,0 +1,16 @@
+* The operand of END is the entry point, so execution starts at "start"
+* rather than at the first instruction
+    org $1000
+skipped:
+    lda #1
+    sta flag
+    exit
+start:
+    lda #2
+    sta flag
+    exit
+
+flag:   rmb 1
+    end start
+
+;! flag = #2
@@ -0,0 +1,48 @@
+* Bitwise, shift, comparison and byte-select operators
+FLAGS equ $A5
+BIT3 equ 1<<3
+MASK equ $0F
+VERSION equ 2
+    org $1000
+start:
+    lda #>table
+    ldb #<table
+    std hilo
+    lda #FLAGS&$7F
+    ora #MASK|BIT3
+    sta bits
+    ldx #~0
+    ldy #-2
+    ldd #table&~MASK
+    std masked
+    ldd #.HIGH(table)<<8|.LOW(table+1)
+    std swapped
+    clra
+    ifne VERSION=2&&FLAGS>$80
+    inca
+    endc
+    ifne VERSION<>2||!MASK
+    lda #$ff
+    endc
+    sta cond
+    exit
+hilo: rmb 2
+bits: rmb 1
+masked: rmb 2
+swapped: rmb 2
+cond: rmb 1
+data:
+    fcb ~MASK,-1&$F0,FLAGS^$FF,VERSION>1,MASK>>2
+    fdb -1,VERSION<<8,table>>4
+    org $2345
+table:
+;! hilo = #$23
+;! hilo+1 = #$45
+;! bits = #$2F
+;! x = #$FFFF
+;! y = #$FFFE
+;! mem(masked,2) = #$23,$40
+;! mem(swapped,2) = #$23,$46
+;! cond = #1
+;! mem(data,5) = #$F0,$F0,$5A,1,3
+;! mem(data+5,6) = #$FF,$FF,2,0,$02,$34
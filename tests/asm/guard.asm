@@ -0,0 +1,45 @@
+* Include guards and INCLUDE within conditional blocks
+WANT_EXTRA equ 0
+    include "include/guarded.inc"
+    include "include/guarded.inc"
+    ifne WANT_EXTRA
+    include "include/missing.inc"
+    endc
+    ifdef NOT_DEFINED
+    include "include/missing.inc"
+    else
+    include "include/guarded.inc"
+    endc
+    org $1000
+start:
+    clra
+    bump
+    bump
+    sta result
+* conditions are unsigned unless they're negated
+    clrb
+    ifgt $8000
+    incb
+    endc
+    iflt $ffff
+    ldb #$ff
+    endc
+    iflt -1
+    incb
+    endc
+    stb flags
+* this condition depends on an address, so it's only decided when the program is built
+    ifne start-$1000
+    include "include/missing.inc"
+    lda #$ff
+    else
+    bump
+    endc
+    sta result2
+    exit
+result: rmb 1
+flags:  rmb 1
+result2: rmb 1
+;! result = #6
+;! flags = #2
+;! result2 = #9
@@ -0,0 +1,10 @@
+* Pull constants and a subroutine in from other files
+    org $1000
+start:
+    lda #SEED
+    bsr triple
+    sta answer
+    exit
+    include "include/triple.inc"
+answer: rmb 1
+;! answer = #63
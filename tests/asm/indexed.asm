@@ -0,0 +1,34 @@
+* Indexed addressing with 5-bit, 8-bit and 16-bit offsets (direct and indirect)
+    org $1000
+start:
+    ldx #table
+    lda 3,x
+    sta five
+    lda 100,x
+    sta eight
+    ldy #table+1000
+    lda -1000,y
+    sta sixteen
+    lda [4,x]
+    sta indirect
+    leau -100,x
+    lda 100,u
+    sta back
+    exit
+
+five:     rmb 1
+eight:    rmb 1
+sixteen:  rmb 1
+indirect: rmb 1
+back:     rmb 1
+    org $2000
+table:    fcb 10,11,12,13
+          fdb table+2
+    org $2064
+    fcb 100
+
+;! five = #13
+;! eight = #100
+;! sixteen = #10
+;! indirect = #12
+;! back = #10
@@ -0,0 +1,54 @@
+* Local (@/.) labels, temporary (numeric) labels and unique labels in macros
+    .macro clamp
+    cmpa #@0
+    bls @ok\@
+    lda #@0
+@ok\@:
+    .endm
+
+    org $1000
+start:
+    clra
+    ldb #3
+@loop:
+    inca
+    decb
+    bne @loop
+    sta first
+second:
+    clra
+    ldb #5
+@loop:
+    adda #2
+    decb
+    bne @loop
+.done:
+    sta result
+    ldx #0
+1
+    leax 1,x
+    cmpx #4
+    bne 1b
+    bra 1f
+    ldx #$ffff          ; skipped
+1
+    stx count
+    lda #9
+    clamp 6
+    sta clamped
+    lda #2
+    clamp 6
+    sta unclamped
+    exit
+
+first:     rmb 1
+result:    rmb 1
+count:     rmb 2
+clamped:   rmb 1
+unclamped: rmb 1
+
+;! first = #3
+;! result = #10
+;! mem(count,2) = #0,4
+;! clamped = #6
+;! unclamped = #2
@@ -0,0 +1,18 @@
+* Sum the bytes of a table using a counted loop
+    org $1000
+start:
+    ldx #table
+    ldb #5
+    clra
+loop:
+    adda ,x+
+    decb
+    bne loop
+    sta sum
+    exit
+table: fcb 1,2,3,4,5
+sum:   rmb 1
+;! a = #15
+;! sum = #15
+;! b = #0
+;! x = #sum
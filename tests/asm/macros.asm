@@ -0,0 +1,76 @@
+* Named macro parameters with defaults, arg counts, argument checks, .EXITM,
+* nested and recursive macro invocations and REPT/ENDR blocks
+    .macro store val,dest=total
+    ifstr \val
+    fcc \val
+    .exitm
+    endc
+    lda #\val
+    sta \dest
+    .endm
+
+    .macro add_to dest,amount=1
+    lda \dest
+    adda #\amount
+    sta \dest
+    .endm
+
+    .macro count_args
+    ldb #\0
+    stb nargs
+    .endm
+
+    .macro double_add dest,amount
+    add_to \dest,\amount
+    add_to \dest,\amount
+    .endm
+
+    .macro countdown count
+    fcb \count
+    ifeq \count
+    .exitm
+    endc
+    countdown \count-1
+    .endm
+
+    .macro maybe_add dest,amount=
+    ifnb \amount
+    add_to \dest,\amount
+    endc
+    .endm
+
+    org $1000
+start:
+    store 5
+    store 7,other
+    add_to total
+    add_to other, 3
+    double_add other,10
+    maybe_add other
+    maybe_add total,4
+    count_args a,b,c
+    rept 3
+    add_to total,2
+    endr
+    ldx #0
+    rept 2
+    rept 2
+    leax 1,x
+    endr
+    endr
+    stx reps
+    exit
+
+total:  rmb 1
+other:  rmb 1
+nargs:  rmb 1
+reps:   rmb 2
+text:   store "hi"
+down:   countdown 3
+
+;! total = #16
+;! other = #30
+;! nargs = #3
+;! mem(reps,2) = #0,4
+;! mem(text,2) = "hi"
+;! mem(down,4) = #3,2,1,0
@@ -0,0 +1,58 @@
+* Memory range, string, screen and condition code assertions
+sam     equ $ffc0
+vram    equ $400
+    org $1000
+start:
+* point the SAM at the usual text screen
+    sta sam+9
+* copy a message to a buffer and to row 2 of the screen
+    ldx #msg
+    ldy #buf
+    ldu #vram+(2*32)+3
+copy:
+    lda ,x+
+    sta ,y+
+    beq done
+* store it the way the ROM does: $40-$5F as they are, below $40 with bit 6
+* flipped, lower case folded to upper case
+    cmpa #$40
+    bhs letter
+    eora #$40
+    bra putc
+letter:
+    cmpa #$60
+    blo putc
+    anda #$df
+putc:
+    sta ,u+
+    bra copy
+done:
+    ldd #$1234
+    std word
+    lda #1
+    suba #1
+    exit
+msg:  fcc "Hi, CoCo"
+      fcb 0
+buf:  rmb 9
+word: rmb 2
+;! buf = "Hi, CoCo"
+;! buf = /Hi, CoCo/
+;! buf+8 = #0
+;! mem(buf,9) = msg
+;! mem(buf,9) = mem(msg,9)
+;! mem(buf,2) = #'H,'i
+;! word = #$12,$34
+;! word = #$1234
+;! mem(word,2) != #0,0
+;! buf != "Hello"
+;! screen(3,2) = "HI, COCO"
+;! screen(3,2) = "hi, coco"
+;! screen(3,2) = #$48,$49,$6C,$60,$43,$4F,$43,$4F
+;! screen(2,2) != " "
+;! screen(0,2) = #0,0,0
+;! cc.z = 1
+;! cc.zero = #1
+;! cc.c = 0
+;! cc.N = 0
+;! cc.halfcarry = 0
@@ -0,0 +1,26 @@
+* Direct page addressing with SETDP and the "<" and ">" operand prefixes
+    org $1000
+start:
+    lda #$20
+    tfr a,dp
+    setdp $20
+    lda #5
+code:
+    sta var         ; direct because var is in the declared page
+    sta >var2       ; extended (forced)
+    lda <var        ; direct (forced)
+    ldb #7
+    stb <$11        ; direct (low byte of the address)
+    setdp off
+    ldx var         ; extended because the direct page is unknown
+code_end:
+    exit
+
+    org $2010
+var:  rmb 1
+var2: rmb 1
+
+;! mem(code,15) = #$97,$10,$B7,$20,$11,$96,$10,$C6,$07,$D7,$11,$BE,$20,$10,$11
+;! var = #5
+;! var2 = #7
+;! dp = #$20
@@ -0,0 +1,20 @@
+* Copy a string until its terminating zero
+    org $1000
+start:
+    ldx #src
+    ldy #dst
+copy:
+    lda ,x+
+    sta ,y+
+    bne copy
+    exit
+src: fcc "HELLO"
+     fcb 0
+dst: rmb 6
+semi: fcb ';,1       ; a comment, with commas
+      fcb "a;b",';'  ; quoted semicolons aren't comments
+;! dst = #'H
+;! dst+4 = #'O
+;! dst+5 = #0
+;! mem(semi,2) = #$3B,1
+;! mem(semi+2,4) = #'a,$3B,'b,$3B
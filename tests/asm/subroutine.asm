@@ -0,0 +1,14 @@
+* Call a subroutine that doubles register A
+    org $1000
+start:
+    lds #$2000
+    lda #21
+    bsr double
+    sta answer
+    exit
+double:
+    asla
+    rts
+answer: rmb 1
+;! answer = #42
+;! s = #$2000
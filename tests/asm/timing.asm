@@ -0,0 +1,29 @@
+* Cycle and instruction count assertions
+* (cycle counts follow the simulator's instruction timing table)
+    org $1000
+start:
+    lds #$2000
+    ldx #table
+    ldb #4
+sum:
+    bsr addone
+    decb
+    bne sum
+done:
+    exit
+addone:
+    inc ,x+
+    rts
+table: fcb 1,2,3,4
+;! table+3 = #5
+;! cycles = 77
+;! cycles < 100
+;! instructions = 23
+;! instructions != 0
+;! cycles(addone,sum+2) = 7
+;! instructions(sum,done) = 20
+;! cycles(sum,done) = 68
+;! cycles(sum,sum) = 17
+;! cycles<=77
+;! instructions>=23
+;! cycles(sum,done)>67